@@ -2,7 +2,7 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{
     parse_macro_input, spanned::Spanned, AttrStyle, Attribute, Data, DataEnum, DataStruct,
-    DeriveInput, Field, Fields, FieldsNamed, Ident, LitStr, Variant,
+    DeriveInput, Field, Fields, FieldsNamed, Ident, ItemFn, LitStr, Path, Variant,
 };
 
 type Result<T> = std::result::Result<T, syn::Error>;
@@ -11,7 +11,7 @@ type Result<T> = std::result::Result<T, syn::Error>;
 pub fn property_list(input: TokenStream) -> TokenStream {
     let derive_input = parse_macro_input!(input as DeriveInput);
     match impl_proplist(&derive_input) {
-        Ok(props) => props.into(),
+        Ok(props) => props,
         Err(err) => err.into_compile_error().into(),
     }
 }
@@ -20,11 +20,109 @@ pub fn property_list(input: TokenStream) -> TokenStream {
 pub fn backend(input: TokenStream) -> TokenStream {
     let derive_input = parse_macro_input!(input as DeriveInput);
     match impl_backends(&derive_input) {
-        Ok(props) => props.into(),
+        Ok(props) => props,
         Err(err) => err.into_compile_error().into(),
     }
 }
 
+/// Run a test against a system, torn down when the test ends
+///
+/// The test takes the system as its only argument, e.g.
+/// `fn boots(system: &mut impl SystemHarness)`. The system is built from
+/// either:
+///
+/// - `config = "path"`: a [`SystemConfig`] file relative to the crate's
+///   manifest directory
+/// - `builder = path`: a function returning `Result<S, Error>` for some
+///   `S: SystemHarness`
+///
+/// `artifacts = "dir"` sets where artifacts are collected if the test
+/// panics, overriding `SYSTEM_HARNESS_ARTIFACTS`, and `collect = path`
+/// adds a function collecting backend-specific artifacts. See
+/// `SystemTest` for details.
+///
+/// [`SystemConfig`]: https://docs.rs/system-harness/latest/system_harness/enum.SystemConfig.html
+#[proc_macro_attribute]
+pub fn system_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args = SystemTestArgs::default();
+    let parser = syn::meta::parser(|meta| args.parse(meta));
+    parse_macro_input!(attr with parser);
+    let test = parse_macro_input!(item as ItemFn);
+    match impl_system_test(args, test) {
+        Ok(test) => test,
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct SystemTestArgs {
+    config: Option<LitStr>,
+    builder: Option<Path>,
+    artifacts: Option<LitStr>,
+    collect: Option<Path>,
+}
+
+impl SystemTestArgs {
+    fn parse(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::parse::Result<()> {
+        if meta.path.is_ident("config") {
+            self.config = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("builder") {
+            self.builder = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("artifacts") {
+            self.artifacts = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("collect") {
+            self.collect = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("Expected config, builder, artifacts or collect"));
+        }
+        Ok(())
+    }
+}
+
+fn impl_system_test(args: SystemTestArgs, test: ItemFn) -> Result<TokenStream> {
+    let ident = &test.sig.ident;
+    if test.sig.inputs.len() != 1 {
+        return Err(syn::Error::new(
+            test.sig.inputs.span(),
+            "System tests take the system as their only argument",
+        ));
+    }
+    let build = match (args.config, args.builder) {
+        (Some(config), None) => quote! {
+            || ::system_harness::SystemConfig::load(
+                ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(#config)
+            )?.build()
+        },
+        (None, Some(builder)) => quote! { #builder },
+        _ => {
+            return Err(syn::Error::new(
+                test.sig.ident.span(),
+                "Expected one of config or builder",
+            ))
+        }
+    };
+    let artifacts = args
+        .artifacts
+        .map(|artifacts| quote! { .artifacts(#artifacts) });
+    let collect = args.collect.map(|collect| quote! { .collect(#collect) });
+    let attrs = &test.attrs;
+    let vis = &test.vis;
+    let mut inner = test.clone();
+    inner.attrs.clear();
+    Ok(quote! {
+        #[test]
+        #(#attrs)*
+        #vis fn #ident() {
+            #inner
+            ::system_harness::SystemTest::new(concat!(module_path!(), "::", stringify!(#ident)))
+                #artifacts
+                #collect
+                .run(#build, |system| #ident(system));
+        }
+    }
+    .into())
+}
+
 fn impl_proplist(input: &DeriveInput) -> Result<TokenStream> {
     let ident = &input.ident;
     match input.data {
@@ -163,7 +261,7 @@ fn insert_prop(local: bool, field: &Field) -> Option<proc_macro2::TokenStream> {
                 }
             },
             Some(SerdeAttribute::Rename(ref rename)) => {
-                let name_str = format!("{}", rename.value());
+                let name_str = rename.value();
                 quote! {
                     props.insert(#name_str, #value);
                 }
@@ -211,8 +309,8 @@ fn parse_attributes(attrs: &[Attribute]) -> Option<SerdeAttribute> {
     let mut flatten = false;
     let mut rename = None;
     for attr in attrs {
-        match attr.style {
-            AttrStyle::Outer => match &attr.meta {
+        if let AttrStyle::Outer = attr.style {
+            match &attr.meta {
                 syn::Meta::List(list) if list.path.is_ident("serde") => {
                     let _ = attr.parse_nested_meta(|meta| {
                         if meta.path.is_ident("flatten") {
@@ -226,15 +324,12 @@ fn parse_attributes(attrs: &[Attribute]) -> Option<SerdeAttribute> {
                     });
                 }
                 _ => {}
-            },
-            _ => {}
-        };
+            }
+        }
     }
     if flatten {
         Some(SerdeAttribute::Flatten)
-    } else if let Some(rename) = rename {
-        Some(SerdeAttribute::Rename(rename))
     } else {
-        None
+        rename.map(SerdeAttribute::Rename)
     }
 }
@@ -0,0 +1,165 @@
+use crate::{Error, ErrorKind};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::net::{Ipv4Addr, TcpListener};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+/// Value of a config field asking for a resource to be allocated
+#[cfg_attr(not(feature = "qemu"), allow(dead_code))]
+pub(crate) const AUTO: &str = "auto";
+
+/// Candidate ports tried before giving up
+const MAX_ATTEMPTS: usize = 32;
+
+/// Counter used to give each allocated MAC address a unique suffix
+static MAC: AtomicU16 = AtomicU16::new(0);
+
+/// Directory holding a lock file for each reserved port
+fn lock_dir() -> PathBuf {
+    std::env::temp_dir().join("system-harness-ports")
+}
+
+/// A TCP port reserved for a system
+///
+/// Ports are reserved by locking a file in the temporary directory, so
+/// harnesses in other processes don't hand out the same port before the
+/// system binds it. The reservation is released when dropped, or when
+/// its process exits however it exits, as the lock goes with the open
+/// file. Lock files are left in place, as removing one could race with
+/// another process locking it.
+#[derive(Debug)]
+pub struct PortReservation {
+    port: u16,
+    /// Locked file, holding the reserving process's PID
+    lock: File,
+}
+
+impl PortReservation {
+    /// Claim a port that was free when checked
+    fn claim(port: u16) -> Result<Option<Self>, Error> {
+        let dir = lock_dir();
+        std::fs::create_dir_all(&dir)?;
+        let mut lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(port.to_string()))?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+        lock.set_len(0)?;
+        write!(lock, "{}", std::process::id())?;
+        log::trace!("Reserved port {port}");
+        Ok(Some(Self { port, lock }))
+    }
+
+    /// Reserved port
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for PortReservation {
+    fn drop(&mut self) {
+        if let Err(err) = self.lock.unlock() {
+            log::warn!("Failed to release port {}: {err}", self.port);
+        }
+    }
+}
+
+/// Reserve a free TCP port on the loopback interface
+pub fn reserve_port() -> Result<PortReservation, Error> {
+    for _ in 0..MAX_ATTEMPTS {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        if let Some(reservation) = PortReservation::claim(listener.local_addr()?.port())? {
+            return Ok(reservation);
+        }
+    }
+    Err(Error::new(
+        ErrorKind::HarnessError,
+        "No free port to reserve",
+    ))
+}
+
+/// Reserve a free TCP port on the loopback interface within a range, for
+/// services that only listen on some ports (e.g. VNC from 5900)
+pub fn reserve_port_in(ports: Range<u16>) -> Result<PortReservation, Error> {
+    let len = ports.len();
+    if len == 0 {
+        return Err(Error::new(ErrorKind::InvalidConfig, "Empty port range"));
+    }
+    // Start at an offset unique to the process so concurrent harnesses
+    // don't all race for the first port
+    let offset = std::process::id() as usize % len;
+    for port in ports.clone().cycle().skip(offset).take(len) {
+        let Ok(listener) = TcpListener::bind((Ipv4Addr::LOCALHOST, port)) else {
+            continue;
+        };
+        if let Some(reservation) = PortReservation::claim(listener.local_addr()?.port())? {
+            return Ok(reservation);
+        }
+    }
+    Err(Error::new(
+        ErrorKind::HarnessError,
+        format!("No free port to reserve in {ports:?}"),
+    ))
+}
+
+/// A MAC address unique to the host
+///
+/// Addresses are locally administered and made of the harness's PID and a
+/// counter, so concurrent harnesses never allocate the same one.
+pub fn unique_mac() -> String {
+    let pid = std::process::id().to_be_bytes();
+    let [high, low] = MAC.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+    format!(
+        "02:{:02x}:{:02x}:{:02x}:{high:02x}:{low:02x}",
+        pid[1], pid[2], pid[3]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn reserve() {
+        let first = reserve_port().unwrap();
+        let lock = lock_dir().join(first.port().to_string());
+        assert_eq!(
+            std::process::id().to_string(),
+            std::fs::read_to_string(&lock).unwrap()
+        );
+        // A reserved port isn't handed out again until released
+        assert!(PortReservation::claim(first.port()).unwrap().is_none());
+        let port = first.port();
+        drop(first);
+        let again = PortReservation::claim(port).unwrap().unwrap();
+        drop(again);
+
+        // A lock left by a process that exited before writing its PID
+        std::fs::write(&lock, "").unwrap();
+        let reclaimed = PortReservation::claim(port).unwrap().unwrap();
+        assert_eq!(
+            std::process::id().to_string(),
+            std::fs::read_to_string(&lock).unwrap()
+        );
+        drop(reclaimed);
+
+        let vnc = reserve_port_in(5900..6900).unwrap();
+        assert!((5900..6900).contains(&vnc.port()));
+    }
+
+    #[test]
+    fn macs() {
+        let first = unique_mac();
+        assert!(first.starts_with("02:"));
+        assert_eq!(17, first.len());
+        assert_ne!(first, unique_mac());
+    }
+}
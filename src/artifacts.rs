@@ -0,0 +1,84 @@
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+/// File listing the artifacts that couldn't be collected
+const ERRORS_FILE: &str = "errors.txt";
+
+/// A directory a bundle of artifacts is collected in
+///
+/// One artifact failing doesn't stop the others from being collected, as a
+/// broken system is when they're needed most. Failures are listed in
+/// `errors.txt` instead.
+pub(crate) struct ArtifactDir {
+    dir: PathBuf,
+    errors: Vec<String>,
+}
+
+impl ArtifactDir {
+    /// Create the directory if it doesn't exist
+    pub(crate) fn create(dir: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            errors: Vec::new(),
+        })
+    }
+
+    /// Path of an artifact in the directory
+    pub(crate) fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Write an artifact, or note why it couldn't be collected
+    pub(crate) fn write(&mut self, name: &str, data: Result<impl AsRef<[u8]>, Error>) {
+        let result = data.and_then(|data| Ok(std::fs::write(self.path(name), data)?));
+        self.check(name, result);
+    }
+
+    /// Note why an artifact written some other way couldn't be collected
+    pub(crate) fn check(&mut self, name: &str, result: Result<(), Error>) {
+        if let Err(err) = result {
+            log::warn!("Failed to collect {name}: {err}");
+            self.errors.push(format!("{name}: {err}"));
+        }
+    }
+
+    /// Finish the bundle, listing any artifacts that couldn't be collected
+    pub(crate) fn finish(self) -> Result<(), Error> {
+        if !self.errors.is_empty() {
+            std::fs::write(self.path(ERRORS_FILE), self.errors.join("\n") + "\n")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn errors() {
+        let dir =
+            std::env::temp_dir().join(format!("system-harness-artifacts-{}", std::process::id()));
+        let mut artifacts = ArtifactDir::create(&dir).unwrap();
+        artifacts.write("status.txt", Ok("Running"));
+        artifacts.check(
+            "screen.ppm",
+            Err(Error::new(ErrorKind::HarnessError, "no display")),
+        );
+        artifacts.finish().unwrap();
+
+        assert_eq!(
+            "Running",
+            std::fs::read_to_string(dir.join("status.txt")).unwrap()
+        );
+        assert!(!dir.join("screen.ppm").exists());
+        assert_eq!(
+            "screen.ppm: no display\n",
+            std::fs::read_to_string(dir.join(ERRORS_FILE)).unwrap()
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{Error, Key, SystemTerminal};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Terminal size assumed when none is given
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// Quote text as a JSON string
+fn quote(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(result, "\\u{:04x}", c as u32);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Bytes of a stream decoded as UTF-8
+///
+/// Characters split across reads are held back until they're complete,
+/// and invalid bytes are replaced.
+#[derive(Default)]
+struct Utf8Stream {
+    pending: Vec<u8>,
+}
+
+impl Utf8Stream {
+    fn decode(&mut self, data: &[u8]) -> String {
+        self.pending.extend_from_slice(data);
+        let mut text = String::new();
+        let mut rest = &self.pending[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(err) => {
+                    let (valid, invalid) = rest.split_at(err.valid_up_to());
+                    text.push_str(&String::from_utf8_lossy(valid));
+                    match err.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+                        None => {
+                            rest = invalid;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+        text
+    }
+}
+
+/// A terminal that records everything sent and received to an
+/// [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) file
+///
+/// The cast can be played back with `asciinema play` or the asciinema
+/// player, to see a failed interactive test as it happened. Output is
+/// recorded as `o` events, input and keys as `i` events and resizes as `r`
+/// events. Each event is flushed as it's written, so the cast is usable
+/// even if the test doesn't finish.
+pub struct AsciicastTerminal<T, W: Write> {
+    terminal: T,
+    cast: W,
+    start: Instant,
+    output: Utf8Stream,
+    input: Utf8Stream,
+}
+
+impl<T: SystemTerminal> AsciicastTerminal<T, BufWriter<File>> {
+    /// Record a terminal to a cast file, replacing it if it exists
+    pub fn create(terminal: T, path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::new(terminal, BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<T: SystemTerminal, W: Write> AsciicastTerminal<T, W> {
+    /// Record a terminal, assuming it has 80 columns and 24 rows
+    pub fn new(terminal: T, cast: W) -> Result<Self, Error> {
+        Self::with_size(terminal, cast, DEFAULT_SIZE.0, DEFAULT_SIZE.1)
+    }
+
+    /// Record a terminal with the given number of columns and rows
+    pub fn with_size(terminal: T, mut cast: W, cols: u16, rows: u16) -> Result<Self, Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        writeln!(
+            cast,
+            "{{\"version\": 2, \"width\": {cols}, \"height\": {rows}, \"timestamp\": {timestamp}}}"
+        )?;
+        cast.flush()?;
+        Ok(Self {
+            terminal,
+            cast,
+            start: Instant::now(),
+            output: Utf8Stream::default(),
+            input: Utf8Stream::default(),
+        })
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> std::io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            self.cast,
+            "[{:.6}, \"{code}\", {}]",
+            self.start.elapsed().as_secs_f64(),
+            quote(data)
+        )?;
+        self.cast.flush()
+    }
+
+    /// Stop recording, returning the terminal and the cast
+    pub fn into_inner(self) -> (T, W) {
+        (self.terminal, self.cast)
+    }
+}
+
+impl<T: SystemTerminal, W: Write> Read for AsciicastTerminal<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.terminal.read(buf)?;
+        let text = self.output.decode(&buf[..len]);
+        self.event("o", &text)?;
+        Ok(len)
+    }
+}
+
+impl<T: SystemTerminal, W: Write> Write for AsciicastTerminal<T, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.terminal.write(buf)?;
+        let text = self.input.decode(&buf[..len]);
+        self.event("i", &text)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.terminal.flush()
+    }
+}
+
+impl<T: SystemTerminal, W: Write> SystemTerminal for AsciicastTerminal<T, W> {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        let data = match key {
+            Key::Enter => "\r",
+        };
+        self.terminal.send_key(key)?;
+        Ok(self.event("i", data)?)
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        self.terminal.resize(cols, rows)?;
+        Ok(self.event("r", &format!("{cols}x{rows}"))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serde_json::Value;
+
+    struct FakeTerminal(Vec<&'static [u8]>);
+
+    impl Read for FakeTerminal {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let output = self.0.remove(0);
+            buf[..output.len()].copy_from_slice(output);
+            Ok(output.len())
+        }
+    }
+
+    impl Write for FakeTerminal {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SystemTerminal for FakeTerminal {
+        fn send_key(&mut self, _key: Key) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn resize(&mut self, _cols: u16, _rows: u16) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record() {
+        // "é" is split across reads, and 0xff isn't UTF-8
+        let terminal = FakeTerminal(vec![b"login: \xc3", b"\xa9\x1b[0m\xff\"\n"]);
+        let mut terminal = AsciicastTerminal::with_size(terminal, Vec::new(), 100, 30).unwrap();
+        let mut output = Vec::new();
+        terminal.read_to_end(&mut output).unwrap();
+        terminal.send_command("root").unwrap();
+        terminal.resize(120, 40).unwrap();
+
+        let (_, cast) = terminal.into_inner();
+        let cast = String::from_utf8(cast).unwrap();
+        let mut lines = cast
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap());
+        let header = lines.next().unwrap();
+        assert_eq!(2, header["version"]);
+        assert_eq!(100, header["width"]);
+        assert_eq!(30, header["height"]);
+        let events: Vec<(String, String)> = lines
+            .map(|event| {
+                assert!(event[0].as_f64().unwrap() >= 0.0);
+                (
+                    event[1].as_str().unwrap().to_string(),
+                    event[2].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("o".to_string(), "login: ".to_string()),
+                ("o".to_string(), "é\x1b[0m\u{fffd}\"\n".to_string()),
+                ("i".to_string(), "root".to_string()),
+                ("i".to_string(), "\r".to_string()),
+                ("r".to_string(), "120x40".to_string()),
+            ],
+            events
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::process::{ProcessSystem, DEFAULT_GRACE_PERIOD};
+use crate::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Default program run inside the root
+const DEFAULT_PROGRAM: &str = "/bin/sh";
+
+/// Counter used to give each generated jail a unique name
+static INSTANCE: AtomicUsize = AtomicUsize::new(0);
+
+/// How the system is confined to its root directory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum ChrootConfinement {
+    /// `chroot(2)`, which requires `CAP_SYS_CHROOT`
+    #[default]
+    Chroot,
+
+    /// A FreeBSD jail created with `jail(8)`, removed when the program
+    /// exits
+    Jail,
+}
+
+/// A configuration for running a program confined to a root directory
+///
+/// The program's standard input and output are the system's terminal. It
+/// is paused and resumed with `SIGSTOP` and `SIGCONT`.
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChrootSystemConfig {
+    /// Root directory of the system
+    root: PathBuf,
+
+    /// How the program is confined (defaults to chroot)
+    #[serde(default)]
+    confinement: ChrootConfinement,
+
+    /// Program to run, as a path inside the root (defaults to `/bin/sh`)
+    program: Option<String>,
+
+    /// Program arguments
+    args: Option<Vec<String>>,
+
+    /// Environment variables, replacing the harness's environment
+    env: Option<BTreeMap<String, String>>,
+
+    /// Working directory inside the root (defaults to `/`)
+    cwd: Option<String>,
+
+    /// Jail name (defaults to a generated, unique name)
+    jail_name: Option<String>,
+
+    /// Seconds to wait for the program to exit when dropped before killing
+    /// it (defaults to 10)
+    grace_period: Option<u64>,
+}
+
+impl ChrootSystemConfig {
+    /// Command running the program in the root
+    fn command(&self) -> Result<Command, Error> {
+        let program = self.program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+        let mut command = match self.confinement {
+            ChrootConfinement::Chroot => {
+                let mut command = Command::new(program);
+                let root = CString::new(self.root.as_os_str().as_bytes())
+                    .map_err(|err| Error::new(ErrorKind::InvalidConfig, err))?;
+                let cwd = CString::new(self.cwd.as_deref().unwrap_or("/"))
+                    .map_err(|err| Error::new(ErrorKind::InvalidConfig, err))?;
+                // SAFETY: chroot and chdir are async-signal-safe and only
+                // read the strings prepared before forking.
+                unsafe {
+                    command.pre_exec(move || {
+                        if libc::chroot(root.as_ptr()) != 0 || libc::chdir(cwd.as_ptr()) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+                command
+            }
+            ChrootConfinement::Jail => {
+                if self.cwd.is_some() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidConfig,
+                        "A working directory is not supported with jails",
+                    ));
+                }
+                let name = self.jail_name.clone().unwrap_or_else(|| {
+                    format!(
+                        "system_harness_{}_{}",
+                        std::process::id(),
+                        INSTANCE.fetch_add(1, Ordering::Relaxed)
+                    )
+                });
+                let mut command = Command::new("jail");
+                command.arg("-c");
+                command.arg(format!("name={name}"));
+                command.arg(format!("path={}", self.root.display()));
+                command.arg(format!("command={program}"));
+                command
+            }
+        };
+        command.args(self.args.iter().flatten());
+        if let Some(env) = &self.env {
+            command.env_clear().envs(env);
+        }
+        Ok(command)
+    }
+
+    /// Start the program
+    pub fn build(&self) -> Result<ChrootSystem, Error> {
+        let grace_period = self
+            .grace_period
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_GRACE_PERIOD);
+        log::trace!("Confining program to {}...", self.root.display());
+        ProcessSystem::spawn(&mut self.command()?, grace_period)
+    }
+}
+
+/// A program running confined to a root directory
+///
+/// [`ProcessSystem::pid`] is the program's process ID, or that of
+/// `jail(8)` for jails.
+pub type ChrootSystem = ProcessSystem;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{Key, SystemHarness, SystemTerminal};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn jail_command() {
+        let config: ChrootSystemConfig = serde_json::from_str(
+            r#"{
+                "root": "/jails/test",
+                "confinement": "jail",
+                "program": "/usr/bin/env",
+                "args": ["-i"],
+                "env": { "TERM": "dumb" },
+                "jail_name": "test"
+            }"#,
+        )
+        .unwrap();
+        let command = config.command().unwrap();
+        assert_eq!("jail", command.get_program());
+        assert_eq!(
+            vec![
+                "-c",
+                "name=test",
+                "path=/jails/test",
+                "command=/usr/bin/env",
+                "-i"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![("TERM".as_ref(), Some("dumb".as_ref()))],
+            command.get_envs().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn jail_cwd() {
+        let config: ChrootSystemConfig = serde_json::from_str(
+            r#"{ "root": "/jails/test", "confinement": "jail", "cwd": "/tmp" }"#,
+        )
+        .unwrap();
+        let err = config.command().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+
+    #[test]
+    fn chroot() {
+        let config: ChrootSystemConfig = serde_json::from_str(
+            r#"{ "root": "/", "program": "/bin/sh", "args": ["-c", "read line; echo $line"] }"#,
+        )
+        .unwrap();
+        // SAFETY: geteuid has no memory safety requirements.
+        if unsafe { libc::geteuid() } != 0 {
+            assert!(config.build().is_err());
+            return;
+        }
+        let mut system = config.build().unwrap();
+        let mut terminal = system.terminal().unwrap();
+        terminal.write_all(b"hello").unwrap();
+        terminal.send_key(Key::Enter).unwrap();
+        let mut output = String::new();
+        terminal.read_to_string(&mut output).unwrap();
+        assert_eq!("hello\n", output);
+        let status = system.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(Some(0), status.code);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{DynSystemHarness, Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+
+/// A configuration for a system of any backend
+///
+/// The backend is selected by a `type` tag next to the backend's own
+/// fields, e.g. `{"type": "qemu", "arch": "x86_64"}`, so config files and
+/// orchestration code don't need to know which backend a system uses.
+/// Backends from other crates are built through a
+/// [`HarnessRegistry`](crate::HarnessRegistry) instead.
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "kebab-case")]
+// Configs are short-lived, so boxing the larger ones isn't worth it
+#[allow(clippy::large_enum_variant)]
+pub enum SystemConfig {
+    #[cfg(feature = "qemu")]
+    Qemu(crate::QemuSystemConfig),
+
+    #[cfg(all(unix, feature = "container"))]
+    Container(crate::ContainerSystemConfig),
+
+    #[cfg(all(unix, feature = "crosvm"))]
+    Crosvm(crate::CrosvmSystemConfig),
+
+    #[cfg(all(unix, feature = "lxd"))]
+    Lxd(crate::LxdSystemConfig),
+
+    #[cfg(all(unix, feature = "remote"))]
+    Remote(crate::RemoteSystemConfig),
+
+    #[cfg(all(unix, feature = "vagrant"))]
+    Vagrant(crate::VagrantSystemConfig),
+
+    #[cfg(all(unix, feature = "xen"))]
+    Xen(crate::XenSystemConfig),
+
+    #[cfg(all(unix, feature = "uml"))]
+    Uml(crate::UmlSystemConfig),
+
+    #[cfg(all(unix, feature = "process"))]
+    Process(crate::ProcessSystemConfig),
+
+    #[cfg(all(unix, feature = "chroot"))]
+    Chroot(crate::ChrootSystemConfig),
+}
+
+impl SystemConfig {
+    /// Load a config from a file
+    ///
+    /// The format is chosen by the file's extension: `yaml` or `yml` for
+    /// YAML and `toml` for TOML, when their features are enabled, and JSON
+    /// otherwise.
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "yaml")]
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml(&contents),
+            // Reached when a format's feature is disabled
+            #[allow(unreachable_patterns)]
+            Some("yaml" | "yml" | "toml") => Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Config format not enabled: {}", path.display()),
+            )),
+            _ => serde_json::from_str(&contents)
+                .map_err(|err| Error::new(ErrorKind::SerializationError, err)),
+        }
+    }
+
+    /// Build the system with its backend
+    pub fn build(&self) -> Result<Box<dyn DynSystemHarness>, Error> {
+        Ok(match self {
+            #[cfg(feature = "qemu")]
+            SystemConfig::Qemu(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "container"))]
+            SystemConfig::Container(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "crosvm"))]
+            SystemConfig::Crosvm(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "lxd"))]
+            SystemConfig::Lxd(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "remote"))]
+            SystemConfig::Remote(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "vagrant"))]
+            SystemConfig::Vagrant(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "xen"))]
+            SystemConfig::Xen(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "uml"))]
+            SystemConfig::Uml(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "process"))]
+            SystemConfig::Process(config) => Box::new(config.build()?),
+            #[cfg(all(unix, feature = "chroot"))]
+            SystemConfig::Chroot(config) => Box::new(config.build()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[cfg(feature = "qemu")]
+    #[test]
+    fn qemu() {
+        let config: SystemConfig =
+            serde_json::from_str(r#"{ "type": "qemu", "arch": "x86_64", "memory": 512 }"#).unwrap();
+        assert!(matches!(config, SystemConfig::Qemu(_)));
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!("qemu", json["type"]);
+        assert_eq!(512, json["memory"]);
+    }
+
+    #[cfg(all(unix, feature = "container"))]
+    #[test]
+    fn container() {
+        let mut json: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/data/container-config.json")).unwrap();
+        json["type"] = "container".into();
+        let config: SystemConfig = serde_json::from_value(json).unwrap();
+        assert!(matches!(config, SystemConfig::Container(_)));
+    }
+
+    #[cfg(all(unix, feature = "process"))]
+    #[test]
+    fn build() {
+        let config: SystemConfig = serde_json::from_str(
+            r#"{ "type": "process", "program": "sh", "args": ["-c", "exit 3"] }"#,
+        )
+        .unwrap();
+        let mut system = config.build().unwrap();
+        let status = system.dyn_wait(std::time::Duration::from_secs(5)).unwrap();
+        assert_eq!(Some(3), status.code);
+    }
+
+    #[cfg(all(feature = "schema", feature = "qemu"))]
+    #[test]
+    fn schema() {
+        let schema = serde_json::to_value(schemars::schema_for!(SystemConfig)).unwrap();
+        let variants = schema["oneOf"].as_array().unwrap();
+        let qemu = variants
+            .iter()
+            .find(|variant| variant["properties"]["type"]["enum"][0] == "qemu")
+            .unwrap();
+        assert!(qemu["properties"]["blockdev"].is_object());
+        assert!(schema["definitions"]["BlockDev"].is_object());
+    }
+
+    #[test]
+    fn unknown_type() {
+        assert!(serde_json::from_str::<SystemConfig>(r#"{ "type": "pdp-11" }"#).is_err());
+        assert!(serde_json::from_str::<SystemConfig>(r#"{ "arch": "x86_64" }"#).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{Error, Key, Status, SystemHarness, SystemTerminal};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+
+/// Character that starts an escape command at the start of a line
+const ESCAPE: u8 = b'~';
+
+/// A command typed in an interactive console
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleCommand {
+    /// `~p`: pause the system, or resume it if paused
+    Pause,
+
+    /// `~s`: snapshot the system
+    Snapshot,
+
+    /// `~k`: send the Enter key
+    SendKey,
+
+    /// `~q`: leave the console
+    Quit,
+}
+
+/// Console input, split into data for the system and escape commands
+#[derive(Debug, PartialEq)]
+pub enum ConsoleInput {
+    /// Data to be written to the system's terminal
+    Data(Vec<u8>),
+
+    /// An escape command
+    Command(ConsoleCommand),
+}
+
+/// Recognizes escape commands in console input
+///
+/// Like `ssh` and `virsh console`, escapes are only recognized at the
+/// start of a line, so a `~` elsewhere reaches the system unchanged. `~~`
+/// sends a single `~`, and `~` followed by anything else is sent as is.
+pub struct ConsoleEscapes {
+    line_start: bool,
+    escaped: bool,
+}
+
+impl Default for ConsoleEscapes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConsoleEscapes {
+    /// Create a parser at the start of a line
+    pub fn new() -> Self {
+        Self {
+            line_start: true,
+            escaped: false,
+        }
+    }
+
+    /// Split input into data and commands, in the order they were typed
+    pub fn feed(&mut self, input: &[u8]) -> Vec<ConsoleInput> {
+        let mut inputs = Vec::new();
+        let mut data = Vec::new();
+        for &byte in input {
+            if self.escaped {
+                self.escaped = false;
+                let command = match byte {
+                    b'p' => ConsoleCommand::Pause,
+                    b's' => ConsoleCommand::Snapshot,
+                    b'k' => ConsoleCommand::SendKey,
+                    b'q' => ConsoleCommand::Quit,
+                    ESCAPE => {
+                        data.push(ESCAPE);
+                        self.line_start = false;
+                        continue;
+                    }
+                    _ => {
+                        data.extend([ESCAPE, byte]);
+                        self.line_start = byte == b'\r' || byte == b'\n';
+                        continue;
+                    }
+                };
+                if !data.is_empty() {
+                    inputs.push(ConsoleInput::Data(std::mem::take(&mut data)));
+                }
+                inputs.push(ConsoleInput::Command(command));
+            } else if self.line_start && byte == ESCAPE {
+                self.escaped = true;
+            } else {
+                data.push(byte);
+                self.line_start = byte == b'\r' || byte == b'\n';
+            }
+        }
+        if !data.is_empty() {
+            inputs.push(ConsoleInput::Data(data));
+        }
+        inputs
+    }
+}
+
+/// Puts a TTY in raw mode until dropped
+///
+/// Keys then reach the system as they are typed, rather than a line at a
+/// time, and control characters aren't handled by the local TTY.
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Put a TTY, such as standard input, in raw mode
+    pub fn enable(tty: &impl AsRawFd) -> Result<Self, Error> {
+        let fd = tty.as_raw_fd();
+        // SAFETY: termios is plain data that tcgetattr initializes.
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: The pointer is to a valid termios.
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut raw = original;
+        // SAFETY: The pointers are to valid termios.
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        Ok(Self { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: The pointer is to a valid termios.
+        if unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) } != 0 {
+            log::warn!(
+                "Error restoring terminal mode: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Write a status line to the console
+fn notice(output: &mut impl Write, message: &str) -> Result<(), Error> {
+    write!(output, "\r\n[{message}]\r\n")?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Wait until the input or the terminal is readable, returning which are
+///
+/// A negative terminal descriptor is ignored.
+fn wait_readable(input: RawFd, terminal: RawFd) -> std::io::Result<(bool, bool)> {
+    let mut fds = [input, terminal].map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    loop {
+        // SAFETY: The pointer and length describe a valid array of pollfd.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            return Ok((fds[0].revents != 0, fds[1].revents != 0));
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Bridge a user's console to the system's terminal until `~q` is typed or
+/// the input ends
+///
+/// A single terminal session is opened. Output from the system is copied
+/// to `output` as it arrives, until the terminal ends. `~s` calls
+/// `snapshot`, since taking snapshots is specific to each backend. Use
+/// [`RawMode`] on an interactive TTY so keys are passed on as they're
+/// typed.
+///
+/// The terminal is polled through its descriptor, so terminals that buffer
+/// output of their own can't be used. LXD's websocket terminal is one.
+pub fn run_console<S>(
+    system: &mut S,
+    mut input: impl Read + AsRawFd,
+    mut output: impl Write,
+    mut snapshot: impl FnMut(&mut S) -> Result<(), Error>,
+) -> Result<(), Error>
+where
+    S: SystemHarness,
+    S::Terminal: AsRawFd,
+{
+    let mut terminal = system.terminal()?;
+    let mut terminal_open = true;
+    let mut escapes = ConsoleEscapes::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let terminal_fd = if terminal_open {
+            terminal.as_raw_fd()
+        } else {
+            -1
+        };
+        let (input_ready, terminal_ready) = wait_readable(input.as_raw_fd(), terminal_fd)?;
+        if terminal_ready {
+            match terminal.read(&mut buf) {
+                Ok(0) | Err(_) => terminal_open = false,
+                Ok(len) => {
+                    output.write_all(&buf[..len])?;
+                    output.flush()?;
+                }
+            }
+        }
+        if !input_ready {
+            continue;
+        }
+        let len = input.read(&mut buf)?;
+        if len == 0 {
+            return Ok(());
+        }
+        for input in escapes.feed(&buf[..len]) {
+            match input {
+                ConsoleInput::Data(data) => {
+                    terminal.write_all(&data)?;
+                    terminal.flush()?;
+                }
+                ConsoleInput::Command(ConsoleCommand::Pause) => {
+                    if system.status()? == Status::Paused {
+                        system.resume()?;
+                        notice(&mut output, "resumed")?;
+                    } else {
+                        system.pause()?;
+                        notice(&mut output, "paused")?;
+                    }
+                }
+                ConsoleInput::Command(ConsoleCommand::Snapshot) => {
+                    snapshot(system)?;
+                    notice(&mut output, "snapshot taken")?;
+                }
+                ConsoleInput::Command(ConsoleCommand::SendKey) => terminal.send_key(Key::Enter)?,
+                ConsoleInput::Command(ConsoleCommand::Quit) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::testing::FakeSystem;
+    use std::os::unix::net::UnixStream;
+
+    fn system(terminal: UnixStream) -> FakeSystem<UnixStream> {
+        FakeSystem::with_terminal(move || Ok(terminal.try_clone()?))
+    }
+
+    #[test]
+    fn escapes() {
+        let mut escapes = ConsoleEscapes::new();
+        assert_eq!(
+            vec![
+                ConsoleInput::Command(ConsoleCommand::Pause),
+                ConsoleInput::Data(b"ls ~p\r".to_vec()),
+                ConsoleInput::Command(ConsoleCommand::Snapshot),
+                ConsoleInput::Data(b"~home~x\n".to_vec()),
+            ],
+            escapes.feed(b"~pls ~p\r~s~~home~x\n")
+        );
+        // Escapes can be split across reads
+        assert_eq!(Vec::<ConsoleInput>::new(), escapes.feed(b"~"));
+        assert_eq!(
+            vec![ConsoleInput::Command(ConsoleCommand::Quit)],
+            escapes.feed(b"q")
+        );
+    }
+
+    #[test]
+    fn console() {
+        let (terminal, mut guest) = UnixStream::pair().unwrap();
+        let mut system = system(terminal);
+        let (input, mut user) = UnixStream::pair().unwrap();
+        guest.write_all(b"login: ").unwrap();
+        user.write_all(b"root\r~p~k~s~q").unwrap();
+        let mut shown = Vec::new();
+        let mut snapshots = 0;
+        run_console(&mut system, input, &mut shown, |_| {
+            snapshots += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert!(system.paused);
+        assert_eq!(1, snapshots);
+
+        let mut typed = [0u8; 6];
+        guest.read_exact(&mut typed).unwrap();
+        assert_eq!(b"root\r\r", &typed);
+        assert_eq!(
+            b"login: \r\n[paused]\r\n\r\n[snapshot taken]\r\n",
+            &shown[..]
+        );
+    }
+
+    #[test]
+    fn console_input_ends() {
+        let (terminal, mut guest) = UnixStream::pair().unwrap();
+        let mut system = system(terminal);
+        let (input, mut user) = UnixStream::pair().unwrap();
+        user.write_all(b"exit\r").unwrap();
+        drop(user);
+        guest.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut shown = Vec::new();
+        run_console(&mut system, input, &mut shown, |_| Ok(())).unwrap();
+        let mut typed = [0u8; 5];
+        guest.read_exact(&mut typed).unwrap();
+        assert_eq!(b"exit\r", &typed);
+        assert!(shown.is_empty());
+    }
+}
@@ -1,7 +1,64 @@
-use crate::{Error, ErrorKind, Status, SystemHarness, SystemTerminal};
+use crate::artifacts::ArtifactDir;
+use crate::metrics::{self, Counter, Histogram};
+use crate::pty::{set_window_size, Pty};
+use crate::retry::RetryPolicy;
+use crate::trace;
+use crate::{
+    CollectArtifacts, Error, ErrorKind, EventPublisher, EventSubscriber, ExecOutput, ExitStatus,
+    HostEndpoint, Key, PortForward, Status, SystemHarness, SystemTerminal, TrafficCapture,
+};
+use cmdstruct::Arg;
+use os_pipe::PipeReader;
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
-use std::process::{Command, Output, Stdio, Child};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Output, Stdio};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+mod events;
+use events::EventSubscribers;
+
+mod api;
+use api::ApiClient;
+pub use api::ContainerTransport;
+
+mod runtime;
+pub use runtime::{ContainerRuntime, RemoteEngine, RuntimeKind};
+
+mod stats;
+pub use stats::ContainerStats;
+
+mod capture;
+use capture::{Capture, DEFAULT_CAPTURE_IMAGE};
+
+mod models;
+pub use models::{
+    ContainerDevice, ContainerLimits, DropPolicy, ExecOptions, Gpus, HealthCheck, ImageBuild,
+    LogConfig, LogOptions, NamespaceMode, NetworkMode, Port, Protocol, PullPolicy, RestartPolicy,
+    SecurityOptions, SelinuxLabel, Signal, TerminalOptions, Volume,
+};
+
+mod pod;
+pub use pod::{PodSystem, PodSystemConfig};
+
+mod compose;
+pub use compose::{ComposeService, ComposeSystem, ComposeSystemConfig};
+
+mod network;
+pub(crate) use network::ContainerNetwork;
+
+/// Label applied to every harness-created container
+///
+/// The value is the PID of the harness process, so containers left behind
+/// by a killed job can be found with `--filter label=system-harness`.
+const HARNESS_LABEL: &str = "system-harness";
+
+/// Interval between state checks while waiting for a container
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 fn strip_last_newline(input: &str) -> &str {
     input
@@ -13,9 +70,7 @@ fn strip_last_newline(input: &str) -> &str {
 /// Process output to result
 fn output_to_result(output: Output) -> Result<String, Error> {
     match output.status.success() {
-        true => Ok(strip_last_newline(
-                std::str::from_utf8(&output.stdout)?
-        ).to_string()),
+        true => Ok(strip_last_newline(std::str::from_utf8(&output.stdout)?).to_string()),
         false => {
             let error = std::str::from_utf8(&output.stderr)?;
             Err(Error::new(ErrorKind::HarnessError, error))
@@ -23,208 +78,1804 @@ fn output_to_result(output: Output) -> Result<String, Error> {
     }
 }
 
+/// Run a command, logging each line of its output
+///
+/// On failure, the error contains the command's standard error.
+fn run_logged(mut command: Command) -> Result<(), Error> {
+    let mut process = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stderr = process.stderr.take().map(|stderr| {
+        std::thread::spawn(move || {
+            let mut errors = Vec::new();
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                log::info!("{line}");
+                errors.push(line);
+            }
+            errors
+        })
+    });
+    if let Some(stdout) = process.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            log::info!("{line}");
+        }
+    }
+    let errors = stderr
+        .and_then(|stderr| stderr.join().ok())
+        .unwrap_or_default();
+    if process.wait()?.success() {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::HarnessError, errors.join("\n")))
+    }
+}
+
 /// A container system config
 #[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ContainerSystemConfig {
+    /// Container runtime (detected from the `PATH` by default)
+    tool: Option<ContainerRuntime>,
 
-    /// Container runtime
-    tool: String,
+    /// Engine on another machine to run the container on
+    remote: Option<RemoteEngine>,
+
+    /// containerd namespace to run the container in (nerdctl only)
+    namespace: Option<String>,
 
     /// Container image
     image: String,
 
+    /// Digest the image must resolve to, such as `sha256:...`
+    ///
+    /// An image referenced as `name@sha256:...` is checked against its own
+    /// digest.
+    digest: Option<String>,
+
+    /// Platform of the image, such as `linux/arm64`
+    ///
+    /// A platform whose architecture differs from the host's runs under
+    /// emulation (e.g. qemu-user registered with binfmt_misc).
+    platform: Option<String>,
+
+    /// How to talk to the container engine (defaults to the CLI)
+    ///
+    /// Containers are always created with the CLI, whatever the transport.
+    transport: Option<ContainerTransport>,
+
+    /// When to pull the image (defaults to missing)
+    pull: Option<PullPolicy>,
+
+    /// Build the image instead of pulling it
+    build: Option<ImageBuild>,
+
+    /// Container name
+    name: Option<String>,
+
+    /// Container labels
+    labels: Option<BTreeMap<String, String>>,
+
+    /// Environment variables
+    env: Option<BTreeMap<String, String>>,
+
+    /// Files of environment variables
+    env_file: Option<Vec<PathBuf>>,
+
+    /// Volumes and bind mounts
+    volumes: Option<Vec<Volume>>,
+
+    /// tmpfs mounts (e.g. `/tmp` or `/run:size=64m`)
+    tmpfs: Option<Vec<String>>,
+
+    /// Mount the root filesystem read-only
+    #[serde(default)]
+    read_only: bool,
+
+    /// Container hostname
+    hostname: Option<String>,
+
+    /// DNS servers
+    dns: Option<Vec<String>>,
+
+    /// Extra host name to IP address mappings
+    add_host: Option<BTreeMap<String, String>>,
+
+    /// Health check overriding the image's
+    healthcheck: Option<HealthCheck>,
+
+    /// Published ports
+    ports: Option<Vec<Port>>,
+
+    /// Network namespace (defaults to the runtime's bridge network)
+    network_mode: Option<NetworkMode>,
+
+    /// PID namespace
+    pid: Option<NamespaceMode>,
+
+    /// IPC namespace
+    ipc: Option<NamespaceMode>,
+
+    /// Resource limits
+    limits: Option<ContainerLimits>,
+
+    /// When the runtime restarts the container
+    restart_policy: Option<RestartPolicy>,
+
+    /// Log driver and its options
+    ///
+    /// [`logs`](ContainerSystem::logs) needs a driver the runtime can read
+    /// back, such as `json-file`, `local` or `journald`.
+    logging: Option<LogConfig>,
+
+    /// Seconds to wait for the container to stop before it is killed
+    /// (defaults to the runtime's, usually 10)
+    stop_timeout: Option<u64>,
+
+    /// How container runtime invocations that fail for transient reasons
+    /// are retried (defaults to never)
+    retry: Option<RetryPolicy>,
+
+    /// What happens to the container when the system is dropped (defaults
+    /// to remove)
+    on_drop: Option<DropPolicy>,
+
+    /// Leave the container running when dropped during a panic, such as a
+    /// failed test assertion
+    #[serde(default)]
+    keep_on_failure: bool,
+
+    /// Command and options of terminals opened with
+    /// [`terminal`](SystemHarness::terminal)
+    terminal: Option<TerminalOptions>,
+
+    /// User to run as (`uid:gid` or name)
+    user: Option<String>,
+
+    /// Working directory in the container
+    workdir: Option<String>,
+
+    /// Run with extended privileges
+    #[serde(default)]
+    privileged: bool,
+
+    /// Capabilities to add
+    cap_add: Option<Vec<String>>,
+
+    /// Capabilities to drop
+    cap_drop: Option<Vec<String>>,
+
+    /// Seccomp, AppArmor, SELinux and privilege confinement
+    security_opt: Option<SecurityOptions>,
+
+    /// Host devices
+    devices: Option<Vec<ContainerDevice>>,
+
+    /// GPUs
+    gpus: Option<Gpus>,
+
+    /// Override the image's entrypoint
+    entrypoint: Option<String>,
+
+    /// Override the image's command
+    cmd: Option<Vec<String>>,
 }
 
 impl ContainerSystemConfig {
+    /// Command creating the container
+    fn create_command(&self, runtime: &ContainerRuntime, pod: Option<&str>) -> Command {
+        let mut command = runtime.command();
+        command.arg("create").arg("-t");
+        if let Some(pod) = pod {
+            command.arg("--pod").arg(pod);
+        }
+        self.name.append_option("--name", &mut command);
+        command
+            .arg("--label")
+            .arg(format!("{HARNESS_LABEL}={}", std::process::id()));
+        for (key, value) in self.labels.iter().flatten() {
+            command.arg("--label").arg(format!("{key}={value}"));
+        }
+        for env_file in self.env_file.iter().flatten() {
+            command.arg("--env-file").arg(env_file);
+        }
+        for (key, value) in self.env.iter().flatten() {
+            command.arg("-e").arg(format!("{key}={value}"));
+        }
+        self.volumes.append_option("-v", &mut command);
+        self.tmpfs.append_option("--tmpfs", &mut command);
+        if self.read_only {
+            command.arg("--read-only");
+        }
+        self.hostname.append_option("--hostname", &mut command);
+        self.dns.append_option("--dns", &mut command);
+        for (host, ip) in self.add_host.iter().flatten() {
+            command.arg("--add-host").arg(format!("{host}:{ip}"));
+        }
+        self.healthcheck.append_arg(&mut command);
+        self.ports.append_option("-p", &mut command);
+        self.network_mode.append_option("--network", &mut command);
+        self.pid.append_option("--pid", &mut command);
+        self.ipc.append_option("--ipc", &mut command);
+        self.limits.append_arg(&mut command);
+        self.restart_policy.append_option("--restart", &mut command);
+        self.logging.append_arg(&mut command);
+        self.stop_timeout
+            .append_option("--stop-timeout", &mut command);
+        self.user.append_option("--user", &mut command);
+        self.workdir.append_option("--workdir", &mut command);
+        if self.privileged {
+            command.arg("--privileged");
+        }
+        self.cap_add.append_option("--cap-add", &mut command);
+        self.cap_drop.append_option("--cap-drop", &mut command);
+        self.security_opt.append_arg(&mut command);
+        self.devices.append_option("--device", &mut command);
+        self.gpus.append_arg(&mut command);
+        self.entrypoint.append_option("--entrypoint", &mut command);
+        self.platform.append_option("--platform", &mut command);
+        command.arg(&self.image);
+        self.cmd.append_arg(&mut command);
+        command
+    }
+
+    /// Check if the image is available locally
+    fn image_exists(&self, runtime: &ContainerRuntime) -> Result<bool, Error> {
+        let _span = trace::image_command(&self.image, "image inspect");
+        Ok(runtime
+            .command()
+            .args(["image", "inspect"])
+            .arg(&self.image)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?
+            .success())
+    }
+
+    /// Pull the image, logging progress
+    fn pull_image(&self, runtime: &ContainerRuntime) -> Result<(), Error> {
+        log::info!("Pulling image: {}", self.image);
+        let _span = trace::image_command(&self.image, "pull");
+        self.retry_policy()
+            .run("pull", || {
+                let mut command = runtime.command();
+                command.arg("pull");
+                self.platform.append_option("--platform", &mut command);
+                command.arg(&self.image);
+                run_logged(command)
+            })
+            .map_err(|err| {
+                Error::new(
+                    ErrorKind::HarnessError,
+                    format!("Failed to pull image '{}': {err}", self.image),
+                )
+            })
+    }
+
+    /// Command building the image
+    fn build_command(&self, runtime: &ContainerRuntime, build: &ImageBuild) -> Command {
+        let mut command = runtime.command();
+        command.arg("build").arg("-t").arg(&self.image);
+        self.platform.append_option("--platform", &mut command);
+        build.append_arg(&mut command);
+        command
+    }
+
+    /// Make the image available according to the build section or pull
+    /// policy
+    fn prepare_image(&self, runtime: &ContainerRuntime) -> Result<(), Error> {
+        if let Some(build) = &self.build {
+            if self.digest.is_some() {
+                return Err(Error::new(
+                    ErrorKind::InvalidConfig,
+                    "A digest can't be verified for a built image",
+                ));
+            }
+            log::info!("Building image: {}", self.image);
+            let _span = trace::image_command(&self.image, "build");
+            return run_logged(self.build_command(runtime, build)).map_err(|err| {
+                Error::new(
+                    ErrorKind::HarnessError,
+                    format!("Failed to build image '{}': {err}", self.image),
+                )
+            });
+        }
+        match self.pull.unwrap_or_default() {
+            PullPolicy::Always => self.pull_image(runtime),
+            PullPolicy::Missing if self.image_exists(runtime)? => Ok(()),
+            PullPolicy::Missing => self.pull_image(runtime),
+            PullPolicy::Never if self.image_exists(runtime)? => Ok(()),
+            PullPolicy::Never => Err(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "Image '{}' not found locally and pull policy is never",
+                    self.image
+                ),
+            )),
+        }?;
+        self.verify_digest(runtime)
+    }
+
+    /// Digest the image is expected to resolve to
+    fn expected_digest(&self) -> Option<&str> {
+        self.digest
+            .as_deref()
+            .or_else(|| self.image.split_once('@').map(|(_, digest)| digest))
+    }
+
+    /// Check that the local image resolves to the expected digest
+    fn verify_digest(&self, runtime: &ContainerRuntime) -> Result<(), Error> {
+        let Some(digest) = self.expected_digest() else {
+            return Ok(());
+        };
+        let _span = trace::image_command(&self.image, "image inspect");
+        let output = self.retry_policy().run("image inspect", || {
+            runtime
+                .command()
+                .args(["image", "inspect", "--format", "{{json .RepoDigests}}"])
+                .arg(&self.image)
+                .output()
+                .map_err(|err| err.into())
+                .and_then(output_to_result)
+        })?;
+        let repo_digests: Vec<String> =
+            serde_json::from_str::<Option<_>>(&output)?.unwrap_or_default();
+        if has_digest(&repo_digests, digest) {
+            log::trace!("Verified image {} has digest {digest}", self.image);
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "Image '{}' doesn't match digest {digest}, found: {}",
+                    self.image,
+                    repo_digests.join(", ")
+                ),
+            ))
+        }
+    }
+
+    /// Configured retry policy, or one that never retries
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry.clone().unwrap_or_else(RetryPolicy::never)
+    }
+
+    /// Configured runtime, or the one detected from the `PATH`
+    pub(crate) fn runtime(&self) -> Result<ContainerRuntime, Error> {
+        let runtime = match &self.tool {
+            Some(runtime) => runtime.clone(),
+            None => ContainerRuntime::detect()?,
+        };
+        let runtime = match &self.remote {
+            Some(remote) => runtime.remote(remote.clone())?,
+            None => runtime,
+        };
+        match &self.namespace {
+            Some(namespace) => runtime.namespace(namespace),
+            None => Ok(runtime),
+        }
+    }
 
     /// Build and run a container based on name
     pub fn build(&self) -> Result<ContainerSystem, Error> {
-        let id = Command::new(&self.tool)
-            .arg("create")
-            .arg("-t") 
-            .arg(&self.image)
+        self.build_in(None)
+    }
+
+    /// Create the container without starting it
+    ///
+    /// Event subscribers can then be attached before the container produces
+    /// any output. Start it with [`start`](ContainerSystem::start).
+    pub fn create(&self) -> Result<ContainerSystem, Error> {
+        self.create_in(None)
+    }
+
+    /// Build and run a container, optionally as a member of a pod
+    pub(crate) fn build_in(&self, pod: Option<&str>) -> Result<ContainerSystem, Error> {
+        let start = Instant::now();
+        let mut system = self.create_in(pod)?;
+        system.start()?;
+        metrics::record(Histogram::BootTime, "container", start.elapsed());
+        Ok(system)
+    }
+
+    /// Build and run a container joined to networks before it starts, on
+    /// which other containers reach it by an alias
+    pub(crate) fn build_on_networks(
+        &self,
+        alias: &str,
+        networks: &[&ContainerNetwork],
+    ) -> Result<ContainerSystem, Error> {
+        if self.network_mode.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "Containers joining networks can't set network_mode",
+            ));
+        }
+        let start = Instant::now();
+        let mut system = self.create_in(None)?;
+        for network in networks {
+            connect_network(
+                &system.runtime,
+                &system.retry,
+                &system.id,
+                network.name(),
+                alias,
+            )?;
+        }
+        system.start()?;
+        metrics::record(Histogram::BootTime, "container", start.elapsed());
+        Ok(system)
+    }
+
+    /// Create a container, optionally as a member of a pod
+    pub(crate) fn create_in(&self, pod: Option<&str>) -> Result<ContainerSystem, Error> {
+        let span = trace::build("container");
+        let runtime = self.runtime()?;
+        if self.remote.is_some()
+            && matches!(
+                self.transport,
+                Some(ContainerTransport::Api { socket: None })
+            )
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "The API transport needs an explicit socket for a remote engine",
+            ));
+        }
+        if let Some(platform) = self.platform.as_deref().filter(|p| is_emulated(p)) {
+            log::info!("Running {} for {platform} under emulation", self.image);
+        }
+        self.prepare_image(&runtime)?;
+        let id = {
+            let _span = trace::image_command(&self.image, "create");
+            self.create_command(&runtime, pod)
+                .output()
+                .map_err(|err| err.into())
+                .and_then(output_to_result)
+                .map_err(|err| {
+                    log::warn!("{err}");
+                    err
+                })?
+        };
+        span.record_system(&id);
+        log::trace!("Created container: {id}");
+
+        let system = ContainerSystem {
+            id,
+            runtime,
+            api: self.transport.as_ref().and_then(ContainerTransport::client),
+            subscribers: EventSubscribers::default(),
+            events: None,
+            drop_policy: self.on_drop.unwrap_or_default(),
+            keep_on_failure: self.keep_on_failure,
+            terminal: self.terminal.clone().unwrap_or_default(),
+            platform: self.platform.clone(),
+            stop_timeout: self.stop_timeout.map(Duration::from_secs),
+            sessions: Sessions::default(),
+            captures: Vec::new(),
+            retry: self.retry_policy(),
+        };
+        Ok(system)
+    }
+}
+
+/// Connect a container to a network, on which other containers reach it
+/// by an alias
+///
+/// A failed attempt may have connected the container anyway, so a retry
+/// finding it already connected succeeds.
+fn connect_network(
+    runtime: &ContainerRuntime,
+    retry: &RetryPolicy,
+    id: &str,
+    network: &str,
+    alias: &str,
+) -> Result<(), Error> {
+    let mut attempted = false;
+    retry.run("network", || {
+        let retrying = std::mem::replace(&mut attempted, true);
+        let result = runtime
+            .command()
+            .args(["network", "connect", "--alias", alias, network, id])
             .output()
             .map_err(|err| err.into())
-            .and_then(output_to_result)
-            .map_err(|err| { log::warn!("{err}"); err })?;
-        log::trace!("Created container: {id}");
+            .and_then(output_to_result);
+        match result {
+            Err(err) if retrying && err.to_string().contains("already") => {
+                log::trace!("Container {id} already connected to {network}: {err}");
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
+    })
+}
 
-        Command::new(&self.tool)
-            .stdout(Stdio::null())
-            .arg("start")
-            .arg(&id)
-            .status()?;
+/// Check if any `repository@digest` reference has the digest
+fn has_digest(repo_digests: &[String], digest: &str) -> bool {
+    repo_digests
+        .iter()
+        .any(|reference| reference.rsplit_once('@').is_some_and(|(_, d)| d == digest))
+}
 
-        Ok(ContainerSystem {
-            id,
-            tool: self.tool.clone()
-        })
+/// Architecture of the harness host as named by OCI platforms
+fn host_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        "powerpc64" => "ppc64",
+        arch => arch,
     }
+}
 
+/// Check if a platform (`os/arch[/variant]`) needs emulation on the host
+fn is_emulated(platform: &str) -> bool {
+    platform
+        .split('/')
+        .nth(1)
+        .is_some_and(|arch| arch != host_arch())
+}
+
+/// Parse a host port from the output of the `port` command
+///
+/// The output lists one `address:port` binding per line.
+fn parse_host_port(output: &str) -> Option<u16> {
+    output
+        .lines()
+        .filter_map(|line| line.rsplit_once(':'))
+        .find_map(|(_, port)| port.trim().parse().ok())
 }
 
 pub struct ContainerSystem {
-    tool: String,
+    runtime: ContainerRuntime,
     id: String,
+    api: Option<ApiClient>,
+    subscribers: EventSubscribers,
+    events: Option<Child>,
+    drop_policy: DropPolicy,
+    keep_on_failure: bool,
+    terminal: TerminalOptions,
+    platform: Option<String>,
+    stop_timeout: Option<Duration>,
+    sessions: Sessions,
+    captures: Vec<Capture>,
+    retry: RetryPolicy,
 }
 
+/// Processes of the terminal sessions opened on a container
+type Sessions = Arc<Mutex<Vec<Weak<Mutex<Child>>>>>;
+
+impl ContainerSystem {
+    /// Platform the container was created for, if one was selected
+    pub fn platform(&self) -> Option<&str> {
+        self.platform.as_deref()
+    }
+
+    /// Check if the container's architecture differs from the host's, so
+    /// it runs under emulation
+    ///
+    /// Only the selected platform is considered and it is compared with the
+    /// harness host, not a remote engine.
+    pub fn emulated(&self) -> bool {
+        self.platform.as_deref().is_some_and(is_emulated)
+    }
+
+    /// Host port a container port is published on
+    ///
+    /// Resolves ports randomly assigned by the container runtime.
+    pub fn host_port(&self, container_port: u16, protocol: Protocol) -> Result<u16, Error> {
+        let _span = trace::container_command(&self.id, "port");
+        let port = format!("{container_port}/{}", protocol.as_str());
+        let output = self.run_runtime(&["port", &self.id, &port])?;
+        parse_host_port(&output).ok_or(Error::new(
+            ErrorKind::HarnessError,
+            format!("Container port {port} is not published"),
+        ))
+    }
+
+    /// Execute a command in the container and capture its output
+    pub fn exec(&self, cmd: &str, args: &[&str]) -> Result<ExecOutput, Error> {
+        self.exec_with(cmd, args, &ExecOptions::default())
+    }
+
+    /// Execute a command in the container with options and capture its output
+    pub fn exec_with(
+        &self,
+        cmd: &str,
+        args: &[&str],
+        options: &ExecOptions,
+    ) -> Result<ExecOutput, Error> {
+        log::trace!("Executing in container {}: {cmd} {args:?}", self.id);
+        let _span = trace::container_command(&self.id, "exec");
+        let mut command = self.runtime.command();
+        command.arg("exec");
+        options.append_arg(&mut command);
+        let output = command
+            .arg(&self.id)
+            .arg(cmd)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()?;
+        Ok(ExecOutput {
+            exit_code: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    /// Read the container's output
+    ///
+    /// When following, the reader blocks for new output until the container
+    /// stops or the reader is dropped.
+    pub fn logs(&self, follow: bool) -> Result<ContainerLogs, Error> {
+        self.logs_with(&LogOptions::default().follow(follow))
+    }
+
+    /// Read the container's output with options
+    pub fn logs_with(&self, options: &LogOptions) -> Result<ContainerLogs, Error> {
+        let _span = trace::container_command(&self.id, "logs");
+        let (reader, writer) = os_pipe::pipe()?;
+        let mut command = self.runtime.command();
+        command.arg("logs");
+        options.append_arg(&mut command);
+        let process = command
+            .arg(&self.id)
+            .stdin(Stdio::null())
+            .stdout(writer.try_clone()?)
+            .stderr(writer)
+            .spawn()?;
+        Ok(ContainerLogs { process, reader })
+    }
+
+    /// Sample the container's resource usage
+    pub fn stats(&self) -> Result<ContainerStats, Error> {
+        let _span = trace::container_command(&self.id, "stats");
+        let output =
+            self.run_runtime(&["stats", "--no-stream", "--format", "{{json .}}", &self.id])?;
+        ContainerStats::parse(&output)
+    }
+
+    /// Perform a lifecycle action such as `start` or `pause`
+    fn lifecycle(&self, action: &str) -> Result<(), Error> {
+        let _span = trace::container_command(&self.id, action);
+        match &self.api {
+            Some(api) => self.retry.run(action, || {
+                api.post(&format!("/containers/{}/{action}", self.id))
+            }),
+            None => self.run_runtime(&[action, &self.id]).map(|_| ()),
+        }
+    }
+
+    /// Run the container runtime and return its output, retrying transient
+    /// failures
+    fn run_runtime(&self, args: &[&str]) -> Result<String, Error> {
+        self.retry.run(args[0], || {
+            self.runtime
+                .command()
+                .args(args)
+                .output()
+                .map_err(|err| err.into())
+                .and_then(output_to_result)
+        })
+    }
+
+    /// Open a terminal session running a command other than the
+    /// configured one
+    ///
+    /// Each session has its own process, so several can be open at once.
+    pub fn terminal_with(
+        &self,
+        options: &TerminalOptions,
+    ) -> Result<ContainerSystemTerminal, Error> {
+        let _span = trace::container_command(&self.id, "exec");
+        let mut command = self.runtime.command();
+        command.arg("exec");
+        options.append_arg(&mut command);
+        command.arg(&self.id).args(options.command_line());
+
+        let (process, stream) = if options.has_tty() {
+            let (process, tty) = Pty::open()?.spawn(&mut command)?;
+            (process, TerminalStream::Tty(tty))
+        } else {
+            let (output, writer) = os_pipe::pipe()?;
+            let mut process = command
+                .stdin(Stdio::piped())
+                .stdout(writer.try_clone()?)
+                .stderr(writer)
+                .spawn()?;
+            let input = process.stdin.take();
+            (process, TerminalStream::Pipe { input, output })
+        };
+        let process = Arc::new(Mutex::new(process));
+        let mut sessions = self
+            .sessions
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::HarnessError, "Terminal sessions poisoned"))?;
+        sessions.retain(|session| session.strong_count() > 0);
+        sessions.push(Arc::downgrade(&process));
+        Ok(ContainerSystemTerminal { process, stream })
+    }
+
+    /// Number of terminal sessions that are still open
+    pub fn sessions(&self) -> usize {
+        self.sessions
+            .lock()
+            .map(|sessions| {
+                sessions
+                    .iter()
+                    .filter(|session| session.strong_count() > 0)
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    /// End every open terminal session
+    ///
+    /// The terminals stay usable as readers and writers but see end of
+    /// file and broken pipes.
+    pub fn close_sessions(&self) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            for session in sessions.drain(..).filter_map(|session| session.upgrade()) {
+                if let Ok(mut process) = session.lock() {
+                    let _ = process.kill();
+                }
+            }
+        }
+    }
+
+    /// Start a created or stopped container
+    pub fn start(&mut self) -> Result<(), Error> {
+        log::trace!("Starting container: {}", &self.id);
+        self.lifecycle("start")
+            .map(|_| log::trace!("Started container: {}", self.id))
+    }
+
+    /// Stop the container, killing it if it doesn't stop within the timeout
+    pub fn stop(&mut self, timeout: Duration) -> Result<(), Error> {
+        log::trace!("Stopping container: {}", &self.id);
+        let _span = trace::container_command(&self.id, "stop");
+        let seconds = timeout.as_secs();
+        match &self.api {
+            Some(api) => self.retry.run("stop", || {
+                api.post(&format!("/containers/{}/stop?t={seconds}", self.id))
+            }),
+            None => self
+                .run_runtime(&["stop", "-t", &seconds.to_string(), &self.id])
+                .map(|_| ()),
+        }
+        .map(|_| log::trace!("Stopped container: {}", self.id))
+    }
+
+    /// Restart the container, killing it if it doesn't stop within the
+    /// timeout
+    pub fn restart(&mut self, timeout: Duration) -> Result<(), Error> {
+        log::trace!("Restarting container: {}", &self.id);
+        let _span = trace::container_command(&self.id, "restart");
+        let seconds = timeout.as_secs();
+        match &self.api {
+            Some(api) => self.retry.run("restart", || {
+                api.post(&format!("/containers/{}/restart?t={seconds}", self.id))
+            }),
+            None => self
+                .run_runtime(&["restart", "-t", &seconds.to_string(), &self.id])
+                .map(|_| ()),
+        }
+        .map(|_| {
+            metrics::increment(Counter::Restarts, "container");
+            log::trace!("Restarted container: {}", self.id)
+        })
+    }
+
+    /// Send a signal to the container's main process
+    pub fn signal(&self, signal: Signal) -> Result<(), Error> {
+        let name = signal.name();
+        log::trace!("Sending {name} to container: {}", &self.id);
+        let _span = trace::container_command(&self.id, "kill");
+        match &self.api {
+            Some(api) => self.retry.run("kill", || {
+                api.post(&format!("/containers/{}/kill?signal={name}", self.id))
+            }),
+            None => self
+                .run_runtime(&["kill", "-s", &name, &self.id])
+                .map(|_| ()),
+        }
+    }
+
+    /// Commit the container's filesystem to an image with the given tag
+    ///
+    /// Returns the ID of the new image.
+    pub fn commit(&self, tag: &str) -> Result<String, Error> {
+        log::trace!("Committing container {} to {tag}", &self.id);
+        let _span = trace::container_command(&self.id, "commit");
+        self.run_runtime(&["commit", &self.id, tag])
+    }
+
+    /// Capture the container's traffic to a pcap file using tcpdump from
+    /// the given image
+    ///
+    /// tcpdump runs in its own container, sharing this container's network
+    /// namespace, so the container's image doesn't need it.
+    pub fn capture_traffic_with(&mut self, path: &Path, image: &str) -> Result<(), Error> {
+        let _span = trace::container_command(&self.id, "capture");
+        let capture = Capture::start(&self.runtime, &self.id, image, path)?;
+        self.captures.push(capture);
+        Ok(())
+    }
+
+    /// Change what happens to the container when the system is dropped
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.drop_policy = policy;
+    }
+
+    /// Forcibly remove the container
+    fn remove(&self, volumes: bool) -> Result<(), Error> {
+        let _span = trace::container_command(&self.id, "rm");
+        match &self.api {
+            Some(api) => api.delete(&format!("/containers/{}?force=true&v={volumes}", self.id)),
+            None => self
+                .runtime
+                .command()
+                .args(["rm", "-f"])
+                .args(volumes.then_some("-v"))
+                .arg(&self.id)
+                .output()
+                .map(|_| ())
+                .map_err(|err| err.into()),
+        }
+    }
+
+    /// Inspect the container
+    fn inspect(&self) -> Result<Inspect, Error> {
+        let _span = trace::container_command(&self.id, "inspect");
+        if let Some(api) = &self.api {
+            return self.retry.run("inspect", || {
+                api.get(&format!("/containers/{}/json", self.id))
+            });
+        }
+        self.run_runtime(&["inspect", &self.id])
+            .map_err(|err| {
+                log::warn!("{err}");
+                err
+            })
+            .and_then(|stdout| {
+                let inspect: Vec<Inspect> = serde_json::from_str(&stdout)?;
+                inspect.into_iter().next().ok_or(Error::new(
+                    ErrorKind::HarnessError,
+                    "Container doesn't exist",
+                ))
+            })
+    }
+
+    /// Detailed state of the container, including its exit code once stopped
+    ///
+    /// Unlike [`status`](SystemHarness::status), this tells a crashed
+    /// container apart from one that stopped cleanly.
+    pub fn detailed_status(&self) -> Result<ContainerState, Error> {
+        self.inspect()?.state.container_state()
+    }
+
+    /// Wait for the container's health check to report healthy
+    ///
+    /// Fails if the container is unhealthy, stops, or has no health check.
+    pub fn wait_healthy(&self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.inspect()?.state;
+            let health = state.health.filter(|health| !health.status.is_empty());
+            let health = health.ok_or(Error::new(
+                ErrorKind::HarnessError,
+                "Container has no health check",
+            ))?;
+            match health.status.as_str() {
+                "healthy" => return Ok(()),
+                "unhealthy" => {
+                    return Err(Error::new(
+                        ErrorKind::HarnessError,
+                        "Container is unhealthy",
+                    ))
+                }
+                _ if !state.running && !state.paused => {
+                    return Err(Error::new(
+                        ErrorKind::HarnessError,
+                        "Container stopped before becoming healthy",
+                    ))
+                }
+                _ => {}
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for container to become healthy",
+                ));
+            }
+            std::thread::sleep(STATE_POLL_INTERVAL);
+        }
+    }
+}
+
+/// A reader over a container's combined stdout and stderr
+pub struct ContainerLogs {
+    process: Child,
+    reader: PipeReader,
+}
+
+impl Read for ContainerLogs {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Drop for ContainerLogs {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// A terminal session in a container
 pub struct ContainerSystemTerminal {
-    process: Child
+    process: Arc<Mutex<Child>>,
+    stream: TerminalStream,
+}
+
+/// Connection to the terminal's command
+enum TerminalStream {
+    /// Pseudo-terminal controlling side
+    Tty(File),
+
+    /// Standard input and merged standard output and error
+    Pipe {
+        input: Option<ChildStdin>,
+        output: PipeReader,
+    },
+}
+
+/// Wait for each container in turn, sharing the timeout between them
+///
+/// Reports the first container that failed, otherwise the last one.
+fn wait_all<'a>(
+    containers: impl Iterator<Item = &'a mut ContainerSystem>,
+    timeout: Duration,
+) -> Result<ExitStatus, Error> {
+    let deadline = Instant::now() + timeout;
+    let mut result = None;
+    for container in containers {
+        let status = container.wait(deadline.saturating_duration_since(Instant::now()))?;
+        if !status.success() {
+            return Ok(status);
+        }
+        result = Some(status);
+    }
+    result.ok_or(Error::new(ErrorKind::HarnessError, "No containers"))
+}
+
+/// Detailed state of a container as reported by the runtime
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContainerState {
+    /// Created but never started
+    Created,
+    Running,
+    Paused,
+
+    /// Being restarted by its restart policy
+    Restarting,
+
+    /// Being removed
+    Removing,
+
+    /// Stopped with an exit code
+    Exited(i32),
+
+    /// Failed to stop or be removed
+    Dead,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct State {
     running: bool,
-    paused: bool
+    paused: bool,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    exit_code: i32,
+    #[serde(default, rename = "OOMKilled")]
+    oom_killed: bool,
+    #[serde(alias = "Healthcheck")]
+    health: Option<Health>,
+}
+
+impl State {
+    /// Map the runtime's status, including Podman's additional states
+    fn container_state(&self) -> Result<ContainerState, Error> {
+        match self.status.as_str() {
+            "created" | "configured" | "initialized" => Ok(ContainerState::Created),
+            "running" | "stopping" => Ok(ContainerState::Running),
+            "paused" => Ok(ContainerState::Paused),
+            "restarting" => Ok(ContainerState::Restarting),
+            "removing" => Ok(ContainerState::Removing),
+            "exited" | "stopped" => Ok(ContainerState::Exited(self.exit_code)),
+            "dead" => Ok(ContainerState::Dead),
+            status => Err(Error::new(
+                ErrorKind::HarnessError,
+                format!("Unhandled container state: {status}"),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Health {
+    status: String,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct Inspect {
-    state: State
+    state: State,
+    #[serde(default)]
+    network_settings: NetworkSettings,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkSettings {
+    /// Host bindings of each published port, such as `80/tcp`
+    #[serde(default)]
+    ports: BTreeMap<String, Option<Vec<PortBinding>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PortBinding {
+    #[serde(default)]
+    host_ip: String,
+    host_port: String,
+}
+
+/// Address a port published on `host_ip` is reached at
+///
+/// Ports published on all addresses are reached at the remote engine's
+/// host, or at the loopback address for a local engine.
+fn published_host(host_ip: &str, runtime: &ContainerRuntime) -> Result<String, Error> {
+    let loopback = match host_ip {
+        "" | "0.0.0.0" => "127.0.0.1",
+        "::" => "::1",
+        ip => return Ok(ip.to_string()),
+    };
+    Ok(runtime
+        .remote_host()?
+        .unwrap_or_else(|| loopback.to_string()))
+}
+
+/// Descriptor the terminal's output is read from
+impl AsRawFd for ContainerSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        match &self.stream {
+            TerminalStream::Tty(tty) => tty.as_raw_fd(),
+            TerminalStream::Pipe { output, .. } => output.as_raw_fd(),
+        }
+    }
 }
 
 impl SystemTerminal for ContainerSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        let tty = matches!(self.stream, TerminalStream::Tty(_));
+        self.write_all(key_sequence(&key, tty))?;
+        self.flush().map_err(|err| err.into())
+    }
 
-    fn send_key(&mut self, _key: crate::Key) -> Result<(), Error> {
-        Err(Error::new(ErrorKind::HarnessError, "Sending a keystroke not supported"))
+    /// The runtime's exec client forwards the new size to the exec session
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        match &self.stream {
+            TerminalStream::Tty(tty) => set_window_size(tty, cols, rows).map_err(|err| err.into()),
+            TerminalStream::Pipe { .. } => Err(Error::new(
+                ErrorKind::HarnessError,
+                "Resizing a terminal without a TTY is not supported",
+            )),
+        }
     }
+}
 
+/// Bytes a terminal sends for a key
+///
+/// Without a TTY there is no line discipline to translate carriage returns,
+/// so Enter is sent as a newline.
+fn key_sequence(key: &Key, tty: bool) -> &'static [u8] {
+    match key {
+        Key::Enter if tty => b"\r",
+        Key::Enter => b"\n",
+    }
 }
 
 impl Read for ContainerSystemTerminal {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.process.stdout.as_mut()
-            .ok_or(std::io::Error::new(
-                    std::io::ErrorKind::BrokenPipe, 
-                    "Can't read from container"
-                    ))
-            .and_then(|stdout| stdout.read(buf))
+        match &mut self.stream {
+            TerminalStream::Tty(tty) => match tty.read(buf) {
+                // Linux reports EIO once the other side of the terminal closes
+                Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(0),
+                result => result,
+            },
+            TerminalStream::Pipe { output, .. } => output.read(buf),
+        }
     }
 }
 
 impl Write for ContainerSystemTerminal {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.process.stdin.as_mut()
-            .ok_or(std::io::Error::new(
-                    std::io::ErrorKind::BrokenPipe, 
-                    "Can't write to container"
-                    ))
-            .and_then(|stdin| stdin.write(buf))
+        match &mut self.stream {
+            TerminalStream::Tty(tty) => tty.write(buf),
+            TerminalStream::Pipe { input, .. } => match input {
+                Some(input) => input.write(buf),
+                None => Err(std::io::ErrorKind::BrokenPipe.into()),
+            },
+        }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.process.stdin.as_mut()
-            .ok_or(std::io::Error::new(
-                    std::io::ErrorKind::BrokenPipe, 
-                    "Can't write to container"
-                    ))
-            .and_then(|stdin| stdin.flush())
+        match &mut self.stream {
+            TerminalStream::Tty(tty) => tty.flush(),
+            TerminalStream::Pipe {
+                input: Some(input), ..
+            } => input.flush(),
+            TerminalStream::Pipe { input: None, .. } => Ok(()),
+        }
     }
 }
 
-
+impl Drop for ContainerSystemTerminal {
+    fn drop(&mut self) {
+        if let Ok(mut process) = self.process.lock() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
 
 impl SystemHarness for ContainerSystem {
-
     type Terminal = ContainerSystemTerminal;
 
     fn terminal(&self) -> Result<Self::Terminal, Error> {
-        let process = Command::new(&self.tool)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .arg("exec")
-            .arg("-it")
-            .arg(&self.id)
-            .arg("sh")
-            .spawn()?;
-        Ok(Self::Terminal {
-            process
-        })
+        self.terminal_with(&self.terminal)
     }
 
     fn pause(&mut self) -> Result<(), Error> {
-        log::trace!("Pausing container: {}", &self.id); 
-        Command::new(&self.tool)
-            .arg("pause")
-            .arg(&self.id)
-            .output()
-            .map_err(|err| err.into())
-            .and_then(output_to_result)
+        log::trace!("Pausing container: {}", &self.id);
+        self.lifecycle("pause")
             .map(|_| log::trace!("Paused container: {}", self.id))
     }
 
     fn resume(&mut self) -> Result<(), Error> {
-        log::trace!("Resuming container: {}", &self.id); 
-        Command::new(&self.tool)
-            .arg("unpause")
-            .arg(&self.id)
-            .output()
-            .map_err(|err| err.into())
-            .and_then(output_to_result)
+        log::trace!("Resuming container: {}", &self.id);
+        self.lifecycle("unpause")
             .map(|_| log::trace!("Resumed container: {}", self.id))
     }
 
+    /// Stop the container within the configured stop timeout
     fn shutdown(&mut self) -> Result<(), Error> {
-        log::trace!("Shutting down container: {}", &self.id); 
-         Command::new(&self.tool)
-            .arg("stop")
-            .arg(&self.id)
-            .output()
-            .map_err(|err| err.into())
-            .and_then(output_to_result)
+        if let Some(timeout) = self.stop_timeout {
+            return self.stop(timeout);
+        }
+        log::trace!("Shutting down container: {}", &self.id);
+        self.lifecycle("stop")
             .map(|_| log::trace!("Stopped container: {}", self.id))
     }
 
     fn status(&mut self) -> Result<Status, Error> {
-        Command::new(&self.tool)
-            .arg("inspect")
-            .arg(&self.id)
-            .output()
-            .map_err(|err| err.into())
-            .and_then(output_to_result)
-            .map_err(|err| { log::warn!("{err}"); err })
-            .and_then(|stdout| {
-                let inspect: Vec<Inspect> = serde_json::from_str(&stdout)?;
-                inspect.into_iter()
-                    .next()
-                    .ok_or(Error::new(ErrorKind::HarnessError, "Container doesn't exist"))
-                    .and_then(|inspect| {
-                        let state = &inspect.state;
-                        if state.running {
-                            Ok(Status::Running)
-                        } else if state.paused {
-                            Ok(Status::Paused)
-                        } else if !state.running && !state.paused {
-                            Ok(Status::Shutdown)
-                        } else {
-                            Err(Error::new(ErrorKind::HarnessError,
-                                    format!("Unhandled status")))
-                        }
-                    })
-            })
+        self.inspect().and_then(|inspect| {
+            let state = &inspect.state;
+            if state.running {
+                Ok(Status::Running)
+            } else if state.paused {
+                Ok(Status::Paused)
+            } else if !state.running && !state.paused {
+                Ok(Status::Shutdown)
+            } else {
+                Err(Error::new(ErrorKind::HarnessError, "Unhandled status"))
+            }
+        })
     }
 
     fn running(&mut self) -> Result<bool, Error> {
         self.status().map(|status| status == Status::Running)
     }
 
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.inspect()?.state;
+            match state.container_state()? {
+                ContainerState::Exited(code) => {
+                    return Ok(ExitStatus {
+                        code: Some(code),
+                        reason: state.oom_killed.then(|| String::from("oom-killed")),
+                    })
+                }
+                ContainerState::Dead => {
+                    return Ok(ExitStatus {
+                        code: None,
+                        reason: Some(String::from("dead")),
+                    })
+                }
+                _ => {}
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("Timed out waiting for container {} to exit", self.id),
+                ));
+            }
+            std::thread::sleep(STATE_POLL_INTERVAL);
+        }
+    }
+}
+
+impl PortForward for ContainerSystem {
+    /// Find the host port a container port is published on
+    ///
+    /// Ports must be published when the container is created, e.g. leaving
+    /// the host port for the runtime to assign. The endpoint is the address
+    /// the port is published on, or for ports published on all addresses,
+    /// the remote engine's host or the loopback address.
+    fn forward_port(&mut self, guest_port: u16) -> Result<HostEndpoint, Error> {
+        let port = format!("{guest_port}/tcp");
+        let inspect = self.inspect()?;
+        let bindings = inspect
+            .network_settings
+            .ports
+            .get(&port)
+            .and_then(Option::as_deref)
+            .unwrap_or_default();
+        // Prefer IPv4, as runtimes publish on both families by default
+        let binding = bindings
+            .iter()
+            .find(|binding| !binding.host_ip.contains(':'))
+            .or(bindings.first())
+            .ok_or(Error::new(
+                ErrorKind::HarnessError,
+                format!("Container port {port} is not published"),
+            ))?;
+        let host_port = binding.host_port.parse().map_err(|_| {
+            Error::new(
+                ErrorKind::HarnessError,
+                format!("Invalid host port for {port}: {}", binding.host_port),
+            )
+        })?;
+        Ok(HostEndpoint::new(
+            published_host(&binding.host_ip, &self.runtime)?,
+            host_port,
+        ))
+    }
+}
+
+impl TrafficCapture for ContainerSystem {
+    /// Capture the container's traffic using tcpdump from the `netshoot`
+    /// image
+    ///
+    /// See [`ContainerSystem::capture_traffic_with`].
+    fn capture_traffic(&mut self, path: &Path) -> Result<(), Error> {
+        self.capture_traffic_with(path, DEFAULT_CAPTURE_IMAGE)
+    }
+
+    fn stop_capture(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for capture in self.captures.drain(..) {
+            result = result.and(capture.stop(&self.runtime));
+        }
+        result
+    }
+}
+
+impl CollectArtifacts for ContainerSystem {
+    /// Collect the container's output (`container.log`) and the runtime's
+    /// inspect output (`inspect.json`)
+    fn collect_artifacts(&mut self, dir: &Path) -> Result<(), Error> {
+        let mut artifacts = ArtifactDir::create(dir)?;
+        let logs = self.logs(false).and_then(|mut logs| {
+            let mut output = Vec::new();
+            logs.read_to_end(&mut output)?;
+            Ok(output)
+        });
+        artifacts.write("container.log", logs);
+        let inspect = {
+            let _span = trace::container_command(&self.id, "inspect");
+            self.run_runtime(&["inspect", &self.id])
+        };
+        artifacts.write("inspect.json", inspect);
+        artifacts.finish()
+    }
+}
+
+impl EventPublisher for ContainerSystem {
+    fn subscribe(&mut self, subscriber: impl EventSubscriber) -> Result<(), Error> {
+        log::trace!("Subscribing events...");
+        if self.events.is_none() {
+            self.events = Some(events::follow(
+                &self.runtime,
+                &self.id,
+                self.subscribers.clone(),
+            )?);
+        }
+        self.subscribers
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::HarnessError, "Event subscribers poisoned"))?
+            .push(Box::new(subscriber));
+        Ok(())
+    }
 }
 
 impl Drop for ContainerSystem {
     fn drop(&mut self) {
-        if let Ok(running) = self.running() {
-            if running {
-                if let Ok(()) = self.shutdown() {
-                    log::trace!("Deleting container: {}", &self.id); 
-                    let _ = Command::new(&self.tool)
-                        .args(&["rm", "-f", &self.id])
-                        .output();
-                } else {
+        self.close_sessions();
+        if let Err(err) = self.stop_capture() {
+            log::warn!("Error stopping traffic capture: {err}");
+        }
+        let policy = if self.keep_on_failure && std::thread::panicking() {
+            log::warn!("Keeping container {} after failure", &self.id);
+            DropPolicy::KeepRunning
+        } else {
+            self.drop_policy
+        };
+        if policy != DropPolicy::KeepRunning {
+            if let Ok(true) = self.running() {
+                if self.shutdown().is_err() {
                     log::warn!("Failed to shutdown: {}", &self.id);
                 }
             }
         }
+        if matches!(policy, DropPolicy::Remove | DropPolicy::RemoveWithVolumes) {
+            log::trace!("Deleting container: {}", &self.id);
+            let _ = self.remove(policy == DropPolicy::RemoveWithVolumes);
+        }
+        if let Some(events) = &mut self.events {
+            let _ = events.kill();
+            let _ = events.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(config: &str) -> Vec<String> {
+        let config: ContainerSystemConfig = serde_json::from_str(config).unwrap();
+        config
+            .create_command(&config.runtime().unwrap(), None)
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    /// Check if the arguments contain the expected sequence
+    fn contains(args: &[String], expected: &[&str]) -> bool {
+        args.windows(expected.len())
+            .any(|window| window == expected)
+    }
+
+    #[test]
+    fn env() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "busybox",
+            "env": { "B": "two", "A": "1" },
+            "env_file": ["test.env"]
+        }"#,
+        );
+        assert!(contains(
+            &args,
+            &["--env-file", "test.env", "-e", "A=1", "-e", "B=two"]
+        ));
+    }
+
+    #[test]
+    fn name_and_labels() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "busybox",
+            "name": "sut",
+            "labels": { "job": "42" }
+        }"#,
+        );
+        assert_eq!(
+            vec![
+                "create".to_string(),
+                "-t".to_string(),
+                "--name".to_string(),
+                "sut".to_string(),
+                "--label".to_string(),
+                format!("system-harness={}", std::process::id()),
+                "--label".to_string(),
+                "job=42".to_string(),
+                "busybox".to_string(),
+            ],
+            args
+        );
+    }
+
+    #[test]
+    fn entrypoint_and_cmd() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "busybox",
+            "entrypoint": "/bin/sh",
+            "cmd": ["-c", "sleep infinity"]
+        }"#,
+        );
+        assert!(contains(
+            &args,
+            &["--entrypoint", "/bin/sh", "busybox", "-c", "sleep infinity"]
+        ));
+    }
+
+    #[test]
+    fn user_and_workdir() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "busybox",
+            "user": "1000:1000",
+            "workdir": "/work"
+        }"#,
+        );
+        assert!(contains(
+            &args,
+            &["--user", "1000:1000", "--workdir", "/work"]
+        ));
+    }
+
+    #[test]
+    fn capabilities() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "busybox",
+            "privileged": true,
+            "cap_add": ["NET_ADMIN"],
+            "cap_drop": ["ALL"]
+        }"#,
+        );
+        assert!(contains(
+            &args,
+            &[
+                "--privileged",
+                "--cap-add",
+                "NET_ADMIN",
+                "--cap-drop",
+                "ALL"
+            ]
+        ));
+    }
+
+    #[test]
+    fn stop_timeout() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "postgres",
+            "stop_timeout": 60
+        }"#,
+        );
+        assert!(contains(&args, &["--stop-timeout", "60"]));
+    }
+
+    #[test]
+    fn read_only() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "busybox",
+            "tmpfs": ["/tmp", "/run:size=64m"],
+            "read_only": true
+        }"#,
+        );
+        assert!(contains(
+            &args,
+            &["--tmpfs", "/tmp", "--tmpfs", "/run:size=64m", "--read-only"]
+        ));
+    }
+
+    #[test]
+    fn name_resolution() {
+        let args = args(
+            r#"{
+            "tool": "podman",
+            "image": "busybox",
+            "hostname": "sut",
+            "dns": ["10.0.0.53"],
+            "add_host": { "db.test": "10.0.0.5" }
+        }"#,
+        );
+        assert!(contains(
+            &args,
+            &[
+                "--hostname",
+                "sut",
+                "--dns",
+                "10.0.0.53",
+                "--add-host",
+                "db.test:10.0.0.5"
+            ]
+        ));
+    }
+
+    #[test]
+    fn build_image() {
+        let config: ContainerSystemConfig = serde_json::from_str(
+            r#"{
+            "tool": "docker",
+            "image": "harness:test",
+            "build": {
+                "context": "tests/data",
+                "file": "tests/data/Containerfile",
+                "args": { "VERSION": "1" },
+                "target": "runtime"
+            }
+        }"#,
+        )
+        .unwrap();
+        let command =
+            config.build_command(&config.runtime().unwrap(), config.build.as_ref().unwrap());
+        assert_eq!(
+            vec![
+                "build",
+                "-t",
+                "harness:test",
+                "-f",
+                "tests/data/Containerfile",
+                "--build-arg",
+                "VERSION=1",
+                "--target",
+                "runtime",
+                "tests/data"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn published_ports() {
+        let inspect: Vec<Inspect> = serde_json::from_str(
+            r#"[{
+            "State": { "Running": true, "Paused": false },
+            "NetworkSettings": {
+                "Ports": {
+                    "22/tcp": [
+                        { "HostIp": "0.0.0.0", "HostPort": "32768" },
+                        { "HostIp": "::", "HostPort": "32768" }
+                    ],
+                    "80/tcp": [{ "HostIp": "192.168.1.5", "HostPort": "8080" }],
+                    "443/tcp": null
+                }
+            }
+        }]"#,
+        )
+        .unwrap();
+        let ports = &inspect[0].network_settings.ports;
+        assert_eq!("192.168.1.5", ports["80/tcp"].as_ref().unwrap()[0].host_ip);
+        assert!(ports["443/tcp"].is_none());
+
+        let local = ContainerRuntime::new("docker");
+        assert_eq!("127.0.0.1", published_host("0.0.0.0", &local).unwrap());
+        assert_eq!("::1", published_host("::", &local).unwrap());
+        assert_eq!(
+            "192.168.1.5",
+            published_host("192.168.1.5", &local).unwrap()
+        );
+        let remote = local
+            .remote(RemoteEngine::Host("ssh://ci@lab-host".to_string()))
+            .unwrap();
+        assert_eq!("lab-host", published_host("0.0.0.0", &remote).unwrap());
+    }
+
+    #[test]
+    fn inspect_health() {
+        let inspect: Vec<Inspect> = serde_json::from_str(
+            r#"[{
+            "State": {
+                "Running": true,
+                "Paused": false,
+                "Health": { "Status": "starting", "FailingStreak": 0 }
+            }
+        }]"#,
+        )
+        .unwrap();
+        assert_eq!("starting", inspect[0].state.health.as_ref().unwrap().status);
+    }
+
+    #[test]
+    fn inspect_state() {
+        let inspect: Vec<Inspect> = serde_json::from_str(
+            r#"[{
+            "State": {
+                "Status": "exited",
+                "Running": false,
+                "Paused": false,
+                "ExitCode": 137
+            }
+        }]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ContainerState::Exited(137),
+            inspect[0].state.container_state().unwrap()
+        );
+        assert!(!inspect[0].state.oom_killed);
+
+        let inspect: Vec<Inspect> = serde_json::from_str(
+            r#"[{
+            "State": { "Status": "configured", "Running": false, "Paused": false }
+        }]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ContainerState::Created,
+            inspect[0].state.container_state().unwrap()
+        );
+    }
+
+    #[test]
+    fn digest() {
+        let config: ContainerSystemConfig = serde_json::from_str(
+            r#"{
+            "image": "alpine@sha256:1234"
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(Some("sha256:1234"), config.expected_digest());
+
+        let repo_digests = vec![String::from("docker.io/library/alpine@sha256:1234")];
+        assert!(has_digest(&repo_digests, "sha256:1234"));
+        assert!(!has_digest(&repo_digests, "sha256:5678"));
+        assert!(!has_digest(&[], "sha256:1234"));
+    }
+
+    #[test]
+    fn platform() {
+        let args = args(
+            r#"{
+            "tool": "docker",
+            "image": "alpine",
+            "platform": "linux/arm64"
+        }"#,
+        );
+        assert!(contains(&args, &["--platform", "linux/arm64", "alpine"]));
+        assert!(!is_emulated(&format!("linux/{}", host_arch())));
+        assert!(is_emulated("linux/s390x") || host_arch() == "s390x");
+    }
+
+    #[test]
+    fn host_port() {
+        assert_eq!(Some(32768), parse_host_port("0.0.0.0:32768\n[::]:32768\n"));
+        assert_eq!(None, parse_host_port(""));
+    }
+
+    #[test]
+    fn enter_key() {
+        assert_eq!(b"\r", key_sequence(&Key::Enter, true));
+        assert_eq!(b"\n", key_sequence(&Key::Enter, false));
+    }
+
+    /// Runtime script recording its arguments to `calls` in a directory of
+    /// its own, with the image present or missing and a pull that succeeds
+    /// or fails
+    #[cfg(unix)]
+    fn fake_runtime(name: &str, present: bool, pull: bool) -> (PathBuf, ContainerRuntime) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("system-harness-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("runtime");
+        let pull = if pull {
+            "echo 'Copying blob'"
+        } else {
+            "echo 'manifest unknown' >&2; exit 125"
+        };
+        // Connecting times out, but the container is connected anyway
+        let connected = dir.join("connected");
+        let network = format!(
+            "if [ -e {0} ]; then echo 'container already connected' >&2; exit 125; fi; \
+             touch {0}; echo 'i/o timeout' >&2; exit 125",
+            connected.display()
+        );
+        std::fs::write(&script, format!(
+            "#!/bin/sh\necho \"$*\" >> {}\ncase \"$1\" in\nimage) exit {} ;;\npull) {pull} ;;\nnetwork) {network} ;;\nesac\n",
+            dir.join("calls").display(),
+            if present { 0 } else { 1 },
+        )).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        (dir, ContainerRuntime::new(script.to_string_lossy()))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connect_retried() {
+        let (dir, runtime) = fake_runtime("connect-retried", true, true);
+        let retry = RetryPolicy::new().backoff(Duration::ZERO);
+        connect_network(&runtime, &retry, "c1", "net1", "db").unwrap();
+        assert_eq!(
+            "network connect --alias db net1 c1\n".repeat(2),
+            std::fs::read_to_string(dir.join("calls")).unwrap()
+        );
+
+        // Only a retry can find the container already connected
+        let err = connect_network(&runtime, &RetryPolicy::never(), "c1", "net1", "db")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("already connected"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Prepare the image with a pull policy, returning the runtime calls
+    #[cfg(unix)]
+    fn prepare(
+        name: &str,
+        policy: &str,
+        present: bool,
+        pull: bool,
+    ) -> (Result<(), Error>, Vec<String>) {
+        let (dir, runtime) = fake_runtime(name, present, pull);
+        let config: ContainerSystemConfig = serde_json::from_value(serde_json::json!({
+            "image": "busybox",
+            "pull": policy
+        }))
+        .unwrap();
+        let result = config.prepare_image(&runtime);
+        let calls = std::fs::read_to_string(dir.join("calls"))
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect();
+        std::fs::remove_dir_all(dir).unwrap();
+        (result, calls)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pull_missing() {
+        let (result, calls) = prepare("pull-missing", "missing", false, true);
+        result.unwrap();
+        assert_eq!(vec!["image inspect busybox", "pull busybox"], calls);
+
+        let (result, calls) = prepare("pull-present", "missing", true, true);
+        result.unwrap();
+        assert_eq!(vec!["image inspect busybox"], calls);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pull_always() {
+        let (result, calls) = prepare("pull-always", "always", true, true);
+        result.unwrap();
+        assert_eq!(vec!["pull busybox"], calls);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pull_never() {
+        let (result, calls) = prepare("pull-never", "never", false, true);
+        let err = result.err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+        assert!(format!("{err}").contains("pull policy is never"));
+        assert_eq!(vec!["image inspect busybox"], calls);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pull_failure() {
+        let (result, _) = prepare("pull-failure", "missing", false, false);
+        let err = result.err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+        let message = format!("{err}");
+        assert!(message.contains("Failed to pull image 'busybox'"));
+        assert!(message.contains("manifest unknown"));
     }
 }
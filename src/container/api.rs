@@ -0,0 +1,157 @@
+use crate::http::parse_response;
+use crate::{Error, ErrorKind};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+/// Default Docker engine API socket
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// How a container system talks to the container engine
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerTransport {
+    /// Run the runtime's command line tool for every operation
+    #[default]
+    Cli,
+
+    /// Use the engine's REST API over a Unix socket for lifecycle and
+    /// status operations
+    ///
+    /// Works with Docker and with Podman's Docker-compatible API socket
+    /// (defaults to `/var/run/docker.sock`). Only starting, pausing,
+    /// resuming, stopping, restarting, killing, inspecting and removing
+    /// containers use the API. Containers are always created with the
+    /// command line tool, as are image pulls, exec, logs, stats and port
+    /// lookups, so the tool must be installed as well.
+    Api { socket: Option<PathBuf> },
+}
+
+impl ContainerTransport {
+    /// API client for the transport, if it uses the API
+    pub(crate) fn client(&self) -> Option<ApiClient> {
+        match self {
+            ContainerTransport::Cli => None,
+            ContainerTransport::Api { socket } => Some(ApiClient {
+                socket: socket
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET)),
+            }),
+        }
+    }
+}
+
+/// Error message returned by the engine
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+/// A minimal HTTP/1.1 client for the container engine API
+pub(crate) struct ApiClient {
+    socket: PathBuf,
+}
+
+impl ApiClient {
+    #[cfg(test)]
+    pub(crate) fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    /// Send a request and return the body of a successful response
+    fn request(&self, method: &str, path: &str) -> Result<Vec<u8>, Error> {
+        log::trace!("API request: {method} {path}");
+        let mut stream = UnixStream::connect(&self.socket)?;
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+        )?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let (status, body) = parse_response(&response)?;
+        match status {
+            200..=299 | 304 => Ok(body),
+            _ => {
+                let message = serde_json::from_slice::<ApiError>(&body)
+                    .map(|error| error.message)
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&body).to_string());
+                Err(Error::new(
+                    ErrorKind::HarnessError,
+                    format!("{method} {path} failed ({status}): {message}"),
+                ))
+            }
+        }
+    }
+
+    /// Send a request without a response body
+    pub fn post(&self, path: &str) -> Result<(), Error> {
+        self.request("POST", path).map(|_| ())
+    }
+
+    /// Send a delete request
+    pub fn delete(&self, path: &str) -> Result<(), Error> {
+        self.request("DELETE", path).map(|_| ())
+    }
+
+    /// Get a JSON document
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let body = self.request("GET", path)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn request() {
+        let dir = std::env::temp_dir().join(format!("system-harness-api-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("engine.sock");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in [
+                "HTTP/1.1 204 No Content\r\n\r\n",
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 31\r\n\r\n{\"message\":\"No such container\"}",
+            ] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                requests.push(line.trim_end().to_string());
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header == "\r\n" {
+                        break;
+                    }
+                }
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        let client = ApiClient::new(socket);
+        client.post("/containers/abc/pause").unwrap();
+        let err = client.delete("/containers/abc?force=true").err().unwrap();
+        assert!(err.to_string().contains("No such container"));
+        assert_eq!(
+            vec![
+                "POST /containers/abc/pause HTTP/1.1",
+                "DELETE /containers/abc?force=true HTTP/1.1"
+            ],
+            server.join().unwrap()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
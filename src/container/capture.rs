@@ -0,0 +1,92 @@
+use super::{output_to_result, ContainerRuntime};
+use crate::{Error, ErrorKind};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Image providing tcpdump for captures
+pub(super) const DEFAULT_CAPTURE_IMAGE: &str = "docker.io/nicolaka/netshoot";
+
+/// Counter used to give each capture container a unique name
+static CAPTURE: AtomicUsize = AtomicUsize::new(0);
+
+/// A tcpdump container sharing a container's network namespace
+pub(super) struct Capture {
+    name: String,
+    process: Child,
+}
+
+impl Capture {
+    /// Start capturing a container's traffic to a pcap file
+    ///
+    /// Returns once tcpdump is listening, so no traffic sent afterwards is
+    /// missed.
+    pub(super) fn start(
+        runtime: &ContainerRuntime,
+        id: &str,
+        image: &str,
+        path: &Path,
+    ) -> Result<Self, Error> {
+        let name = format!(
+            "system-harness-capture-{}-{}",
+            std::process::id(),
+            CAPTURE.fetch_add(1, Ordering::Relaxed)
+        );
+        log::trace!("Capturing traffic of container {id} to {}", path.display());
+        // Packets are written to stdout as they're captured (-U), so the
+        // file is usable even if the capture isn't stopped cleanly
+        let mut process = runtime
+            .command()
+            .args(["run", "--rm", "--name", &name])
+            .arg("--network")
+            .arg(format!("container:{id}"))
+            .args(["--cap-add", "NET_RAW", "--cap-add", "NET_ADMIN"])
+            .arg(image)
+            .args(["tcpdump", "-i", "any", "-U", "-w", "-"])
+            .stdin(Stdio::null())
+            .stdout(File::create(path)?)
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stderr = match process.stderr.take() {
+            Some(stderr) => BufReader::new(stderr),
+            None => return Err(Error::new(ErrorKind::PipeError, "No capture output")),
+        };
+        let mut output = String::new();
+        loop {
+            let mut line = String::new();
+            if stderr.read_line(&mut line)? == 0 {
+                let _ = process.wait();
+                return Err(Error::new(
+                    ErrorKind::HarnessError,
+                    format!("Failed to capture traffic: {}", output.trim()),
+                ));
+            }
+            if line.contains("listening on") {
+                break;
+            }
+            output.push_str(&line);
+        }
+        // tcpdump reports statistics when it exits, so keep draining
+        std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+        Ok(Self { name, process })
+    }
+
+    /// Stop capturing and wait for the pcap file to be closed
+    pub(super) fn stop(mut self, runtime: &ContainerRuntime) -> Result<(), Error> {
+        log::trace!("Stopping capture: {}", self.name);
+        let result = runtime
+            .command()
+            .args(["stop", "-t", "5", &self.name])
+            .output()
+            .map_err(|err| err.into())
+            .and_then(output_to_result);
+        if result.is_err() {
+            let _ = self.process.kill();
+        }
+        self.process.wait()?;
+        result.map(|_| ())
+    }
+}
@@ -0,0 +1,706 @@
+use super::{output_to_result, wait_all, ContainerRuntime, ContainerSystem, ContainerSystemConfig};
+use super::{ContainerSystemTerminal, RemoteEngine, HARNESS_LABEL};
+use crate::{Error, ErrorKind, ExitStatus, Status, SystemHarness};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Network joined by services that don't list any networks
+const DEFAULT_NETWORK: &str = "default";
+
+/// Default seconds to wait for a dependency's health check
+const DEFAULT_HEALTHY_TIMEOUT: u64 = 120;
+
+/// A service in a composition
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ComposeService {
+    #[serde(flatten)]
+    container: ContainerSystemConfig,
+
+    /// Services that must be started first
+    #[serde(default)]
+    depends_on: Vec<String>,
+
+    /// Networks joined (defaults to the composition's default network)
+    #[serde(default)]
+    networks: Vec<String>,
+}
+
+/// A config for several named containers started and torn down as a unit
+///
+/// Services are started in dependency order. Each one is connected to the
+/// networks it joins before it starts, and can be reached by its service
+/// name on them. A service whose dependency has a health check is only
+/// started once that dependency is healthy.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ComposeSystemConfig {
+    /// Container runtime (detected from the `PATH` by default)
+    ///
+    /// Every service is run with this runtime.
+    tool: Option<ContainerRuntime>,
+
+    /// Engine on another machine to run every service on
+    remote: Option<RemoteEngine>,
+
+    /// containerd namespace to run every service in (nerdctl only)
+    namespace: Option<String>,
+
+    /// Prefix of created containers, networks and volumes
+    ///
+    /// Defaults to `system-harness-<pid>`.
+    project: Option<String>,
+
+    /// Networks shared between services
+    #[serde(default)]
+    networks: Vec<String>,
+
+    /// Named volumes shared between services
+    #[serde(default)]
+    volumes: Vec<String>,
+
+    /// Seconds to wait for a dependency to become healthy (defaults to 120)
+    healthy_timeout: Option<u64>,
+
+    /// Services by name
+    services: BTreeMap<String, ComposeService>,
+}
+
+impl ComposeSystemConfig {
+    /// Order services so that dependencies are started first
+    fn start_order(&self) -> Result<Vec<&str>, Error> {
+        fn visit<'a>(
+            config: &'a ComposeSystemConfig,
+            name: &'a str,
+            visiting: &mut Vec<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> Result<(), Error> {
+            if order.contains(&name) {
+                return Ok(());
+            }
+            if visiting.contains(&name) {
+                return Err(Error::new(
+                    ErrorKind::InvalidConfig,
+                    format!("Dependency cycle: {} -> {name}", visiting.join(" -> ")),
+                ));
+            }
+            let service = config.services.get(name).ok_or(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Unknown service '{name}'"),
+            ))?;
+            visiting.push(name);
+            for dependency in &service.depends_on {
+                visit(config, dependency, visiting, order)?;
+            }
+            visiting.pop();
+            order.push(name);
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        for name in self.services.keys() {
+            visit(self, name, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// Networks joined by a service
+    fn service_networks<'a>(&'a self, service: &'a ComposeService) -> Result<Vec<&'a str>, Error> {
+        if service.container.network_mode.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "Services join the composition's networks and can't set network_mode",
+            ));
+        }
+        if service.networks.is_empty() {
+            return Ok(vec![DEFAULT_NETWORK]);
+        }
+        service
+            .networks
+            .iter()
+            .map(|network| match self.networks.contains(network) {
+                true => Ok(network.as_str()),
+                false => Err(Error::new(
+                    ErrorKind::InvalidConfig,
+                    format!("Unknown network '{network}'"),
+                )),
+            })
+            .collect()
+    }
+
+    /// Run a runtime command that creates a resource
+    fn create(&self, runtime: &ContainerRuntime, kind: &str, name: &str) -> Result<(), Error> {
+        runtime
+            .command()
+            .args([kind, "create", "--label"])
+            .arg(format!("{HARNESS_LABEL}={}", std::process::id()))
+            .arg(name)
+            .output()
+            .map_err(|err| err.into())
+            .and_then(output_to_result)
+            .map(|_| log::trace!("Created {kind}: {name}"))
+    }
+
+    /// Start all services
+    pub fn build(&self) -> Result<ComposeSystem, Error> {
+        let order = self.start_order()?;
+        let mut runtime = match &self.tool {
+            Some(runtime) => runtime.clone(),
+            None => ContainerRuntime::detect()?,
+        };
+        if let Some(remote) = &self.remote {
+            runtime = runtime.remote(remote.clone())?;
+        }
+        if let Some(namespace) = &self.namespace {
+            runtime = runtime.namespace(namespace)?;
+        }
+        let project = self
+            .project
+            .clone()
+            .unwrap_or_else(|| format!("system-harness-{}", std::process::id()));
+        let mut system = ComposeSystem {
+            runtime: runtime.clone(),
+            services: Vec::new(),
+            networks: Vec::new(),
+            volumes: Vec::new(),
+        };
+
+        let mut networks: Vec<&str> = self.networks.iter().map(String::as_str).collect();
+        if self
+            .services
+            .values()
+            .any(|service| service.networks.is_empty())
+        {
+            networks.push(DEFAULT_NETWORK);
+        }
+        for network in networks {
+            let network = format!("{project}_{network}");
+            self.create(&runtime, "network", &network)?;
+            system.networks.push(network);
+        }
+        for volume in &self.volumes {
+            let volume = format!("{project}_{volume}");
+            self.create(&runtime, "volume", &volume)?;
+            system.volumes.push(volume);
+        }
+
+        let healthy_timeout =
+            Duration::from_secs(self.healthy_timeout.unwrap_or(DEFAULT_HEALTHY_TIMEOUT));
+        for name in order {
+            let service = &self.services[name];
+            for dependency in &service.depends_on {
+                if self.services[dependency].container.healthcheck.is_some() {
+                    log::trace!("Waiting for {dependency} to become healthy");
+                    if let Some(dependency) = system.service(dependency) {
+                        dependency.wait_healthy(healthy_timeout)?;
+                    }
+                }
+            }
+
+            let volumes = service.container.volumes.clone().map(|volumes| {
+                volumes
+                    .into_iter()
+                    .map(
+                        |volume| match self.volumes.iter().any(|v| v == volume.source()) {
+                            true => {
+                                let source = format!("{project}_{}", volume.source());
+                                volume.with_source(source)
+                            }
+                            false => volume,
+                        },
+                    )
+                    .collect()
+            });
+            let container = ContainerSystemConfig {
+                tool: Some(runtime.clone()),
+                remote: None,
+                namespace: None,
+                name: Some(
+                    service
+                        .container
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("{project}_{name}")),
+                ),
+                volumes,
+                ..service.container.clone()
+            };
+
+            let networks = self.service_networks(service)?;
+            let mut container = container.create_in(None)?;
+            for network in networks {
+                runtime
+                    .command()
+                    .args(["network", "connect", "--alias", name])
+                    .arg(format!("{project}_{network}"))
+                    .arg(&container.id)
+                    .output()
+                    .map_err(|err| err.into())
+                    .and_then(output_to_result)?;
+            }
+            container.start()?;
+            system.services.push((name.to_string(), container));
+        }
+        Ok(system)
+    }
+}
+
+/// A value that compose files write as either a list or a mapping
+#[cfg(feature = "yaml")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListOrMap {
+    List(Vec<String>),
+    Map(BTreeMap<String, Option<serde_yaml::Value>>),
+}
+
+#[cfg(feature = "yaml")]
+impl ListOrMap {
+    /// Entries as `key=value` strings, or keys alone for mappings without
+    /// values
+    fn entries(self, separator: &str) -> Vec<String> {
+        match self {
+            ListOrMap::List(list) => list,
+            ListOrMap::Map(map) => map
+                .into_iter()
+                .map(|(key, value)| match value.as_ref().map(yaml_scalar) {
+                    Some(Some(value)) => format!("{key}{separator}{value}"),
+                    _ => key,
+                })
+                .collect(),
+        }
+    }
+
+    /// Keys of a mapping, or the entries of a list
+    fn keys(self) -> Vec<String> {
+        match self {
+            ListOrMap::List(list) => list,
+            ListOrMap::Map(map) => map.into_keys().collect(),
+        }
+    }
+}
+
+/// A value that compose files write as either a string or a list
+#[cfg(feature = "yaml")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrList {
+    String(String),
+    List(Vec<String>),
+}
+
+#[cfg(feature = "yaml")]
+impl StringOrList {
+    fn into_list(self) -> Vec<String> {
+        match self {
+            StringOrList::String(value) => value.split_whitespace().map(String::from).collect(),
+            StringOrList::List(list) => list,
+        }
+    }
+}
+
+#[cfg(feature = "yaml")]
+fn yaml_scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(value) => Some(value.clone()),
+        serde_yaml::Value::Number(value) => Some(value.to_string()),
+        serde_yaml::Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// The supported subset of a compose file health check
+#[cfg(feature = "yaml")]
+#[derive(Deserialize)]
+struct ComposeFileHealthCheck {
+    test: StringOrList,
+    interval: Option<String>,
+    timeout: Option<String>,
+    retries: Option<u32>,
+    start_period: Option<String>,
+}
+
+/// The supported subset of a compose file service
+#[cfg(feature = "yaml")]
+#[derive(Deserialize)]
+struct ComposeFileService {
+    image: String,
+    container_name: Option<String>,
+    command: Option<StringOrList>,
+    entrypoint: Option<StringOrList>,
+    environment: Option<ListOrMap>,
+    env_file: Option<StringOrList>,
+    labels: Option<ListOrMap>,
+    ports: Option<Vec<serde_yaml::Value>>,
+    volumes: Option<Vec<String>>,
+    tmpfs: Option<StringOrList>,
+    depends_on: Option<ListOrMap>,
+    networks: Option<ListOrMap>,
+    user: Option<String>,
+    working_dir: Option<String>,
+    hostname: Option<String>,
+    extra_hosts: Option<ListOrMap>,
+    dns: Option<StringOrList>,
+    privileged: Option<bool>,
+    read_only: Option<bool>,
+    cap_add: Option<Vec<String>>,
+    cap_drop: Option<Vec<String>>,
+    healthcheck: Option<ComposeFileHealthCheck>,
+}
+
+/// The supported subset of a compose file
+#[cfg(feature = "yaml")]
+#[derive(Deserialize)]
+struct ComposeFile {
+    name: Option<String>,
+    services: BTreeMap<String, ComposeFileService>,
+    #[serde(default)]
+    networks: BTreeMap<String, serde_yaml::Value>,
+    #[serde(default)]
+    volumes: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Convert a compose port such as `127.0.0.1:8080:80/udp` to a port config
+#[cfg(feature = "yaml")]
+fn compose_port(port: &serde_yaml::Value) -> Result<serde_json::Value, Error> {
+    let port = yaml_scalar(port).ok_or(Error::new(
+        ErrorKind::InvalidConfig,
+        "Only short port syntax is supported",
+    ))?;
+    let invalid = || Error::new(ErrorKind::InvalidConfig, format!("Invalid port '{port}'"));
+    let (port_spec, protocol) = port.split_once('/').unwrap_or((&port, "tcp"));
+    let parts: Vec<&str> = port_spec.rsplitn(3, ':').collect();
+    let container_port: u16 = parts[0].parse().map_err(|_| invalid())?;
+    let mut config = serde_json::json!({
+        "container-port": container_port,
+        "protocol": protocol,
+    });
+    if let Some(host_port) = parts.get(1).filter(|host_port| !host_port.is_empty()) {
+        let host_port: u16 = host_port.parse().map_err(|_| invalid())?;
+        config["host-port"] = host_port.into();
+    }
+    if let Some(host_ip) = parts.get(2) {
+        config["host-ip"] = host_ip.to_string().into();
+    }
+    Ok(config)
+}
+
+/// Convert a compose volume such as `data:/var/lib/data:ro` to a volume config
+#[cfg(feature = "yaml")]
+fn compose_volume(volume: &str) -> Result<serde_json::Value, Error> {
+    let mut parts = volume.split(':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(source), Some(target), options) => {
+            let options: Vec<&str> = options.map(|o| o.split(',').collect()).unwrap_or_default();
+            let selinux = if options.contains(&"Z") {
+                Some("private")
+            } else if options.contains(&"z") {
+                Some("shared")
+            } else {
+                None
+            };
+            Ok(serde_json::json!({
+                "source": source,
+                "target": target,
+                "read-only": options.contains(&"ro"),
+                "selinux": selinux,
+            }))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidConfig,
+            format!("Only bind and named volumes with a target are supported: '{volume}'"),
+        )),
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl ComposeFileService {
+    /// Convert to a service config
+    fn into_service(self) -> Result<ComposeService, Error> {
+        let to_map = |entries: Vec<String>, separator: char| -> BTreeMap<String, String> {
+            entries
+                .into_iter()
+                .filter_map(|entry| {
+                    entry
+                        .split_once(separator)
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                })
+                .collect()
+        };
+        let healthcheck = self.healthcheck.map(|healthcheck| {
+            let test = healthcheck.test.into_list();
+            let cmd = match test.first().map(String::as_str) {
+                Some("CMD-SHELL") | Some("CMD") => test[1..].join(" "),
+                _ => test.join(" "),
+            };
+            serde_json::json!({
+                "cmd": cmd,
+                "interval": healthcheck.interval,
+                "timeout": healthcheck.timeout,
+                "retries": healthcheck.retries,
+                "start-period": healthcheck.start_period,
+            })
+        });
+        let service = serde_json::json!({
+            "image": self.image,
+            "name": self.container_name,
+            "cmd": self.command.map(StringOrList::into_list),
+            "entrypoint": self.entrypoint.map(|entrypoint| entrypoint.into_list().join(" ")),
+            "env": self.environment.map(|env| to_map(env.entries("="), '=')),
+            "env_file": self.env_file.map(StringOrList::into_list),
+            "labels": self.labels.map(|labels| to_map(labels.entries("="), '=')),
+            "ports": self
+                .ports
+                .iter()
+                .flatten()
+                .map(compose_port)
+                .collect::<Result<Vec<_>, _>>()?,
+            "volumes": self
+                .volumes
+                .iter()
+                .flatten()
+                .map(|volume| compose_volume(volume))
+                .collect::<Result<Vec<_>, _>>()?,
+            "tmpfs": self.tmpfs.map(StringOrList::into_list),
+            "depends_on": self.depends_on.map(ListOrMap::keys).unwrap_or_default(),
+            "networks": self.networks.map(ListOrMap::keys).unwrap_or_default(),
+            "user": self.user,
+            "workdir": self.working_dir,
+            "hostname": self.hostname,
+            "add_host": self.extra_hosts.map(|hosts| to_map(hosts.entries(":"), ':')),
+            "dns": self.dns.map(StringOrList::into_list),
+            "privileged": self.privileged.unwrap_or_default(),
+            "read_only": self.read_only.unwrap_or_default(),
+            "cap_add": self.cap_add,
+            "cap_drop": self.cap_drop,
+            "healthcheck": healthcheck,
+        });
+        Ok(serde_json::from_value(service)?)
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl ComposeSystemConfig {
+    /// Load a config from the supported subset of a docker-compose.yml file
+    ///
+    /// Images must be prebuilt, and only short port and volume syntax is
+    /// supported. Network and volume options are ignored.
+    pub fn from_compose_file(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let file = std::fs::read_to_string(path)?;
+        Self::from_compose_str(&file)
+    }
+
+    /// Load a config from the contents of a docker-compose.yml file
+    pub fn from_compose_str(contents: &str) -> Result<Self, Error> {
+        let file: ComposeFile = serde_yaml::from_str(contents)
+            .map_err(|err| Error::new(ErrorKind::SerializationError, err))?;
+        Ok(Self {
+            tool: None,
+            remote: None,
+            namespace: None,
+            project: file.name,
+            networks: file.networks.into_keys().collect(),
+            volumes: file.volumes.into_keys().collect(),
+            healthy_timeout: None,
+            services: file
+                .services
+                .into_iter()
+                .map(|(name, service)| Ok((name, service.into_service()?)))
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+}
+
+/// A running composition of containers
+pub struct ComposeSystem {
+    runtime: ContainerRuntime,
+    services: Vec<(String, ContainerSystem)>,
+    networks: Vec<String>,
+    volumes: Vec<String>,
+}
+
+impl ComposeSystem {
+    /// Container of a service
+    pub fn service(&self, name: &str) -> Option<&ContainerSystem> {
+        self.services
+            .iter()
+            .find(|(service, _)| service == name)
+            .map(|(_, container)| container)
+    }
+
+    /// Services and their containers in start order
+    pub fn services(&self) -> impl Iterator<Item = (&str, &ContainerSystem)> {
+        self.services
+            .iter()
+            .map(|(name, container)| (name.as_str(), container))
+    }
+}
+
+impl SystemHarness for ComposeSystem {
+    type Terminal = ContainerSystemTerminal;
+
+    /// Get a terminal for the first service started
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        self.services
+            .first()
+            .ok_or(Error::new(ErrorKind::HarnessError, "No services"))?
+            .1
+            .terminal()
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        for (_, service) in &mut self.services {
+            service.pause()?;
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        for (_, service) in &mut self.services {
+            service.resume()?;
+        }
+        Ok(())
+    }
+
+    /// Stop services in reverse start order
+    fn shutdown(&mut self) -> Result<(), Error> {
+        for (_, service) in self.services.iter_mut().rev() {
+            service.shutdown()?;
+        }
+        Ok(())
+    }
+
+    /// The least active status of all services
+    fn status(&mut self) -> Result<Status, Error> {
+        let mut status = Status::Running;
+        for (_, service) in &mut self.services {
+            match service.status()? {
+                Status::Shutdown => return Ok(Status::Shutdown),
+                Status::Paused => status = Status::Paused,
+                _ => {}
+            }
+        }
+        Ok(status)
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        self.status().map(|status| status == Status::Running)
+    }
+
+    /// Wait for every service to exit
+    ///
+    /// Reports the first service that failed, otherwise the last service
+    /// started.
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        wait_all(
+            self.services.iter_mut().map(|(_, service)| service),
+            timeout,
+        )
+    }
+}
+
+impl Drop for ComposeSystem {
+    fn drop(&mut self) {
+        while let Some((name, service)) = self.services.pop() {
+            log::trace!("Tearing down service: {name}");
+            drop(service);
+        }
+        for (kind, names) in [("network", &self.networks), ("volume", &self.volumes)] {
+            for name in names {
+                let removed = self
+                    .runtime
+                    .command()
+                    .args([kind, "rm", name])
+                    .output()
+                    .map_err(|err| err.into())
+                    .and_then(output_to_result);
+                if let Err(err) = removed {
+                    log::warn!("Failed to remove {kind} {name}: {err}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn config(services: &str) -> ComposeSystemConfig {
+        serde_json::from_str(&format!(
+            r#"{{ "networks": ["backend"], "services": {services} }}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn start_order() {
+        let config = config(
+            r#"{
+                "app": { "image": "app", "depends_on": ["db", "cache"] },
+                "cache": { "image": "redis" },
+                "db": { "image": "postgres", "networks": ["backend"] }
+            }"#,
+        );
+        assert_eq!(vec!["db", "cache", "app"], config.start_order().unwrap());
+        assert_eq!(
+            vec!["backend"],
+            config.service_networks(&config.services["db"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let config = config(
+            r#"{
+                "a": { "image": "a", "depends_on": ["b"] },
+                "b": { "image": "b", "depends_on": ["a"] }
+            }"#,
+        );
+        let err = config.start_order().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn compose_file() {
+        let config = ComposeSystemConfig::from_compose_str(
+            r#"
+name: shop
+services:
+  app:
+    image: shop:latest
+    command: ["serve", "--port", "8080"]
+    environment:
+      DATABASE_URL: postgres://db/shop
+      DEBUG: 1
+    ports:
+      - "8080"
+      - "127.0.0.1:9090:9090/udp"
+    depends_on:
+      db:
+        condition: service_healthy
+  db:
+    image: postgres:16
+    volumes:
+      - data:/var/lib/postgresql/data
+    healthcheck:
+      test: ["CMD-SHELL", "pg_isready"]
+      interval: 5s
+volumes:
+  data: {}
+"#,
+        )
+        .unwrap();
+        assert_eq!(Some("shop"), config.project.as_deref());
+        assert_eq!(vec!["data"], config.volumes);
+        assert_eq!(vec!["db", "app"], config.start_order().unwrap());
+        let app = &config.services["app"];
+        assert_eq!(
+            Some(&"1".to_string()),
+            app.container.env.as_ref().unwrap().get("DEBUG")
+        );
+        assert_eq!(2, app.container.ports.as_ref().unwrap().len());
+        assert!(config.services["db"].container.healthcheck.is_some());
+    }
+}
@@ -0,0 +1,127 @@
+use super::{ContainerRuntime, RuntimeKind};
+use crate::metrics::{self, Counter};
+use crate::{Error, Event, EventKind, EventSubscriber};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Subscribers shared between a container and its event stream
+pub(crate) type EventSubscribers = Arc<Mutex<Vec<Box<dyn EventSubscriber>>>>;
+
+/// An event from the runtime's events stream
+///
+/// Docker and nerdctl report both `Action` and the deprecated `status`,
+/// while Podman only reports `Status`.
+#[derive(Deserialize)]
+struct RuntimeEvent {
+    #[serde(rename = "Action")]
+    action: Option<String>,
+
+    #[serde(alias = "Status")]
+    status: Option<String>,
+
+    #[serde(rename = "timeNano")]
+    time_nano: Option<u64>,
+}
+
+/// Map a line of a runtime's events stream to an event
+fn parse_event(runtime: RuntimeKind, line: &str) -> Option<Event> {
+    let event: RuntimeEvent = serde_json::from_str(line).ok()?;
+    let action = match runtime {
+        RuntimeKind::Podman => event.status,
+        _ => event.action.or(event.status),
+    }?;
+    log::trace!("Saw {action} event");
+    // Podman names the event of a container exiting `died`
+    let died = match runtime {
+        RuntimeKind::Podman => "died",
+        _ => "die",
+    };
+    let kind = match action.as_str() {
+        action if action == died => EventKind::Shutdown,
+        "pause" => EventKind::Pause,
+        "unpause" => EventKind::Resume,
+        "oom" => EventKind::OutOfMemory,
+        _ => return None,
+    };
+    let timestamp = event
+        .time_nano
+        .map(|nanos| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos))
+        .unwrap_or_else(SystemTime::now);
+    Some(Event { kind, timestamp })
+}
+
+/// Follow the events of a container, forwarding them to subscribers
+///
+/// The returned process must be killed to stop following.
+pub(crate) fn follow(
+    runtime: &ContainerRuntime,
+    id: &str,
+    subscribers: EventSubscribers,
+) -> Result<Child, Error> {
+    let kind = runtime.kind();
+    let mut process = runtime
+        .command()
+        .arg("events")
+        .arg("--filter")
+        .arg(format!("container={id}"))
+        .args(["--format", "{{json .}}"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(stdout) = process.stdout.take() {
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if let Some(event) = parse_event(kind, &line) {
+                    metrics::increment(Counter::EventsReceived, "container");
+                    if let Ok(mut subscribers) = subscribers.lock() {
+                        for subscriber in subscribers.iter_mut() {
+                            subscriber.on_event(&event);
+                        }
+                    }
+                }
+            }
+        });
+    }
+    Ok(process)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn docker_event() {
+        let event = parse_event(
+            RuntimeKind::Docker,
+            r#"{"status":"pause","id":"abc","Type":"container","Action":"pause","time":1700000000,"timeNano":1700000000000000000}"#,
+        )
+        .unwrap();
+        assert_eq!(EventKind::Pause, event.kind);
+        assert_eq!(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            event.timestamp
+        );
+    }
+
+    #[test]
+    fn podman_event() {
+        let event = parse_event(
+            RuntimeKind::Podman,
+            r#"{"ID":"abc","Name":"sut","Status":"oom","Type":"container"}"#,
+        )
+        .unwrap();
+        assert_eq!(EventKind::OutOfMemory, event.kind);
+        let event = parse_event(RuntimeKind::Podman, r#"{"ID":"abc","Status":"died"}"#).unwrap();
+        assert_eq!(EventKind::Shutdown, event.kind);
+        assert!(parse_event(
+            RuntimeKind::Podman,
+            r#"{"ID":"abc","Status":"exec_create"}"#
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,1103 @@
+use cmdstruct::Arg;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// SELinux relabeling of a volume
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum SelinuxLabel {
+    /// Label shared between containers (`z`)
+    Shared,
+
+    /// Label private to the container (`Z`)
+    Private,
+}
+
+/// A volume or bind mount (`-v`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct Volume {
+    /// Host path or named volume
+    source: String,
+
+    /// Path in the container
+    target: String,
+
+    /// Mount read-only
+    #[serde(default)]
+    read_only: bool,
+
+    /// SELinux relabeling
+    selinux: Option<SelinuxLabel>,
+}
+
+impl Volume {
+    /// Mount a host path or named volume at a path in the container
+    pub fn new(source: impl Into<String>, target: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            target: target.into(),
+            read_only: false,
+            selinux: None,
+        }
+    }
+
+    /// Mount read-only
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Relabel the source for SELinux
+    pub fn selinux(mut self, label: SelinuxLabel) -> Self {
+        self.selinux = Some(label);
+        self
+    }
+
+    /// Host path or named volume
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Replace the host path or named volume
+    pub(crate) fn with_source(mut self, source: String) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+impl Arg for Volume {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        let mut options = Vec::new();
+        if self.read_only {
+            options.push("ro");
+        }
+        match self.selinux {
+            Some(SelinuxLabel::Shared) => options.push("z"),
+            Some(SelinuxLabel::Private) => options.push("Z"),
+            None => {}
+        }
+        let mut volume = format!("{}:{}", self.source, self.target);
+        if !options.is_empty() {
+            volume.push(':');
+            volume.push_str(&options.join(","));
+        }
+        command.arg(volume);
+    }
+}
+
+/// A host device passed into a container (`--device`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct ContainerDevice {
+    /// Device path on the host
+    host: String,
+
+    /// Device path in the container (defaults to the host path)
+    container: Option<String>,
+
+    /// Cgroup permissions (e.g. `rwm`)
+    permissions: Option<String>,
+}
+
+impl ContainerDevice {
+    /// Pass a host device into the container at the same path
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            container: None,
+            permissions: None,
+        }
+    }
+
+    /// Device path in the container
+    pub fn container(mut self, container: impl Into<String>) -> Self {
+        self.container = Some(container.into());
+        self
+    }
+
+    /// Cgroup permissions (e.g. `rwm`)
+    pub fn permissions(mut self, permissions: impl Into<String>) -> Self {
+        self.permissions = Some(permissions.into());
+        self
+    }
+}
+
+impl Arg for ContainerDevice {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        let mut device = self.host.clone();
+        if self.container.is_some() || self.permissions.is_some() {
+            device.push(':');
+            device.push_str(self.container.as_deref().unwrap_or(&self.host));
+        }
+        if let Some(permissions) = &self.permissions {
+            device.push(':');
+            device.push_str(permissions);
+        }
+        command.arg(device);
+    }
+}
+
+/// Confinement of a container (`--security-opt`)
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct SecurityOptions {
+    /// Seccomp profile file, or `unconfined`
+    seccomp: Option<String>,
+
+    /// AppArmor profile name, or `unconfined`
+    apparmor: Option<String>,
+
+    /// SELinux label options, such as `type:container_t` or `disable`
+    label: Option<Vec<String>>,
+
+    /// Prevent processes from gaining privileges, such as through setuid
+    #[serde(default)]
+    no_new_privileges: bool,
+}
+
+impl SecurityOptions {
+    /// Seccomp profile file, or `unconfined`
+    pub fn seccomp(mut self, profile: impl Into<String>) -> Self {
+        self.seccomp = Some(profile.into());
+        self
+    }
+
+    /// AppArmor profile name, or `unconfined`
+    pub fn apparmor(mut self, profile: impl Into<String>) -> Self {
+        self.apparmor = Some(profile.into());
+        self
+    }
+
+    /// Add an SELinux label option
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label.get_or_insert_with(Vec::new).push(label.into());
+        self
+    }
+
+    /// Prevent processes from gaining privileges
+    pub fn no_new_privileges(mut self) -> Self {
+        self.no_new_privileges = true;
+        self
+    }
+}
+
+impl Arg for SecurityOptions {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        if let Some(profile) = &self.seccomp {
+            command
+                .arg("--security-opt")
+                .arg(format!("seccomp={profile}"));
+        }
+        if let Some(profile) = &self.apparmor {
+            command
+                .arg("--security-opt")
+                .arg(format!("apparmor={profile}"));
+        }
+        for label in self.label.iter().flatten() {
+            command.arg("--security-opt").arg(format!("label={label}"));
+        }
+        if self.no_new_privileges {
+            command.arg("--security-opt").arg("no-new-privileges");
+        }
+    }
+}
+
+/// GPUs made available to a container
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Gpus {
+    /// Every GPU on the host (`--gpus all`)
+    All,
+
+    /// GPUs by index or UUID (`--gpus device=...`)
+    Devices(Vec<String>),
+
+    /// Container Device Interface names, such as `nvidia.com/gpu=all`
+    ///
+    /// Podman exposes GPUs this way (`--device`).
+    Cdi(Vec<String>),
+}
+
+impl Arg for Gpus {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        match self {
+            Gpus::All => {
+                command.arg("--gpus").arg("all");
+            }
+            // The value is parsed as CSV, so the list must be quoted
+            Gpus::Devices(devices) => {
+                command
+                    .arg("--gpus")
+                    .arg(format!("\"device={}\"", devices.join(",")));
+            }
+            Gpus::Cdi(devices) => {
+                for device in devices {
+                    command.arg("--device").arg(device);
+                }
+            }
+        }
+    }
+}
+
+/// Transport protocol of a published port
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+impl Protocol {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Sctp => "sctp",
+        }
+    }
+}
+
+/// A published port (`-p`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct Port {
+    /// Port in the container
+    container_port: u16,
+
+    /// Port on the host (randomly assigned if not set)
+    host_port: Option<u16>,
+
+    /// Host address to bind
+    host_ip: Option<String>,
+
+    /// Transport protocol (defaults to TCP)
+    #[serde(default)]
+    protocol: Protocol,
+}
+
+impl Port {
+    /// Publish a container port on a random host port
+    pub fn new(container_port: u16) -> Self {
+        Self {
+            container_port,
+            host_port: None,
+            host_ip: None,
+            protocol: Protocol::default(),
+        }
+    }
+
+    /// Publish on a fixed host port
+    pub fn host_port(mut self, host_port: u16) -> Self {
+        self.host_port = Some(host_port);
+        self
+    }
+
+    /// Bind to a host address
+    pub fn host_ip(mut self, host_ip: impl Into<String>) -> Self {
+        self.host_ip = Some(host_ip.into());
+        self
+    }
+
+    /// Transport protocol
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+}
+
+impl Arg for Port {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        let host_port = self.host_port.map(|port| port.to_string());
+        let port = match (&self.host_ip, host_port) {
+            (Some(ip), host_port) => format!("{ip}:{}:", host_port.unwrap_or_default()),
+            (None, Some(host_port)) => format!("{host_port}:"),
+            (None, None) => String::new(),
+        };
+        command.arg(format!(
+            "{port}{}/{}",
+            self.container_port,
+            self.protocol.as_str()
+        ));
+    }
+}
+
+/// When to pull the container image
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Always pull the image
+    Always,
+
+    /// Pull the image if it isn't available locally
+    #[default]
+    Missing,
+
+    /// Never pull the image
+    Never,
+}
+
+/// Instructions for building the container image
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct ImageBuild {
+    /// Build context directory
+    context: PathBuf,
+
+    /// Containerfile or Dockerfile (defaults to the one in the context)
+    file: Option<PathBuf>,
+
+    /// Build arguments
+    args: Option<BTreeMap<String, String>>,
+
+    /// Target build stage
+    target: Option<String>,
+}
+
+impl ImageBuild {
+    /// Build from the given context directory
+    pub fn new(context: impl Into<PathBuf>) -> Self {
+        Self {
+            context: context.into(),
+            file: None,
+            args: None,
+            target: None,
+        }
+    }
+
+    /// Containerfile or Dockerfile
+    pub fn file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Add a build argument
+    pub fn arg(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.args
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Target build stage
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+}
+
+impl Arg for ImageBuild {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        if let Some(file) = &self.file {
+            command.arg("-f").arg(file);
+        }
+        for (key, value) in self.args.iter().flatten() {
+            command.arg("--build-arg").arg(format!("{key}={value}"));
+        }
+        self.target.append_option("--target", command);
+        command.arg(&self.context);
+    }
+}
+
+/// A container health check
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthCheck {
+    /// Command run in the container to check health
+    cmd: String,
+
+    /// Time between checks (e.g. `5s`)
+    interval: Option<String>,
+
+    /// Time allowed for a check (e.g. `3s`)
+    timeout: Option<String>,
+
+    /// Consecutive failures before the container is unhealthy
+    retries: Option<u32>,
+
+    /// Time after start during which failures are not counted
+    start_period: Option<String>,
+}
+
+impl HealthCheck {
+    /// Check health with the given command
+    pub fn new(cmd: impl Into<String>) -> Self {
+        Self {
+            cmd: cmd.into(),
+            interval: None,
+            timeout: None,
+            retries: None,
+            start_period: None,
+        }
+    }
+
+    /// Time between checks (e.g. `5s`)
+    pub fn interval(mut self, interval: impl Into<String>) -> Self {
+        self.interval = Some(interval.into());
+        self
+    }
+
+    /// Time allowed for a check (e.g. `3s`)
+    pub fn timeout(mut self, timeout: impl Into<String>) -> Self {
+        self.timeout = Some(timeout.into());
+        self
+    }
+
+    /// Consecutive failures before the container is unhealthy
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Time after start during which failures are not counted
+    pub fn start_period(mut self, start_period: impl Into<String>) -> Self {
+        self.start_period = Some(start_period.into());
+        self
+    }
+}
+
+impl Arg for HealthCheck {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        command.arg("--health-cmd").arg(&self.cmd);
+        self.interval.append_option("--health-interval", command);
+        self.timeout.append_option("--health-timeout", command);
+        self.retries.append_option("--health-retries", command);
+        self.start_period
+            .append_option("--health-start-period", command);
+    }
+}
+
+/// Options for a command executed in a container
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct ExecOptions {
+    /// Environment variables
+    env: Option<BTreeMap<String, String>>,
+
+    /// User to run as (`uid:gid` or name)
+    user: Option<String>,
+
+    /// Working directory
+    workdir: Option<String>,
+}
+
+impl ExecOptions {
+    /// Set an environment variable
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// User to run as (`uid:gid` or name)
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Working directory
+    pub fn workdir(mut self, workdir: impl Into<String>) -> Self {
+        self.workdir = Some(workdir.into());
+        self
+    }
+}
+
+impl Arg for ExecOptions {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        for (key, value) in self.env.iter().flatten() {
+            command.arg("-e").arg(format!("{key}={value}"));
+        }
+        self.user.append_option("--user", command);
+        self.workdir.append_option("--workdir", command);
+    }
+}
+
+/// Options for interactive terminals opened in a container
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct TerminalOptions {
+    /// Command and arguments to run (defaults to `sh`)
+    command: Option<Vec<String>>,
+
+    /// Environment, user and working directory of the command
+    #[serde(flatten)]
+    exec: ExecOptions,
+
+    /// Allocate a TTY (defaults to true)
+    #[serde(default = "default_tty")]
+    tty: bool,
+}
+
+fn default_tty() -> bool {
+    true
+}
+
+impl Default for TerminalOptions {
+    fn default() -> Self {
+        Self {
+            command: None,
+            exec: ExecOptions::default(),
+            tty: default_tty(),
+        }
+    }
+}
+
+impl TerminalOptions {
+    /// Command and arguments to run
+    pub fn command(mut self, command: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.command = Some(command.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set an environment variable
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.exec = self.exec.env(key, value);
+        self
+    }
+
+    /// User to run as (`uid:gid` or name)
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.exec = self.exec.user(user);
+        self
+    }
+
+    /// Working directory
+    pub fn workdir(mut self, workdir: impl Into<String>) -> Self {
+        self.exec = self.exec.workdir(workdir);
+        self
+    }
+
+    /// Allocate a TTY
+    pub fn tty(mut self, tty: bool) -> Self {
+        self.tty = tty;
+        self
+    }
+
+    pub(crate) fn has_tty(&self) -> bool {
+        self.tty
+    }
+
+    /// Command and arguments to run
+    pub(crate) fn command_line(&self) -> Vec<String> {
+        self.command
+            .clone()
+            .unwrap_or_else(|| vec![String::from("sh")])
+    }
+}
+
+impl Arg for TerminalOptions {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        command.arg("-i");
+        if self.tty {
+            command.arg("-t");
+        }
+        self.exec.append_arg(command);
+    }
+}
+
+/// Where the runtime stores a container's output (`--log-driver`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct LogConfig {
+    /// Log driver, such as `json-file`, `journald` or `none`
+    driver: String,
+
+    /// Driver options, such as `max-size` or `tag`
+    options: Option<BTreeMap<String, String>>,
+}
+
+impl LogConfig {
+    /// Use the given log driver
+    pub fn new(driver: impl Into<String>) -> Self {
+        Self {
+            driver: driver.into(),
+            options: None,
+        }
+    }
+
+    /// Set a driver option
+    pub fn option(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+}
+
+impl Arg for LogConfig {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        command.arg("--log-driver").arg(&self.driver);
+        for (key, value) in self.options.iter().flatten() {
+            command.arg("--log-opt").arg(format!("{key}={value}"));
+        }
+    }
+}
+
+/// Options for reading container logs
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LogOptions {
+    /// Keep streaming new output
+    #[serde(default)]
+    follow: bool,
+
+    /// Only show output since a timestamp or relative time (e.g. `10m`)
+    since: Option<String>,
+
+    /// Prefix each line with a timestamp
+    #[serde(default)]
+    timestamps: bool,
+}
+
+impl LogOptions {
+    /// Keep streaming new output
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Only show output since a timestamp or relative time (e.g. `10m`)
+    pub fn since(mut self, since: impl Into<String>) -> Self {
+        self.since = Some(since.into());
+        self
+    }
+
+    /// Prefix each line with a timestamp
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+}
+
+impl Arg for LogOptions {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        if self.follow {
+            command.arg("--follow");
+        }
+        self.since.append_option("--since", command);
+        if self.timestamps {
+            command.arg("--timestamps");
+        }
+    }
+}
+
+/// A signal delivered to a container's main process
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Signal {
+    Hup,
+    Int,
+    Quit,
+    Kill,
+    Usr1,
+    Usr2,
+    Term,
+    Cont,
+    Stop,
+    Winch,
+
+    /// A signal by number
+    Number(i32),
+}
+
+impl Signal {
+    /// Signal name or number as accepted by the runtime
+    pub(crate) fn name(&self) -> String {
+        match self {
+            Signal::Hup => String::from("SIGHUP"),
+            Signal::Int => String::from("SIGINT"),
+            Signal::Quit => String::from("SIGQUIT"),
+            Signal::Kill => String::from("SIGKILL"),
+            Signal::Usr1 => String::from("SIGUSR1"),
+            Signal::Usr2 => String::from("SIGUSR2"),
+            Signal::Term => String::from("SIGTERM"),
+            Signal::Cont => String::from("SIGCONT"),
+            Signal::Stop => String::from("SIGSTOP"),
+            Signal::Winch => String::from("SIGWINCH"),
+            Signal::Number(number) => number.to_string(),
+        }
+    }
+}
+
+/// What happens to a container when its system is dropped
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum DropPolicy {
+    /// Leave the container running
+    KeepRunning,
+
+    /// Stop the container but keep it for inspection
+    StopOnly,
+
+    /// Stop and remove the container
+    #[default]
+    Remove,
+
+    /// Stop and remove the container and its anonymous volumes
+    RemoveWithVolumes,
+}
+
+/// When the runtime restarts a container (`--restart`)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Never restart
+    No,
+
+    /// Always restart when the container exits
+    Always,
+
+    /// Restart unless the container was explicitly stopped
+    UnlessStopped,
+
+    /// Restart when the container exits with a non-zero status
+    OnFailure { max_retries: Option<u32> },
+}
+
+impl Arg for RestartPolicy {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        command.arg(match self {
+            RestartPolicy::No => String::from("no"),
+            RestartPolicy::Always => String::from("always"),
+            RestartPolicy::UnlessStopped => String::from("unless-stopped"),
+            RestartPolicy::OnFailure { max_retries: None } => String::from("on-failure"),
+            RestartPolicy::OnFailure {
+                max_retries: Some(retries),
+            } => format!("on-failure:{retries}"),
+        });
+    }
+}
+
+/// Which PID or IPC namespace a container uses (`--pid`, `--ipc`)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum NamespaceMode {
+    /// A namespace of the container's own
+    Private,
+
+    /// The host's namespace
+    Host,
+
+    /// Another container's namespace, by name or ID
+    Container(String),
+}
+
+impl Arg for NamespaceMode {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        command.arg(match self {
+            NamespaceMode::Private => String::from("private"),
+            NamespaceMode::Host => String::from("host"),
+            NamespaceMode::Container(container) => format!("container:{container}"),
+        });
+    }
+}
+
+/// How a container is networked (`--network`)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkMode {
+    /// The runtime's default bridge network
+    Bridge,
+
+    /// The host's network namespace
+    Host,
+
+    /// Loopback only
+    None,
+
+    /// Another container's network namespace, by name or ID
+    Container(String),
+
+    /// A user-defined network
+    Network(String),
+}
+
+impl Arg for NetworkMode {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        command.arg(match self {
+            NetworkMode::Bridge => String::from("bridge"),
+            NetworkMode::Host => String::from("host"),
+            NetworkMode::None => String::from("none"),
+            NetworkMode::Container(container) => format!("container:{container}"),
+            NetworkMode::Network(network) => network.clone(),
+        });
+    }
+}
+
+/// Resource limits of a container
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct ContainerLimits {
+    /// Memory limit (e.g. `512m`)
+    memory: Option<String>,
+
+    /// Memory plus swap limit (e.g. `1g`, or `-1` for unlimited swap)
+    memory_swap: Option<String>,
+
+    /// Number of CPUs (e.g. `1.5`)
+    cpus: Option<f64>,
+
+    /// Maximum number of processes
+    pids_limit: Option<i64>,
+}
+
+impl ContainerLimits {
+    /// Memory limit (e.g. `512m`)
+    pub fn memory(mut self, memory: impl Into<String>) -> Self {
+        self.memory = Some(memory.into());
+        self
+    }
+
+    /// Memory plus swap limit
+    pub fn memory_swap(mut self, memory_swap: impl Into<String>) -> Self {
+        self.memory_swap = Some(memory_swap.into());
+        self
+    }
+
+    /// Number of CPUs
+    pub fn cpus(mut self, cpus: f64) -> Self {
+        self.cpus = Some(cpus);
+        self
+    }
+
+    /// Maximum number of processes
+    pub fn pids_limit(mut self, pids_limit: i64) -> Self {
+        self.pids_limit = Some(pids_limit);
+        self
+    }
+}
+
+impl Arg for ContainerLimits {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        self.memory.append_option("--memory", command);
+        self.memory_swap.append_option("--memory-swap", command);
+        self.cpus.append_option("--cpus", command);
+        self.pids_limit.append_option("--pids-limit", command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn volume_arg() {
+        let mut command = std::process::Command::new("test");
+        Volume::new("/fixtures", "/data").append_option("-v", &mut command);
+        Volume::new("results", "/results")
+            .read_only()
+            .selinux(SelinuxLabel::Private)
+            .append_option("-v", &mut command);
+        assert_eq!(
+            vec!["-v", "/fixtures:/data", "-v", "results:/results:ro,Z"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn device_arg() {
+        let mut command = std::process::Command::new("test");
+        ContainerDevice::new("/dev/kvm").append_arg(&mut command);
+        ContainerDevice::new("/dev/ttyUSB0")
+            .container("/dev/ttyS0")
+            .append_arg(&mut command);
+        ContainerDevice::new("/dev/tpm0")
+            .permissions("rw")
+            .append_arg(&mut command);
+        assert_eq!(
+            vec![
+                "/dev/kvm",
+                "/dev/ttyUSB0:/dev/ttyS0",
+                "/dev/tpm0:/dev/tpm0:rw"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn port_arg() {
+        let mut command = std::process::Command::new("test");
+        Port::new(80).append_arg(&mut command);
+        Port::new(53)
+            .host_port(5353)
+            .protocol(Protocol::Udp)
+            .append_arg(&mut command);
+        Port::new(22).host_ip("127.0.0.1").append_arg(&mut command);
+        assert_eq!(
+            vec!["80/tcp", "5353:53/udp", "127.0.0.1::22/tcp"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn security_options_arg() {
+        let mut command = std::process::Command::new("test");
+        SecurityOptions::default()
+            .seccomp("profile.json")
+            .apparmor("unconfined")
+            .label("type:container_t")
+            .no_new_privileges()
+            .append_arg(&mut command);
+        assert_eq!(
+            vec![
+                "--security-opt",
+                "seccomp=profile.json",
+                "--security-opt",
+                "apparmor=unconfined",
+                "--security-opt",
+                "label=type:container_t",
+                "--security-opt",
+                "no-new-privileges"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn namespace_arg() {
+        let mut command = std::process::Command::new("test");
+        let pid: NamespaceMode = serde_json::from_str(r#""host""#).unwrap();
+        let network: NetworkMode = serde_json::from_str(r#"{ "container": "db" }"#).unwrap();
+        pid.append_option("--pid", &mut command);
+        network.append_option("--network", &mut command);
+        assert_eq!(
+            vec!["--pid", "host", "--network", "container:db"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn gpus_arg() {
+        let mut command = std::process::Command::new("test");
+        Gpus::All.append_arg(&mut command);
+        Gpus::Devices(vec!["0".to_string(), "1".to_string()]).append_arg(&mut command);
+        Gpus::Cdi(vec!["nvidia.com/gpu=all".to_string()]).append_arg(&mut command);
+        assert_eq!(
+            vec![
+                "--gpus",
+                "all",
+                "--gpus",
+                "\"device=0,1\"",
+                "--device",
+                "nvidia.com/gpu=all"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn exec_options_arg() {
+        let mut command = std::process::Command::new("test");
+        ExecOptions::default()
+            .env("LANG", "C")
+            .user("nobody")
+            .workdir("/tmp")
+            .append_arg(&mut command);
+        assert_eq!(
+            vec!["-e", "LANG=C", "--user", "nobody", "--workdir", "/tmp"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn terminal_options() {
+        let options: TerminalOptions =
+            serde_json::from_str(r#"{"command": ["bash", "-l"], "user": "root", "tty": false}"#)
+                .unwrap();
+        let mut command = std::process::Command::new("test");
+        options.append_arg(&mut command);
+        assert_eq!(
+            vec!["-i", "--user", "root"],
+            command.get_args().collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["bash", "-l"], options.command_line());
+
+        let options = TerminalOptions::default();
+        assert!(options.has_tty());
+        assert_eq!(vec!["sh"], options.command_line());
+    }
+
+    #[test]
+    fn log_config_arg() {
+        let mut command = std::process::Command::new("test");
+        LogConfig::new("json-file")
+            .option("max-size", "10m")
+            .option("max-file", "3")
+            .append_arg(&mut command);
+        assert_eq!(
+            vec![
+                "--log-driver",
+                "json-file",
+                "--log-opt",
+                "max-file=3",
+                "--log-opt",
+                "max-size=10m"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn log_options_arg() {
+        let mut command = std::process::Command::new("test");
+        LogOptions::default()
+            .follow(true)
+            .since("10m")
+            .timestamps(true)
+            .append_arg(&mut command);
+        assert_eq!(
+            vec!["--follow", "--since", "10m", "--timestamps"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn restart_policy_arg() {
+        let mut command = std::process::Command::new("test");
+        let policies: Vec<RestartPolicy> =
+            serde_json::from_str(r#"["unless-stopped", { "on-failure": { "max_retries": 3 } }]"#)
+                .unwrap();
+        policies.append_option("--restart", &mut command);
+        assert_eq!(
+            vec!["--restart", "unless-stopped", "--restart", "on-failure:3"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn limits_arg() {
+        let mut command = std::process::Command::new("test");
+        ContainerLimits::default()
+            .memory("256m")
+            .cpus(0.5)
+            .pids_limit(64)
+            .append_arg(&mut command);
+        assert_eq!(
+            vec!["--memory", "256m", "--cpus", "0.5", "--pids-limit", "64"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+}
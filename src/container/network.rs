@@ -0,0 +1,50 @@
+use super::{output_to_result, ContainerRuntime, HARNESS_LABEL};
+use crate::Error;
+
+/// A user-defined network that containers are attached to
+///
+/// The network is removed when dropped, so it must outlive the containers
+/// attached to it.
+pub(crate) struct ContainerNetwork {
+    runtime: ContainerRuntime,
+    name: String,
+}
+
+impl ContainerNetwork {
+    /// Create a network
+    pub(crate) fn create(runtime: &ContainerRuntime, name: &str) -> Result<Self, Error> {
+        runtime
+            .command()
+            .args(["network", "create", "--label"])
+            .arg(format!("{HARNESS_LABEL}={}", std::process::id()))
+            .arg(name)
+            .output()
+            .map_err(|err| err.into())
+            .and_then(output_to_result)?;
+        log::trace!("Created network: {name}");
+        Ok(Self {
+            runtime: runtime.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    /// Name of the network
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for ContainerNetwork {
+    fn drop(&mut self) {
+        let removed = self
+            .runtime
+            .command()
+            .args(["network", "rm", &self.name])
+            .output()
+            .map_err(|err| err.into())
+            .and_then(output_to_result);
+        if let Err(err) = removed {
+            log::warn!("Failed to remove network {}: {err}", self.name);
+        }
+    }
+}
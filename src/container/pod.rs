@@ -0,0 +1,252 @@
+use super::{output_to_result, ContainerSystem, ContainerSystemConfig, ContainerSystemTerminal};
+use super::{wait_all, ContainerRuntime, Port, RemoteEngine, HARNESS_LABEL};
+use crate::{Error, ErrorKind, ExitStatus, Status, SystemHarness};
+use cmdstruct::Arg;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::time::Duration;
+
+fn default_tool() -> ContainerRuntime {
+    ContainerRuntime::new("podman")
+}
+
+/// A config for a Podman pod of containers sharing a network namespace
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PodSystemConfig {
+    /// Pod runtime (defaults to `podman`)
+    #[serde(default = "default_tool")]
+    tool: ContainerRuntime,
+
+    /// Engine on another machine to run the pod on
+    remote: Option<RemoteEngine>,
+
+    /// Pod name
+    name: Option<String>,
+
+    /// Ports published by the pod
+    ports: Option<Vec<Port>>,
+
+    /// Member containers, started in order
+    ///
+    /// Each member is run with the pod's runtime.
+    containers: Vec<ContainerSystemConfig>,
+}
+
+impl PodSystemConfig {
+    /// Runtime targeting the configured engine
+    fn runtime(&self) -> Result<ContainerRuntime, Error> {
+        match &self.remote {
+            Some(remote) => self.tool.clone().remote(remote.clone()),
+            None => Ok(self.tool.clone()),
+        }
+    }
+
+    /// Command creating the pod
+    fn create_command(&self, runtime: &ContainerRuntime) -> Command {
+        let mut command = runtime.command();
+        command.args(["pod", "create"]);
+        self.name.append_option("--name", &mut command);
+        command
+            .arg("--label")
+            .arg(format!("{HARNESS_LABEL}={}", std::process::id()));
+        self.ports.append_option("-p", &mut command);
+        command
+    }
+
+    /// Create the pod and run its containers
+    pub fn build(&self) -> Result<PodSystem, Error> {
+        let runtime = self.runtime()?;
+        let id = self
+            .create_command(&runtime)
+            .output()
+            .map_err(|err| err.into())
+            .and_then(output_to_result)?;
+        log::trace!("Created pod: {id}");
+        let mut pod = PodSystem {
+            runtime: runtime.clone(),
+            id,
+            containers: Vec::new(),
+        };
+        for container in &self.containers {
+            let container = ContainerSystemConfig {
+                tool: Some(runtime.clone()),
+                remote: None,
+                namespace: None,
+                ..container.clone()
+            };
+            pod.containers.push(container.build_in(Some(&pod.id))?);
+        }
+        Ok(pod)
+    }
+}
+
+/// State reported by `pod inspect`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodInspect {
+    state: String,
+}
+
+/// Podman 4 reports a single object while Podman 5 reports a list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PodInspectOutput {
+    One(PodInspect),
+    Many(Vec<PodInspect>),
+}
+
+/// A running Podman pod
+///
+/// The pod's lifecycle is managed as a whole while each member container
+/// remains available for terminals and exec.
+pub struct PodSystem {
+    runtime: ContainerRuntime,
+    id: String,
+    containers: Vec<ContainerSystem>,
+}
+
+impl PodSystem {
+    /// Member containers in the order they were configured
+    pub fn containers(&self) -> &[ContainerSystem] {
+        &self.containers
+    }
+
+    /// Member container at the given index
+    pub fn container(&self, index: usize) -> Option<&ContainerSystem> {
+        self.containers.get(index)
+    }
+
+    /// Run a pod subcommand
+    fn pod(&self, action: &str) -> Result<String, Error> {
+        self.runtime
+            .command()
+            .args(["pod", action])
+            .arg(&self.id)
+            .output()
+            .map_err(|err| err.into())
+            .and_then(output_to_result)
+    }
+}
+
+impl SystemHarness for PodSystem {
+    type Terminal = ContainerSystemTerminal;
+
+    /// Get a terminal for the first member container
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        self.containers
+            .first()
+            .ok_or(Error::new(ErrorKind::HarnessError, "Pod has no containers"))?
+            .terminal()
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.pod("pause").map(|_| ())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.pod("unpause").map(|_| ())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.pod("stop").map(|_| ())
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        let output = self.pod("inspect")?;
+        let inspect = match serde_json::from_str(&output)? {
+            PodInspectOutput::One(inspect) => inspect,
+            PodInspectOutput::Many(inspect) => inspect
+                .into_iter()
+                .next()
+                .ok_or(Error::new(ErrorKind::HarnessError, "Pod doesn't exist"))?,
+        };
+        parse_state(&inspect.state)
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        self.status().map(|status| status == Status::Running)
+    }
+
+    /// Wait for every member container to exit
+    ///
+    /// Reports the first member that failed, otherwise the last member.
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        wait_all(self.containers.iter_mut(), timeout)
+    }
+}
+
+/// Map a pod state to a status
+fn parse_state(state: &str) -> Result<Status, Error> {
+    match state {
+        "Running" | "Degraded" => Ok(Status::Running),
+        "Paused" => Ok(Status::Paused),
+        "Created" | "Stopped" | "Exited" | "Dead" => Ok(Status::Shutdown),
+        _ => Err(Error::new(
+            ErrorKind::HarnessError,
+            format!("Unhandled pod state: {state}"),
+        )),
+    }
+}
+
+impl Drop for PodSystem {
+    fn drop(&mut self) {
+        log::trace!("Deleting pod: {}", &self.id);
+        if let Err(err) = self.pod("stop").and_then(|_| {
+            self.runtime
+                .command()
+                .args(["pod", "rm", "-f", &self.id])
+                .output()
+                .map_err(|err| err.into())
+                .and_then(output_to_result)
+        }) {
+            log::warn!("Failed to delete pod {}: {err}", &self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn create_pod() {
+        let config: PodSystemConfig = serde_json::from_str(
+            r#"{
+                "name": "sut",
+                "ports": [{ "container-port": 8080 }],
+                "containers": [
+                    { "image": "postgres" },
+                    { "image": "service" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "pod".to_string(),
+                "create".to_string(),
+                "--name".to_string(),
+                "sut".to_string(),
+                "--label".to_string(),
+                format!("system-harness={}", std::process::id()),
+                "-p".to_string(),
+                "8080/tcp".to_string(),
+            ],
+            config
+                .create_command(&config.tool)
+                .get_args()
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn pod_state() {
+        let inspect: PodInspectOutput = serde_json::from_str(r#"[{"State":"Degraded"}]"#).unwrap();
+        let PodInspectOutput::Many(inspect) = inspect else {
+            panic!("Expected a list")
+        };
+        assert_eq!(Status::Running, parse_state(&inspect[0].state).unwrap());
+    }
+}
@@ -0,0 +1,390 @@
+use crate::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Runtimes searched for by [`ContainerRuntime::detect`], in order of
+/// preference
+const CANDIDATES: [&str; 3] = ["podman", "docker", "nerdctl"];
+
+/// Family of a container runtime's command-line interface
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RuntimeKind {
+    Docker,
+    Podman,
+    Nerdctl,
+
+    /// A Docker-compatible CLI that isn't otherwise recognized
+    Other,
+}
+
+/// A container engine on another machine
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum RemoteEngine {
+    /// Engine URL, such as `ssh://user@lab-host` or `tcp://lab-host:2376`
+    ///
+    /// Docker takes it like `DOCKER_HOST`, Podman as a remote `--url` and
+    /// nerdctl as the containerd address.
+    Host(String),
+
+    /// A named Podman system connection or Docker context
+    Connection(String),
+}
+
+/// A container runtime command-line tool
+///
+/// Deserialized from the name or path of the tool. Most commands are shared
+/// by all runtimes; where their output differs, it is parsed according to
+/// the runtime's [`RuntimeKind`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct ContainerRuntime {
+    program: String,
+    kind: RuntimeKind,
+    remote: Option<RemoteEngine>,
+    namespace: Option<String>,
+}
+
+impl ContainerRuntime {
+    /// Runtime invoked as the given program
+    pub fn new(program: impl Into<String>) -> Self {
+        let program = program.into();
+        let kind = match Path::new(&program)
+            .file_name()
+            .and_then(|name| name.to_str())
+        {
+            Some("docker") => RuntimeKind::Docker,
+            Some("podman") => RuntimeKind::Podman,
+            Some("nerdctl") => RuntimeKind::Nerdctl,
+            _ => RuntimeKind::Other,
+        };
+        Self {
+            program,
+            kind,
+            remote: None,
+            namespace: None,
+        }
+    }
+
+    /// Target a remote engine instead of the local one
+    ///
+    /// nerdctl has no named connections.
+    pub fn remote(mut self, remote: RemoteEngine) -> Result<Self, Error> {
+        if self.kind == RuntimeKind::Nerdctl && matches!(remote, RemoteEngine::Connection(_)) {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "nerdctl doesn't support named connections",
+            ));
+        }
+        self.remote = Some(remote);
+        Ok(self)
+    }
+
+    /// Find the first of podman, docker and nerdctl on the `PATH`
+    pub fn detect() -> Result<Self, Error> {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        CANDIDATES
+            .iter()
+            .find(|candidate| std::env::split_paths(&path).any(|dir| dir.join(candidate).is_file()))
+            .map(|candidate| Self::new(*candidate))
+            .ok_or(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "No container runtime found, tried: {}",
+                    CANDIDATES.join(", ")
+                ),
+            ))
+    }
+
+    /// Run everything in a containerd namespace, isolated from the host's
+    /// other workloads
+    ///
+    /// Only nerdctl has namespaces.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Result<Self, Error> {
+        if self.kind != RuntimeKind::Nerdctl {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "Namespaces are only supported by nerdctl",
+            ));
+        }
+        self.namespace = Some(namespace.into());
+        Ok(self)
+    }
+
+    /// Program name or path
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// Family of the runtime
+    pub fn kind(&self) -> RuntimeKind {
+        self.kind
+    }
+
+    /// Whether the engine runs without root privileges
+    pub fn rootless(&self) -> Result<bool, Error> {
+        let format = match self.kind {
+            RuntimeKind::Podman => "{{.Host.Security.Rootless}}",
+            _ => "{{json .SecurityOptions}}",
+        };
+        let output = self
+            .command()
+            .args(["info", "--format", format])
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        let info = std::str::from_utf8(&output.stdout)?;
+        Ok(parse_rootless(self.kind, info))
+    }
+
+    /// Host the remote engine publishes ports on, or `None` for the local
+    /// engine
+    pub(crate) fn remote_host(&self) -> Result<Option<String>, Error> {
+        let url = match &self.remote {
+            None => return Ok(None),
+            Some(RemoteEngine::Host(url)) => url.clone(),
+            Some(RemoteEngine::Connection(name)) => self.connection_url(name)?,
+        };
+        Ok(url_host(&url).map(String::from))
+    }
+
+    /// URL of a named Podman system connection or Docker context
+    fn connection_url(&self, name: &str) -> Result<String, Error> {
+        let mut command = Command::new(&self.program);
+        match self.kind {
+            RuntimeKind::Podman => command.args([
+                "system",
+                "connection",
+                "list",
+                "--format",
+                "{{.Name}} {{.URI}}",
+            ]),
+            _ => command.args([
+                "context",
+                "inspect",
+                "--format",
+                "{{.Endpoints.docker.Host}}",
+                name,
+            ]),
+        };
+        let output = command.stdin(Stdio::null()).output()?;
+        if !output.status.success() {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        let stdout = std::str::from_utf8(&output.stdout)?;
+        let url = match self.kind {
+            RuntimeKind::Podman => stdout
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .find_map(|(connection, url)| (connection == name).then_some(url)),
+            _ => Some(stdout.trim()),
+        };
+        url.filter(|url| !url.is_empty())
+            .map(String::from)
+            .ok_or(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("No engine URL for connection {name}"),
+            ))
+    }
+
+    /// Command invoking the runtime
+    pub(crate) fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        match (&self.remote, self.kind) {
+            (None, _) => {}
+            (Some(RemoteEngine::Host(url)), RuntimeKind::Podman) => {
+                command.args(["--remote", "--url", url]);
+            }
+            (Some(RemoteEngine::Host(url)), RuntimeKind::Nerdctl) => {
+                command.args(["--address", url]);
+            }
+            (Some(RemoteEngine::Host(url)), _) => {
+                command.args(["--host", url]);
+            }
+            (Some(RemoteEngine::Connection(name)), RuntimeKind::Podman) => {
+                command.args(["--connection", name]);
+            }
+            (Some(RemoteEngine::Connection(name)), _) => {
+                command.args(["--context", name]);
+            }
+        }
+        if let Some(namespace) = &self.namespace {
+            command.args(["--namespace", namespace]);
+        }
+        command
+    }
+}
+
+impl From<String> for ContainerRuntime {
+    fn from(program: String) -> Self {
+        Self::new(program)
+    }
+}
+
+impl From<ContainerRuntime> for String {
+    fn from(runtime: ContainerRuntime) -> Self {
+        runtime.program
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for ContainerRuntime {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        String::from("ContainerRuntime")
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// Host part of an engine URL, or `None` for a local socket
+fn url_host(url: &str) -> Option<&str> {
+    let (scheme, rest) = url.split_once("://")?;
+    if matches!(scheme, "unix" | "npipe") {
+        return None;
+    }
+    let authority = rest.split('/').next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(host) => host.split_once(']')?.0,
+        None => host.split_once(':').map_or(host, |(host, _)| host),
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+/// Podman reports a boolean while Docker-compatible runtimes list a
+/// `name=rootless` security option
+fn parse_rootless(kind: RuntimeKind, info: &str) -> bool {
+    match kind {
+        RuntimeKind::Podman => info.trim() == "true",
+        _ => info.contains("name=rootless"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn kind() {
+        assert_eq!(RuntimeKind::Podman, ContainerRuntime::new("podman").kind());
+        assert_eq!(
+            RuntimeKind::Docker,
+            ContainerRuntime::new("/usr/bin/docker").kind()
+        );
+        assert_eq!(RuntimeKind::Other, ContainerRuntime::new("finch").kind());
+    }
+
+    #[test]
+    fn deserialize() {
+        let runtime: ContainerRuntime = serde_json::from_str(r#""nerdctl""#).unwrap();
+        assert_eq!(RuntimeKind::Nerdctl, runtime.kind());
+        assert_eq!(r#""nerdctl""#, serde_json::to_string(&runtime).unwrap());
+    }
+
+    #[test]
+    fn engine_hosts() {
+        assert_eq!(
+            Some("lab-host"),
+            url_host("ssh://user@lab-host:22/run/podman.sock")
+        );
+        assert_eq!(Some("10.0.0.2"), url_host("tcp://10.0.0.2:2376"));
+        assert_eq!(Some("fe80::1"), url_host("ssh://[fe80::1]:22"));
+        assert_eq!(None, url_host("unix:///run/docker.sock"));
+
+        let runtime = ContainerRuntime::new("docker")
+            .remote(RemoteEngine::Host("tcp://lab-host:2376".to_string()))
+            .unwrap();
+        assert_eq!(Some("lab-host".to_string()), runtime.remote_host().unwrap());
+        assert_eq!(None, ContainerRuntime::new("docker").remote_host().unwrap());
+    }
+
+    #[test]
+    fn remote() {
+        let args = |runtime: ContainerRuntime| -> Vec<String> {
+            runtime
+                .command()
+                .get_args()
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect()
+        };
+        let host = RemoteEngine::Host("ssh://lab".to_string());
+        let connection = RemoteEngine::Connection("lab".to_string());
+        assert_eq!(
+            vec!["--host", "ssh://lab"],
+            args(
+                ContainerRuntime::new("docker")
+                    .remote(host.clone())
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            vec!["--remote", "--url", "ssh://lab"],
+            args(ContainerRuntime::new("podman").remote(host).unwrap())
+        );
+        assert_eq!(
+            vec!["--connection", "lab"],
+            args(
+                ContainerRuntime::new("podman")
+                    .remote(connection.clone())
+                    .unwrap()
+            )
+        );
+        assert!(ContainerRuntime::new("nerdctl").remote(connection).is_err());
+    }
+
+    #[test]
+    fn rootless() {
+        assert!(parse_rootless(RuntimeKind::Podman, "true\n"));
+        assert!(!parse_rootless(RuntimeKind::Podman, "false\n"));
+        assert!(parse_rootless(
+            RuntimeKind::Docker,
+            r#"["name=seccomp,profile=builtin","name=rootless"]"#
+        ));
+        assert!(!parse_rootless(
+            RuntimeKind::Docker,
+            r#"["name=seccomp,profile=builtin"]"#
+        ));
+    }
+
+    #[test]
+    fn namespace() {
+        let runtime = ContainerRuntime::new("nerdctl")
+            .remote(RemoteEngine::Host(
+                "unix:///run/containerd.sock".to_string(),
+            ))
+            .unwrap()
+            .namespace("harness")
+            .unwrap();
+        assert_eq!(
+            vec![
+                "--address",
+                "unix:///run/containerd.sock",
+                "--namespace",
+                "harness"
+            ],
+            runtime.command().get_args().collect::<Vec<_>>()
+        );
+        assert!(ContainerRuntime::new("docker")
+            .namespace("harness")
+            .is_err());
+    }
+}
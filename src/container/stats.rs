@@ -0,0 +1,174 @@
+use crate::{Error, ErrorKind};
+use serde::Deserialize;
+
+/// A resource usage sample of a container
+///
+/// Statistics the runtime can't sample, which it shows as `--`, are `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContainerStats {
+    /// CPU usage as a percentage of one CPU
+    pub cpu_percent: Option<f64>,
+
+    /// Memory usage in bytes
+    pub memory_usage: Option<u64>,
+
+    /// Memory limit in bytes
+    pub memory_limit: Option<u64>,
+
+    /// Bytes received over the network
+    pub network_rx: Option<u64>,
+
+    /// Bytes transmitted over the network
+    pub network_tx: Option<u64>,
+
+    /// Bytes read from block devices
+    pub block_read: Option<u64>,
+
+    /// Bytes written to block devices
+    pub block_write: Option<u64>,
+
+    /// Number of processes
+    pub pids: Option<u64>,
+}
+
+/// Human readable statistics reported by the runtime's stats command
+#[derive(Deserialize)]
+struct RawStats {
+    #[serde(rename = "CPUPerc", alias = "cpu_percent")]
+    cpu_percent: String,
+
+    #[serde(rename = "MemUsage", alias = "mem_usage")]
+    memory_usage: String,
+
+    #[serde(rename = "NetIO", alias = "net_io")]
+    network_io: String,
+
+    #[serde(rename = "BlockIO", alias = "block_io")]
+    block_io: String,
+
+    #[serde(rename = "PIDs", alias = "pids")]
+    pids: String,
+}
+
+fn invalid(value: &str) -> Error {
+    Error::new(
+        ErrorKind::SerializationError,
+        format!("Invalid statistic '{value}'"),
+    )
+}
+
+/// Whether the runtime couldn't sample a statistic
+fn is_missing(value: &str) -> bool {
+    value.trim() == "--"
+}
+
+/// Parse a statistic that may be missing
+fn parse_optional<T: std::str::FromStr>(value: &str) -> Result<Option<T>, Error> {
+    if is_missing(value) {
+        return Ok(None);
+    }
+    value.trim().parse().map(Some).map_err(|_| invalid(value))
+}
+
+/// Parse a size such as `1.5MiB` or `12kB` into bytes
+fn parse_size(value: &str) -> Result<Option<u64>, Error> {
+    if is_missing(value) {
+        return Ok(None);
+    }
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid(value))?;
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "kB" | "KB" => 1000,
+        "MB" => 1000_u64.pow(2),
+        "GB" => 1000_u64.pow(3),
+        "TB" => 1000_u64.pow(4),
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        _ => return Err(invalid(value)),
+    };
+    Ok(Some((number * multiplier as f64).round() as u64))
+}
+
+/// Parse a pair of sizes such as `1.2MiB / 7.6GiB`
+fn parse_size_pair(value: &str) -> Result<(Option<u64>, Option<u64>), Error> {
+    if is_missing(value) {
+        return Ok((None, None));
+    }
+    let (first, second) = value.split_once('/').ok_or_else(|| invalid(value))?;
+    Ok((parse_size(first)?, parse_size(second)?))
+}
+
+impl ContainerStats {
+    /// Parse a line of `stats --no-stream --format '{{json .}}'` output
+    pub(crate) fn parse(line: &str) -> Result<Self, Error> {
+        let raw: RawStats = serde_json::from_str(line)?;
+        let cpu_percent = raw.cpu_percent.trim().trim_end_matches('%');
+        let (memory_usage, memory_limit) = parse_size_pair(&raw.memory_usage)?;
+        let (network_rx, network_tx) = parse_size_pair(&raw.network_io)?;
+        let (block_read, block_write) = parse_size_pair(&raw.block_io)?;
+        Ok(Self {
+            cpu_percent: parse_optional(cpu_percent)?,
+            memory_usage,
+            memory_limit,
+            network_rx,
+            network_tx,
+            block_read,
+            block_write,
+            pids: parse_optional(&raw.pids)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn docker_stats() {
+        let stats = ContainerStats::parse(
+            r#"{"BlockIO":"4.1kB / 0B","CPUPerc":"1.50%","Container":"abc","ID":"abc","MemPerc":"0.02%","MemUsage":"1.5MiB / 7.6GiB","Name":"sut","NetIO":"1.2kB / 656B","PIDs":"3"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ContainerStats {
+                cpu_percent: Some(1.5),
+                memory_usage: Some(1_572_864),
+                memory_limit: Some(8_160_437_862),
+                network_rx: Some(1_200),
+                network_tx: Some(656),
+                block_read: Some(4_100),
+                block_write: Some(0),
+                pids: Some(3),
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn missing_stats() {
+        let stats = ContainerStats::parse(
+            r#"{"BlockIO":"-- / --","CPUPerc":"--","MemUsage":"1.5MiB / --","NetIO":"--","PIDs":"--"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ContainerStats {
+                memory_usage: Some(1_572_864),
+                ..Default::default()
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn invalid_size() {
+        assert!(parse_size("12 parsecs").is_err());
+    }
+}
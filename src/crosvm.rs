@@ -0,0 +1,488 @@
+use crate::runtime::RuntimeDir;
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use cmdstruct::Arg;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind as IoErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Default time allowed for crosvm to start
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time allowed for crosvm to exit before it is killed
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Interval between checks while waiting for crosvm
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Files created by a system in its runtime directory
+const RUNTIME_FILES: [&str; 3] = ["crosvm.sock", "serial.sock", "crosvm.stderr"];
+
+fn default_executable() -> String {
+    String::from("crosvm")
+}
+
+/// A disk image attached to a crosvm guest (`--block`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct CrosvmDisk {
+    /// Path of the disk image
+    path: String,
+
+    /// Attach the disk read-only
+    #[serde(default)]
+    read_only: bool,
+
+    /// Use the disk as the root filesystem
+    #[serde(default)]
+    root: bool,
+}
+
+impl CrosvmDisk {
+    /// Attach a writable disk image
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            read_only: false,
+            root: false,
+        }
+    }
+
+    /// Attach the disk read-only
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Use the disk as the root filesystem
+    pub fn root(mut self) -> Self {
+        self.root = true;
+        self
+    }
+}
+
+impl Arg for CrosvmDisk {
+    fn append_arg(&self, command: &mut Command) {
+        let mut disk = format!("path={}", self.path);
+        if self.read_only {
+            disk.push_str(",ro=true");
+        }
+        if self.root {
+            disk.push_str(",root=true");
+        }
+        command.arg(disk);
+    }
+}
+
+/// A configuration for running a crosvm guest
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CrosvmSystemConfig {
+    /// crosvm executable (defaults to `crosvm`)
+    #[serde(default = "default_executable")]
+    executable: String,
+
+    /// Guest kernel image
+    kernel: String,
+
+    /// Initial ramdisk
+    initrd: Option<String>,
+
+    /// Kernel command line parameters
+    params: Option<Vec<String>>,
+
+    /// Number of virtual CPUs
+    cpus: Option<usize>,
+
+    /// Guest memory in MiB
+    memory: Option<usize>,
+
+    /// Disk images
+    disks: Option<Vec<CrosvmDisk>>,
+
+    /// Host TAP interfaces connected to the guest
+    taps: Option<Vec<String>>,
+
+    /// Directory for the control and serial sockets
+    ///
+    /// Defaults to a temporary directory unique to each system.
+    runtime_dir: Option<PathBuf>,
+
+    /// Seconds to wait for crosvm to start (defaults to 30)
+    startup_timeout: Option<u64>,
+
+    /// Seconds to wait for crosvm to exit when dropped before killing it
+    /// (defaults to 10)
+    grace_period: Option<u64>,
+
+    /// Extra crosvm run args
+    extra_args: Option<Vec<String>>,
+}
+
+impl CrosvmSystemConfig {
+    /// Command running the guest with the given control and serial sockets
+    fn run_command(&self, control: &Path, serial: &Path) -> Command {
+        let mut command = Command::new(&self.executable);
+        command.arg("run");
+        command.arg("--socket").arg(control);
+        command.arg("--serial").arg(format!(
+            "type=unix-stream,path={},num=1,console=true,input-unix-stream=true",
+            serial.display()
+        ));
+        self.cpus.append_option("--cpus", &mut command);
+        self.memory.append_option("--mem", &mut command);
+        self.initrd.append_option("--initrd", &mut command);
+        self.params.append_option("--params", &mut command);
+        self.disks.append_option("--block", &mut command);
+        for tap in self.taps.iter().flatten() {
+            command.arg("--net").arg(format!("tap-name={tap}"));
+        }
+        self.extra_args.append_arg(&mut command);
+        command.arg(&self.kernel);
+        command
+    }
+
+    /// Start the guest
+    pub fn build(&self) -> Result<CrosvmSystem, Error> {
+        let runtime_dir = RuntimeDir::new(self.runtime_dir.as_deref(), &RUNTIME_FILES)?;
+        let control = runtime_dir.file("crosvm.sock");
+        let serial_path = runtime_dir.file("serial.sock");
+        let stderr_path = runtime_dir.file("crosvm.stderr");
+
+        // crosvm connects to the serial socket, so it must be listening first
+        let _ = std::fs::remove_file(&serial_path);
+        let listener = UnixListener::bind(&serial_path)?;
+        listener.set_nonblocking(true)?;
+
+        log::trace!("Starting crosvm...");
+        let mut process = self
+            .run_command(&control, &serial_path)
+            .stdin(Stdio::null())
+            .stderr(std::fs::File::create(&stderr_path)?)
+            .spawn()?;
+
+        let timeout = self
+            .startup_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+        let deadline = Instant::now() + timeout;
+        let serial = match wait_started(&mut process, &listener, &control, &stderr_path, deadline) {
+            Ok(serial) => serial,
+            Err(err) => {
+                let _ = process.kill();
+                let _ = process.wait();
+                return Err(err);
+            }
+        };
+        log::trace!("crosvm started.");
+
+        Ok(CrosvmSystem {
+            executable: self.executable.clone(),
+            process,
+            serial,
+            control,
+            paused: false,
+            grace_period: self
+                .grace_period
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_GRACE_PERIOD),
+            runtime_dir,
+        })
+    }
+}
+
+/// Wait for a started crosvm to connect to the serial socket and create
+/// its control socket
+fn wait_started(
+    process: &mut Child,
+    listener: &UnixListener,
+    control: &Path,
+    stderr_path: &Path,
+    deadline: Instant,
+) -> Result<UnixStream, Error> {
+    let startup_error = |message: &str| {
+        let stderr = std::fs::read_to_string(stderr_path).unwrap_or_default();
+        format!("{message}: {}", stderr.trim())
+    };
+    let serial = loop {
+        if let Some(status) = process.try_wait()? {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                startup_error(&format!("crosvm exited during startup ({status})")),
+            ));
+        }
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(err) if err.kind() == IoErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(Error::new(
+                        ErrorKind::Timeout,
+                        startup_error("Timed out starting crosvm"),
+                    ));
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    };
+    serial.set_nonblocking(false)?;
+    while !control.exists() {
+        if Instant::now() >= deadline {
+            return Err(Error::new(
+                ErrorKind::Timeout,
+                startup_error("Timed out waiting for control socket"),
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(serial)
+}
+
+/// A running crosvm guest
+pub struct CrosvmSystem {
+    executable: String,
+    process: Child,
+    serial: UnixStream,
+    control: PathBuf,
+    paused: bool,
+    grace_period: Duration,
+    runtime_dir: RuntimeDir,
+}
+
+impl CrosvmSystem {
+    /// Directory holding the guest's sockets and crosvm's stderr log
+    pub fn runtime_dir(&self) -> &Path {
+        self.runtime_dir.path()
+    }
+
+    /// Send a command over the control socket
+    fn control(&self, action: &str) -> Result<(), Error> {
+        log::trace!("Sending {action} to crosvm");
+        let output = Command::new(&self.executable)
+            .arg(action)
+            .arg(&self.control)
+            .stdin(Stdio::null())
+            .output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "crosvm {action} failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))
+        }
+    }
+
+    /// Wait for crosvm to exit, returning its exit code
+    fn wait_exit(&mut self, deadline: Instant) -> Result<Option<i32>, Error> {
+        loop {
+            if let Some(status) = self.process.try_wait()? {
+                return Ok(status.code());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for crosvm to exit",
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// A terminal on the guest's serial console
+pub struct CrosvmSystemTerminal {
+    serial: UnixStream,
+}
+
+impl Read for CrosvmSystemTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.serial.read(buf)
+    }
+}
+
+impl Write for CrosvmSystemTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.serial.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.serial.flush()
+    }
+}
+
+/// Descriptor the terminal's output is read from
+impl AsRawFd for CrosvmSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.serial.as_raw_fd()
+    }
+}
+
+impl SystemTerminal for CrosvmSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\r")?,
+        }
+        self.flush().map_err(|err| err.into())
+    }
+}
+
+impl SystemHarness for CrosvmSystem {
+    type Terminal = CrosvmSystemTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        Ok(CrosvmSystemTerminal {
+            serial: self.serial.try_clone()?,
+        })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.control("suspend")?;
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.control("resume")?;
+        self.paused = false;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.control("stop")
+    }
+
+    /// crosvm has no status query, so the state is tracked by the harness
+    fn status(&mut self) -> Result<Status, Error> {
+        if self.process.try_wait()?.is_some() {
+            Ok(Status::Shutdown)
+        } else if self.paused {
+            Ok(Status::Paused)
+        } else {
+            Ok(Status::Running)
+        }
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.process.try_wait()?.is_none())
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let code = self.wait_exit(Instant::now() + timeout)?;
+        Ok(ExitStatus { code, reason: None })
+    }
+}
+
+impl Drop for CrosvmSystem {
+    fn drop(&mut self) {
+        if let Ok(true) = self.running() {
+            log::trace!("Stopping crosvm...");
+            if let Err(err) = self.control("stop") {
+                log::warn!("Error stopping crosvm: {err}");
+            }
+            if self.wait_exit(Instant::now() + self.grace_period).is_err() {
+                log::warn!(
+                    "crosvm did not exit within {:?}, killing...",
+                    self.grace_period
+                );
+                let _ = self.process.kill();
+                let _ = self.process.wait();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn run_command() {
+        let config: CrosvmSystemConfig = serde_json::from_str(
+            r#"{
+                "kernel": "bzImage",
+                "params": ["console=ttyS0"],
+                "cpus": 2,
+                "memory": 1024,
+                "disks": [{ "path": "rootfs.img", "read-only": true, "root": true }],
+                "taps": ["tap0"]
+            }"#,
+        )
+        .unwrap();
+        let command =
+            config.run_command(Path::new("/run/crosvm.sock"), Path::new("/run/serial.sock"));
+        assert_eq!("crosvm", command.get_program());
+        assert_eq!(
+            vec![
+                "run",
+                "--socket",
+                "/run/crosvm.sock",
+                "--serial",
+                "type=unix-stream,path=/run/serial.sock,num=1,console=true,input-unix-stream=true",
+                "--cpus",
+                "2",
+                "--mem",
+                "1024",
+                "--params",
+                "console=ttyS0",
+                "--block",
+                "path=rootfs.img,ro=true,root=true",
+                "--net",
+                "tap-name=tap0",
+                "bzImage"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn early_exit_reports_stderr() {
+        let config: CrosvmSystemConfig = serde_json::from_str(
+            r#"{ "executable": "false", "kernel": "bzImage", "startup_timeout": 5 }"#,
+        )
+        .unwrap();
+        let err = config.build().err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn startup_timeout_kills_crosvm() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("crosvm-timeout-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pid_file = dir.join("pid");
+        let executable = dir.join("crosvm");
+        std::fs::write(
+            &executable,
+            format!(
+                "#!/bin/sh\necho $$ > {}\nexec sleep 30\n",
+                pid_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let config: CrosvmSystemConfig = serde_json::from_value(serde_json::json!({
+            "executable": executable,
+            "kernel": "bzImage",
+            "startup_timeout": 1
+        }))
+        .unwrap();
+        let err = config.build().err().unwrap();
+        assert_eq!(ErrorKind::Timeout, err.kind());
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        assert!(!Path::new(&format!("/proc/{}", pid.trim())).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde_path_to_error::Segment;
+use std::collections::BTreeMap;
+
+/// Severity of a problem found in a config
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// The config can't be used as is
+    Error,
+
+    /// The config can be used, but probably doesn't do what was intended
+    Warning,
+}
+
+/// A problem found in a config
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// JSON pointer to the offending value (e.g. `/blockdev/1/node-name`),
+    /// empty for the config as a whole
+    pub pointer: String,
+
+    /// Severity of the problem
+    pub severity: Severity,
+
+    /// Description of the problem
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Create an error diagnostic
+    pub fn error(pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            pointer: pointer.into(),
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    /// Create a warning diagnostic
+    pub fn warning(pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            pointer: pointer.into(),
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.pointer.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.pointer, self.message)
+        }
+    }
+}
+
+/// Escape a key for use in a JSON pointer
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Deserialize a config, reporting every field that fails rather than
+/// only the first
+///
+/// After each error, the offending top-level field or list item is set
+/// aside and deserialization is retried, so one diagnostic is reported
+/// for each of them.
+pub fn check_config<T: DeserializeOwned>(config: &Value) -> Result<T, Vec<Diagnostic>> {
+    let mut working = config.clone();
+    let mut diagnostics = Vec::new();
+    // Keys set aside, and the original index of each remaining list item
+    let mut removed = Vec::new();
+    let mut indices: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    loop {
+        let err = match serde_path_to_error::deserialize(working.clone()) {
+            Ok(config) if diagnostics.is_empty() => return Ok(config),
+            Ok(_) => return Err(diagnostics),
+            Err(err) => err,
+        };
+        let message = err.inner().to_string();
+        let segments: Vec<&Segment> = err.path().iter().collect();
+        if segments.is_empty()
+            && removed
+                .iter()
+                .any(|key| message == format!("missing field `{key}`"))
+        {
+            // Only missing because it was set aside
+            return Err(diagnostics);
+        }
+
+        let mut pointer = String::new();
+        for (position, segment) in segments.iter().enumerate() {
+            match segment {
+                Segment::Seq { index } => {
+                    let index = match (position, segments.first()) {
+                        (1, Some(Segment::Map { key })) => indices
+                            .get(key)
+                            .map(|indices| indices[*index])
+                            .unwrap_or(*index),
+                        _ => *index,
+                    };
+                    pointer.push_str(&format!("/{index}"));
+                }
+                Segment::Map { key } => pointer.push_str(&format!("/{}", escape(key))),
+                Segment::Enum { variant } => pointer.push_str(&format!("/{}", escape(variant))),
+                Segment::Unknown => break,
+            }
+        }
+        diagnostics.push(Diagnostic::error(pointer, message));
+
+        let Some(Segment::Map { key }) = segments.first() else {
+            return Err(diagnostics);
+        };
+        let Some(object) = working.as_object_mut() else {
+            return Err(diagnostics);
+        };
+        match (object.get_mut(key.as_str()), segments.get(1)) {
+            (Some(Value::Array(items)), Some(Segment::Seq { index })) if *index < items.len() => {
+                items.remove(*index);
+                indices
+                    .entry(key.clone())
+                    .or_insert_with(|| (0..=items.len()).collect())
+                    .remove(*index);
+            }
+            (Some(_), _) => {
+                object.remove(key.as_str());
+                removed.push(key.clone());
+            }
+            (None, _) => return Err(diagnostics),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Disk {
+        path: String,
+        size: Option<u64>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Config {
+        name: String,
+        memory: Option<u64>,
+        disks: Option<Vec<Disk>>,
+    }
+
+    #[test]
+    fn all_errors() {
+        let config: Value = serde_json::from_str(
+            r#"{
+                "name": 1,
+                "memory": "lots",
+                "disks": [
+                    { "path": "a" },
+                    { "size": 1 },
+                    { "path": "c" },
+                    { "path": "d", "size": -1 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let diagnostics = check_config::<Config>(&config).err().unwrap();
+        assert_eq!(
+            vec![
+                "/disks/1: missing field `path`",
+                "/disks/3/size: invalid value: integer `-1`, expected u64",
+                "/memory: invalid type: string \"lots\", expected u64",
+                "/name: invalid type: integer `1`, expected a string",
+            ],
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity == Severity::Error));
+    }
+
+    #[test]
+    fn valid() {
+        let config = serde_json::json!({ "name": "test", "disks": [{ "path": "a" }] });
+        let config = check_config::<Config>(&config).unwrap();
+        assert_eq!(1, config.disks.unwrap().len());
+    }
+
+    #[test]
+    fn missing_field() {
+        let config = serde_json::json!({ "memory": 512 });
+        let diagnostics = check_config::<Config>(&config).err().unwrap();
+        assert_eq!(
+            vec![Diagnostic::error("", "missing field `name`")],
+            diagnostics
+        );
+    }
+}
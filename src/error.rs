@@ -3,6 +3,9 @@ use std::error;
 
 /// Type of error
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum ErrorKind {
     /// System is already running
     AlreadyRunning,
@@ -17,7 +20,14 @@ pub enum ErrorKind {
     SerializationError,
 
     /// General I/O errors
+    #[cfg_attr(feature = "serde", serde(rename = "io"))]
     IO,
+
+    /// Operation did not complete in time
+    Timeout,
+
+    /// System configuration is invalid
+    InvalidConfig,
 }
 
 /// System harness error
@@ -30,7 +40,7 @@ pub struct Error {
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.error.to_string())
+        write!(f, "{}", self.error)
     }
 }
 
@@ -59,6 +69,7 @@ impl From<std::ffi::NulError> for Error {
     }
 }
 
+#[cfg(feature = "serde_json")]
 impl From<serde_json::Error> for Error {
     fn from(error: serde_json::Error) -> Self {
         Self::new(ErrorKind::IO, error)
@@ -82,7 +93,7 @@ impl serde::ser::Error for Error {
     {
         Self {
             kind: ErrorKind::SerializationError,
-            error: std::io::Error::new(std::io::ErrorKind::Other, format!("{msg}")).into(),
+            error: std::io::Error::other(format!("{msg}")).into(),
         }
     }
 }
@@ -0,0 +1,120 @@
+use crate::{Error, ErrorKind};
+use serde::de::DeserializeOwned;
+
+/// Deserialize a config from YAML
+#[cfg(feature = "yaml")]
+pub(crate) fn from_yaml<T: DeserializeOwned>(yaml: &str) -> Result<T, Error> {
+    serde_yaml::from_str(yaml).map_err(|err| Error::new(ErrorKind::SerializationError, err))
+}
+
+/// Deserialize a config from TOML
+#[cfg(feature = "toml")]
+pub(crate) fn from_toml<T: DeserializeOwned>(toml: &str) -> Result<T, Error> {
+    toml::from_str(toml).map_err(|err| Error::new(ErrorKind::SerializationError, err))
+}
+
+/// Add `from_yaml` and `from_toml` constructors to a config
+macro_rules! config_formats {
+    ($config:ty) => {
+        impl $config {
+            /// Load a config from YAML
+            #[cfg(feature = "yaml")]
+            pub fn from_yaml(yaml: &str) -> Result<Self, Error> {
+                from_yaml(yaml)
+            }
+
+            /// Load a config from TOML
+            #[cfg(feature = "toml")]
+            pub fn from_toml(toml: &str) -> Result<Self, Error> {
+                from_toml(toml)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "qemu")]
+config_formats!(crate::QemuSystemConfig);
+
+#[cfg(all(unix, feature = "container"))]
+config_formats!(crate::ContainerSystemConfig);
+
+config_formats!(crate::SystemConfig);
+
+config_formats!(crate::ScenarioConfig);
+
+#[cfg(all(test, feature = "qemu"))]
+mod tests {
+
+    use super::*;
+    use crate::{QemuSystemConfig, SystemConfig};
+    use cmdstruct::Command;
+
+    const JSON_CONFIG: &str = include_str!("../tests/data/qemu-config.json");
+
+    fn args(config: &QemuSystemConfig) -> Vec<String> {
+        config
+            .command()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml() {
+        let config = QemuSystemConfig::from_yaml(
+            r#"
+arch: i386
+memory: 512
+machine:
+  type: q35
+device:
+  - driver: virtio-blk
+    drive: f1
+blockdev:
+  - driver: file
+    node-name: f1
+    filename: tests/data/test.raw
+"#,
+        )
+        .unwrap();
+        let expected: QemuSystemConfig = serde_json::from_str(JSON_CONFIG).unwrap();
+        assert_eq!(args(&expected), args(&config));
+
+        let config = SystemConfig::from_yaml("type: qemu\narch: i386\n").unwrap();
+        assert!(matches!(config, SystemConfig::Qemu(_)));
+
+        let err = QemuSystemConfig::from_yaml("arch: [").err().unwrap();
+        assert_eq!(ErrorKind::SerializationError, err.kind());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml() {
+        let config = QemuSystemConfig::from_toml(
+            r#"
+arch = "i386"
+memory = 512
+machine = { type = "q35" }
+
+[[device]]
+driver = "virtio-blk"
+drive = "f1"
+
+[[blockdev]]
+driver = "file"
+node-name = "f1"
+filename = "tests/data/test.raw"
+"#,
+        )
+        .unwrap();
+        let expected: QemuSystemConfig = serde_json::from_str(JSON_CONFIG).unwrap();
+        assert_eq!(args(&expected), args(&config));
+
+        let config = SystemConfig::from_toml("type = \"qemu\"\narch = \"i386\"\n").unwrap();
+        assert!(matches!(config, SystemConfig::Qemu(_)));
+
+        let err = QemuSystemConfig::from_toml("arch = ").err().unwrap();
+        assert_eq!(ErrorKind::SerializationError, err.kind());
+    }
+}
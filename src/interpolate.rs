@@ -0,0 +1,167 @@
+use crate::{Error, ErrorKind};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+
+/// Variables substituted into config text before it is deserialized
+///
+/// `${NAME}` is replaced by the variable's value and `${NAME:-default}`
+/// falls back to `default` when the variable is unset. `$$` is a literal
+/// `$`, and any other `$` is left as is. Substitution happens on the text,
+/// so placeholders can also stand in for numbers, e.g. `"memory":
+/// ${MEMORY:-512}`.
+///
+/// Variables set explicitly take precedence over environment variables.
+#[derive(Clone, Debug)]
+pub struct ConfigVars {
+    vars: BTreeMap<String, String>,
+    env: bool,
+}
+
+impl Default for ConfigVars {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigVars {
+    /// Create variables backed by the environment
+    pub fn new() -> Self {
+        Self {
+            vars: BTreeMap::new(),
+            env: true,
+        }
+    }
+
+    /// Create variables without the environment
+    pub fn empty() -> Self {
+        Self {
+            vars: BTreeMap::new(),
+            env: false,
+        }
+    }
+
+    /// Set a variable
+    pub fn set(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Value of a variable
+    fn get(&self, name: &str) -> Option<String> {
+        self.vars
+            .get(name)
+            .cloned()
+            .or_else(|| self.env.then(|| std::env::var(name).ok()).flatten())
+    }
+
+    /// Substitute the variables into text
+    pub fn interpolate(&self, text: &str) -> Result<String, Error> {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('$') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("$$") {
+                result.push('$');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("${") {
+                let end = after.find('}').ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidConfig, "Unterminated variable in config")
+                })?;
+                let (name, default) = match after[..end].split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (&after[..end], None),
+                };
+                let value = self
+                    .get(name)
+                    .or_else(|| default.map(String::from))
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidConfig,
+                            format!("Undefined variable in config: {name}"),
+                        )
+                    })?;
+                result.push_str(&value);
+                rest = &after[end + 1..];
+            } else {
+                result.push('$');
+                rest = &rest[1..];
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    /// Load a config from JSON with the variables substituted
+    pub fn parse_json<T: DeserializeOwned>(&self, json: &str) -> Result<T, Error> {
+        serde_json::from_str(&self.interpolate(json)?)
+            .map_err(|err| Error::new(ErrorKind::SerializationError, err))
+    }
+
+    /// Load a config from YAML with the variables substituted
+    #[cfg(feature = "yaml")]
+    pub fn parse_yaml<T: DeserializeOwned>(&self, yaml: &str) -> Result<T, Error> {
+        crate::format::from_yaml(&self.interpolate(yaml)?)
+    }
+
+    /// Load a config from TOML with the variables substituted
+    #[cfg(feature = "toml")]
+    pub fn parse_toml<T: DeserializeOwned>(&self, toml: &str) -> Result<T, Error> {
+        crate::format::from_toml(&self.interpolate(toml)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn interpolate() {
+        let vars = ConfigVars::empty()
+            .set("IMAGE", "disk.qcow2")
+            .set("EMPTY", "");
+        assert_eq!(
+            "-hda disk.qcow2 -m 512 $HOME $ ${IMAGE}",
+            vars.interpolate("-hda ${IMAGE} -m ${MEMORY:-512} $HOME $$ $${IMAGE}")
+                .unwrap()
+        );
+        assert_eq!("[]", vars.interpolate("[${EMPTY:-default}]").unwrap());
+    }
+
+    #[test]
+    fn invalid() {
+        let vars = ConfigVars::empty();
+        for text in ["${UNDEFINED}", "${UNTERMINATED"] {
+            let err = vars.interpolate(text).err().unwrap();
+            assert_eq!(ErrorKind::InvalidConfig, err.kind());
+        }
+    }
+
+    #[test]
+    fn environment() {
+        let vars = ConfigVars::new().set("CARGO_PKG_NAME", "overridden");
+        assert_eq!(
+            format!("{} overridden", std::env::var("PATH").unwrap()),
+            vars.interpolate("${PATH} ${CARGO_PKG_NAME}").unwrap()
+        );
+        assert!(ConfigVars::empty().interpolate("${PATH}").is_err());
+    }
+
+    #[cfg(feature = "qemu")]
+    #[test]
+    fn parse_json() {
+        use crate::QemuSystemConfig;
+        use cmdstruct::Command;
+
+        let config: QemuSystemConfig = ConfigVars::empty()
+            .set("MEMORY", "2048")
+            .parse_json(r#"{ "arch": "${ARCH:-x86_64}", "memory": ${MEMORY} }"#)
+            .unwrap();
+        assert_eq!("qemu-system-x86_64", config.command().get_program());
+        assert_eq!(
+            vec!["-m", "2048"],
+            config.command().get_args().collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,9 +1,9 @@
 #![doc = include_str!("../README.md")]
 //!
 //! # QEMU
-//! 
-//! A [`QemuSystem`](`crate::QemuSystem`) that implements 
-//! [`SystemHarness`](`crate::SystemHarness`) can be instantiated using a 
+//!
+//! A [`QemuSystem`](`crate::QemuSystem`) that implements
+//! [`SystemHarness`](`crate::SystemHarness`) can be instantiated using a
 //! [`QemuSystemConfig`](`crate::QemuSystemConfig`) that can be deserialized
 //! using serde.
 //!
@@ -16,8 +16,8 @@
 //!```
 //! # Containers
 //!
-//! A [`ContainerSystem`](`crate::ContainerSystem`) that implements 
-//! [`SystemHarness`](`crate::SystemHarness`) can be instantiated using a 
+//! A [`ContainerSystem`](`crate::ContainerSystem`) that implements
+//! [`SystemHarness`](`crate::SystemHarness`) can be instantiated using a
 //! [`ContainerSystemConfig`](`crate::ContainerSystemConfig`) that can be deserialized
 //! using serde.
 //!
@@ -25,8 +25,45 @@
 //!```json
 #![doc = include_str!("../tests/data/container-config.json")]
 //!```
+//! # Any backend
+//!
+//! A [`SystemConfig`](`crate::SystemConfig`) selects its backend with a
+//! `type` tag next to the backend's own fields (e.g. `"type": "qemu"`), and
+//! builds a boxed [`DynSystemHarness`](`crate::DynSystemHarness`).
+//!
+//! With the `schema` feature, the config types implement schemars'
+//! `JsonSchema`, so a JSON Schema that editors can validate and complete
+//! config files with is generated by `schemars::schema_for!(SystemConfig)`.
+//!
+//! # Scenarios
+//!
+//! A [`ScenarioConfig`](`crate::ScenarioConfig`) builds several named
+//! systems, possibly of different backends, in dependency order, e.g. a
+//! server and the clients talking to it. The resulting
+//! [`Scenario`](`crate::Scenario`) exposes them by name and tears them all
+//! down when dropped.
+//!
+//! # Tracing
+//!
+//! With the `tracing` feature, harness activity is wrapped in `tracing`
+//! spans carrying the system's id: a `build` span for each QEMU or
+//! container system built, a `qmp` span for each QMP command and a
+//! `container` span for each container runtime invocation. Log records
+//! are still emitted through `log`, so they show up under these spans
+//! when `log` is bridged to `tracing` (e.g. with `tracing-log`).
+//!
+//! # Metrics
+//!
+//! Commands sent, events received, restarts, boot times and command
+//! latencies are passed to the [`MetricsRecorder`](`crate::MetricsRecorder`)
+//! set with [`set_metrics_recorder`](`crate::set_metrics_recorder`), e.g.
+//! to track harness performance across CI runs. Without a recorder they
+//! are dropped.
+use std::fmt::Display;
 use std::io::{Read, Write};
-use std::time::SystemTime;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 /// System keyboard key
 #[derive(Debug, PartialEq)]
@@ -44,12 +81,16 @@ pub enum Status {
 }
 
 /// Type of event
+///
+/// More kinds of events may be added, so matches need a wildcard arm.
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum EventKind {
     Shutdown,
     Resume,
     Pause,
     Suspend,
+    OutOfMemory,
 }
 
 /// A machine event
@@ -61,6 +102,70 @@ pub struct Event {
     pub timestamp: SystemTime,
 }
 
+/// Output of a command executed in a system
+#[derive(Debug)]
+pub struct ExecOutput {
+    /// Exit code of the command, if it exited normally
+    pub exit_code: Option<i32>,
+
+    /// Captured standard output
+    pub stdout: Vec<u8>,
+
+    /// Captured standard error
+    pub stderr: Vec<u8>,
+}
+
+/// How a system that ran to completion exited
+#[derive(Debug, PartialEq)]
+pub struct ExitStatus {
+    /// Exit code of the container or QEMU process, if known
+    pub code: Option<i32>,
+
+    /// Reason reported by the system, such as QEMU's `guest-shutdown`
+    pub reason: Option<String>,
+}
+
+impl ExitStatus {
+    /// Whether the system exited with code zero
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// An address on the host that reaches a port in a system
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostEndpoint {
+    /// Host name or IP address to connect to
+    pub host: String,
+
+    /// Port on the host
+    pub port: u16,
+}
+
+impl HostEndpoint {
+    /// Endpoint at a host and port
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+        }
+    }
+}
+
+impl Display for HostEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl ToSocketAddrs for HostEndpoint {
+    type Iter = std::vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+        (self.host.as_str(), self.port).to_socket_addrs()
+    }
+}
+
 /// A trait representing event listener
 pub trait EventSubscriber: Send + Sync + 'static {
     /// Action to be performed on event
@@ -69,10 +174,9 @@ pub trait EventSubscriber: Send + Sync + 'static {
 
 /// A trait representing a harnessed system
 pub trait SystemHarness {
-
     type Terminal: SystemTerminal;
 
-    /// Get a terminal for the system 
+    /// Get a terminal for the system
     fn terminal(&self) -> Result<Self::Terminal, Error>;
 
     /// Pause system
@@ -89,22 +193,227 @@ pub trait SystemHarness {
 
     /// Check if harness is running
     fn running(&mut self) -> Result<bool, Error>;
+
+    /// Wait for the system to exit on its own
+    ///
+    /// Fails with a timeout error if the system is still running once the
+    /// timeout elapses. By default, [`running`](Self::running) is polled
+    /// and no exit code or reason is reported.
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let deadline = Instant::now() + timeout;
+        while self.running()? {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for system to exit",
+                ));
+            }
+            std::thread::sleep(remaining.min(Duration::from_millis(50)));
+        }
+        Ok(ExitStatus {
+            code: None,
+            reason: None,
+        })
+    }
+}
+
+/// An object-safe form of [`SystemHarness`]
+///
+/// Implemented for every [`SystemHarness`], so systems of different
+/// backends can be handled as `Box<dyn DynSystemHarness>`, which in turn
+/// implements [`SystemHarness`] with a boxed terminal. Methods are
+/// prefixed to avoid clashing with [`SystemHarness`].
+pub trait DynSystemHarness {
+    /// Get a boxed terminal for the system
+    fn dyn_terminal(&self) -> Result<Box<dyn SystemTerminal>, Error>;
+
+    /// Pause system
+    fn dyn_pause(&mut self) -> Result<(), Error>;
+
+    /// Resume system
+    fn dyn_resume(&mut self) -> Result<(), Error>;
+
+    /// Shutdown system
+    fn dyn_shutdown(&mut self) -> Result<(), Error>;
+
+    /// Get system status
+    fn dyn_status(&mut self) -> Result<Status, Error>;
+
+    /// Check if harness is running
+    fn dyn_running(&mut self) -> Result<bool, Error>;
+
+    /// Wait for the system to exit on its own
+    fn dyn_wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error>;
+}
+
+impl<T> DynSystemHarness for T
+where
+    T: SystemHarness,
+    T::Terminal: 'static,
+{
+    fn dyn_terminal(&self) -> Result<Box<dyn SystemTerminal>, Error> {
+        Ok(Box::new(self.terminal()?))
+    }
+
+    fn dyn_pause(&mut self) -> Result<(), Error> {
+        self.pause()
+    }
+
+    fn dyn_resume(&mut self) -> Result<(), Error> {
+        self.resume()
+    }
+
+    fn dyn_shutdown(&mut self) -> Result<(), Error> {
+        self.shutdown()
+    }
+
+    fn dyn_status(&mut self) -> Result<Status, Error> {
+        self.status()
+    }
+
+    fn dyn_running(&mut self) -> Result<bool, Error> {
+        self.running()
+    }
+
+    fn dyn_wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        self.wait(timeout)
+    }
+}
+
+impl<H> SystemHarness for Box<H>
+where
+    H: DynSystemHarness + ?Sized,
+{
+    type Terminal = Box<dyn SystemTerminal>;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        (**self).dyn_terminal()
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        (**self).dyn_pause()
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        (**self).dyn_resume()
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        (**self).dyn_shutdown()
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        (**self).dyn_status()
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        (**self).dyn_running()
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        (**self).dyn_wait(timeout)
+    }
 }
 
 /// A trait representing a harnessed system that should be
 /// treated as a terminal
 pub trait SystemTerminal: Write + Read {
-
     /// Send key to emulator
     fn send_key(&mut self, key: Key) -> Result<(), Error>;
 
+    /// Set the terminal window size in columns and rows
+    ///
+    /// Not supported by default, as serial consoles and pipes carry no
+    /// window size.
+    fn resize(&mut self, _cols: u16, _rows: u16) -> Result<(), Error> {
+        Err(Error::new(
+            ErrorKind::HarnessError,
+            "Resizing this terminal is not supported",
+        ))
+    }
+
     /// Send a command to the terminal
     fn send_command(&mut self, command: &str) -> Result<(), Error> {
-        self.write(command.as_bytes())?;
+        self.write_all(command.as_bytes())?;
         self.flush()?;
         self.send_key(Key::Enter)
     }
 
+    /// Log in on a console, leaving the terminal at a shell prompt
+    ///
+    /// Enter is sent first, in case the login prompt was printed before
+    /// the terminal was opened. The login is skipped if a shell prompt is
+    /// already shown, and the password is only needed if it's prompted
+    /// for. Reads block, so set a timeout on the terminal's underlying
+    /// channel if the console might never show a prompt.
+    fn login(
+        &mut self,
+        username: &str,
+        password: Option<&str>,
+        prompts: &LoginPrompts,
+    ) -> Result<(), Error> {
+        login::login(self, username, password, prompts)
+    }
+}
+
+impl<T> SystemTerminal for Box<T>
+where
+    T: SystemTerminal + ?Sized,
+{
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        (**self).send_key(key)
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        (**self).resize(cols, rows)
+    }
+
+    fn send_command(&mut self, command: &str) -> Result<(), Error> {
+        (**self).send_command(command)
+    }
+}
+
+/// A trait representing a system that files can be transferred to and from
+pub trait FileTransfer {
+    /// Write data to a file in the system, replacing its contents
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Read the contents of a file in the system
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, Error>;
+}
+
+/// A trait representing a system whose TCP ports can be reached from the
+/// host
+pub trait PortForward {
+    /// Forward a TCP port in the system to the host
+    ///
+    /// Returns the address to connect to, e.g. with a `TcpStream`.
+    fn forward_port(&mut self, guest_port: u16) -> Result<HostEndpoint, Error>;
+}
+
+/// A trait representing a system whose network traffic can be captured
+pub trait TrafficCapture {
+    /// Start capturing the system's network traffic to a pcap file
+    ///
+    /// Capturing continues until stopped or the system is dropped.
+    fn capture_traffic(&mut self, path: &Path) -> Result<(), Error>;
+
+    /// Stop every capture, closing their pcap files
+    fn stop_capture(&mut self) -> Result<(), Error>;
+}
+
+/// A trait representing a system that can dump what's needed to diagnose a
+/// failure
+///
+/// Pass [`collect_artifacts`](CollectArtifacts::collect_artifacts) to
+/// [`SystemTest::collect`] to collect them when a test fails.
+pub trait CollectArtifacts {
+    /// Collect logs, status and other diagnostics into a directory
+    ///
+    /// Artifacts that can't be collected are listed in `errors.txt`
+    /// rather than failing the rest.
+    fn collect_artifacts(&mut self, dir: &Path) -> Result<(), Error>;
 }
 
 /// An event publisher
@@ -113,6 +422,29 @@ pub trait EventPublisher {
     fn subscribe(&mut self, subscriber: impl EventSubscriber) -> Result<(), Error>;
 }
 
+/// An object-safe form of [`EventPublisher`]
+///
+/// Implemented for every [`EventPublisher`].
+pub trait DynEventPublisher {
+    /// Subscribe a boxed event listener
+    fn dyn_subscribe(&mut self, subscriber: Box<dyn EventSubscriber>) -> Result<(), Error>;
+}
+
+impl<T> DynEventPublisher for T
+where
+    T: EventPublisher,
+{
+    fn dyn_subscribe(&mut self, subscriber: Box<dyn EventSubscriber>) -> Result<(), Error> {
+        self.subscribe(subscriber)
+    }
+}
+
+impl EventSubscriber for Box<dyn EventSubscriber> {
+    fn on_event(&mut self, event: &Event) {
+        (**self).on_event(event)
+    }
+}
+
 impl<F> EventSubscriber for F
 where
     F: FnMut(&Event) + Send + Sync + 'static,
@@ -126,20 +458,207 @@ mod error;
 pub use error::Error;
 pub use error::ErrorKind;
 
+mod system_test;
+pub use system_test::SystemTest;
+
+#[cfg(test)]
+mod testing;
+
+mod allocator;
+pub use allocator::{reserve_port, reserve_port_in, unique_mac, PortReservation};
+
+#[cfg_attr(not(any(feature = "qemu", feature = "container")), allow(dead_code))]
+mod artifacts;
+
+#[cfg(feature = "serde")]
+#[cfg_attr(not(any(feature = "qemu", feature = "container")), allow(dead_code))]
+mod retry;
+#[cfg(feature = "serde")]
+pub use retry::RetryPolicy;
+pub use system_harness_macros::system_test;
+
+#[cfg_attr(not(any(feature = "qemu", feature = "container")), allow(dead_code))]
+mod metrics;
+pub use metrics::{set_metrics_recorder, Counter, Histogram, MetricsRecorder};
+
+mod login;
+pub use login::LoginPrompts;
+
+mod text;
+pub use text::{LineReader, PlainTextReader};
+
+mod asciicast;
+pub use asciicast::AsciicastTerminal;
+
+mod timeboxed;
+pub use timeboxed::{Timeboxed, TimeboxedTerminal};
+
+mod transcript;
+pub use transcript::{EntryKind, Normalize, RecordingTerminal, Transcript, TranscriptEntry};
+
+#[cfg(all(feature = "serde", feature = "serde_json"))]
+mod registry;
+#[cfg(all(feature = "serde", feature = "serde_json"))]
+pub use registry::{ConfigFactory, HarnessFactory, HarnessRegistry};
+
+/// Compile items only when at least one backend is enabled
+macro_rules! cfg_backend {
+    ($($item:item)*) => {
+        $(
+            #[cfg(any(
+                feature = "qemu",
+                all(
+                    target_family = "unix",
+                    any(
+                        feature = "container",
+                        feature = "crosvm",
+                        feature = "lxd",
+                        feature = "remote",
+                        feature = "vagrant",
+                        feature = "xen",
+                        feature = "uml",
+                        feature = "process"
+                    )
+                )
+            ))]
+            $item
+        )*
+    };
+}
+
+cfg_backend! {
+    mod config;
+    pub use config::SystemConfig;
+
+    mod scenario;
+    pub use scenario::{Scenario, ScenarioConfig, ScenarioSystem};
+
+    #[cfg(all(feature = "serde", any(feature = "yaml", feature = "toml")))]
+    mod format;
+}
+
+#[cfg(all(
+    feature = "serde",
+    feature = "serde_json",
+    feature = "serde_path_to_error"
+))]
+mod diagnostics;
+#[cfg(all(
+    feature = "serde",
+    feature = "serde_json",
+    feature = "serde_path_to_error"
+))]
+pub use diagnostics::{check_config, Diagnostic, Severity};
+
+#[cfg(all(feature = "serde", feature = "serde_json"))]
+mod interpolate;
+#[cfg(all(feature = "serde", feature = "serde_json"))]
+pub use interpolate::ConfigVars;
+
+#[cfg(all(target_family = "unix", feature = "libc"))]
+mod console;
+#[cfg(all(target_family = "unix", feature = "libc"))]
+pub use console::*;
+
+#[cfg(all(target_family = "unix", feature = "ssh"))]
+mod ssh;
+#[cfg(all(target_family = "unix", feature = "ssh"))]
+pub use ssh::{Ssh, SshSession};
+
 #[cfg(all(target_family = "unix", feature = "container"))]
 mod container;
 #[cfg(all(target_family = "unix", feature = "container"))]
 pub use container::*;
 
-#[cfg(all(target_family = "unix", feature = "qemu"))]
+#[cfg(any(
+    feature = "qemu",
+    all(
+        target_family = "unix",
+        any(
+            feature = "crosvm",
+            feature = "vagrant",
+            feature = "xen",
+            feature = "uml"
+        )
+    )
+))]
+#[cfg_attr(not(feature = "qemu"), allow(dead_code))]
+mod runtime;
+
+#[cfg_attr(not(any(feature = "qemu", feature = "container")), allow(dead_code))]
+mod trace;
+
+#[cfg(all(target_family = "unix", any(feature = "container", feature = "lxd")))]
+mod http;
+
+#[cfg(all(
+    target_family = "unix",
+    any(
+        feature = "container",
+        feature = "remote",
+        feature = "vagrant",
+        feature = "xen",
+        feature = "uml"
+    )
+))]
+#[cfg_attr(not(all(feature = "container", feature = "remote")), allow(dead_code))]
+mod pty;
+
+#[cfg(feature = "qemu")]
 mod qemu;
-#[cfg(all(target_family = "unix", feature = "qemu"))]
+#[cfg(feature = "qemu")]
 pub use qemu::*;
 
+#[cfg(all(target_family = "unix", feature = "crosvm"))]
+mod crosvm;
+#[cfg(all(target_family = "unix", feature = "crosvm"))]
+pub use crosvm::*;
+
+#[cfg(all(target_family = "unix", feature = "lxd"))]
+mod lxd;
+#[cfg(all(target_family = "unix", feature = "lxd"))]
+pub use lxd::*;
+
+#[cfg(all(target_family = "unix", feature = "remote"))]
+mod power;
+#[cfg(all(target_family = "unix", feature = "remote"))]
+pub use power::*;
+
+#[cfg(all(target_family = "unix", feature = "remote"))]
+mod remote;
+#[cfg(all(target_family = "unix", feature = "remote"))]
+pub use remote::*;
+
+#[cfg(all(target_family = "unix", feature = "vagrant"))]
+mod vagrant;
+#[cfg(all(target_family = "unix", feature = "vagrant"))]
+pub use vagrant::*;
+
+#[cfg(all(target_family = "unix", feature = "xen"))]
+mod xen;
+#[cfg(all(target_family = "unix", feature = "xen"))]
+pub use xen::*;
+
+#[cfg(all(target_family = "unix", feature = "uml"))]
+mod uml;
+#[cfg(all(target_family = "unix", feature = "uml"))]
+pub use uml::*;
+
+#[cfg(all(target_family = "unix", feature = "process"))]
+mod process;
+#[cfg(all(target_family = "unix", feature = "process"))]
+pub use process::*;
+
+#[cfg(all(target_family = "unix", feature = "chroot"))]
+mod chroot;
+#[cfg(all(target_family = "unix", feature = "chroot"))]
+pub use chroot::*;
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::testing::FakeSystem;
 
     struct FakeEventPublisher(Vec<Box<dyn EventSubscriber>>);
 
@@ -162,10 +681,107 @@ mod tests {
         }
     }
 
+    #[test]
+    fn host_endpoint() {
+        let endpoint = HostEndpoint::new("127.0.0.1", 2222);
+        assert_eq!("127.0.0.1:2222", endpoint.to_string());
+        assert_eq!(
+            vec![SocketAddr::from(([127, 0, 0, 1], 2222))],
+            endpoint.to_socket_addrs().unwrap().collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn fn_subscribe() {
         let mut publisher = FakeEventPublisher(Vec::new());
         publisher.subscribe(|_event: &Event| {}).unwrap();
         publisher.publish();
     }
+
+    #[test]
+    fn dyn_subscribe() {
+        let mut publishers: Vec<Box<dyn DynEventPublisher>> = vec![
+            Box::new(FakeEventPublisher(Vec::new())),
+            Box::new(FakeEventPublisher(Vec::new())),
+        ];
+        for publisher in &mut publishers {
+            publisher
+                .dyn_subscribe(Box::new(|_event: &Event| {}))
+                .unwrap();
+        }
+    }
+
+    /// A system relying on the default wait
+    struct Polled(FakeSystem);
+
+    impl SystemHarness for Polled {
+        type Terminal = <FakeSystem as SystemHarness>::Terminal;
+
+        fn terminal(&self) -> Result<Self::Terminal, Error> {
+            self.0.terminal()
+        }
+
+        fn pause(&mut self) -> Result<(), Error> {
+            self.0.pause()
+        }
+
+        fn resume(&mut self) -> Result<(), Error> {
+            self.0.resume()
+        }
+
+        fn shutdown(&mut self) -> Result<(), Error> {
+            self.0.shutdown()
+        }
+
+        fn status(&mut self) -> Result<Status, Error> {
+            self.0.status()
+        }
+
+        fn running(&mut self) -> Result<bool, Error> {
+            self.0.running()
+        }
+    }
+
+    #[test]
+    fn default_wait() {
+        let mut system = Polled(FakeSystem::new());
+        let err = system.wait(Duration::from_millis(100)).err().unwrap();
+        assert_eq!(ErrorKind::Timeout, err.kind());
+
+        let shutdown = system.0.shutdown.clone();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            shutdown.store(true, std::sync::atomic::Ordering::SeqCst);
+        });
+        let status = system.wait(Duration::from_secs(10)).unwrap();
+        assert_eq!(None, status.code);
+        stopper.join().unwrap();
+    }
+
+    /// Generic code accepts boxed systems of any backend
+    fn pause_all<S: SystemHarness>(systems: &mut [S]) -> Result<(), Error> {
+        for system in systems {
+            system.pause()?;
+            system.terminal()?.send_command("sync")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn boxed_systems() {
+        let mut paused = FakeSystem::new();
+        paused.pause().unwrap();
+        let mut systems: Vec<Box<dyn DynSystemHarness>> =
+            vec![Box::new(FakeSystem::new()), Box::new(paused)];
+        pause_all(&mut systems).unwrap();
+        for system in &mut systems {
+            assert_eq!(Status::Paused, system.status().unwrap());
+            system.shutdown().unwrap();
+            assert!(!system.running().unwrap());
+        }
+        let mut terminal = systems[0].terminal().unwrap();
+        terminal.send_command("true").unwrap();
+        let err = terminal.resize(80, 24).err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+    }
 }
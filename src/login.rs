@@ -0,0 +1,214 @@
+use crate::text::AnsiStripper;
+use crate::{Error, ErrorKind, Key, SystemTerminal};
+
+/// Text that the steps of a console login wait for
+///
+/// Prompts are matched as plain text, after ANSI escape sequences are
+/// stripped, anywhere in the output.
+#[derive(Clone, Debug)]
+pub struct LoginPrompts {
+    /// Prompts for the username, `login: ` by default
+    pub login: Vec<String>,
+
+    /// Prompts for the password, `Password: ` or `password: ` by default
+    pub password: Vec<String>,
+
+    /// Shell prompts showing the login succeeded, `# ` or `$ ` by default
+    pub shell: Vec<String>,
+
+    /// Messages showing the login failed, `Login incorrect` by default
+    pub failure: Vec<String>,
+}
+
+impl Default for LoginPrompts {
+    fn default() -> Self {
+        Self {
+            login: vec![String::from("login: ")],
+            password: vec![String::from("Password: "), String::from("password: ")],
+            shell: vec![String::from("# "), String::from("$ ")],
+            failure: vec![String::from("Login incorrect")],
+        }
+    }
+}
+
+/// What a login step saw
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Seen {
+    Login,
+    Password,
+    Shell,
+    Failure,
+}
+
+/// Read until one of the prompts is seen
+fn wait_for<T: SystemTerminal + ?Sized>(
+    terminal: &mut T,
+    prompts: &[(&[String], Seen)],
+) -> Result<Seen, Error> {
+    let mut stripper = AnsiStripper::default();
+    let mut output = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = terminal.read(&mut buf)?;
+        if len == 0 {
+            return Err(Error::new(
+                ErrorKind::PipeError,
+                "Terminal closed while waiting for a login prompt",
+            ));
+        }
+        stripper.strip(&buf[..len], &mut output);
+        let found = prompts
+            .iter()
+            .filter_map(|(patterns, seen)| {
+                patterns
+                    .iter()
+                    .filter(|pattern| !pattern.is_empty())
+                    .filter_map(|pattern| {
+                        output
+                            .windows(pattern.len())
+                            .position(|window| window == pattern.as_bytes())
+                    })
+                    .min()
+                    .map(|position| (position, seen))
+            })
+            .min_by_key(|(position, _)| *position);
+        if let Some((_, seen)) = found {
+            return Ok(*seen);
+        }
+    }
+}
+
+/// Log in on a console and wait for a shell prompt
+///
+/// See [`SystemTerminal::login`].
+pub(crate) fn login<T: SystemTerminal + ?Sized>(
+    terminal: &mut T,
+    username: &str,
+    password: Option<&str>,
+    prompts: &LoginPrompts,
+) -> Result<(), Error> {
+    // Consoles that have already printed their prompt print it again
+    terminal.send_key(Key::Enter)?;
+    let seen = wait_for(
+        terminal,
+        &[(&prompts.login, Seen::Login), (&prompts.shell, Seen::Shell)],
+    )?;
+    if seen == Seen::Shell {
+        log::trace!("Already logged in");
+        return Ok(());
+    }
+
+    log::trace!("Logging in as {username}...");
+    terminal.send_command(username)?;
+    let seen = wait_for(
+        terminal,
+        &[
+            (&prompts.password, Seen::Password),
+            (&prompts.shell, Seen::Shell),
+            (&prompts.failure, Seen::Failure),
+        ],
+    )?;
+    let seen = match seen {
+        Seen::Password => {
+            let password = password.ok_or_else(|| {
+                Error::new(
+                    ErrorKind::HarnessError,
+                    format!("Password prompted for {username}, but none was given"),
+                )
+            })?;
+            terminal.send_command(password)?;
+            wait_for(
+                terminal,
+                &[
+                    (&prompts.shell, Seen::Shell),
+                    (&prompts.failure, Seen::Failure),
+                ],
+            )?
+        }
+        seen => seen,
+    };
+    match seen {
+        Seen::Shell => {
+            log::trace!("Logged in as {username}");
+            Ok(())
+        }
+        _ => Err(Error::new(
+            ErrorKind::HarnessError,
+            format!("Failed to log in as {username}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::testing::FakeTerminal;
+
+    /// A console that replies to each line typed into it
+    fn replying(replies: &[&'static [u8]]) -> FakeTerminal {
+        FakeTerminal::new(b"", replies)
+    }
+
+    #[test]
+    fn password() {
+        let mut console = replying(&[
+            b"\r\nguest \x1b[1mlogin: \x1b[0m",
+            b"Password: ",
+            b"Last login: never\r\n\x1b[32mroot@guest\x1b[0m:~# ",
+        ]);
+        console
+            .login("root", Some("secret"), &LoginPrompts::default())
+            .unwrap();
+        assert_eq!(b"\nroot\nsecret\n", console.typed.as_slice());
+    }
+
+    #[test]
+    fn no_password() {
+        let mut console = replying(&[b"login: ", b"$ "]);
+        console
+            .login("user", None, &LoginPrompts::default())
+            .unwrap();
+
+        let mut console = replying(&[b"# "]);
+        console
+            .login("root", None, &LoginPrompts::default())
+            .unwrap();
+        assert_eq!(b"\n", console.typed.as_slice());
+    }
+
+    #[test]
+    fn custom_prompts() {
+        let prompts = LoginPrompts {
+            login: vec![String::from("Username:")],
+            shell: vec![String::from("> ")],
+            ..LoginPrompts::default()
+        };
+        let mut console = replying(&[b"Username:", b"Password: ", b"router> "]);
+        console.login("admin", Some("admin"), &prompts).unwrap();
+    }
+
+    #[test]
+    fn failure() {
+        let mut console = replying(&[b"login: ", b"Password: ", b"\r\nLogin incorrect\r\nlogin: "]);
+        let err = console
+            .login("root", Some("wrong"), &LoginPrompts::default())
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+
+        let mut console = replying(&[b"login: ", b"Password: "]);
+        let err = console
+            .login("root", None, &LoginPrompts::default())
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+
+        let mut console = replying(&[b"login: "]);
+        let err = console
+            .login("root", None, &LoginPrompts::default())
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::PipeError, err.kind());
+    }
+}
@@ -0,0 +1,383 @@
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+mod client;
+use client::LxdClient;
+
+mod websocket;
+use websocket::WebSocket;
+
+/// Sockets of the LXD and Incus daemons, in order of preference
+const DEFAULT_SOCKETS: [&str; 3] = [
+    "/var/snap/lxd/common/lxd/unix.socket",
+    "/var/lib/lxd/unix.socket",
+    "/var/lib/incus/unix.socket",
+];
+
+/// Default time allowed to launch an instance, including image downloads
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default time allowed for an instance to stop cleanly
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval between state checks while waiting for an instance
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Counter used to give each generated instance a unique name
+static INSTANCE: AtomicUsize = AtomicUsize::new(0);
+
+/// A configuration for running an LXD or Incus system container
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LxdSystemConfig {
+    /// Daemon API socket
+    ///
+    /// Defaults to the first LXD or Incus socket found.
+    socket: Option<PathBuf>,
+
+    /// Instance name (defaults to a generated, unique name)
+    name: Option<String>,
+
+    /// Image alias or fingerprint
+    image: String,
+
+    /// Image server, e.g. `https://images.linuxcontainers.org`
+    image_server: Option<String>,
+
+    /// Image server protocol (defaults to `simplestreams`)
+    image_protocol: Option<String>,
+
+    /// Profiles applied to the instance
+    profiles: Option<Vec<String>>,
+
+    /// Instance configuration keys, e.g. `limits.cpu`
+    config: Option<BTreeMap<String, String>>,
+
+    /// Command run for terminals (defaults to `/bin/sh`)
+    shell: Option<Vec<String>>,
+
+    /// Environment of terminal commands
+    environment: Option<BTreeMap<String, String>>,
+
+    /// Seconds to wait for the instance to launch (defaults to 300)
+    startup_timeout: Option<u64>,
+
+    /// Seconds to wait for the instance to stop (defaults to 30)
+    stop_timeout: Option<u64>,
+}
+
+impl LxdSystemConfig {
+    /// Configured socket or the first one found
+    fn socket(&self) -> Result<PathBuf, Error> {
+        if let Some(socket) = &self.socket {
+            return Ok(socket.clone());
+        }
+        DEFAULT_SOCKETS
+            .iter()
+            .map(PathBuf::from)
+            .find(|socket| socket.exists())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidConfig, "No LXD or Incus socket found"))
+    }
+
+    /// Body of the request creating the instance
+    fn create_request(&self, name: &str) -> Value {
+        let mut source = json!({ "type": "image" });
+        if self.image.len() == 64 && self.image.chars().all(|c| c.is_ascii_hexdigit()) {
+            source["fingerprint"] = json!(self.image);
+        } else {
+            source["alias"] = json!(self.image);
+        }
+        if let Some(server) = &self.image_server {
+            source["server"] = json!(server);
+            source["protocol"] = json!(self.image_protocol.as_deref().unwrap_or("simplestreams"));
+        }
+        let mut request = json!({
+            "name": name,
+            "type": "container",
+            "source": source,
+        });
+        if let Some(profiles) = &self.profiles {
+            request["profiles"] = json!(profiles);
+        }
+        if let Some(config) = &self.config {
+            request["config"] = json!(config);
+        }
+        request
+    }
+
+    /// Launch the instance
+    pub fn build(&self) -> Result<LxdSystem, Error> {
+        let client = LxdClient::new(self.socket()?);
+        let name = self.name.clone().unwrap_or_else(|| {
+            format!(
+                "system-harness-{}-{}",
+                std::process::id(),
+                INSTANCE.fetch_add(1, Ordering::Relaxed)
+            )
+        });
+        let timeout = self
+            .startup_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+
+        log::trace!("Creating instance {name}...");
+        client.run(
+            "POST",
+            "/1.0/instances",
+            Some(&self.create_request(&name)),
+            timeout,
+        )?;
+        let system = LxdSystem {
+            client,
+            name,
+            shell: self
+                .shell
+                .clone()
+                .unwrap_or_else(|| vec![String::from("/bin/sh")]),
+            environment: self.environment.clone().unwrap_or_default(),
+            stop_timeout: self
+                .stop_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_STOP_TIMEOUT),
+        };
+        log::trace!("Starting instance {}...", system.name);
+        system.change_state("start", false, timeout)?;
+        Ok(system)
+    }
+}
+
+/// Runtime state of an instance
+#[derive(Deserialize)]
+struct InstanceState {
+    status: String,
+}
+
+/// Map an instance status to a system status
+fn instance_status(status: &str) -> Result<Status, Error> {
+    match status {
+        "Running" | "Starting" | "Stopping" => Ok(Status::Running),
+        "Frozen" | "Freezing" => Ok(Status::Paused),
+        "Stopped" => Ok(Status::Shutdown),
+        status => Err(Error::new(
+            ErrorKind::HarnessError,
+            format!("Unexpected instance status: {status}"),
+        )),
+    }
+}
+
+/// A running LXD or Incus system container
+pub struct LxdSystem {
+    client: LxdClient,
+    name: String,
+    shell: Vec<String>,
+    environment: BTreeMap<String, String>,
+    stop_timeout: Duration,
+}
+
+impl LxdSystem {
+    /// Name of the instance
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Run a state change action on the instance
+    fn change_state(&self, action: &str, force: bool, timeout: Duration) -> Result<(), Error> {
+        let request = json!({
+            "action": action,
+            "timeout": timeout.as_secs(),
+            "force": force,
+        });
+        let path = format!("/1.0/instances/{}/state", self.name);
+        self.client.run("PUT", &path, Some(&request), timeout)?;
+        Ok(())
+    }
+
+    fn state(&self) -> Result<InstanceState, Error> {
+        self.client
+            .get(&format!("/1.0/instances/{}/state", self.name))
+    }
+}
+
+/// An interactive command in the instance
+///
+/// Output arrives in websocket frames that are buffered until read, so
+/// the socket being readable doesn't tell whether output is waiting. The
+/// terminal therefore doesn't implement `AsRawFd`, and
+/// `run_console` doesn't support LXD systems.
+pub struct LxdSystemTerminal {
+    data: WebSocket,
+    control: WebSocket,
+}
+
+impl Read for LxdSystemTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl Write for LxdSystemTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.data.flush()
+    }
+}
+
+impl SystemTerminal for LxdSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\r")?,
+        }
+        self.flush().map_err(|err| err.into())
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        let message = json!({
+            "command": "window-resize",
+            "args": { "width": cols.to_string(), "height": rows.to_string() },
+        });
+        self.control.send_text(&message.to_string())?;
+        Ok(())
+    }
+}
+
+impl Drop for LxdSystemTerminal {
+    fn drop(&mut self) {
+        let _ = self.data.close();
+        let _ = self.control.close();
+    }
+}
+
+impl SystemHarness for LxdSystem {
+    type Terminal = LxdSystemTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        let request = json!({
+            "command": self.shell,
+            "environment": self.environment,
+            "interactive": true,
+            "wait-for-websocket": true,
+            "width": 80,
+            "height": 24,
+        });
+        let path = format!("/1.0/instances/{}/exec", self.name);
+        let operation = self.client.start("POST", &path, Some(&request))?;
+        let secret = |fd: &str| {
+            operation.metadata["metadata"]["fds"][fd]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::HarnessError, format!("Missing {fd} websocket"))
+                })
+        };
+        let data = self.client.websocket(&operation, &secret("0")?)?;
+        let control = self.client.websocket(&operation, &secret("control")?)?;
+        Ok(LxdSystemTerminal { data, control })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.change_state("freeze", false, self.stop_timeout)
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.change_state("unfreeze", false, self.stop_timeout)
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.change_state("stop", false, self.stop_timeout)
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        instance_status(&self.state()?.status)
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.status()? == Status::Running)
+    }
+
+    /// Instances report no exit code, so only the stop is observed
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let deadline = Instant::now() + timeout;
+        while self.status()? != Status::Shutdown {
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("Timed out waiting for instance {} to stop", self.name),
+                ));
+            }
+            std::thread::sleep(STATE_POLL_INTERVAL);
+        }
+        Ok(ExitStatus {
+            code: None,
+            reason: None,
+        })
+    }
+}
+
+impl Drop for LxdSystem {
+    fn drop(&mut self) {
+        if !matches!(self.status(), Ok(Status::Shutdown)) {
+            log::trace!("Stopping instance {}...", self.name);
+            if let Err(err) = self.change_state("stop", true, self.stop_timeout) {
+                log::warn!("Error stopping instance {}: {err}", self.name);
+            }
+        }
+        log::trace!("Deleting instance {}...", self.name);
+        let path = format!("/1.0/instances/{}", self.name);
+        if let Err(err) = self.client.run("DELETE", &path, None, self.stop_timeout) {
+            log::warn!("Error deleting instance {}: {err}", self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn create_request() {
+        let config: LxdSystemConfig = serde_json::from_str(
+            r#"{
+                "image": "debian/12",
+                "image_server": "https://images.linuxcontainers.org",
+                "profiles": ["default"],
+                "config": { "limits.cpu": "2" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            json!({
+                "name": "test",
+                "type": "container",
+                "source": {
+                    "type": "image",
+                    "alias": "debian/12",
+                    "server": "https://images.linuxcontainers.org",
+                    "protocol": "simplestreams"
+                },
+                "profiles": ["default"],
+                "config": { "limits.cpu": "2" }
+            }),
+            config.create_request("test")
+        );
+    }
+
+    #[test]
+    fn status() {
+        assert_eq!(Status::Running, instance_status("Running").unwrap());
+        assert_eq!(Status::Paused, instance_status("Frozen").unwrap());
+        assert_eq!(Status::Shutdown, instance_status("Stopped").unwrap());
+        assert!(instance_status("Error").is_err());
+    }
+}
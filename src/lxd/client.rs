@@ -0,0 +1,208 @@
+use super::websocket::WebSocket;
+use crate::http::parse_response;
+use crate::{Error, ErrorKind};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Envelope of every LXD API response
+#[derive(Deserialize)]
+struct Response {
+    #[serde(rename = "type")]
+    kind: String,
+
+    #[serde(default)]
+    error: String,
+
+    #[serde(default)]
+    metadata: Value,
+}
+
+/// A background operation of the LXD daemon
+#[derive(Deserialize)]
+pub(crate) struct Operation {
+    pub id: String,
+
+    pub status: String,
+
+    #[serde(default)]
+    pub err: String,
+
+    #[serde(default)]
+    pub metadata: Value,
+}
+
+impl Operation {
+    /// API path of the operation
+    fn path(&self) -> String {
+        format!("/1.0/operations/{}", self.id)
+    }
+}
+
+/// A minimal client for the LXD and Incus REST API
+pub(crate) struct LxdClient {
+    socket: PathBuf,
+}
+
+impl LxdClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    /// Send a request and return the metadata of a successful response
+    fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value, Error> {
+        log::trace!("LXD request: {method} {path}");
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let mut stream = UnixStream::connect(&self.socket)?;
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let (status, body) = parse_response(&response)?;
+        let response: Response = serde_json::from_slice(&body)?;
+        if response.kind == "error" || !(200..=299).contains(&status) {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                format!("{method} {path} failed ({status}): {}", response.error),
+            ));
+        }
+        Ok(response.metadata)
+    }
+
+    /// Get the metadata of a resource
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        Ok(serde_json::from_value(self.request("GET", path, None)?)?)
+    }
+
+    /// Start a background operation
+    pub fn start(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Operation, Error> {
+        Ok(serde_json::from_value(self.request(method, path, body)?)?)
+    }
+
+    /// Wait for an operation to finish successfully
+    pub fn wait(&self, operation: &Operation, timeout: Duration) -> Result<Operation, Error> {
+        let path = format!("{}/wait?timeout={}", operation.path(), timeout.as_secs());
+        let operation: Operation = self.get(&path)?;
+        match operation.status.as_str() {
+            "Success" => Ok(operation),
+            "Pending" | "Running" => Err(Error::new(
+                ErrorKind::Timeout,
+                format!("Timed out waiting for operation {}", operation.id),
+            )),
+            status => Err(Error::new(
+                ErrorKind::HarnessError,
+                format!("Operation {} {status}: {}", operation.id, operation.err),
+            )),
+        }
+    }
+
+    /// Run an operation to completion
+    pub fn run(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+        timeout: Duration,
+    ) -> Result<Operation, Error> {
+        let operation = self.start(method, path, body)?;
+        self.wait(&operation, timeout)
+    }
+
+    /// Connect to a websocket of an operation
+    pub fn websocket(&self, operation: &Operation, secret: &str) -> Result<WebSocket, Error> {
+        let path = format!("{}/websocket?secret={secret}", operation.path());
+        WebSocket::connect(&self.socket, &path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn operation() {
+        let dir = std::env::temp_dir().join(format!("system-harness-lxd-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("unix.socket");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for body in [
+                r#"{"type":"async","metadata":{"id":"abc","status":"Running"}}"#,
+                r#"{"type":"sync","metadata":{"id":"abc","status":"Success"}}"#,
+                r#"{"type":"error","error":"Instance not found","error_code":404}"#,
+            ] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                requests.push(line.trim_end().to_string());
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if let Some(value) = header.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if header == "\r\n" {
+                        break;
+                    }
+                }
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).unwrap();
+                let status = if body.contains("error_code") {
+                    "404 Not Found"
+                } else {
+                    "200 OK"
+                };
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+            requests
+        });
+        let client = LxdClient::new(&socket);
+        client
+            .run(
+                "PUT",
+                "/1.0/instances/test/state",
+                Some(&json!({ "action": "freeze" })),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        let err = client.get::<Value>("/1.0/instances/missing").err().unwrap();
+        assert!(err.to_string().contains("Instance not found"));
+        assert_eq!(
+            vec![
+                "PUT /1.0/instances/test/state HTTP/1.1",
+                "GET /1.0/operations/abc/wait?timeout=5 HTTP/1.1",
+                "GET /1.0/instances/missing HTTP/1.1"
+            ],
+            server.join().unwrap()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
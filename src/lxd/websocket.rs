@@ -0,0 +1,263 @@
+use crate::http::parse_response;
+use crate::{Error, ErrorKind};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Handshake key sent to the server
+///
+/// The key only guards against caching proxies, which a Unix socket
+/// doesn't have, so a fixed key is sufficient.
+const HANDSHAKE_KEY: &str = "c3lzdGVtLWhhcm5lc3Mta2V5";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Read a single frame, returning its opcode and unmasked payload
+fn read_frame(reader: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    let opcode = head[0] & 0x0f;
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut mask = [0u8; 4];
+    let masked = head[1] & 0x80 != 0;
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    if masked {
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+    }
+    Ok((opcode, payload))
+}
+
+/// Encode a single masked client frame
+fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| byte ^ mask[index % 4]),
+    );
+    frame
+}
+
+/// A minimal websocket client over a Unix socket
+pub(crate) struct WebSocket {
+    stream: UnixStream,
+
+    /// Payload of the last data frame not yet read
+    pending: Vec<u8>,
+
+    /// Read position in the pending payload
+    offset: usize,
+
+    /// Whether the close handshake has happened
+    closed: bool,
+}
+
+impl WebSocket {
+    /// Connect to the websocket at the given path
+    pub fn connect(socket: &Path, path: &str) -> Result<Self, Error> {
+        log::trace!("Websocket connect: {path}");
+        let mut stream = UnixStream::connect(socket)?;
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Key: {HANDSHAKE_KEY}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n"
+        )?;
+        // Read the head a byte at a time so frames are left in the stream
+        let mut head = Vec::new();
+        let mut byte = [0u8];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte)?;
+            head.push(byte[0]);
+        }
+        let (status, _) = parse_response(&head)?;
+        if status != 101 {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                format!("Websocket upgrade of {path} failed ({status})"),
+            ));
+        }
+        Ok(Self {
+            stream,
+            pending: Vec::new(),
+            offset: 0,
+            closed: false,
+        })
+    }
+
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        self.stream
+            .write_all(&encode_frame(opcode, payload, nanos.to_ne_bytes()))
+    }
+
+    /// Send a text message
+    pub fn send_text(&mut self, text: &str) -> std::io::Result<()> {
+        self.send(OPCODE_TEXT, text.as_bytes())
+    }
+
+    /// Start the close handshake
+    pub fn close(&mut self) -> std::io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.send(OPCODE_CLOSE, &[])
+    }
+
+    /// Receive the next non-empty data payload, or `None` once closed
+    fn receive(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            let (opcode, payload) = read_frame(&mut self.stream)?;
+            match opcode {
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY if !payload.is_empty() => {
+                    return Ok(Some(payload))
+                }
+                OPCODE_CLOSE => {
+                    self.close()?;
+                    return Ok(None);
+                }
+                OPCODE_PING => self.send(OPCODE_PONG, &payload)?,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Read for WebSocket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset >= self.pending.len() {
+            if self.closed {
+                return Ok(0);
+            }
+            match self.receive()? {
+                Some(payload) => {
+                    self.pending = payload;
+                    self.offset = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.pending.len() - self.offset);
+        buf[..count].copy_from_slice(&self.pending[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+}
+
+impl Write for WebSocket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.send(OPCODE_BINARY, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn frame_roundtrip() {
+        let payload = vec![7u8; 300];
+        let frame = encode_frame(OPCODE_BINARY, &payload, [1, 2, 3, 4]);
+        assert_eq!([0x82, 0x80 | 126, 1, 44], frame[..4]);
+        assert_eq!(
+            (OPCODE_BINARY, payload),
+            read_frame(&mut &frame[..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn connect() {
+        let dir = std::env::temp_dir().join(format!("system-harness-ws-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("unix.socket");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+            }
+            let stream = reader.get_mut();
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n")
+                .unwrap();
+            stream.write_all(&[0x89, 0]).unwrap();
+            stream.write_all(&[0x82, 5]).unwrap();
+            stream.write_all(b"hello").unwrap();
+            let pong = read_frame(stream).unwrap();
+            let input = read_frame(stream).unwrap();
+            stream.write_all(&[0x88, 0]).unwrap();
+            let close = read_frame(stream).unwrap();
+            vec![pong, input, close]
+        });
+        let mut websocket = WebSocket::connect(&socket, "/1.0/operations/abc/websocket").unwrap();
+        let mut buf = [0u8; 16];
+        let count = websocket.read(&mut buf).unwrap();
+        assert_eq!(b"hello", &buf[..count]);
+        websocket.write_all(b"ls\r").unwrap();
+        assert_eq!(0, websocket.read(&mut buf).unwrap());
+        assert_eq!(
+            vec![
+                (OPCODE_PONG, Vec::new()),
+                (OPCODE_BINARY, b"ls\r".to_vec()),
+                (OPCODE_CLOSE, Vec::new())
+            ],
+            server.join().unwrap()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{Error, ErrorKind};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Recorder every metric is passed to, once set
+static RECORDER: OnceLock<Box<dyn MetricsRecorder>> = OnceLock::new();
+
+/// A count of harness operations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Counter {
+    /// Commands sent to a system, such as QMP commands or container runtime
+    /// invocations
+    CommandsSent,
+
+    /// Events received from a system
+    EventsReceived,
+
+    /// Systems restarted or reset by the harness
+    Restarts,
+}
+
+impl Counter {
+    /// Name of the metric, e.g. `system_harness_commands_sent`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CommandsSent => "system_harness_commands_sent",
+            Self::EventsReceived => "system_harness_events_received",
+            Self::Restarts => "system_harness_restarts",
+        }
+    }
+}
+
+/// A distribution of harness operation durations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Histogram {
+    /// Time from building a system until it's ready
+    BootTime,
+
+    /// Time from sending a command until it completes
+    CommandLatency,
+}
+
+impl Histogram {
+    /// Name of the metric, e.g. `system_harness_boot_time`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BootTime => "system_harness_boot_time",
+            Self::CommandLatency => "system_harness_command_latency",
+        }
+    }
+}
+
+/// Receives the harness's metrics, e.g. to forward them to a metrics crate
+/// or CI dashboard
+///
+/// Each metric is labelled with the backend it came from, such as `qemu`
+/// or `container`. Metrics a recorder doesn't implement are dropped.
+pub trait MetricsRecorder: Send + Sync {
+    /// Increment a counter
+    fn increment(&self, counter: Counter, backend: &'static str) {
+        let _ = (counter, backend);
+    }
+
+    /// Record a duration in a histogram
+    fn record(&self, histogram: Histogram, backend: &'static str, value: Duration) {
+        let _ = (histogram, backend, value);
+    }
+}
+
+/// Set the recorder for the rest of the process
+///
+/// Like a logger, the recorder can only be set once. Until it is set,
+/// metrics are dropped.
+pub fn set_metrics_recorder(recorder: impl MetricsRecorder + 'static) -> Result<(), Error> {
+    RECORDER
+        .set(Box::new(recorder))
+        .map_err(|_| Error::new(ErrorKind::HarnessError, "Metrics recorder is already set"))
+}
+
+/// Increment a counter
+pub(crate) fn increment(counter: Counter, backend: &'static str) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.increment(counter, backend);
+    }
+}
+
+/// Record a duration in a histogram
+pub(crate) fn record(histogram: Histogram, backend: &'static str, value: Duration) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.record(histogram, backend, value);
+    }
+}
+
+/// Counts a command when created and records its latency when dropped
+pub(crate) struct CommandTimer {
+    backend: &'static str,
+    start: Instant,
+}
+
+impl CommandTimer {
+    pub(crate) fn start(backend: &'static str) -> Self {
+        increment(Counter::CommandsSent, backend);
+        Self {
+            backend,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for CommandTimer {
+    fn drop(&mut self) {
+        record(
+            Histogram::CommandLatency,
+            self.backend,
+            self.start.elapsed(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Metrics recorded for the `test` backend
+    static RECORDED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct TestRecorder;
+
+    impl MetricsRecorder for TestRecorder {
+        fn increment(&self, counter: Counter, backend: &'static str) {
+            if backend == "test" {
+                RECORDED.lock().unwrap().push(counter.name().to_string());
+            }
+        }
+
+        fn record(&self, histogram: Histogram, backend: &'static str, value: Duration) {
+            if backend == "test" && value < Duration::from_secs(60) {
+                RECORDED.lock().unwrap().push(histogram.name().to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn recorder() {
+        set_metrics_recorder(TestRecorder).unwrap();
+        assert!(set_metrics_recorder(TestRecorder).is_err());
+
+        drop(CommandTimer::start("test"));
+        increment(Counter::Restarts, "test");
+        record(Histogram::BootTime, "test", Duration::from_secs(1));
+        assert_eq!(
+            vec![
+                "system_harness_commands_sent",
+                "system_harness_command_latency",
+                "system_harness_restarts",
+                "system_harness_boot_time",
+            ],
+            *RECORDED.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use crate::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Time a machine is left off while power cycling
+const POWER_CYCLE_DELAY: Duration = Duration::from_secs(5);
+
+/// Default IPMI interface
+const DEFAULT_IPMI_INTERFACE: &str = "lanplus";
+
+/// Default SNMP write community of PDUs
+const DEFAULT_COMMUNITY: &str = "private";
+
+/// Power state of a machine
+#[derive(Debug, PartialEq)]
+pub enum PowerState {
+    On,
+    Off,
+}
+
+/// A trait representing control over a machine's power
+pub trait PowerController {
+    /// Turn the machine on
+    fn power_on(&self) -> Result<(), Error>;
+
+    /// Turn the machine off without a clean shutdown
+    fn power_off(&self) -> Result<(), Error>;
+
+    /// Turn the machine off and on again
+    fn power_cycle(&self) -> Result<(), Error> {
+        switch_off_on(self)
+    }
+
+    /// Hard reset the machine (defaults to a power cycle)
+    fn reset(&self) -> Result<(), Error> {
+        self.power_cycle()
+    }
+
+    /// Current power state of the machine
+    fn power_state(&self) -> Result<PowerState, Error>;
+}
+
+/// Power cycle by switching the power off, then on
+fn switch_off_on(controller: &(impl PowerController + ?Sized)) -> Result<(), Error> {
+    controller.power_off()?;
+    std::thread::sleep(POWER_CYCLE_DELAY);
+    controller.power_on()
+}
+
+/// A power controller configuration
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerControl {
+    /// IPMI chassis control through `ipmitool`
+    Ipmi {
+        host: String,
+        user: String,
+        password: String,
+
+        /// ipmitool interface (defaults to `lanplus`)
+        interface: Option<String>,
+    },
+
+    /// Redfish `ComputerSystem.Reset` through `curl`
+    Redfish {
+        /// Base URL of the BMC, e.g. `https://bmc.lab`
+        url: String,
+        user: String,
+        password: String,
+
+        /// Id of the computer system (defaults to the first one)
+        system: Option<String>,
+
+        /// Accept the BMC's certificate without verifying it
+        #[serde(default)]
+        insecure: bool,
+    },
+
+    /// A PDU outlet switched over SNMP
+    Pdu {
+        host: String,
+
+        /// Write community (defaults to `private`)
+        community: Option<String>,
+
+        /// OID of the outlet's control object
+        oid: String,
+
+        /// Value turning the outlet on (defaults to 1)
+        on: Option<i64>,
+
+        /// Value turning the outlet off (defaults to 2)
+        off: Option<i64>,
+
+        /// Value rebooting the outlet, if the PDU has one
+        reboot: Option<i64>,
+    },
+
+    /// Local commands
+    Command {
+        on: Vec<String>,
+        off: Vec<String>,
+
+        /// Command that succeeds when the machine is on
+        status: Option<Vec<String>>,
+    },
+}
+
+/// Run a command, returning its standard output
+fn run(command: &mut Command, input: Option<&str>) -> Result<String, Error> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let mut process = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = process.stdin.take() {
+        stdin.write_all(input.unwrap_or_default().as_bytes())?;
+    }
+    let output = process.wait_with_output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(Error::new(
+            ErrorKind::HarnessError,
+            format!(
+                "{program} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+/// Command from a configured command line
+fn command_line(command_line: &[String]) -> Result<Command, Error> {
+    let (program, args) = command_line
+        .split_first()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidConfig, "Empty power command"))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+/// Quote a value for a curl config file
+fn curl_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl PowerControl {
+    /// ipmitool chassis power command, with the password in the environment
+    fn ipmitool(&self, action: &str) -> Command {
+        let mut command = Command::new("ipmitool");
+        if let PowerControl::Ipmi {
+            host,
+            user,
+            password,
+            interface,
+        } = self
+        {
+            command
+                .arg("-I")
+                .arg(interface.as_deref().unwrap_or(DEFAULT_IPMI_INTERFACE))
+                .args(["-H", host, "-U", user, "-E"])
+                .args(["chassis", "power", action])
+                .env("IPMI_PASSWORD", password);
+        }
+        command
+    }
+
+    /// Request a Redfish resource, returning the response body
+    ///
+    /// Credentials are passed as a curl config on standard input so they
+    /// don't show up in the process list.
+    fn redfish(&self, path: &str, body: Option<&str>) -> Result<String, Error> {
+        let PowerControl::Redfish {
+            url,
+            user,
+            password,
+            insecure,
+            ..
+        } = self
+        else {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                "Not a Redfish controller",
+            ));
+        };
+        let mut command = Command::new("curl");
+        command.args(["--silent", "--show-error", "--fail", "--config", "-"]);
+        if *insecure {
+            command.arg("--insecure");
+        }
+        if let Some(body) = body {
+            command
+                .args(["-X", "POST", "-H", "Content-Type: application/json", "-d"])
+                .arg(body);
+        }
+        command.arg(format!("{}{path}", url.trim_end_matches('/')));
+        let config = format!("user = {}\n", curl_quote(&format!("{user}:{password}")));
+        run(&mut command, Some(&config))
+    }
+
+    /// Path of the Redfish computer system
+    fn redfish_system(&self) -> Result<String, Error> {
+        if let PowerControl::Redfish {
+            system: Some(system),
+            ..
+        } = self
+        {
+            return Ok(format!("/redfish/v1/Systems/{system}"));
+        }
+        let systems: serde_json::Value =
+            serde_json::from_str(&self.redfish("/redfish/v1/Systems", None)?)?;
+        systems["Members"][0]["@odata.id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| Error::new(ErrorKind::HarnessError, "BMC reports no systems"))
+    }
+
+    fn redfish_reset(&self, reset_type: &str) -> Result<(), Error> {
+        let path = format!("{}/Actions/ComputerSystem.Reset", self.redfish_system()?);
+        let body = serde_json::json!({ "ResetType": reset_type }).to_string();
+        self.redfish(&path, Some(&body)).map(|_| ())
+    }
+
+    /// snmpset or snmpget command for the PDU outlet
+    fn snmp(&self, value: Option<i64>) -> Command {
+        let mut command = Command::new(if value.is_some() {
+            "snmpset"
+        } else {
+            "snmpget"
+        });
+        if let PowerControl::Pdu {
+            host,
+            community,
+            oid,
+            ..
+        } = self
+        {
+            command
+                .args(["-v2c", "-c"])
+                .arg(community.as_deref().unwrap_or(DEFAULT_COMMUNITY));
+            match value {
+                Some(value) => command.args([host, oid, "i", &value.to_string()]),
+                None => command.args(["-Oqv", host, oid]),
+            };
+        }
+        command
+    }
+}
+
+impl PowerController for PowerControl {
+    fn power_on(&self) -> Result<(), Error> {
+        match self {
+            PowerControl::Ipmi { .. } => run(&mut self.ipmitool("on"), None).map(|_| ()),
+            PowerControl::Redfish { .. } => self.redfish_reset("On"),
+            PowerControl::Pdu { on, .. } => {
+                run(&mut self.snmp(Some(on.unwrap_or(1))), None).map(|_| ())
+            }
+            PowerControl::Command { on, .. } => run(&mut command_line(on)?, None).map(|_| ()),
+        }
+    }
+
+    fn power_off(&self) -> Result<(), Error> {
+        match self {
+            PowerControl::Ipmi { .. } => run(&mut self.ipmitool("off"), None).map(|_| ()),
+            PowerControl::Redfish { .. } => self.redfish_reset("ForceOff"),
+            PowerControl::Pdu { off, .. } => {
+                run(&mut self.snmp(Some(off.unwrap_or(2))), None).map(|_| ())
+            }
+            PowerControl::Command { off, .. } => run(&mut command_line(off)?, None).map(|_| ()),
+        }
+    }
+
+    fn power_cycle(&self) -> Result<(), Error> {
+        match self {
+            PowerControl::Ipmi { .. } => run(&mut self.ipmitool("cycle"), None).map(|_| ()),
+            _ => switch_off_on(self),
+        }
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        match self {
+            PowerControl::Ipmi { .. } => run(&mut self.ipmitool("reset"), None).map(|_| ()),
+            PowerControl::Redfish { .. } => self.redfish_reset("ForceRestart"),
+            PowerControl::Pdu {
+                reboot: Some(reboot),
+                ..
+            } => run(&mut self.snmp(Some(*reboot)), None).map(|_| ()),
+            _ => self.power_cycle(),
+        }
+    }
+
+    fn power_state(&self) -> Result<PowerState, Error> {
+        match self {
+            PowerControl::Ipmi { .. } => {
+                let output = run(&mut self.ipmitool("status"), None)?;
+                match output.trim().ends_with(" on") {
+                    true => Ok(PowerState::On),
+                    false => Ok(PowerState::Off),
+                }
+            }
+            PowerControl::Redfish { .. } => {
+                let system: serde_json::Value =
+                    serde_json::from_str(&self.redfish(&self.redfish_system()?, None)?)?;
+                match system["PowerState"].as_str() {
+                    Some("On") | Some("PoweringOff") => Ok(PowerState::On),
+                    Some("Off") | Some("PoweringOn") => Ok(PowerState::Off),
+                    state => Err(Error::new(
+                        ErrorKind::HarnessError,
+                        format!("Unexpected Redfish power state: {state:?}"),
+                    )),
+                }
+            }
+            PowerControl::Pdu { on, .. } => {
+                let output = run(&mut self.snmp(None), None)?;
+                match output.trim().parse::<i64>() {
+                    Ok(value) if value == on.unwrap_or(1) => Ok(PowerState::On),
+                    Ok(_) => Ok(PowerState::Off),
+                    Err(_) => Err(Error::new(
+                        ErrorKind::HarnessError,
+                        format!("Unexpected outlet state: {}", output.trim()),
+                    )),
+                }
+            }
+            PowerControl::Command {
+                status: Some(status),
+                ..
+            } => match command_line(status)?
+                .stdin(Stdio::null())
+                .status()?
+                .success()
+            {
+                true => Ok(PowerState::On),
+                false => Ok(PowerState::Off),
+            },
+            PowerControl::Command { status: None, .. } => Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "No power status command configured",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn ipmitool() {
+        let control: PowerControl = serde_json::from_str(
+            r#"{ "ipmi": { "host": "bmc.lab", "user": "admin", "password": "secret" } }"#,
+        )
+        .unwrap();
+        let command = control.ipmitool("cycle");
+        assert_eq!(
+            vec![
+                "-I", "lanplus", "-H", "bmc.lab", "-U", "admin", "-E", "chassis", "power", "cycle"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+        assert!(command
+            .get_envs()
+            .any(|(name, value)| name == "IPMI_PASSWORD" && value == Some("secret".as_ref())));
+    }
+
+    #[test]
+    fn snmp() {
+        let control: PowerControl = serde_json::from_str(
+            r#"{ "pdu": { "host": "pdu.lab", "oid": "1.3.6.1.4.1.318.1.1.12.3.3.1.1.4.8" } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "-v2c",
+                "-c",
+                "private",
+                "pdu.lab",
+                "1.3.6.1.4.1.318.1.1.12.3.3.1.1.4.8",
+                "i",
+                "2"
+            ],
+            control.snmp(Some(2)).get_args().collect::<Vec<_>>()
+        );
+        assert_eq!("snmpget", control.snmp(None).get_program());
+    }
+
+    #[test]
+    fn curl_config() {
+        assert_eq!(r#""admin:p\"a\\ss""#, curl_quote(r#"admin:p"a\ss"#));
+    }
+
+    #[test]
+    fn commands() {
+        let control = PowerControl::Command {
+            on: vec![String::from("true")],
+            off: vec![String::from("false")],
+            status: Some(vec![String::from("false")]),
+        };
+        control.power_on().unwrap();
+        assert_eq!(
+            ErrorKind::HarnessError,
+            control.power_off().err().unwrap().kind()
+        );
+        assert_eq!(PowerState::Off, control.power_state().unwrap());
+    }
+}
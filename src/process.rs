@@ -0,0 +1,297 @@
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Default time allowed for the process to exit when dropped before it is
+/// killed
+pub(crate) const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Interval between checks while waiting for the process
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A configuration for supervising a local process
+///
+/// The process's standard input and output are the system's terminal. It
+/// is paused and resumed with `SIGSTOP` and `SIGCONT`.
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProcessSystemConfig {
+    /// Program to run
+    program: String,
+
+    /// Program arguments
+    args: Option<Vec<String>>,
+
+    /// Environment variables added to the harness's environment
+    env: Option<BTreeMap<String, String>>,
+
+    /// Working directory (defaults to the harness's)
+    cwd: Option<PathBuf>,
+
+    /// Seconds to wait for the process to exit when dropped before killing
+    /// it (defaults to 10)
+    grace_period: Option<u64>,
+}
+
+impl ProcessSystemConfig {
+    /// Command running the program
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(self.args.iter().flatten());
+        command.envs(self.env.iter().flatten());
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        command
+    }
+
+    /// Start the process
+    pub fn build(&self) -> Result<ProcessSystem, Error> {
+        let grace_period = self
+            .grace_period
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_GRACE_PERIOD);
+        ProcessSystem::spawn(&mut self.command(), grace_period)
+    }
+}
+
+/// A supervised local process
+pub struct ProcessSystem {
+    process: Child,
+    stdin: File,
+    stdout: File,
+    paused: bool,
+    grace_period: Duration,
+}
+
+impl ProcessSystem {
+    /// Spawn the command with piped standard input and output
+    pub(crate) fn spawn(command: &mut Command, grace_period: Duration) -> Result<Self, Error> {
+        // Its own process group lets signals reach the process's children
+        command
+            .process_group(0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        log::trace!("Starting {}...", command.get_program().to_string_lossy());
+        let mut process = command.spawn()?;
+        let stdin = process
+            .stdin
+            .take()
+            .map(|stdin| File::from(OwnedFd::from(stdin)));
+        let stdout = process
+            .stdout
+            .take()
+            .map(|stdout| File::from(OwnedFd::from(stdout)));
+        let (Some(stdin), Some(stdout)) = (stdin, stdout) else {
+            return Err(Error::new(
+                ErrorKind::PipeError,
+                "Missing standard I/O pipes",
+            ));
+        };
+        Ok(Self {
+            process,
+            stdin,
+            stdout,
+            paused: false,
+            grace_period,
+        })
+    }
+
+    /// Process ID of the process
+    pub fn pid(&self) -> u32 {
+        self.process.id()
+    }
+
+    /// Send a signal to the process group
+    fn signal(&self, signal: libc::c_int) -> Result<(), Error> {
+        // SAFETY: kill has no memory safety requirements.
+        if unsafe { libc::kill(-(self.process.id() as libc::pid_t), signal) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Wait for the process to exit
+    fn wait_exit(&mut self, deadline: Instant) -> Result<std::process::ExitStatus, Error> {
+        loop {
+            if let Some(status) = self.process.try_wait()? {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for the process to exit",
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// The standard input and output of a supervised process
+pub struct ProcessSystemTerminal {
+    stdin: File,
+    stdout: File,
+}
+
+impl Read for ProcessSystemTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for ProcessSystemTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+/// Descriptor the terminal's output is read from
+impl AsRawFd for ProcessSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stdout.as_raw_fd()
+    }
+}
+
+impl SystemTerminal for ProcessSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\n")?,
+        }
+        self.flush().map_err(|err| err.into())
+    }
+}
+
+impl SystemHarness for ProcessSystem {
+    type Terminal = ProcessSystemTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        Ok(ProcessSystemTerminal {
+            stdin: self.stdin.try_clone()?,
+            stdout: self.stdout.try_clone()?,
+        })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.signal(libc::SIGSTOP)?;
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.signal(libc::SIGCONT)?;
+        self.paused = false;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.signal(libc::SIGTERM)?;
+        // A stopped process only handles the signal once continued
+        if self.paused {
+            self.resume()?;
+        }
+        Ok(())
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        if self.process.try_wait()?.is_some() {
+            Ok(Status::Shutdown)
+        } else if self.paused {
+            Ok(Status::Paused)
+        } else {
+            Ok(Status::Running)
+        }
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.process.try_wait()?.is_none())
+    }
+
+    /// A process killed by a signal reports the signal as the reason
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let status = self.wait_exit(Instant::now() + timeout)?;
+        Ok(ExitStatus {
+            code: status.code(),
+            reason: status.signal().map(|signal| format!("signal {signal}")),
+        })
+    }
+}
+
+impl Drop for ProcessSystem {
+    fn drop(&mut self) {
+        if let Ok(true) = self.running() {
+            log::trace!("Stopping process...");
+            if let Err(err) = self.shutdown() {
+                log::warn!("Error stopping process: {err}");
+            }
+            if self.wait_exit(Instant::now() + self.grace_period).is_err() {
+                log::warn!(
+                    "Process did not exit within {:?}, killing...",
+                    self.grace_period
+                );
+                let _ = self.signal(libc::SIGKILL);
+                let _ = self.process.wait();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn config(script: &str) -> ProcessSystemConfig {
+        ProcessSystemConfig {
+            program: String::from("sh"),
+            args: Some(vec![String::from("-c"), String::from(script)]),
+            env: Some(BTreeMap::from([(
+                String::from("GREETING"),
+                String::from("hello"),
+            )])),
+            cwd: Some(PathBuf::from("/")),
+            grace_period: Some(1),
+        }
+    }
+
+    #[test]
+    fn terminal() {
+        let mut system = config("read name; echo \"$GREETING $name from $(pwd)\"")
+            .build()
+            .unwrap();
+        let mut terminal = system.terminal().unwrap();
+        terminal.write_all(b"world").unwrap();
+        terminal.send_key(Key::Enter).unwrap();
+        let mut output = String::new();
+        terminal.read_to_string(&mut output).unwrap();
+        assert_eq!("hello world from /\n", output);
+        let status = system.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(Some(0), status.code);
+        assert!(!system.running().unwrap());
+    }
+
+    #[test]
+    fn pause_and_shutdown() {
+        let mut system = config("sleep 30").build().unwrap();
+        system.pause().unwrap();
+        assert_eq!(Status::Paused, system.status().unwrap());
+        system.shutdown().unwrap();
+        let status = system.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(None, status.code);
+        assert_eq!(Some(format!("signal {}", libc::SIGTERM)), status.reason);
+        assert_eq!(Status::Shutdown, system.status().unwrap());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+/// A pseudo-terminal pair
+pub(crate) struct Pty {
+    /// Controlling side, read and written by the harness
+    pub master: File,
+
+    /// Terminal side, given to the child process
+    pub slave: File,
+}
+
+impl Pty {
+    /// Open a new pseudo-terminal
+    pub fn open() -> std::io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        // SAFETY: openpty writes two valid descriptors on success, and the
+        // null name, termios and window size arguments are optional.
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: both descriptors were just opened and are owned here.
+        unsafe {
+            Ok(Self {
+                master: File::from_raw_fd(master),
+                slave: File::from_raw_fd(slave),
+            })
+        }
+    }
+
+    /// Spawn the command with the terminal as its controlling terminal,
+    /// returning the process and the controlling side
+    ///
+    /// Owning the terminal gives the process job control and lets it
+    /// receive `SIGWINCH` when the window size changes.
+    pub fn spawn(self, command: &mut Command) -> std::io::Result<(Child, File)> {
+        command
+            .stdin(self.slave.try_clone()?)
+            .stdout(self.slave.try_clone()?)
+            .stderr(self.slave);
+        // SAFETY: setsid and ioctl are async-signal-safe and standard input
+        // is the terminal by the time the hook runs.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok((command.spawn()?, self.master))
+    }
+}
+
+/// A process running on its own pseudo-terminal, killed when dropped
+pub(crate) struct PtySession {
+    process: Child,
+    tty: File,
+}
+
+impl PtySession {
+    /// Spawn the command on a new pseudo-terminal
+    pub fn spawn(command: &mut Command) -> std::io::Result<Self> {
+        let (process, tty) = Pty::open()?.spawn(command)?;
+        Ok(Self { process, tty })
+    }
+
+    /// Resize the terminal, signalling the process
+    pub fn resize(&self, cols: u16, rows: u16) -> std::io::Result<()> {
+        set_window_size(&self.tty, cols, rows)
+    }
+}
+
+impl Read for PtySession {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.tty.read(buf) {
+            // Linux reports EIO once the other side of the terminal closes
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+impl Write for PtySession {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tty.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.tty.flush()
+    }
+}
+
+impl AsRawFd for PtySession {
+    fn as_raw_fd(&self) -> RawFd {
+        self.tty.as_raw_fd()
+    }
+}
+
+impl Drop for PtySession {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Set the window size of the terminal behind the descriptor
+pub(crate) fn set_window_size(tty: &File, cols: u16, rows: u16) -> std::io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: the descriptor is open for the lifetime of the borrow and
+    // TIOCSWINSZ only reads the given winsize.
+    if unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Get the window size of the terminal behind the descriptor
+#[cfg(test)]
+fn window_size(tty: &File) -> std::io::Result<(u16, u16)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ writes a winsize into the given pointer.
+    if unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((size.ws_col, size.ws_row))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn echo() {
+        let mut pty = Pty::open().unwrap();
+        pty.master.write_all(b"hi\n").unwrap();
+        let mut buf = [0u8; 2];
+        pty.slave.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hi", &buf);
+    }
+
+    #[test]
+    fn controlling_terminal() {
+        let (mut process, mut master) = Pty::open()
+            .unwrap()
+            .spawn(Command::new("sh").args(["-c", "exec < /dev/tty && echo ok"]))
+            .unwrap();
+        assert!(process.wait().unwrap().success());
+        let mut buf = [0u8; 2];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ok", &buf);
+    }
+
+    #[test]
+    fn session_eof() {
+        let mut session = PtySession::spawn(Command::new("echo").arg("done")).unwrap();
+        let mut output = String::new();
+        session.read_to_string(&mut output).unwrap();
+        assert_eq!("done\r\n", output);
+    }
+
+    #[test]
+    fn resize() {
+        let pty = Pty::open().unwrap();
+        set_window_size(&pty.master, 132, 43).unwrap();
+        assert_eq!((132, 43), window_size(&pty.slave).unwrap());
+    }
+}
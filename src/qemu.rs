@@ -1,18 +1,207 @@
-use crate::{Error, ErrorKind, EventPublisher, EventSubscriber, Key, Status, SystemHarness, SystemTerminal};
+use crate::{
+    CollectArtifacts, Error, ErrorKind, EventPublisher, EventSubscriber, ExitStatus, FileTransfer,
+    HostEndpoint, Key, PortForward, PortReservation, Status, SystemHarness, SystemTerminal,
+    TrafficCapture,
+};
 use cmdstruct::Command;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
-use std::process::Child;
+use std::net::Ipv4Addr;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 mod args;
 
 mod models;
-use models::*;
+use models::Backend;
+pub use models::{
+    BlockDev, Boot, CharDev, Device, Discard, FwCfg, Icount, KernelCommandLine, Machine, NetDev,
+    OnOff, Plugin, RecordReplay, Rtc, RtcClock, Smp,
+};
 
 mod qmp;
 use qmp::QmpStream;
 
+mod limits;
+pub use limits::ResourceLimits;
+
+mod output;
+use output::{OutputPump, OutputSubscribers};
+pub use output::{OutputSink, OutputStream, OutputSubscriber};
+
+mod ready;
+pub use ready::ReadyCondition;
+
+use crate::allocator::AUTO;
+use crate::artifacts::ArtifactDir;
+use crate::metrics::{self, Histogram};
+use crate::retry::RetryPolicy;
+use crate::runtime::RuntimeDir;
+use crate::trace;
+
+mod qga;
+pub use qga::{GuestAgent, GuestIpAddress, GuestNetworkInterface, GuestShutdownMode};
+
+mod validate;
+
+mod handle;
+pub use handle::QemuSystemHandle;
+
+mod builder;
+pub use builder::QemuSystemConfigBuilder;
+
+mod presets;
+pub use presets::QemuPreset;
+
+mod transport;
+use transport::Channel;
+pub use transport::{QemuEndpoint, QemuTransport};
+
+pub mod images;
+
+mod cloud_init;
+pub use cloud_init::{CloudInit, CloudInitUser, SeedFormat};
+
+mod ignition;
+pub use ignition::Ignition;
+
+mod network;
+pub use network::{NetworkNode, VirtualNetwork};
+
+/// Default time allowed for QEMU to start accepting QMP connections
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Ports of the VNC displays QEMU can listen on, from display 0
+const VNC_PORTS: std::ops::Range<u16> = 5900..6900;
+
+/// Number of traffic captures started, so each `filter-dump` gets its own id
+static CAPTURE: AtomicUsize = AtomicUsize::new(0);
+
+/// A config with `auto` values replaced by allocated resources
+#[derive(Default)]
+struct Allocated {
+    config: QemuSystemConfig,
+    gdb: Option<HostEndpoint>,
+    vnc: Option<HostEndpoint>,
+}
+
+/// Default time allowed for a ready condition to be met
+const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default time allowed for QEMU to exit before it is killed
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Time allowed for more serial output when collecting artifacts
+const SERIAL_DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Longest delay between QMP connection attempts
+const MAX_CONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// Files created by a system in its runtime directory
+const RUNTIME_FILES: [&str; 5] = [
+    "qmp.sock",
+    "serial.sock",
+    "qga.sock",
+    "qemu.stderr",
+    "plugin.log",
+];
+
+/// Connect to the QMP monitor, retrying with backoff until the timeout
+///
+/// Fails early if the QEMU process exits, including its exit status and
+/// whatever it wrote to stderr.
+fn connect_qmp(
+    process: &mut Child,
+    endpoint: &QemuEndpoint,
+    timeout: Duration,
+    stderr: impl FnOnce() -> String,
+) -> Result<Channel, Error> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(10);
+    loop {
+        if let Some(status) = process.try_wait()? {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                format!("QEMU exited during startup ({status}): {}", stderr().trim()),
+            ));
+        }
+        match endpoint.connect() {
+            Ok(stream) => return Ok(stream),
+            Err(err) if Instant::now() >= deadline => {
+                let _ = process.kill();
+                let _ = process.wait();
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("Timed out connecting to QMP socket: {err}"),
+                ));
+            }
+            Err(_) => {
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(MAX_CONNECT_DELAY);
+            }
+        }
+    }
+}
+
+/// Wait for a process to exit, killing it after the grace period
+fn reap(process: &mut Child, grace_period: Duration) {
+    let deadline = Instant::now() + grace_period;
+    while Instant::now() < deadline {
+        match process.try_wait() {
+            Ok(Some(_)) => return,
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(err) => {
+                log::warn!("Error waiting for system: {err}");
+                break;
+            }
+        }
+    }
+    log::warn!("System did not exit within {grace_period:?}, killing...");
+    if let Err(err) = process.kill() {
+        log::warn!("Error killing system: {err}");
+    }
+    let _ = process.wait();
+}
+
+/// QEMU process that is killed if startup fails before it is released
+struct StartingProcess(Option<Child>);
+
+impl StartingProcess {
+    /// Keep the process running past startup
+    fn release(mut self) -> Child {
+        self.0.take().expect("process already released")
+    }
+}
+
+impl std::ops::Deref for StartingProcess {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        self.0.as_ref().expect("process already released")
+    }
+}
+
+impl std::ops::DerefMut for StartingProcess {
+    fn deref_mut(&mut self) -> &mut Child {
+        self.0.as_mut().expect("process already released")
+    }
+}
+
+impl Drop for StartingProcess {
+    fn drop(&mut self) {
+        if let Some(process) = &mut self.0 {
+            log::trace!("Killing system that failed to start...");
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
 fn qemu_system_bin(config: &QemuSystemConfig) -> String {
     format!("qemu-system-{}", config.arch)
 }
@@ -21,7 +210,8 @@ fn qemu_system_bin(config: &QemuSystemConfig) -> String {
 ///
 /// This config can be serialized and deserialized using
 /// serde.
-#[derive(Clone, Command, Serialize, Deserialize)]
+#[derive(Clone, Default, Command, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[command(executable_fn = qemu_system_bin)]
 pub struct QemuSystemConfig {
     arch: String,
@@ -38,6 +228,7 @@ pub struct QemuSystemConfig {
     #[arg(option = "-smp")]
     smp: Option<Smp>,
 
+    /// Accelerator, e.g. `kvm` on Linux or `whpx` on Windows
     #[arg(option = "-accel")]
     accel: Option<String>,
 
@@ -68,52 +259,598 @@ pub struct QemuSystemConfig {
     #[arg(option = "-blockdev")]
     blockdev: Option<Vec<BlockDev>>,
 
+    #[arg(option = "-kernel")]
+    kernel: Option<String>,
+
+    #[arg(option = "-initrd")]
+    initrd: Option<String>,
+
+    #[arg(option = "-append")]
+    append: Option<KernelCommandLine>,
+
+    #[arg(option = "-fw_cfg")]
+    fw_cfg: Option<Vec<FwCfg>>,
+
+    /// Real-time clock, e.g. to start the guest at another date
+    #[arg(option = "-rtc")]
+    rtc: Option<Rtc>,
+
+    /// Instruction counting, to run the guest in deterministic virtual time
+    #[arg(option = "-icount")]
+    icount: Option<Icount>,
+
+    /// TCG plugins instrumenting the guest, e.g. to count instructions
+    #[arg(option = "-plugin")]
+    plugins: Option<Vec<Plugin>>,
+
+    /// File plugin output is written to
+    ///
+    /// Defaults to `plugin.log` in the runtime directory, which is removed
+    /// with the system.
+    plugin_log: Option<PathBuf>,
+
+    /// GDB server (e.g. `tcp::1234`), or `auto` for a free loopback port
+    #[arg(option = "-gdb")]
+    gdb: Option<String>,
+
+    /// VNC display (e.g. `:1`), or `auto` for a free loopback display
+    #[arg(option = "-vnc")]
+    vnc: Option<String>,
+
+    /// Directory for the QMP, serial and guest agent sockets
+    ///
+    /// Defaults to a temporary directory unique to each system.
+    runtime_dir: Option<PathBuf>,
+
+    /// Transport for the QMP, serial and guest agent channels (defaults to
+    /// Unix sockets on Unix hosts and TCP elsewhere)
+    transport: Option<QemuTransport>,
+
+    /// Seconds to wait for QEMU to start (defaults to 30)
+    startup_timeout: Option<u64>,
+
+    /// Condition that must be met before the system is considered ready
+    ready: Option<ReadyCondition>,
+
+    /// Seconds to wait for the ready condition (defaults to 300)
+    ready_timeout: Option<u64>,
+
+    /// Destination for QEMU's standard output (defaults to inherit)
+    stdout: Option<OutputSink>,
+
+    /// Destination for QEMU's standard error (defaults to inherit)
+    stderr: Option<OutputSink>,
+
+    /// Seconds to wait for QEMU to exit when dropped before killing it
+    /// (defaults to 10)
+    grace_period: Option<u64>,
+
+    /// Resource limits for the QEMU process
+    limits: Option<ResourceLimits>,
+
+    /// How QMP commands that fail for transient reasons are retried
+    /// (defaults to never)
+    retry: Option<RetryPolicy>,
+
+    /// Attach a QEMU guest agent channel
+    #[serde(default)]
+    guest_agent: bool,
+
     /// Extra QEMU args
-    extra_args: Option<Vec<String>>
+    extra_args: Option<Vec<String>>,
 }
 
 impl QemuSystemConfig {
+    /// Replace `auto` values with resources allocated for the system
+    ///
+    /// Reserved ports are added to the reservations, which should be kept
+    /// until QEMU has bound them.
+    fn allocate(&self, reservations: &mut Vec<PortReservation>) -> Result<Allocated, Error> {
+        let mut config = self.clone();
+        for device in config.device.iter_mut().flatten() {
+            device.allocate_mac();
+        }
+        let endpoint = |port| HostEndpoint::new(Ipv4Addr::LOCALHOST.to_string(), port);
+        let mut allocated = Allocated::default();
+        if config.gdb.as_deref() == Some(AUTO) {
+            let reservation = crate::reserve_port()?;
+            config.gdb = Some(format!("tcp:{}", endpoint(reservation.port())));
+            allocated.gdb = Some(endpoint(reservation.port()));
+            reservations.push(reservation);
+        }
+        if config.vnc.as_deref() == Some(AUTO) {
+            let reservation = crate::reserve_port_in(VNC_PORTS)?;
+            let display = reservation.port() - VNC_PORTS.start;
+            config.vnc = Some(format!("{}:{display}", Ipv4Addr::LOCALHOST));
+            allocated.vnc = Some(endpoint(reservation.port()));
+            reservations.push(reservation);
+        }
+        allocated.config = config;
+        Ok(allocated)
+    }
+
     pub fn build(&self) -> Result<QemuSystem, Error> {
-        let mut command = self.command();
+        let start = Instant::now();
+        let span = trace::build("qemu");
+        let runtime_dir = RuntimeDir::new(self.runtime_dir.as_deref(), &RUNTIME_FILES)?;
+        let id = runtime_dir.path().display().to_string();
+        span.record_system(&id);
+        let transport = self.transport.unwrap_or_default();
+        let mut reservations = Vec::new();
+        let qmp_endpoint = QemuEndpoint::new(transport, &runtime_dir, "qmp", &mut reservations)?;
+        let serial_endpoint =
+            QemuEndpoint::new(transport, &runtime_dir, "serial", &mut reservations)?;
+        let qga_endpoint = QemuEndpoint::new(transport, &runtime_dir, "qga", &mut reservations)?;
+        let stderr_path = runtime_dir.file("qemu.stderr");
+        let allocated = self.allocate(&mut reservations)?;
+        let mut command = allocated.config.command();
+        let plugin_log = match &self.plugins {
+            Some(plugins) if !plugins.is_empty() => {
+                let path = match &self.plugin_log {
+                    Some(path) => path.clone(),
+                    None => runtime_dir.file("plugin.log"),
+                };
+                command.args(["-d", "plugin", "-D"]).arg(&path);
+                Some(path)
+            }
+            _ => None,
+        };
 
         command.arg("-nographic");
-        command.args(["-qmp", "unix:qmp.sock,server=on,wait=off"]);
-        command.args(["-serial", "unix:serial.sock,server=on,wait=off"]);
+        command.arg("-qmp");
+        command.arg(qmp_endpoint.chardev());
+        command.arg("-serial");
+        command.arg(serial_endpoint.chardev());
+        if self.guest_agent {
+            command.arg("-chardev");
+            command.arg(format!("{},id=qga0", qga_endpoint.chardev_backend()));
+            command.args(["-device", "virtio-serial"]);
+            command.args([
+                "-device",
+                "virtserialport,chardev=qga0,name=org.qemu.guest_agent.0",
+            ]);
+        }
 
         if let Some(extra_args) = &self.extra_args {
             command.args(extra_args);
         }
 
+        if let Some(limits) = &self.limits {
+            command = limits.apply(command)?;
+        }
+
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+
         log::trace!("Starting system...");
-        let mut process = command.spawn()?;
+        let mut process = StartingProcess(Some(command.spawn()?));
 
-        log::trace!("Connecting to QMP socket...");
-        let mut qmp_socket = None;
-        while process.try_wait()?.is_none() && qmp_socket.is_none() {
-            qmp_socket = UnixStream::connect("qmp.sock").ok();
+        let output_subscribers = OutputSubscribers::default();
+        if let Some(stdout) = process.stdout.take() {
+            let subscribers = output_subscribers.clone();
+            OutputPump::new(OutputStream::Stdout, self.stdout.as_ref(), subscribers)?.spawn(stdout);
         }
-        let qmp = QmpStream::new(qmp_socket.unwrap())?;
-        log::trace!("Connecting to serial socket...");
-        let serial = UnixStream::connect("serial.sock")?;
-        log::trace!("System ready.");
-        Ok(QemuSystem {
-            process,
+        let stderr_pump = match process.stderr.take() {
+            Some(stderr) => {
+                let subscribers = output_subscribers.clone();
+                let pump =
+                    OutputPump::new(OutputStream::Stderr, self.stderr.as_ref(), subscribers)?
+                        .tee(std::fs::File::create(&stderr_path)?);
+                Some(pump.spawn(stderr))
+            }
+            None => None,
+        };
+
+        log::trace!("Connecting to QMP monitor...");
+        let timeout = self
+            .startup_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+        let qmp_socket = connect_qmp(&mut process, &qmp_endpoint, timeout, || {
+            if let Some(stderr_pump) = stderr_pump {
+                let _ = stderr_pump.join();
+            }
+            std::fs::read_to_string(&stderr_path).unwrap_or_default()
+        })?;
+        let qmp = QmpStream::new(qmp_socket, id)?;
+        log::trace!("Connecting to serial console...");
+        let serial = serial_endpoint.connect()?;
+        let (guest_agent, qga_endpoint) = if self.guest_agent {
+            log::trace!("Connecting to guest agent...");
+            (
+                Some(GuestAgent::new(qga_endpoint.connect()?)),
+                Some(qga_endpoint),
+            )
+        } else {
+            (None, None)
+        };
+        let mut system = QemuSystem {
+            process: Some(process.release()),
             serial,
             qmp,
-        })
+            guest_agent,
+            qmp_endpoint,
+            serial_endpoint,
+            guest_agent_endpoint: qga_endpoint,
+            output_subscribers,
+            runtime_dir: Some(runtime_dir),
+            netdevs: self
+                .netdev
+                .iter()
+                .flatten()
+                .map(|netdev| netdev.id().to_string())
+                .collect(),
+            captures: Vec::new(),
+            reservations,
+            gdb: allocated.gdb,
+            vnc: allocated.vnc,
+            plugin_log,
+            ready: self.ready.clone(),
+            ready_timeout: self
+                .ready_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_READY_TIMEOUT),
+            grace_period: self
+                .grace_period
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_GRACE_PERIOD),
+        };
+        if let Some(retry) = &self.retry {
+            system.set_retry(retry.clone());
+        }
+        system.wait_ready()?;
+        metrics::record(Histogram::BootTime, "qemu", start.elapsed());
+        log::trace!("System ready.");
+        Ok(system)
+    }
+
+    /// Start a system replaying the execution this config recorded
+    ///
+    /// The config must record to a file with [`Icount::record`], and the
+    /// recording system must have exited. Input sent to the replaying
+    /// system is ignored, as the recorded input is replayed instead.
+    pub fn replay(&self) -> Result<QemuSystem, Error> {
+        let icount = match &self.icount {
+            Some(icount) => icount.replay()?,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidConfig,
+                    "Only a system recorded to a file can be replayed",
+                ))
+            }
+        };
+        log::trace!("Replaying recorded execution...");
+        QemuSystemConfig {
+            icount: Some(icount),
+            ..self.clone()
+        }
+        .build()
     }
 }
 
+/// Method used to shut down a QEMU system
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShutdownMethod {
+    /// ACPI power button press via `system_powerdown`
+    Acpi,
+
+    /// Request through the guest agent via `guest-shutdown`
+    GuestAgent(GuestShutdownMode),
+}
+
 /// A running QEMU system
 pub struct QemuSystem {
-    process: Child,
-    serial: UnixStream,
+    /// QEMU process, if started by the harness
+    process: Option<Child>,
+    serial: Channel,
     qmp: QmpStream,
+    guest_agent: Option<GuestAgent>,
+    qmp_endpoint: QemuEndpoint,
+    serial_endpoint: QemuEndpoint,
+    guest_agent_endpoint: Option<QemuEndpoint>,
+    output_subscribers: OutputSubscribers,
+    runtime_dir: Option<RuntimeDir>,
+    /// Ids of the configured network backends
+    netdevs: Vec<String>,
+    /// Ids of the `filter-dump` objects capturing traffic
+    captures: Vec<String>,
+    /// Ports reserved for the system
+    reservations: Vec<PortReservation>,
+    gdb: Option<HostEndpoint>,
+    vnc: Option<HostEndpoint>,
+    /// File plugin output is written to, if plugins are loaded
+    plugin_log: Option<PathBuf>,
+    ready: Option<ReadyCondition>,
+    ready_timeout: Duration,
+    grace_period: Duration,
+}
+
+impl QemuSystem {
+    /// Attach to an externally started QEMU instance
+    ///
+    /// Only the QMP monitor and serial console are connected. The instance
+    /// is not stopped when the system is dropped.
+    pub fn attach(
+        qmp: impl Into<QemuEndpoint>,
+        serial: impl Into<QemuEndpoint>,
+    ) -> Result<Self, Error> {
+        let qmp_endpoint = qmp.into();
+        let serial_endpoint = serial.into();
+        log::trace!("Attaching to QMP monitor: {}", qmp_endpoint.chardev());
+        let qmp = QmpStream::new(qmp_endpoint.connect()?, qmp_endpoint.chardev())?;
+        let serial = serial_endpoint.connect()?;
+        Ok(Self {
+            process: None,
+            serial,
+            qmp,
+            guest_agent: None,
+            qmp_endpoint,
+            serial_endpoint,
+            guest_agent_endpoint: None,
+            output_subscribers: OutputSubscribers::default(),
+            runtime_dir: None,
+            netdevs: Vec::new(),
+            captures: Vec::new(),
+            reservations: Vec::new(),
+            gdb: None,
+            vnc: None,
+            plugin_log: None,
+            ready: None,
+            ready_timeout: DEFAULT_READY_TIMEOUT,
+            grace_period: DEFAULT_GRACE_PERIOD,
+        })
+    }
+
+    /// Detach from the system, leaving it running
+    ///
+    /// Returns a handle that can be serialized and used to re-open the
+    /// system later, possibly from another process.
+    pub fn detach(mut self) -> Result<QemuSystemHandle, Error> {
+        let runtime_dir = self.runtime_dir.take().ok_or(Error::new(
+            ErrorKind::HarnessError,
+            "Only systems started by the harness can be detached",
+        ))?;
+        let pid = self.process.take().map(|process| process.id());
+        log::trace!("Detaching system: {}", runtime_dir.path().display());
+        Ok(QemuSystemHandle {
+            pid,
+            owned: runtime_dir.owned(),
+            runtime_dir: runtime_dir.keep(),
+            qmp: self.qmp_endpoint.clone(),
+            serial: self.serial_endpoint.clone(),
+            guest_agent: self.guest_agent_endpoint.clone(),
+        })
+    }
+
+    /// Retry QMP commands that fail for transient reasons, reconnecting to
+    /// the monitor before each retry
+    pub fn set_retry(&mut self, retry: RetryPolicy) {
+        self.qmp.set_retry(retry, self.qmp_endpoint.clone());
+    }
+
+    /// Process ID of the QEMU process, if started by the harness
+    pub fn pid(&self) -> Option<u32> {
+        self.process.as_ref().map(Child::id)
+    }
+
+    /// Wait until the configured ready condition is met
+    ///
+    /// Returns immediately if no condition is configured. A serial
+    /// condition consumes the console output it reads.
+    pub fn wait_ready(&mut self) -> Result<(), Error> {
+        let deadline = Instant::now() + self.ready_timeout;
+        match &self.ready {
+            None => Ok(()),
+            Some(ReadyCondition::Serial { pattern }) => {
+                log::trace!("Waiting for serial output matching '{pattern}'...");
+                let mut serial = self.serial.try_clone()?;
+                let set_timeout =
+                    |serial: &mut Channel, timeout| serial.set_read_timeout(Some(timeout));
+                let result = ready::wait_for_match(&mut serial, pattern, deadline, set_timeout);
+                serial.set_read_timeout(None)?;
+                result
+            }
+            Some(ReadyCondition::GuestAgent) => {
+                log::trace!("Waiting for guest agent...");
+                self.wait_for_guest(self.ready_timeout)
+            }
+            Some(ReadyCondition::TcpPort { host, port }) => {
+                log::trace!("Waiting for {host}:{port}...");
+                ready::wait_for_port(host, *port, deadline)
+            }
+        }
+    }
+
+    /// Subscribe to lines written by the QEMU process to stdout or stderr
+    ///
+    /// Only output written after subscribing is delivered.
+    pub fn subscribe_output(&mut self, subscriber: impl OutputSubscriber) -> Result<(), Error> {
+        self.output_subscribers
+            .lock()
+            .map_err(|err| Error::new(ErrorKind::HarnessError, err.to_string()))?
+            .push(Box::new(subscriber));
+        Ok(())
+    }
+
+    /// Directory containing the system's runtime sockets, if started by
+    /// the harness
+    pub fn runtime_dir(&self) -> Option<&Path> {
+        self.runtime_dir.as_ref().map(RuntimeDir::path)
+    }
+
+    /// Host endpoint of the GDB server, if allocated with `gdb: auto`
+    pub fn gdb_endpoint(&self) -> Option<&HostEndpoint> {
+        self.gdb.as_ref()
+    }
+
+    /// Host endpoint of the VNC display, if allocated with `vnc: auto`
+    pub fn vnc_endpoint(&self) -> Option<&HostEndpoint> {
+        self.vnc.as_ref()
+    }
+
+    /// Output written by the configured TCG plugins so far
+    ///
+    /// Many plugins only report when QEMU exits, so wait for the system to
+    /// exit before reading their output.
+    pub fn plugin_output(&self) -> Result<String, Error> {
+        let path = self
+            .plugin_log
+            .as_ref()
+            .ok_or(Error::new(ErrorKind::HarnessError, "No plugins configured"))?;
+        Ok(std::fs::read_to_string(path)?)
+    }
+
+    /// Get a connection to the guest agent
+    pub fn guest_agent(&self) -> Result<GuestAgent, Error> {
+        self.guest_agent
+            .as_ref()
+            .ok_or(Error::new(
+                ErrorKind::HarnessError,
+                "Guest agent not configured",
+            ))
+            .and_then(GuestAgent::try_clone)
+    }
+
+    /// Shut down the system using the given method
+    pub fn shutdown_with(&mut self, method: ShutdownMethod) -> Result<(), Error> {
+        match method {
+            ShutdownMethod::Acpi => self
+                .qmp
+                .send_command(qmp::QmpCommand::SystemPowerdown)
+                .map(|_| ()),
+            ShutdownMethod::GuestAgent(mode) => self.guest_agent()?.shutdown(mode),
+        }
+    }
+
+    /// Set the guest's wall clock forward, e.g. past a certificate's expiry
+    ///
+    /// Needs the guest agent, which sets the clock with `guest-set-time`.
+    /// The virtual clock QEMU runs the guest on isn't stepped, so timers
+    /// and sleeps in the guest don't end any sooner. To have guest sleeps
+    /// pass without waiting, run with [`Icount`] and `sleep=off`.
+    pub fn advance_guest_clock(&mut self, by: Duration) -> Result<(), Error> {
+        let mut guest_agent = self.guest_agent()?;
+        let time = guest_agent.time()? + by;
+        log::trace!("Setting guest clock forward by {by:?}");
+        guest_agent.set_time(Some(time))
+    }
+
+    /// Run a human monitor command, failing if it reports an error
+    fn human_monitor_command(&mut self, command_line: String) -> Result<(), Error> {
+        self.qmp
+            .send_command(qmp::QmpCommand::HumanMonitorCommand(
+                qmp::HumanMonitorCommand { command_line },
+            ))
+            .and_then(|ret| match ret {
+                qmp::QmpReturn::HumanMonitor(output) if output.trim().is_empty() => Ok(()),
+                qmp::QmpReturn::HumanMonitor(output) => Err(Error::new(
+                    ErrorKind::HarnessError,
+                    output.trim().to_string(),
+                )),
+                _ => Err(Error::new(ErrorKind::HarnessError, "Unexpected return")),
+            })
+    }
+
+    /// Save a snapshot of the whole system under the given tag
+    pub fn save_snapshot(&mut self, tag: &str) -> Result<(), Error> {
+        log::trace!("Saving snapshot: {tag}");
+        self.human_monitor_command(format!("savevm {tag}"))
+    }
+
+    /// Save a snapshot with guest filesystems frozen for consistency
+    ///
+    /// Requires the guest agent. Filesystems are thawed even if saving
+    /// the snapshot fails, in which case that error is returned.
+    pub fn save_frozen_snapshot(&mut self, tag: &str) -> Result<(), Error> {
+        let mut agent = self.guest_agent()?;
+        agent.fsfreeze()?;
+        let result = self.save_snapshot(tag);
+        let thawed = agent.fsthaw().map(|_| ());
+        match result {
+            Ok(()) => thawed,
+            Err(err) => {
+                if let Err(thaw_err) = thawed {
+                    log::warn!("Failed to thaw guest filesystems: {thaw_err}");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Restore the system to a previously saved snapshot
+    pub fn load_snapshot(&mut self, tag: &str) -> Result<(), Error> {
+        log::trace!("Loading snapshot: {tag}");
+        self.human_monitor_command(format!("loadvm {tag}"))
+    }
+
+    /// Delete a previously saved snapshot
+    pub fn delete_snapshot(&mut self, tag: &str) -> Result<(), Error> {
+        log::trace!("Deleting snapshot: {tag}");
+        self.human_monitor_command(format!("delvm {tag}"))
+    }
+
+    /// Capture traffic on a network backend to a pcap file
+    ///
+    /// Attaches a `filter-dump` object to the backend, which QEMU writes
+    /// each packet to as it passes. Use this to pick the backend of a system
+    /// with several, or of an attached system, whose backends aren't known.
+    /// A backend can be captured more than once, to separate files.
+    pub fn capture_netdev(&mut self, netdev: &str, path: &Path) -> Result<(), Error> {
+        let file = std::path::absolute(path)?;
+        let id = format!(
+            "system-harness-dump-{netdev}-{}-{}",
+            std::process::id(),
+            CAPTURE.fetch_add(1, Ordering::Relaxed)
+        );
+        log::trace!("Capturing traffic on {netdev} to {}", file.display());
+        self.qmp
+            .send_command(qmp::QmpCommand::ObjectAdd(qmp::ObjectAdd {
+                qom_type: String::from("filter-dump"),
+                id: id.clone(),
+                properties: BTreeMap::from([
+                    (String::from("netdev"), netdev.to_string()),
+                    (String::from("file"), file.to_string_lossy().into_owned()),
+                ]),
+            }))?;
+        self.captures.push(id);
+        Ok(())
+    }
+
+    /// Read the serial output that hasn't been read yet
+    fn drain_serial(&self) -> Result<Vec<u8>, Error> {
+        let mut serial = self.serial.try_clone()?;
+        serial.set_read_timeout(Some(SERIAL_DRAIN_TIMEOUT))?;
+        let mut output = Vec::new();
+        let mut buf = [0u8; 4096];
+        let result = loop {
+            match serial.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(len) => output.extend_from_slice(&buf[..len]),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break Ok(())
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        serial.set_read_timeout(None)?;
+        result?;
+        Ok(output)
+    }
+
+    /// Wait until the guest agent responds, indicating the guest OS is up
+    pub fn wait_for_guest(&self, timeout: Duration) -> Result<(), Error> {
+        self.guest_agent()?.wait(timeout)
+    }
 }
 
 pub struct QemuSystemTerminal {
-    serial: UnixStream,
-    qmp: QmpStream
+    serial: Channel,
+    qmp: QmpStream,
 }
 
 impl Read for QemuSystemTerminal {
@@ -132,8 +869,15 @@ impl Write for QemuSystemTerminal {
     }
 }
 
-impl SystemTerminal for QemuSystemTerminal {
+/// Descriptor the terminal's output is read from
+#[cfg(unix)]
+impl AsRawFd for QemuSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.serial.as_raw_fd()
+    }
+}
 
+impl SystemTerminal for QemuSystemTerminal {
     fn send_key(&mut self, key: Key) -> Result<(), Error> {
         self.qmp
             .send_command(qmp::QmpCommand::SendKey(qmp::KeyCommand {
@@ -141,28 +885,29 @@ impl SystemTerminal for QemuSystemTerminal {
             }))
             .map(|_| ())
     }
-
 }
 
 impl SystemHarness for QemuSystem {
-
     type Terminal = QemuSystemTerminal;
 
     fn terminal(&self) -> Result<Self::Terminal, Error> {
         let serial = self.serial.try_clone()?;
         let qmp = self.qmp.try_clone()?;
-        Ok(QemuSystemTerminal {
-            serial,
-            qmp
-        })
+        Ok(QemuSystemTerminal { serial, qmp })
     }
 
-
     fn running(&mut self) -> Result<bool, Error> {
-        self.process
-            .try_wait()
-            .map(|status| status == None)
-            .map_err(|err| err.into())
+        match &mut self.process {
+            Some(process) => process
+                .try_wait()
+                .map(|status| status.is_none())
+                .map_err(|err| err.into()),
+            None => match self.status() {
+                Ok(status) => Ok(status != Status::Shutdown),
+                Err(err) if err.kind() == ErrorKind::PipeError => Ok(false),
+                Err(err) => Err(err),
+            },
+        }
     }
 
     fn pause(&mut self) -> Result<(), Error> {
@@ -174,9 +919,7 @@ impl SystemHarness for QemuSystem {
     }
 
     fn shutdown(&mut self) -> Result<(), Error> {
-        self.qmp
-            .send_command(qmp::QmpCommand::SystemPowerdown)
-            .map(|_| ())
+        self.shutdown_with(ShutdownMethod::Acpi)
     }
 
     fn status(&mut self) -> Result<Status, Error> {
@@ -184,12 +927,128 @@ impl SystemHarness for QemuSystem {
             .send_command(qmp::QmpCommand::QueryStatus)
             .and_then(|ret| match ret {
                 qmp::QmpReturn::StatusInfo(status) => status.try_into(),
-                _ => Err(Error::new(
-                    ErrorKind::HarnessError,
-                    format!("Unexpected return"),
-                )),
+                _ => Err(Error::new(ErrorKind::HarnessError, "Unexpected return")),
             })
     }
+
+    /// Wait for the guest to shut down and QEMU to exit
+    ///
+    /// The reason is taken from QMP's `SHUTDOWN` event. The exit code is only
+    /// known for instances started by the harness, which must exit on
+    /// shutdown (i.e. without `-no-shutdown`).
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let deadline = Instant::now() + timeout;
+        let timeout_error =
+            || Error::new(ErrorKind::Timeout, "Timed out waiting for system to exit");
+        let reason = match self.qmp.wait_for_shutdown(deadline) {
+            Err(_) if Instant::now() >= deadline => return Err(timeout_error()),
+            reason => reason?,
+        };
+        let code = match &mut self.process {
+            Some(process) => loop {
+                if let Some(status) = process.try_wait()? {
+                    break status.code();
+                }
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(timeout_error());
+                }
+                std::thread::sleep(remaining.min(Duration::from_millis(50)));
+            },
+            None => None,
+        };
+        Ok(ExitStatus { code, reason })
+    }
+}
+
+impl FileTransfer for QemuSystem {
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), Error> {
+        self.guest_agent()?.write_file(path, data)
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, Error> {
+        self.guest_agent()?.read_file(path)
+    }
+}
+
+impl PortForward for QemuSystem {
+    /// Forward a port with `hostfwd_add` on the first user network backend
+    /// (`-netdev user`), listening on a free loopback port
+    fn forward_port(&mut self, guest_port: u16) -> Result<HostEndpoint, Error> {
+        let reservation = crate::reserve_port()?;
+        let host_port = reservation.port();
+        log::trace!("Forwarding guest port {guest_port} to host port {host_port}");
+        self.human_monitor_command(format!(
+            "hostfwd_add tcp:{}:{host_port}-:{guest_port}",
+            Ipv4Addr::LOCALHOST
+        ))?;
+        self.reservations.push(reservation);
+        Ok(HostEndpoint::new(
+            Ipv4Addr::LOCALHOST.to_string(),
+            host_port,
+        ))
+    }
+}
+
+impl TrafficCapture for QemuSystem {
+    /// Capture traffic on the first configured network backend
+    ///
+    /// See [`QemuSystem::capture_netdev`].
+    fn capture_traffic(&mut self, path: &Path) -> Result<(), Error> {
+        let netdev = self.netdevs.first().cloned().ok_or(Error::new(
+            ErrorKind::HarnessError,
+            "No network backend configured to capture traffic on",
+        ))?;
+        self.capture_netdev(&netdev, path)
+    }
+
+    fn stop_capture(&mut self) -> Result<(), Error> {
+        while let Some(id) = self.captures.pop() {
+            log::trace!("Stopping capture: {id}");
+            self.qmp
+                .send_command(qmp::QmpCommand::ObjectDel(qmp::ObjectDel { id }))?;
+        }
+        Ok(())
+    }
+}
+
+impl CollectArtifacts for QemuSystem {
+    /// Collect the serial output not read yet (`serial.log`), QEMU's
+    /// standard error (`qemu.stderr`), plugin output (`plugin.log`), a
+    /// screendump (`screen.ppm`) and the QMP status (`qmp-status.json`)
+    ///
+    /// Standard error and plugin output are only available for systems
+    /// started by the harness and a screendump only for those with a display.
+    fn collect_artifacts(&mut self, dir: &Path) -> Result<(), Error> {
+        let mut artifacts = ArtifactDir::create(dir)?;
+        artifacts.write("serial.log", self.drain_serial());
+        if let Some(runtime_dir) = &self.runtime_dir {
+            let stderr = std::fs::read(runtime_dir.file("qemu.stderr"));
+            artifacts.write("qemu.stderr", stderr.map_err(Error::from));
+        }
+        if let Some(plugin_log) = &self.plugin_log {
+            let output = std::fs::read(plugin_log);
+            artifacts.write("plugin.log", output.map_err(Error::from));
+        }
+        let screendump = std::path::absolute(artifacts.path("screen.ppm"))
+            .map_err(Error::from)
+            .and_then(|filename| {
+                self.qmp
+                    .send_command(qmp::QmpCommand::Screendump(qmp::Screendump {
+                        filename: filename.to_string_lossy().into_owned(),
+                    }))
+            });
+        artifacts.check("screen.ppm", screendump.map(|_| ()));
+        let status = self
+            .qmp
+            .send_command(qmp::QmpCommand::QueryStatus)
+            .and_then(|ret| match ret {
+                qmp::QmpReturn::StatusInfo(status) => Ok(serde_json::to_vec_pretty(&status)?),
+                _ => Err(Error::new(ErrorKind::HarnessError, "Unexpected return")),
+            });
+        artifacts.write("qmp-status.json", status);
+        artifacts.finish()
+    }
 }
 
 impl EventPublisher for QemuSystem {
@@ -200,12 +1059,26 @@ impl EventPublisher for QemuSystem {
 
 impl Drop for QemuSystem {
     fn drop(&mut self) {
+        if self.process.is_none() {
+            // Attached systems keep running, so leave them as they were found
+            if let Err(err) = self.stop_capture() {
+                log::warn!("Error stopping traffic capture: {err}");
+            }
+            return;
+        }
         if let Ok(true) = self.running() {
             log::trace!("Stopping running system...");
-            if let Err(err) = self.qmp.send_command(qmp::QmpCommand::Quit) {
+            let quit = self
+                .qmp
+                .set_read_timeout(Some(self.grace_period))
+                .and_then(|_| self.qmp.send_command(qmp::QmpCommand::Quit));
+            if let Err(err) = quit {
                 log::warn!("Error quiting system: {err}");
             }
         }
+        if let Some(process) = &mut self.process {
+            reap(process, self.grace_period);
+        }
     }
 }
 
@@ -214,17 +1087,257 @@ mod tests {
 
     use super::*;
 
+    #[cfg(unix)]
+    #[test]
+    fn early_exit_reports_stderr() {
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let stderr_path = runtime_dir.file("qemu.stderr");
+        let mut process = std::process::Command::new("sh")
+            .args(["-c", "echo bad option >&2; exit 1"])
+            .stderr(Stdio::from(std::fs::File::create(&stderr_path).unwrap()))
+            .spawn()
+            .unwrap();
+        let err = connect_qmp(
+            &mut process,
+            &runtime_dir.file("qmp.sock").into(),
+            Duration::from_secs(5),
+            || std::fs::read_to_string(&stderr_path).unwrap_or_default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+        assert!(format!("{err}").contains("bad option"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reap_kills_after_grace_period() {
+        let mut process = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let start = Instant::now();
+        reap(&mut process, Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(process.try_wait().unwrap().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn starting_process_killed_on_drop() {
+        let process = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = process.id() as libc::pid_t;
+        drop(StartingProcess(Some(process)));
+        assert_ne!(0, unsafe { libc::kill(pid, 0) });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attach() {
+        use std::io::BufRead;
+        use std::os::unix::net::UnixListener;
+
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let qmp_path = runtime_dir.file("qmp.sock");
+        let serial_path = runtime_dir.file("serial.sock");
+        let qmp_listener = UnixListener::bind(&qmp_path).unwrap();
+        let _serial_listener = UnixListener::bind(&serial_path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = qmp_listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            writeln!(stream, r#"{{"QMP": {{"version": {{"qemu": {{"major": 8, "minor": 2, "micro": 0}}, "package": ""}}, "capabilities": []}}}}"#).unwrap();
+            let mut buf = Vec::new();
+            reader.read_until(b'}', &mut buf).unwrap();
+            writeln!(stream, r#"{{"return": {{}}}}"#).unwrap();
+            reader.read_until(b'}', &mut buf).unwrap();
+            writeln!(
+                stream,
+                r#"{{"return": {{"running": true, "singlestep": false, "status": "running"}}}}"#
+            )
+            .unwrap();
+        });
+        let mut system = QemuSystem::attach(&qmp_path, &serial_path).unwrap();
+        assert_eq!(None, system.pid());
+        assert!(system.running().unwrap());
+        server.join().unwrap();
+        assert!(!system.running().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn retry_reconnects() {
+        use std::io::BufRead;
+        use std::os::unix::net::UnixListener;
+
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let qmp_path = runtime_dir.file("qmp.sock");
+        let serial_path = runtime_dir.file("serial.sock");
+        let qmp_listener = UnixListener::bind(&qmp_path).unwrap();
+        let _serial_listener = UnixListener::bind(&serial_path).unwrap();
+        let server = std::thread::spawn(move || {
+            // The first connection drops while a command is in flight
+            for reply in [
+                None,
+                Some(r#"{"return": {"running": true, "singlestep": false, "status": "running"}}"#),
+            ] {
+                let (mut stream, _) = qmp_listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                writeln!(stream, r#"{{"QMP": {{"version": {{"qemu": {{"major": 8, "minor": 2, "micro": 0}}, "package": ""}}, "capabilities": []}}}}"#).unwrap();
+                let mut buf = Vec::new();
+                reader.read_until(b'}', &mut buf).unwrap();
+                writeln!(stream, r#"{{"return": {{}}}}"#).unwrap();
+                reader.read_until(b'}', &mut buf).unwrap();
+                if let Some(reply) = reply {
+                    writeln!(stream, "{reply}").unwrap();
+                }
+            }
+        });
+        let mut system = QemuSystem::attach(&qmp_path, &serial_path).unwrap();
+        system.set_retry(RetryPolicy::new().backoff(Duration::ZERO));
+        assert_eq!(Status::Running, system.status().unwrap());
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn retry_keeps_terminals() {
+        use std::io::BufRead;
+        use std::os::unix::net::UnixListener;
+
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let qmp_path = runtime_dir.file("qmp.sock");
+        let serial_path = runtime_dir.file("serial.sock");
+        let qmp_listener = UnixListener::bind(&qmp_path).unwrap();
+        let _serial_listener = UnixListener::bind(&serial_path).unwrap();
+        let server = std::thread::spawn(move || {
+            let mut connections = Vec::new();
+            for reply in [
+                None,
+                Some(r#"{"return": {"running": true, "singlestep": false, "status": "running"}}"#),
+            ] {
+                let (mut stream, _) = qmp_listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                writeln!(stream, r#"{{"QMP": {{"version": {{"qemu": {{"major": 8, "minor": 2, "micro": 0}}, "package": ""}}, "capabilities": []}}}}"#).unwrap();
+                let mut buf = Vec::new();
+                reader.read_until(b'}', &mut buf).unwrap();
+                writeln!(stream, r#"{{"return": {{}}}}"#).unwrap();
+                reader.read_until(b'}', &mut buf).unwrap();
+                match reply {
+                    Some(reply) => writeln!(stream, "{reply}").unwrap(),
+                    None => stream.shutdown(std::net::Shutdown::Write).unwrap(),
+                }
+                connections.push(reader);
+            }
+            // The terminal still writes to the first connection
+            let mut sent = String::new();
+            connections[0].read_to_string(&mut sent).unwrap();
+            sent
+        });
+        let mut system = QemuSystem::attach(&qmp_path, &serial_path).unwrap();
+        system.set_retry(RetryPolicy::new().backoff(Duration::ZERO));
+        let mut terminal = system.terminal().unwrap();
+        assert_eq!(Status::Running, system.status().unwrap());
+        let _ = terminal.send_key(Key::Enter);
+        drop(terminal);
+        assert!(server.join().unwrap().contains("send-key"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn retry_skips_lost_commands() {
+        use std::io::BufRead;
+        use std::os::unix::net::UnixListener;
+
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let qmp_path = runtime_dir.file("qmp.sock");
+        let serial_path = runtime_dir.file("serial.sock");
+        let qmp_listener = UnixListener::bind(&qmp_path).unwrap();
+        let _serial_listener = UnixListener::bind(&serial_path).unwrap();
+        let server = std::thread::spawn(move || {
+            // The connection drops after `stop` is received, which may have
+            // paused the guest
+            let (mut stream, _) = qmp_listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            writeln!(stream, r#"{{"QMP": {{"version": {{"qemu": {{"major": 8, "minor": 2, "micro": 0}}, "package": ""}}, "capabilities": []}}}}"#).unwrap();
+            let mut buf = Vec::new();
+            reader.read_until(b'}', &mut buf).unwrap();
+            writeln!(stream, r#"{{"return": {{}}}}"#).unwrap();
+            reader.read_until(b'}', &mut buf).unwrap();
+            qmp_listener
+        });
+        let mut system = QemuSystem::attach(&qmp_path, &serial_path).unwrap();
+        system.set_retry(RetryPolicy::new().backoff(Duration::ZERO));
+        let err = system.pause().err().unwrap();
+        assert_eq!(ErrorKind::PipeError, err.kind());
+        let qmp_listener = server.join().unwrap();
+        qmp_listener.set_nonblocking(true).unwrap();
+        assert!(qmp_listener.accept().is_err());
+    }
+
+    #[test]
+    fn replay_needs_recording() {
+        let config = QemuSystemConfig::builder()
+            .arch("x86_64")
+            .icount(Icount::auto())
+            .build();
+        let err = config.replay().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+        let config = QemuSystemConfig::builder().arch("x86_64").build();
+        let err = config.replay().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+
     #[test]
     fn json_config() {
-        const JSON_CONFIG: &'static str = include_str!("../tests/data/qemu-config.json");
+        const JSON_CONFIG: &str = include_str!("../tests/data/qemu-config.json");
         let config: QemuSystemConfig = serde_json::from_str(JSON_CONFIG).unwrap();
         let command = config.command();
         assert_eq!("qemu-system-i386", command.get_program());
         assert_eq!(
-            vec!["-machine", "type=q35", "-m", "512",
-                "-device", "driver=virtio-blk,drive=f1",
-                "-blockdev", "driver=file,node-name=f1,filename=tests/data/test.raw"],
+            vec![
+                "-machine",
+                "type=q35",
+                "-m",
+                "512",
+                "-device",
+                "driver=virtio-blk,drive=f1",
+                "-blockdev",
+                "driver=file,node-name=f1,filename=tests/data/test.raw"
+            ],
             command.get_args().collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn allocate() {
+        let config = QemuSystemConfig::builder()
+            .arch("x86_64")
+            .device(Device::new("virtio-net-pci").property("mac", "auto"))
+            .gdb("auto")
+            .vnc(":1")
+            .build();
+        let mut reservations = Vec::new();
+        let allocated = config.allocate(&mut reservations).unwrap();
+        let gdb = allocated.gdb.unwrap();
+        assert_eq!(1, reservations.len());
+        assert_eq!(gdb.port, reservations[0].port());
+        assert!(allocated.vnc.is_none());
+        let args = |config: &QemuSystemConfig| -> Vec<String> {
+            config
+                .command()
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+        let first = args(&allocated.config);
+        assert_eq!(format!("tcp:{gdb}"), first[3]);
+        assert_eq!(":1", first[5]);
+        assert!(first[1].starts_with("driver=virtio-net-pci,mac=02:"));
+        // Each system gets a MAC of its own
+        let second = args(&config.allocate(&mut reservations).unwrap().config);
+        assert_ne!(first[1], second[1]);
+    }
 }
@@ -59,7 +59,7 @@ impl Debug for Property<'_> {
 
 impl Display for Property<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let value = self.value.value().unwrap_or(String::new());
+        let value = self.value.value().unwrap_or_default();
         write!(f, "{}={}", self.key, value)
     }
 }
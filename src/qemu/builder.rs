@@ -0,0 +1,316 @@
+use super::{
+    Backend, BlockDev, Boot, CharDev, Device, FwCfg, Icount, KernelCommandLine, Machine, NetDev,
+    OutputSink, Plugin, QemuSystemConfig, QemuTransport, ReadyCondition, ResourceLimits, Rtc, Smp,
+};
+use crate::RetryPolicy;
+use std::path::PathBuf;
+
+/// A builder for [`QemuSystemConfig`]
+///
+/// Created with [`QemuSystemConfig::builder`],
+/// [`QemuSystemConfig::preset`] or [`QemuSystemConfig::with_overrides`].
+#[derive(Clone, Default)]
+pub struct QemuSystemConfigBuilder {
+    config: QemuSystemConfig,
+}
+
+impl QemuSystemConfig {
+    /// Create a builder for a configuration
+    pub fn builder() -> QemuSystemConfigBuilder {
+        QemuSystemConfigBuilder::default()
+    }
+
+    /// Create a copy of this configuration with some fields overridden
+    pub fn with_overrides(
+        &self,
+        overrides: impl FnOnce(QemuSystemConfigBuilder) -> QemuSystemConfigBuilder,
+    ) -> Self {
+        overrides(QemuSystemConfigBuilder {
+            config: self.clone(),
+        })
+        .build()
+    }
+}
+
+impl QemuSystemConfigBuilder {
+    /// Finish building the configuration
+    pub fn build(self) -> QemuSystemConfig {
+        self.config
+    }
+
+    /// Target architecture (e.g. `x86_64`)
+    pub fn arch(mut self, arch: impl Into<String>) -> Self {
+        self.config.arch = arch.into();
+        self
+    }
+
+    /// Boot options
+    pub fn boot(mut self, boot: Boot) -> Self {
+        self.config.boot = Some(boot);
+        self
+    }
+
+    /// CPU model
+    pub fn cpu(mut self, cpu: impl Into<String>) -> Self {
+        self.config.cpu = Some(cpu.into());
+        self
+    }
+
+    /// Machine type and properties
+    pub fn machine(mut self, machine: Machine) -> Self {
+        self.config.machine = Some(machine);
+        self
+    }
+
+    /// CPU topology
+    pub fn smp(mut self, smp: Smp) -> Self {
+        self.config.smp = Some(smp);
+        self
+    }
+
+    /// Accelerator (e.g. `kvm`, `whpx` or `tcg`)
+    pub fn accel(mut self, accel: impl Into<String>) -> Self {
+        self.config.accel = Some(accel.into());
+        self
+    }
+
+    /// BIOS file
+    pub fn bios(mut self, bios: impl Into<String>) -> Self {
+        self.config.bios = Some(bios.into());
+        self
+    }
+
+    /// Memory in megabytes
+    pub fn memory(mut self, memory: usize) -> Self {
+        self.config.memory = Some(memory);
+        self
+    }
+
+    /// CD-ROM image
+    pub fn cdrom(mut self, cdrom: impl Into<String>) -> Self {
+        self.config.cdrom = Some(cdrom.into());
+        self
+    }
+
+    /// First hard disk image
+    pub fn hda(mut self, hda: impl Into<String>) -> Self {
+        self.config.hda = Some(hda.into());
+        self
+    }
+
+    /// Second hard disk image
+    pub fn hdb(mut self, hdb: impl Into<String>) -> Self {
+        self.config.hdb = Some(hdb.into());
+        self
+    }
+
+    /// Add a device
+    pub fn device(mut self, device: Device) -> Self {
+        self.config.device.get_or_insert_with(Vec::new).push(device);
+        self
+    }
+
+    /// Add a character device backend
+    pub fn chardev(mut self, id: impl Into<String>, chardev: CharDev) -> Self {
+        self.config
+            .chardev
+            .get_or_insert_with(Vec::new)
+            .push(Backend::new(id.into(), chardev));
+        self
+    }
+
+    /// Add a network backend
+    pub fn netdev(mut self, id: impl Into<String>, netdev: NetDev) -> Self {
+        self.config
+            .netdev
+            .get_or_insert_with(Vec::new)
+            .push(Backend::new(id.into(), netdev));
+        self
+    }
+
+    /// Add a block device node
+    pub fn blockdev(mut self, blockdev: BlockDev) -> Self {
+        self.config
+            .blockdev
+            .get_or_insert_with(Vec::new)
+            .push(blockdev);
+        self
+    }
+
+    /// Kernel image to boot directly
+    pub fn kernel(mut self, kernel: impl Into<String>) -> Self {
+        self.config.kernel = Some(kernel.into());
+        self
+    }
+
+    /// Initial ramdisk for the kernel
+    pub fn initrd(mut self, initrd: impl Into<String>) -> Self {
+        self.config.initrd = Some(initrd.into());
+        self
+    }
+
+    /// Kernel command line
+    pub fn append(mut self, append: KernelCommandLine) -> Self {
+        self.config.append = Some(append);
+        self
+    }
+
+    /// Add a firmware configuration item
+    pub fn fw_cfg(mut self, fw_cfg: FwCfg) -> Self {
+        self.config.fw_cfg.get_or_insert_with(Vec::new).push(fw_cfg);
+        self
+    }
+
+    /// Real-time clock options
+    pub fn rtc(mut self, rtc: Rtc) -> Self {
+        self.config.rtc = Some(rtc);
+        self
+    }
+
+    /// Instruction counting options
+    pub fn icount(mut self, icount: Icount) -> Self {
+        self.config.icount = Some(icount);
+        self
+    }
+
+    /// Add a TCG plugin
+    pub fn plugin(mut self, plugin: Plugin) -> Self {
+        self.config
+            .plugins
+            .get_or_insert_with(Vec::new)
+            .push(plugin);
+        self
+    }
+
+    /// File plugin output is written to
+    pub fn plugin_log(mut self, plugin_log: impl Into<PathBuf>) -> Self {
+        self.config.plugin_log = Some(plugin_log.into());
+        self
+    }
+
+    /// GDB server (e.g. `tcp::1234`), or `auto` for a free loopback port
+    pub fn gdb(mut self, gdb: impl Into<String>) -> Self {
+        self.config.gdb = Some(gdb.into());
+        self
+    }
+
+    /// VNC display (e.g. `:1`), or `auto` for a free loopback display
+    pub fn vnc(mut self, vnc: impl Into<String>) -> Self {
+        self.config.vnc = Some(vnc.into());
+        self
+    }
+
+    /// Directory for runtime sockets
+    pub fn runtime_dir(mut self, runtime_dir: impl Into<PathBuf>) -> Self {
+        self.config.runtime_dir = Some(runtime_dir.into());
+        self
+    }
+
+    /// Transport for the QMP, serial and guest agent channels
+    pub fn transport(mut self, transport: QemuTransport) -> Self {
+        self.config.transport = Some(transport);
+        self
+    }
+
+    /// Seconds to wait for QEMU to start
+    pub fn startup_timeout(mut self, seconds: u64) -> Self {
+        self.config.startup_timeout = Some(seconds);
+        self
+    }
+
+    /// Condition that must be met before the system is considered ready
+    pub fn ready(mut self, ready: ReadyCondition) -> Self {
+        self.config.ready = Some(ready);
+        self
+    }
+
+    /// Seconds to wait for the ready condition
+    pub fn ready_timeout(mut self, seconds: u64) -> Self {
+        self.config.ready_timeout = Some(seconds);
+        self
+    }
+
+    /// Destination for QEMU's standard output
+    pub fn stdout(mut self, sink: OutputSink) -> Self {
+        self.config.stdout = Some(sink);
+        self
+    }
+
+    /// Destination for QEMU's standard error
+    pub fn stderr(mut self, sink: OutputSink) -> Self {
+        self.config.stderr = Some(sink);
+        self
+    }
+
+    /// Seconds to wait for QEMU to exit when dropped before killing it
+    pub fn grace_period(mut self, seconds: u64) -> Self {
+        self.config.grace_period = Some(seconds);
+        self
+    }
+
+    /// Resource limits for the QEMU process
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.config.limits = Some(limits);
+        self
+    }
+
+    /// How QMP commands that fail for transient reasons are retried
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = Some(retry);
+        self
+    }
+
+    /// Attach a QEMU guest agent channel
+    pub fn guest_agent(mut self, guest_agent: bool) -> Self {
+        self.config.guest_agent = guest_agent;
+        self
+    }
+
+    /// Add an extra QEMU argument
+    pub fn extra_arg(mut self, arg: impl Into<String>) -> Self {
+        self.config
+            .extra_args
+            .get_or_insert_with(Vec::new)
+            .push(arg.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use cmdstruct::Command;
+
+    #[test]
+    fn builder_matches_json_config() {
+        let config = QemuSystemConfig::builder()
+            .arch("i386")
+            .machine(Machine::new("q35"))
+            .memory(512)
+            .device(Device::new("virtio-blk").property("drive", "f1"))
+            .blockdev(BlockDev::new("file", "f1").property("filename", "tests/data/test.raw"))
+            .build();
+        const JSON_CONFIG: &str = include_str!("../../tests/data/qemu-config.json");
+        let expected: QemuSystemConfig = serde_json::from_str(JSON_CONFIG).unwrap();
+        assert_eq!(
+            expected.command().get_args().collect::<Vec<_>>(),
+            config.command().get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let base = QemuSystemConfig::builder()
+            .arch("x86_64")
+            .memory(512)
+            .build();
+        let config =
+            base.with_overrides(|builder| builder.memory(2048).smp(Smp::default().cpus(2)));
+        assert_eq!(
+            vec!["-smp", "cpus=2", "-m", "2048"],
+            config.command().get_args().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(512), base.memory);
+    }
+}
@@ -0,0 +1,319 @@
+use super::{BlockDev, Device, QemuSystemConfig};
+use crate::runtime::RuntimeDir;
+use crate::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+/// A user created by cloud-init
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CloudInitUser {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sudo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shell: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plain_text_passwd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_passwd: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    ssh_authorized_keys: Vec<String>,
+}
+
+impl CloudInitUser {
+    /// Create a user with the given name
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Grant passwordless sudo
+    pub fn sudo(mut self) -> Self {
+        self.sudo = Some(String::from("ALL=(ALL) NOPASSWD:ALL"));
+        self
+    }
+
+    /// Login shell
+    pub fn shell(mut self, shell: impl Into<String>) -> Self {
+        self.shell = Some(shell.into());
+        self
+    }
+
+    /// Plain text password, also unlocking password login
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.plain_text_passwd = Some(password.into());
+        self.lock_passwd = Some(false);
+        self
+    }
+
+    /// Add an authorized SSH public key
+    pub fn ssh_authorized_key(mut self, key: impl Into<String>) -> Self {
+        self.ssh_authorized_keys.push(key.into());
+        self
+    }
+}
+
+/// Format of a cloud-init seed image
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeedFormat {
+    /// ISO 9660 image built with `genisoimage`, `mkisofs` or `xorrisofs`
+    Iso,
+
+    /// FAT image built with `mkfs.vfat` and `mcopy`
+    Vfat,
+}
+
+/// cloud-init user-data contents
+#[derive(Serialize)]
+struct CloudConfig<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    users: &'a [CloudInitUser],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    runcmd: &'a [String],
+}
+
+/// cloud-init meta-data contents
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct MetaData<'a> {
+    instance_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_hostname: Option<&'a str>,
+}
+
+/// A cloud-init NoCloud data source
+///
+/// Renders user-data, meta-data and network-config and packs them into a
+/// seed image labeled `cidata` that cloud images detect at boot.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CloudInit {
+    instance_id: String,
+    hostname: Option<String>,
+    #[serde(default)]
+    users: Vec<CloudInitUser>,
+    #[serde(default)]
+    runcmd: Vec<String>,
+    user_data: Option<String>,
+    network_config: Option<String>,
+}
+
+impl CloudInit {
+    /// Create a data source with the given instance ID
+    pub fn new(instance_id: impl Into<String>) -> Self {
+        Self {
+            instance_id: instance_id.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Hostname of the instance
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Add a user
+    pub fn user(mut self, user: CloudInitUser) -> Self {
+        self.users.push(user);
+        self
+    }
+
+    /// Add a command run on first boot
+    pub fn runcmd(mut self, command: impl Into<String>) -> Self {
+        self.runcmd.push(command.into());
+        self
+    }
+
+    /// Use raw user-data instead of rendering it from the other options
+    pub fn user_data(mut self, user_data: impl Into<String>) -> Self {
+        self.user_data = Some(user_data.into());
+        self
+    }
+
+    /// Network configuration (version 1 or 2 YAML)
+    pub fn network_config(mut self, network_config: impl Into<String>) -> Self {
+        self.network_config = Some(network_config.into());
+        self
+    }
+
+    /// Render the user-data file
+    ///
+    /// The cloud-config is rendered as JSON, which is valid YAML.
+    pub fn render_user_data(&self) -> Result<String, Error> {
+        if let Some(user_data) = &self.user_data {
+            return Ok(user_data.clone());
+        }
+        let config = CloudConfig {
+            hostname: self.hostname.as_deref(),
+            users: &self.users,
+            runcmd: &self.runcmd,
+        };
+        Ok(format!(
+            "#cloud-config\n{}\n",
+            serde_json::to_string(&config)?
+        ))
+    }
+
+    /// Render the meta-data file
+    pub fn render_meta_data(&self) -> Result<String, Error> {
+        let meta_data = MetaData {
+            instance_id: &self.instance_id,
+            local_hostname: self.hostname.as_deref(),
+        };
+        Ok(serde_json::to_string(&meta_data)?)
+    }
+
+    /// Write a seed image to the given path
+    pub fn write_seed(&self, path: impl AsRef<Path>, format: SeedFormat) -> Result<(), Error> {
+        let staging = RuntimeDir::new(None, &[])?;
+        let mut files = vec![
+            ("user-data", self.render_user_data()?),
+            ("meta-data", self.render_meta_data()?),
+        ];
+        if let Some(network_config) = &self.network_config {
+            files.push(("network-config", network_config.clone()));
+        }
+        for (name, contents) in &files {
+            std::fs::write(staging.file(name), contents)?;
+        }
+        let path = path.as_ref();
+        let paths = files.iter().map(|(name, _)| staging.file(name));
+        match format {
+            SeedFormat::Iso => {
+                let tool = ["genisoimage", "mkisofs", "xorrisofs"]
+                    .into_iter()
+                    .find(|tool| Command::new(tool).arg("-version").output().is_ok())
+                    .ok_or(Error::new(
+                        ErrorKind::HarnessError,
+                        "No ISO creation tool found (genisoimage, mkisofs or xorrisofs)",
+                    ))?;
+                let mut command = Command::new(tool);
+                command
+                    .args(["-quiet", "-volid", "cidata", "-joliet", "-rock", "-output"])
+                    .arg(path)
+                    .args(paths);
+                run(command)
+            }
+            SeedFormat::Vfat => {
+                let _ = std::fs::remove_file(path);
+                let mut mkfs = Command::new("mkfs.vfat");
+                mkfs.args(["-n", "CIDATA", "-C"]).arg(path).arg("2048");
+                run(mkfs)?;
+                let mut mcopy = Command::new("mcopy");
+                mcopy.arg("-oi").arg(path).args(paths).arg("::");
+                run(mcopy)
+            }
+        }
+    }
+
+    /// Write a seed image and attach it to a copy of the config
+    pub fn attach(
+        &self,
+        config: &QemuSystemConfig,
+        path: impl AsRef<Path>,
+        format: SeedFormat,
+    ) -> Result<QemuSystemConfig, Error> {
+        let path = path.as_ref();
+        self.write_seed(path, format)?;
+        let filename = path.to_string_lossy();
+        Ok(config.with_overrides(|builder| {
+            builder
+                .blockdev(
+                    BlockDev::new("file", "cidata")
+                        .property("filename", filename)
+                        .property("read-only", "on"),
+                )
+                .device(Device::new("virtio-blk-pci").property("drive", "cidata"))
+        }))
+    }
+}
+
+/// Run a command, turning a failed exit into an error
+fn run(mut command: Command) -> Result<(), Error> {
+    log::trace!("Running: {command:?}");
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::HarnessError,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        let cloud_init = CloudInit::new("test-1")
+            .hostname("guest")
+            .user(
+                CloudInitUser::new("tester")
+                    .sudo()
+                    .ssh_authorized_key("ssh-ed25519 AAAA test"),
+            )
+            .runcmd("touch /ready");
+        assert_eq!(
+            concat!(
+                "#cloud-config\n",
+                r#"{"hostname":"guest","users":[{"name":"tester","sudo":"ALL=(ALL) NOPASSWD:ALL","#,
+                r#""ssh_authorized_keys":["ssh-ed25519 AAAA test"]}],"runcmd":["touch /ready"]}"#,
+                "\n"
+            ),
+            cloud_init.render_user_data().unwrap()
+        );
+        assert_eq!(
+            r#"{"instance-id":"test-1","local-hostname":"guest"}"#,
+            cloud_init.render_meta_data().unwrap()
+        );
+    }
+
+    #[test]
+    fn render_password_user() {
+        let cloud_init =
+            CloudInit::new("test-2").user(CloudInitUser::new("tester").password("secret"));
+        assert_eq!(
+            concat!(
+                "#cloud-config\n",
+                r#"{"users":[{"name":"tester","plain_text_passwd":"secret","lock_passwd":false}]}"#,
+                "\n"
+            ),
+            cloud_init.render_user_data().unwrap()
+        );
+        assert_eq!(
+            r#"{"instance-id":"test-2"}"#,
+            cloud_init.render_meta_data().unwrap()
+        );
+    }
+
+    #[test]
+    fn raw_user_data() {
+        let cloud_init = CloudInit::new("test-3")
+            .hostname("guest")
+            .runcmd("touch /ready")
+            .user_data("#!/bin/sh\necho hello\n");
+        assert_eq!(
+            "#!/bin/sh\necho hello\n",
+            cloud_init.render_user_data().unwrap()
+        );
+    }
+
+    #[test]
+    fn failed_command() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo no space left >&2; exit 1"]);
+        let err = run(command).err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+        assert!(format!("{err}").contains("no space left"));
+    }
+}
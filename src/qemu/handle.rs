@@ -0,0 +1,107 @@
+use super::{qmp, GuestAgent, QemuEndpoint, QemuSystem, RUNTIME_FILES};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A serializable handle to a detached QEMU system
+///
+/// The handle can be saved and used by a later process to re-open the
+/// system with [`QemuSystemHandle::open`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QemuSystemHandle {
+    /// Process ID of the QEMU process
+    pub pid: Option<u32>,
+
+    /// Directory containing the system's runtime sockets
+    pub runtime_dir: PathBuf,
+
+    /// Whether the runtime directory was created by the harness
+    #[serde(default)]
+    pub owned: bool,
+
+    /// QMP monitor endpoint
+    pub qmp: QemuEndpoint,
+
+    /// Serial console endpoint
+    pub serial: QemuEndpoint,
+
+    /// Guest agent endpoint, if configured
+    pub guest_agent: Option<QemuEndpoint>,
+}
+
+impl QemuSystemHandle {
+    /// Re-open the system
+    ///
+    /// The re-opened system is not stopped when dropped.
+    pub fn open(&self) -> Result<QemuSystem, Error> {
+        let mut system = QemuSystem::attach(self.qmp.clone(), self.serial.clone())?;
+        if let Some(endpoint) = &self.guest_agent {
+            system.guest_agent = Some(GuestAgent::new(endpoint.connect()?));
+            system.guest_agent_endpoint = Some(endpoint.clone());
+        }
+        Ok(system)
+    }
+
+    /// Stop the system and clean up its runtime directory
+    ///
+    /// A directory created by the harness is removed. A configured
+    /// directory is kept, and only the files the system created in it
+    /// are removed.
+    pub fn destroy(self) -> Result<(), Error> {
+        let mut system = self.open()?;
+        log::trace!("Stopping detached system...");
+        system.qmp.send_command(qmp::QmpCommand::Quit)?;
+        if self.owned {
+            std::fs::remove_dir_all(&self.runtime_dir)?;
+        } else {
+            for file in RUNTIME_FILES {
+                let _ = std::fs::remove_file(self.runtime_dir.join(file));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn destroy_keeps_configured_dir() {
+        use crate::runtime::RuntimeDir;
+        use std::io::{BufRead, Write};
+        use std::os::unix::net::UnixListener;
+
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let qmp_path = runtime_dir.file("qmp.sock");
+        let serial_path = runtime_dir.file("serial.sock");
+        let qmp_listener = UnixListener::bind(&qmp_path).unwrap();
+        let _serial_listener = UnixListener::bind(&serial_path).unwrap();
+        std::fs::write(runtime_dir.file("disk.qcow2"), b"").unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = qmp_listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            writeln!(stream, r#"{{"QMP": {{"version": {{"qemu": {{"major": 8, "minor": 2, "micro": 0}}, "package": ""}}, "capabilities": []}}}}"#).unwrap();
+            let mut buf = Vec::new();
+            for _ in 0..2 {
+                reader.read_until(b'}', &mut buf).unwrap();
+                writeln!(stream, r#"{{"return": {{}}}}"#).unwrap();
+            }
+        });
+        let handle = QemuSystemHandle {
+            pid: None,
+            runtime_dir: runtime_dir.path().to_path_buf(),
+            owned: false,
+            qmp: qmp_path.clone().into(),
+            serial: serial_path.clone().into(),
+            guest_agent: None,
+        };
+        handle.destroy().unwrap();
+        server.join().unwrap();
+        assert!(!qmp_path.exists());
+        assert!(!serial_path.exists());
+        assert!(runtime_dir.file("disk.qcow2").exists());
+    }
+}
@@ -0,0 +1,115 @@
+use super::{FwCfg, QemuSystemConfig};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// fw_cfg item read by Ignition on QEMU
+const IGNITION_FW_CFG: &str = "opt/com.coreos/config";
+
+/// Source of an Ignition config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum IgnitionSource {
+    Config(serde_json::Value),
+    File(PathBuf),
+}
+
+/// An Ignition config for Fedora CoreOS or Flatcar guests
+///
+/// The config is passed to the guest through fw_cfg.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ignition {
+    source: IgnitionSource,
+}
+
+impl Ignition {
+    /// Use an Ignition config rendered from a value
+    pub fn new(config: impl Serialize) -> Result<Self, Error> {
+        Ok(Self {
+            source: IgnitionSource::Config(serde_json::to_value(config)?),
+        })
+    }
+
+    /// Use an existing Ignition config file
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            source: IgnitionSource::File(path.into()),
+        }
+    }
+
+    /// Attach the config to a copy of the QEMU config
+    ///
+    /// A config rendered from a value is first written to the given path;
+    /// an existing config file is used in place and the path is ignored.
+    pub fn attach(
+        &self,
+        config: &QemuSystemConfig,
+        path: impl AsRef<Path>,
+    ) -> Result<QemuSystemConfig, Error> {
+        let file = match &self.source {
+            IgnitionSource::Config(value) => {
+                let path = path.as_ref();
+                std::fs::write(path, serde_json::to_vec(value)?)?;
+                path.to_path_buf()
+            }
+            IgnitionSource::File(file) => file.clone(),
+        };
+        let file = file.to_string_lossy();
+        Ok(config.with_overrides(|builder| builder.fw_cfg(FwCfg::file(IGNITION_FW_CFG, file))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::runtime::RuntimeDir;
+    use cmdstruct::Command;
+
+    fn args(config: &QemuSystemConfig) -> Vec<String> {
+        config
+            .command()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn attach_rendered_config() {
+        let dir = RuntimeDir::new(None, &[]).unwrap();
+        let path = dir.file("config.ign");
+        let config = QemuSystemConfig::builder().arch("x86_64").build();
+        let ignition = Ignition::new(serde_json::json!({
+            "ignition": { "version": "3.4.0" }
+        }))
+        .unwrap();
+        let config = ignition.attach(&config, &path).unwrap();
+        assert_eq!(
+            r#"{"ignition":{"version":"3.4.0"}}"#,
+            std::fs::read_to_string(&path).unwrap()
+        );
+        assert_eq!(
+            vec![
+                String::from("-fw_cfg"),
+                format!("name=opt/com.coreos/config,file={}", path.display())
+            ],
+            args(&config)
+        );
+    }
+
+    #[test]
+    fn attach_existing_file() {
+        let config = QemuSystemConfig::builder().arch("x86_64").build();
+        let config = Ignition::from_file("tests/data/config.ign")
+            .attach(&config, "ignored.ign")
+            .unwrap();
+        assert!(!Path::new("ignored.ign").exists());
+        assert_eq!(
+            vec![
+                "-fw_cfg",
+                "name=opt/com.coreos/config,file=tests/data/config.ign"
+            ],
+            args(&config)
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Disk image management using `qemu-img`
+use crate::{Error, ErrorKind};
+use serde::Deserialize;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+/// Information about a disk image
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImageInfo {
+    /// Image file name
+    pub filename: String,
+
+    /// Image format (e.g. `qcow2`)
+    pub format: String,
+
+    /// Size of the disk as seen by the guest in bytes
+    pub virtual_size: u64,
+
+    /// Space used by the image on the host in bytes
+    pub actual_size: Option<u64>,
+
+    /// Cluster size in bytes
+    pub cluster_size: Option<u64>,
+
+    /// Backing file, if the image is an overlay
+    pub backing_filename: Option<String>,
+
+    /// Format of the backing file
+    pub backing_filename_format: Option<String>,
+
+    /// Whether the image was not closed cleanly
+    pub dirty_flag: Option<bool>,
+}
+
+/// Run `qemu-img` with the given arguments
+fn qemu_img<I, S>(args: I) -> Result<String, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut command = Command::new("qemu-img");
+    command.args(args);
+    log::trace!("Running: {command:?}");
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::new(
+            ErrorKind::HarnessError,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// Create an empty image of the given format and size (e.g. `10G`)
+pub fn create(path: impl AsRef<Path>, format: &str, size: &str) -> Result<(), Error> {
+    qemu_img([
+        OsStr::new("create"),
+        OsStr::new("-f"),
+        OsStr::new(format),
+        path.as_ref().as_os_str(),
+        OsStr::new(size),
+    ])
+    .map(|_| ())
+}
+
+/// Create a qcow2 overlay on top of a backing image
+///
+/// Writes go to the overlay, leaving the backing image untouched.
+pub fn create_overlay(
+    path: impl AsRef<Path>,
+    backing: impl AsRef<Path>,
+    backing_format: &str,
+) -> Result<(), Error> {
+    qemu_img([
+        OsStr::new("create"),
+        OsStr::new("-f"),
+        OsStr::new("qcow2"),
+        OsStr::new("-b"),
+        backing.as_ref().as_os_str(),
+        OsStr::new("-F"),
+        OsStr::new(backing_format),
+        path.as_ref().as_os_str(),
+    ])
+    .map(|_| ())
+}
+
+/// Convert an image to another format
+pub fn convert(
+    source: impl AsRef<Path>,
+    destination: impl AsRef<Path>,
+    format: &str,
+) -> Result<(), Error> {
+    qemu_img([
+        OsStr::new("convert"),
+        OsStr::new("-O"),
+        OsStr::new(format),
+        source.as_ref().as_os_str(),
+        destination.as_ref().as_os_str(),
+    ])
+    .map(|_| ())
+}
+
+/// Create an internal snapshot in an image
+pub fn create_snapshot(path: impl AsRef<Path>, name: &str) -> Result<(), Error> {
+    snapshot(path.as_ref(), "-c", name)
+}
+
+/// Revert an image to an internal snapshot
+pub fn apply_snapshot(path: impl AsRef<Path>, name: &str) -> Result<(), Error> {
+    snapshot(path.as_ref(), "-a", name)
+}
+
+/// Delete an internal snapshot from an image
+pub fn delete_snapshot(path: impl AsRef<Path>, name: &str) -> Result<(), Error> {
+    snapshot(path.as_ref(), "-d", name)
+}
+
+fn snapshot(path: &Path, operation: &str, name: &str) -> Result<(), Error> {
+    qemu_img([
+        OsStr::new("snapshot"),
+        OsStr::new(operation),
+        OsStr::new(name),
+        path.as_os_str(),
+    ])
+    .map(|_| ())
+}
+
+/// Get information about an image
+pub fn info(path: impl AsRef<Path>) -> Result<ImageInfo, Error> {
+    let output = qemu_img([
+        OsStr::new("info"),
+        OsStr::new("--output=json"),
+        path.as_ref().as_os_str(),
+    ])?;
+    serde_json::from_str(&output).map_err(|err| Error::new(ErrorKind::SerializationError, err))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse_info() {
+        const INFO: &str = r#"{
+            "virtual-size": 10737418240,
+            "filename": "overlay.qcow2",
+            "cluster-size": 65536,
+            "format": "qcow2",
+            "actual-size": 200704,
+            "backing-filename": "base.raw",
+            "backing-filename-format": "raw",
+            "dirty-flag": false
+        }"#;
+        let info: ImageInfo = serde_json::from_str(INFO).unwrap();
+        assert_eq!("qcow2", info.format);
+        assert_eq!(10737418240, info.virtual_size);
+        assert_eq!(Some("base.raw"), info.backing_filename.as_deref());
+        assert_eq!(Some(false), info.dirty_flag);
+    }
+}
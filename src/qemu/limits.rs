@@ -0,0 +1,271 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+/// Resource limits applied to the QEMU process
+///
+/// Scope limits are enforced by running QEMU in a transient systemd scope
+/// (`systemd-run --scope`) backed by cgroup v2. Process limits are set with
+/// `setrlimit` before QEMU starts and need neither systemd nor cgroups.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct ResourceLimits {
+    /// Run the scope in the user's service manager instead of the system's
+    #[serde(default)]
+    user: bool,
+
+    /// Maximum memory (e.g. `2G`)
+    memory_max: Option<String>,
+
+    /// CPU time quota relative to one CPU (e.g. `200%`)
+    cpu_quota: Option<String>,
+
+    /// IO weight between 1 and 10000
+    io_weight: Option<usize>,
+
+    /// Maximum number of tasks
+    tasks_max: Option<usize>,
+
+    /// Maximum virtual address space in bytes (`RLIMIT_AS`)
+    address_space: Option<u64>,
+
+    /// Maximum CPU time in seconds (`RLIMIT_CPU`)
+    cpu_time: Option<u64>,
+
+    /// Maximum number of open files (`RLIMIT_NOFILE`)
+    open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// No limits, until some are set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the scope in the user's service manager instead of the system's
+    pub fn user(mut self, user: bool) -> Self {
+        self.user = user;
+        self
+    }
+
+    /// Maximum memory (e.g. `2G`)
+    pub fn memory_max(mut self, memory_max: impl Into<String>) -> Self {
+        self.memory_max = Some(memory_max.into());
+        self
+    }
+
+    /// CPU time quota relative to one CPU (e.g. `200%`)
+    pub fn cpu_quota(mut self, cpu_quota: impl Into<String>) -> Self {
+        self.cpu_quota = Some(cpu_quota.into());
+        self
+    }
+
+    /// IO weight between 1 and 10000
+    pub fn io_weight(mut self, io_weight: usize) -> Self {
+        self.io_weight = Some(io_weight);
+        self
+    }
+
+    /// Maximum number of tasks
+    pub fn tasks_max(mut self, tasks_max: usize) -> Self {
+        self.tasks_max = Some(tasks_max);
+        self
+    }
+
+    /// Maximum virtual address space in bytes (`RLIMIT_AS`)
+    pub fn address_space(mut self, bytes: u64) -> Self {
+        self.address_space = Some(bytes);
+        self
+    }
+
+    /// Maximum CPU time in seconds (`RLIMIT_CPU`)
+    pub fn cpu_time(mut self, seconds: u64) -> Self {
+        self.cpu_time = Some(seconds);
+        self
+    }
+
+    /// Maximum number of open files (`RLIMIT_NOFILE`)
+    pub fn open_files(mut self, open_files: u64) -> Self {
+        self.open_files = Some(open_files);
+        self
+    }
+
+    /// Apply these limits to a command
+    pub(crate) fn apply(&self, command: Command) -> Result<Command, Error> {
+        let mut command = if self.scoped() {
+            self.wrap(&command)
+        } else {
+            command
+        };
+        self.set_rlimits(&mut command)?;
+        Ok(command)
+    }
+
+    /// Whether any limit requires a systemd scope
+    fn scoped(&self) -> bool {
+        self.memory_max.is_some()
+            || self.cpu_quota.is_some()
+            || self.io_weight.is_some()
+            || self.tasks_max.is_some()
+    }
+
+    /// Set process limits on a command before it executes
+    #[cfg(unix)]
+    fn set_rlimits(&self, command: &mut Command) -> Result<(), Error> {
+        use std::os::unix::process::CommandExt;
+
+        let limits: Vec<_> = [
+            (libc::RLIMIT_AS, self.address_space),
+            (libc::RLIMIT_CPU, self.cpu_time),
+            (libc::RLIMIT_NOFILE, self.open_files),
+        ]
+        .into_iter()
+        .filter_map(|(resource, value)| value.map(|value| (resource, value as libc::rlim_t)))
+        .collect();
+        if limits.is_empty() {
+            return Ok(());
+        }
+        // SAFETY: setrlimit is async-signal-safe and only reads the limits
+        // collected before forking.
+        unsafe {
+            command.pre_exec(move || {
+                for &(resource, value) in &limits {
+                    let limit = libc::rlimit {
+                        rlim_cur: value,
+                        rlim_max: value,
+                    };
+                    if libc::setrlimit(resource, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Set process limits on a command before it executes
+    #[cfg(not(unix))]
+    fn set_rlimits(&self, _command: &mut Command) -> Result<(), Error> {
+        if self.address_space.is_some() || self.cpu_time.is_some() || self.open_files.is_some() {
+            return Err(Error::new(
+                crate::ErrorKind::InvalidConfig,
+                "Process limits are only supported on Unix",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Wrap a command so it runs under these limits
+    pub(crate) fn wrap(&self, command: &Command) -> Command {
+        let mut wrapped = Command::new("systemd-run");
+        if self.user {
+            wrapped.arg("--user");
+        }
+        wrapped.args(["--scope", "--quiet", "--collect"]);
+        let properties = [
+            ("MemoryMax", self.memory_max.clone()),
+            ("CPUQuota", self.cpu_quota.clone()),
+            ("IOWeight", self.io_weight.map(|weight| weight.to_string())),
+            ("TasksMax", self.tasks_max.map(|tasks| tasks.to_string())),
+        ];
+        for (key, value) in properties {
+            if let Some(value) = value {
+                wrapped.arg("-p").arg(format!("{key}={value}"));
+            }
+        }
+        wrapped
+            .arg("--")
+            .arg(command.get_program())
+            .args(command.get_args());
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn wrap_command() {
+        let limits = ResourceLimits {
+            memory_max: Some("1G".to_string()),
+            tasks_max: Some(64),
+            ..Default::default()
+        };
+        let mut command = Command::new("qemu-system-i386");
+        command.args(["-m", "512"]);
+        let wrapped = limits.wrap(&command);
+        assert_eq!("systemd-run", wrapped.get_program());
+        assert_eq!(
+            vec![
+                "--scope",
+                "--quiet",
+                "--collect",
+                "-p",
+                "MemoryMax=1G",
+                "-p",
+                "TasksMax=64",
+                "--",
+                "qemu-system-i386",
+                "-m",
+                "512"
+            ],
+            wrapped.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn set_limits() {
+        let limits = ResourceLimits::new()
+            .user(true)
+            .memory_max("2G")
+            .cpu_quota("200%")
+            .io_weight(100);
+        let wrapped = limits.wrap(&Command::new("qemu-system-i386"));
+        assert_eq!(
+            vec![
+                "--user",
+                "--scope",
+                "--quiet",
+                "--collect",
+                "-p",
+                "MemoryMax=2G",
+                "-p",
+                "CPUQuota=200%",
+                "-p",
+                "IOWeight=100",
+                "--",
+                "qemu-system-i386"
+            ],
+            wrapped.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unscoped_command() {
+        let limits = ResourceLimits {
+            open_files: Some(64),
+            ..Default::default()
+        };
+        let command = limits.apply(Command::new("qemu-system-i386")).unwrap();
+        assert_eq!("qemu-system-i386", command.get_program());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_limits() {
+        let limits = ResourceLimits {
+            open_files: Some(64),
+            cpu_time: Some(30),
+            ..Default::default()
+        };
+        let mut command = Command::new("sh");
+        command.args(["-c", "ulimit -n; ulimit -t"]);
+        let output = limits.apply(command).unwrap().output().unwrap();
+        assert_eq!("64\n30\n", String::from_utf8_lossy(&output.stdout));
+    }
+}
@@ -1,10 +1,15 @@
+use crate::allocator::AUTO;
 use crate::qemu::args::PropertyValue;
+use crate::{Diagnostic, Error, ErrorKind};
 use cmdstruct::Arg;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 use system_harness_macros::{Backend, PropertyList};
 
-#[derive(Clone, Serialize, Deserialize, PropertyList)]
+/// Boot options (`-boot`)
+#[derive(Clone, Default, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct Boot {
     menu: Option<OnOff>,
@@ -15,10 +20,38 @@ pub struct Boot {
     splash_time: Option<String>,
     splash: Option<String>,
     once: Option<String>,
-    order: Option<String>
+    order: Option<String>,
+}
+
+impl Boot {
+    /// Boot drive order (e.g. `cd`)
+    pub fn order(mut self, order: impl Into<String>) -> Self {
+        self.order = Some(order.into());
+        self
+    }
+
+    /// Boot drive order for the first boot only
+    pub fn once(mut self, once: impl Into<String>) -> Self {
+        self.once = Some(once.into());
+        self
+    }
+
+    /// Enable or disable the interactive boot menu
+    pub fn menu(mut self, menu: OnOff) -> Self {
+        self.menu = Some(menu);
+        self
+    }
+
+    /// Only boot from the devices in the boot order
+    pub fn strict(mut self, strict: OnOff) -> Self {
+        self.strict = Some(strict);
+        self
+    }
 }
 
+/// Block device discard strategy
 #[derive(Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum Discard {
     Ignore,
@@ -34,7 +67,9 @@ impl PropertyValue for Discard {
     }
 }
 
+/// A block device node (`-blockdev`)
 #[derive(Clone, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct BlockDev {
     /// Block device driver
@@ -51,12 +86,77 @@ pub struct BlockDev {
     properties: BTreeMap<String, String>,
 }
 
+impl BlockDev {
+    /// Create a block device node
+    pub fn new(driver: impl Into<String>, node_name: impl Into<String>) -> Self {
+        Self {
+            driver: driver.into(),
+            node_name: node_name.into(),
+            discard: None,
+            properties: BTreeMap::new(),
+        }
+    }
+
+    /// Set the discard strategy
+    pub fn discard(mut self, discard: Discard) -> Self {
+        self.discard = Some(discard);
+        self
+    }
+
+    /// Set a driver property
+    pub fn property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties.insert(key.into(), value.into());
+        self
+    }
+
+    /// File opened by a `file` node
+    pub(crate) fn filename(&self) -> Option<&str> {
+        if self.driver == "file" {
+            self.properties.get("filename").map(String::as_str)
+        } else {
+            None
+        }
+    }
+
+    /// Node name of the block device
+    pub(crate) fn node_name(&self) -> &str {
+        &self.node_name
+    }
+
+    /// Check that a file backing the block device exists
+    pub(crate) fn validate(&self, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+        if let Some(filename) = self.filename() {
+            if !std::path::Path::new(filename).exists() {
+                diagnostics.push(Diagnostic::error(
+                    format!("{pointer}/filename"),
+                    format!(
+                        "blockdev '{}' file '{filename}' does not exist",
+                        self.node_name
+                    ),
+                ));
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Backend<T> {
     backend: T,
     id: String,
 }
 
+impl<T> Backend<T> {
+    pub(crate) fn new(id: String, backend: T) -> Self {
+        Self { backend, id }
+    }
+
+    /// Id other arguments refer to the backend by
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+}
+
 impl<T> Arg for Backend<T>
 where
     T: super::args::Backend,
@@ -75,23 +175,27 @@ impl<T: Clone> Clone for Backend<T> {
     fn clone(&self) -> Self {
         Self {
             backend: self.backend.clone(),
-            id: self.id.clone()
+            id: self.id.clone(),
         }
     }
 }
 
+/// A character device backend (`-chardev`)
 #[derive(Clone, Serialize, Deserialize, Backend)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum CharDev {
     Stdio,
     Socket { path: String },
 }
 
+/// An on/off property value
 #[derive(Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum OnOff {
     On,
-    Off
+    Off,
 }
 
 impl PropertyValue for OnOff {
@@ -103,7 +207,9 @@ impl PropertyValue for OnOff {
     }
 }
 
+/// A network backend (`-netdev`)
 #[derive(Clone, Serialize, Deserialize, Backend)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum NetDev {
     User {
@@ -111,11 +217,33 @@ pub enum NetDev {
 
         net: String,
 
-        host: String
+        host: String,
+    },
+
+    /// Connected to other VMs through a UDP multicast group
+    Socket {
+        /// Multicast group and port (e.g. `230.0.0.1:1234`)
+        mcast: Option<String>,
+
+        /// Address multicast traffic is sent from
+        localaddr: Option<String>,
+    },
+
+    /// Connected to a host TAP interface
+    Tap {
+        ifname: String,
+
+        /// Script run to set the interface up (`no` for none)
+        script: Option<String>,
+
+        /// Script run to take the interface down (`no` for none)
+        downscript: Option<String>,
     },
 }
 
+/// A device (`-device`)
 #[derive(Clone, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Device {
     /// Device driver
     driver: String,
@@ -125,7 +253,32 @@ pub struct Device {
     properties: BTreeMap<String, String>,
 }
 
-#[derive(Clone, Serialize, Deserialize, PropertyList)]
+impl Device {
+    /// Create a device using the given driver
+    pub fn new(driver: impl Into<String>) -> Self {
+        Self {
+            driver: driver.into(),
+            properties: BTreeMap::new(),
+        }
+    }
+
+    /// Set a driver property
+    pub fn property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties.insert(key.into(), value.into());
+        self
+    }
+
+    /// Replace an `auto` MAC address with one unique to the host
+    pub(crate) fn allocate_mac(&mut self) {
+        if let Some(mac) = self.properties.get_mut("mac").filter(|mac| *mac == AUTO) {
+            *mac = crate::unique_mac();
+        }
+    }
+}
+
+/// CPU topology (`-smp`)
+#[derive(Clone, Default, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Smp {
     /// Number of CPUs
     cpus: Option<usize>,
@@ -149,7 +302,59 @@ pub struct Smp {
     threads: Option<usize>,
 }
 
-#[derive(Clone, Serialize, Deserialize, PropertyList)]
+impl Smp {
+    /// Set the number of CPUs
+    pub fn cpus(mut self, cpus: usize) -> Self {
+        self.cpus = Some(cpus);
+        self
+    }
+
+    /// Set the maximum number of CPUs
+    pub fn maxcpus(mut self, maxcpus: usize) -> Self {
+        self.maxcpus = Some(maxcpus);
+        self
+    }
+
+    /// Set the number of sockets
+    pub fn sockets(mut self, sockets: usize) -> Self {
+        self.sockets = Some(sockets);
+        self
+    }
+
+    /// Set the number of cores per socket
+    pub fn cores(mut self, cores: usize) -> Self {
+        self.cores = Some(cores);
+        self
+    }
+
+    /// Set the number of threads per core
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Check that CPU counts are consistent
+    pub(crate) fn validate(&self, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+        if self.cpus == Some(0) {
+            diagnostics.push(Diagnostic::error(
+                format!("{pointer}/cpus"),
+                "smp cpus must be greater than zero",
+            ));
+        }
+        if let (Some(cpus), Some(maxcpus)) = (self.cpus, self.maxcpus) {
+            if cpus > maxcpus {
+                diagnostics.push(Diagnostic::error(
+                    format!("{pointer}/cpus"),
+                    format!("smp cpus ({cpus}) exceeds maxcpus ({maxcpus})"),
+                ));
+            }
+        }
+    }
+}
+
+/// Machine type and properties (`-machine`)
+#[derive(Clone, Default, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Machine {
     /// Machine type
     #[serde(rename = "type")]
@@ -159,6 +364,419 @@ pub struct Machine {
     properties: BTreeMap<String, String>,
 }
 
+impl Machine {
+    /// Create a machine of the given type
+    pub fn new(machine_type: impl Into<String>) -> Self {
+        Self {
+            r#type: Some(machine_type.into()),
+            properties: BTreeMap::new(),
+        }
+    }
+
+    /// Set a machine property
+    pub fn property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties.insert(key.into(), value.into());
+        self
+    }
+
+    /// Accelerators requested through the machine's `accel` property
+    pub(crate) fn accelerators(&self) -> impl Iterator<Item = &str> {
+        self.properties
+            .get("accel")
+            .into_iter()
+            .flat_map(|accel| accel.split(':'))
+    }
+
+    /// Warn about properties QEMU's machines don't have
+    ///
+    /// Only the generic properties and those of the common machine types
+    /// are known, so these are warnings rather than errors.
+    pub(crate) fn validate(&self, pointer: &str, diagnostics: &mut Vec<Diagnostic>) {
+        for key in self.properties.keys() {
+            if !MACHINE_PROPERTIES.contains(&key.as_str()) {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{pointer}/{key}"),
+                    format!("'{key}' is not a known machine property"),
+                ));
+            }
+        }
+    }
+}
+
+/// Properties of QEMU's generic, x86, arm and microvm machines
+const MACHINE_PROPERTIES: &[&str] = &[
+    "accel",
+    "acpi",
+    "append",
+    "auto-enable-numa",
+    "confidential-guest-support",
+    "default-bus-bypass-iommu",
+    "dtb",
+    "dump-guest-core",
+    "dumpdtb",
+    "firmware",
+    "gic-version",
+    "graphics",
+    "highmem",
+    "hmat",
+    "hpet",
+    "i8042",
+    "initrd",
+    "iommu",
+    "isa-serial",
+    "its",
+    "kernel",
+    "kernel-irqchip",
+    "kvm-shadow-mem",
+    "max-ram-below-4g",
+    "mem-merge",
+    "memory-backend",
+    "memory-encryption",
+    "mte",
+    "nvdimm",
+    "pcie",
+    "pflash0",
+    "pflash1",
+    "phandle-start",
+    "pic",
+    "pit",
+    "ras",
+    "rtc",
+    "sata",
+    "secure",
+    "smbios-entry-point-type",
+    "smm",
+    "suppress-vmdesc",
+    "usb",
+    "virtualization",
+    "vmport",
+    "x-option-roms",
+];
+
+/// A firmware configuration item (`-fw_cfg`)
+#[derive(Clone, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FwCfg {
+    /// Item name (e.g. `opt/com.coreos/config`)
+    name: String,
+
+    /// File providing the item contents
+    file: Option<String>,
+
+    /// Literal item contents
+    string: Option<String>,
+}
+
+impl FwCfg {
+    /// Create an item with contents read from a file
+    pub fn file(name: impl Into<String>, file: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            file: Some(file.into()),
+            string: None,
+        }
+    }
+
+    /// Create an item with literal contents
+    pub fn string(name: impl Into<String>, string: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            file: None,
+            string: Some(string.into()),
+        }
+    }
+
+    /// File providing the item contents, if any
+    pub(crate) fn path(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+}
+
+/// Clock driving the real-time clock
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum RtcClock {
+    /// Host system time, including adjustments made to it
+    Host,
+
+    /// Host time that is never adjusted
+    Rt,
+
+    /// Virtual time, which stops while the guest is paused
+    Vm,
+}
+
+impl PropertyValue for RtcClock {
+    fn value(&self) -> Option<String> {
+        match self {
+            RtcClock::Host => Some(String::from("host")),
+            RtcClock::Rt => Some(String::from("rt")),
+            RtcClock::Vm => Some(String::from("vm")),
+        }
+    }
+}
+
+/// Real-time clock options (`-rtc`)
+#[derive(Clone, Default, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Rtc {
+    /// Date the clock starts at: `utc`, `localtime` or a date such as
+    /// `2038-01-19T03:14:07`
+    base: Option<String>,
+
+    /// Clock driving the real-time clock (defaults to `host`)
+    clock: Option<RtcClock>,
+
+    /// Compensation for missed timer interrupts (`slew` or `none`)
+    driftfix: Option<String>,
+}
+
+impl Rtc {
+    /// Date the clock starts at: `utc`, `localtime` or a date such as
+    /// `2038-01-19T03:14:07`
+    pub fn base(mut self, base: impl Into<String>) -> Self {
+        self.base = Some(base.into());
+        self
+    }
+
+    /// Time the clock starts at, in UTC
+    ///
+    /// Times before the Unix epoch start the clock at the epoch.
+    pub fn base_time(self, time: SystemTime) -> Self {
+        self.base(utc_date(time))
+    }
+
+    /// Clock driving the real-time clock
+    pub fn clock(mut self, clock: RtcClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Compensation for missed timer interrupts (`slew` or `none`)
+    pub fn driftfix(mut self, driftfix: impl Into<String>) -> Self {
+        self.driftfix = Some(driftfix.into());
+        self
+    }
+}
+
+/// Format a time as a UTC date QEMU accepts (`YYYY-MM-DDTHH:MM:SS`)
+fn utc_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil date from days since the epoch, counting in 400 year eras that
+    // start on March 1st so leap days fall at the end of the year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Whether an execution is recorded or replayed
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordReplay {
+    /// Record non-deterministic events to the replay file
+    Record,
+
+    /// Replay the events recorded in the replay file
+    Replay,
+}
+
+impl PropertyValue for RecordReplay {
+    fn value(&self) -> Option<String> {
+        match self {
+            RecordReplay::Record => Some(String::from("record")),
+            RecordReplay::Replay => Some(String::from("replay")),
+        }
+    }
+}
+
+/// Instruction counting options (`-icount`)
+///
+/// Virtual time is derived from the number of instructions executed rather
+/// than the host clock, which makes guest timing deterministic. TCG only.
+///
+/// An execution can also be recorded, to be replayed exactly later. Block
+/// devices must then be opened in snapshot mode through the `blkreplay`
+/// driver, so the replay starts from the same disk contents. See QEMU's
+/// record/replay documentation for what else can be replayed.
+#[derive(Clone, Default, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Icount {
+    /// Virtual nanoseconds per instruction as a power of two, or `auto`
+    shift: Option<String>,
+
+    /// Sleep while the guest is idle (defaults to `on`)
+    ///
+    /// When `off`, virtual time skips ahead to the next timer whenever the
+    /// guest is idle, so guest sleeps and timeouts pass without waiting.
+    sleep: Option<OnOff>,
+
+    /// Slow the guest down so virtual time doesn't run ahead of the host
+    align: Option<OnOff>,
+
+    /// Record or replay the execution
+    rr: Option<RecordReplay>,
+
+    /// File the execution is recorded to or replayed from
+    rrfile: Option<String>,
+
+    /// Name of the snapshot taken when recording starts, and loaded when
+    /// replaying
+    rrsnapshot: Option<String>,
+}
+
+impl Icount {
+    /// Count instructions, adjusting the time per instruction to keep
+    /// virtual time close to the host's
+    pub fn auto() -> Self {
+        Self {
+            shift: Some(String::from("auto")),
+            ..Self::default()
+        }
+    }
+
+    /// Count instructions, each taking 2^`shift` virtual nanoseconds
+    pub fn shift(shift: usize) -> Self {
+        Self {
+            shift: Some(shift.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Sleep while the guest is idle, rather than skipping ahead
+    pub fn sleep(mut self, sleep: OnOff) -> Self {
+        self.sleep = Some(sleep);
+        self
+    }
+
+    /// Slow the guest down so virtual time doesn't run ahead of the host
+    pub fn align(mut self, align: OnOff) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Record the execution to a file
+    pub fn record(mut self, rrfile: impl Into<String>) -> Self {
+        self.rr = Some(RecordReplay::Record);
+        self.rrfile = Some(rrfile.into());
+        self
+    }
+
+    /// Snapshot taken when recording starts, and loaded when replaying
+    pub fn rrsnapshot(mut self, rrsnapshot: impl Into<String>) -> Self {
+        self.rrsnapshot = Some(rrsnapshot.into());
+        self
+    }
+
+    /// The options that replay what these options record
+    pub(crate) fn replay(&self) -> Result<Self, Error> {
+        match (self.rr, &self.rrfile) {
+            (Some(RecordReplay::Record), Some(_)) => Ok(Self {
+                rr: Some(RecordReplay::Replay),
+                ..self.clone()
+            }),
+            _ => Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "Only a system recorded to a file can be replayed",
+            )),
+        }
+    }
+}
+
+/// A TCG plugin instrumenting the guest (`-plugin`)
+///
+/// Plugins write their output, e.g. instruction counts, to QEMU's log. See
+/// [`QemuSystem::plugin_output`](crate::QemuSystem::plugin_output).
+#[derive(Clone, Serialize, Deserialize, PropertyList)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Plugin {
+    /// Shared library implementing the plugin (e.g. `libinsn.so`)
+    file: String,
+
+    /// Plugin arguments
+    #[serde(flatten)]
+    args: BTreeMap<String, String>,
+}
+
+impl Plugin {
+    /// Load a plugin from a shared library
+    pub fn new(file: impl Into<String>) -> Self {
+        Self {
+            file: file.into(),
+            args: BTreeMap::new(),
+        }
+    }
+
+    /// Set a plugin argument
+    pub fn arg(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.args.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// A kernel command line (`-append`)
+///
+/// Parameters are kept in the order they were added.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct KernelCommandLine(Vec<String>);
+
+impl KernelCommandLine {
+    /// Add a `key=value` parameter
+    ///
+    /// Values containing whitespace are quoted.
+    pub fn param(mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+        let value = value.as_ref();
+        if value.contains(char::is_whitespace) {
+            self.0.push(format!("{}=\"{value}\"", key.as_ref()));
+        } else {
+            self.0.push(format!("{}={value}", key.as_ref()));
+        }
+        self
+    }
+
+    /// Add a parameter without a value
+    pub fn flag(mut self, key: impl Into<String>) -> Self {
+        self.0.push(key.into());
+        self
+    }
+}
+
+impl std::fmt::Display for KernelCommandLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.join(" "))
+    }
+}
+
+impl Arg for KernelCommandLine {
+    fn append_arg(&self, command: &mut std::process::Command) {
+        command.arg(self.to_string());
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -168,7 +786,7 @@ mod tests {
 
     #[test]
     fn chardev() {
-        const EXPECTED: &'static str = r#"{"backend":{"socket":{"path":"test.sock"}},"id":"abc"}"#;
+        const EXPECTED: &str = r#"{"backend":{"socket":{"path":"test.sock"}},"id":"abc"}"#;
         let chardev = Backend::<CharDev> {
             id: "abc".to_string(),
             backend: CharDev::Socket {
@@ -198,4 +816,75 @@ mod tests {
             command.get_args().collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn rtc_arg() {
+        let mut command = std::process::Command::new("test");
+        let base = UNIX_EPOCH + std::time::Duration::from_secs(2_147_483_647);
+        Rtc::default()
+            .base_time(base)
+            .clock(RtcClock::Vm)
+            .append_arg(&mut command);
+        assert_eq!(
+            vec!["base=2038-01-19T03:14:07,clock=vm"],
+            command.get_args().collect::<Vec<_>>()
+        );
+        assert_eq!("1970-01-01T00:00:00", utc_date(UNIX_EPOCH));
+        assert_eq!(
+            "2000-02-29T12:00:00",
+            utc_date(UNIX_EPOCH + std::time::Duration::from_secs(951_825_600))
+        );
+    }
+
+    #[test]
+    fn icount_arg() {
+        let mut command = std::process::Command::new("test");
+        Icount::auto().sleep(OnOff::Off).append_arg(&mut command);
+        let record = Icount::auto().record("run.rr");
+        record.append_arg(&mut command);
+        record.replay().unwrap().append_arg(&mut command);
+        assert_eq!(
+            vec![
+                "shift=auto,sleep=off",
+                "shift=auto,rr=record,rrfile=run.rr",
+                "shift=auto,rr=replay,rrfile=run.rr"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+        assert!(Icount::auto().replay().is_err());
+    }
+
+    #[test]
+    fn plugin_arg() {
+        let mut command = std::process::Command::new("test");
+        Plugin::new("/usr/lib/qemu/plugins/libinsn.so")
+            .arg("inline", "on")
+            .append_arg(&mut command);
+        assert_eq!(
+            vec!["file=/usr/lib/qemu/plugins/libinsn.so,inline=on"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn fw_cfg_arg() {
+        let mut command = std::process::Command::new("test");
+        FwCfg::file("opt/com.coreos/config", "config.ign").append_arg(&mut command);
+        assert_eq!(
+            vec!["name=opt/com.coreos/config,file=config.ign"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn kernel_command_line() {
+        let cmdline = KernelCommandLine::default()
+            .param("console", "ttyS0,115200")
+            .flag("quiet")
+            .param("systemd.setenv", "A=b c");
+        assert_eq!(
+            r#"console=ttyS0,115200 quiet systemd.setenv="A=b c""#,
+            cmdline.to_string()
+        );
+    }
 }
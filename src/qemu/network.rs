@@ -0,0 +1,297 @@
+use super::{Device, NetDev, QemuSystemConfig};
+use crate::{Error, ErrorKind};
+use serde::Serialize;
+use std::fmt::Display;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Multicast group the VMs of multicast networks join
+const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(230, 0, 0, 1);
+
+/// NIC VMs are attached with by default
+const DEFAULT_NIC: &str = "virtio-net-pci";
+
+/// Counter used to tell networks apart in MACs and interface names
+static NETWORK: AtomicU8 = AtomicU8::new(0);
+
+/// How the VMs of a network are connected
+enum Segment {
+    /// A UDP multicast group on the loopback interface
+    Multicast(SocketAddrV4),
+
+    /// A host bridge with a TAP interface per VM
+    #[cfg(target_os = "linux")]
+    Bridge(String),
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Segment::Multicast(group) => write!(f, "multicast {group}"),
+            #[cfg(target_os = "linux")]
+            Segment::Bridge(bridge) => write!(f, "bridge {bridge}"),
+        }
+    }
+}
+
+/// A VM attached to a [`VirtualNetwork`]
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkNode {
+    /// Name the VM was attached under
+    pub name: String,
+
+    /// MAC address of the VM's NIC
+    pub mac: String,
+
+    /// Id of the VM's network backend
+    pub netdev: String,
+
+    /// Host TAP interface of the VM, on bridged networks
+    pub tap: Option<String>,
+}
+
+/// An isolated L2 segment shared by QEMU systems
+///
+/// Each VM attached gets a NIC on the segment with a MAC unique to it, so
+/// cluster software can be tested across VMs without configuring host
+/// networking by hand. The segment isn't connected to any host interface.
+/// [`Display`] prints the topology.
+pub struct VirtualNetwork {
+    name: String,
+    index: u8,
+    segment: Segment,
+    nic: String,
+    nodes: Vec<NetworkNode>,
+}
+
+impl VirtualNetwork {
+    fn new(name: String, index: u8, segment: Segment) -> Self {
+        Self {
+            name,
+            index,
+            segment,
+            nic: DEFAULT_NIC.to_string(),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Check that a name can be part of a QEMU id
+    fn check_name(name: &str) -> Result<(), Error> {
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+        match valid {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Invalid network name '{name}'"),
+            )),
+        }
+    }
+
+    /// A network on a multicast group of its own
+    ///
+    /// Needs no privileges. Traffic is sent on the loopback interface.
+    pub fn multicast(name: impl Into<String>) -> Result<Self, Error> {
+        let name = name.into();
+        Self::check_name(&name)?;
+        // A port free on the host keeps the group apart from other networks
+        let port = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+        let group = SocketAddrV4::new(MULTICAST_GROUP, port);
+        let index = NETWORK.fetch_add(1, Ordering::Relaxed);
+        Ok(Self::new(name, index, Segment::Multicast(group)))
+    }
+
+    /// A network on a host bridge, deleted with its TAP interfaces when
+    /// dropped
+    ///
+    /// Creating the bridge and TAP interfaces needs `CAP_NET_ADMIN`.
+    #[cfg(target_os = "linux")]
+    pub fn bridge(name: impl Into<String>) -> Result<Self, Error> {
+        let name = name.into();
+        Self::check_name(&name)?;
+        let index = NETWORK.fetch_add(1, Ordering::Relaxed);
+        let bridge = format!("shb{:x}.{index:x}", std::process::id());
+        ip(&["link", "add", "name", &bridge, "type", "bridge"])?;
+        log::trace!("Created bridge: {bridge}");
+        let network = Self::new(name, index, Segment::Bridge(bridge.clone()));
+        ip(&["link", "set", &bridge, "up"])?;
+        Ok(network)
+    }
+
+    /// NIC VMs are attached with (defaults to `virtio-net-pci`)
+    pub fn nic(mut self, driver: impl Into<String>) -> Self {
+        self.nic = driver.into();
+        self
+    }
+
+    /// Name of the network
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// VMs attached to the network
+    pub fn nodes(&self) -> &[NetworkNode] {
+        &self.nodes
+    }
+
+    /// Attach a VM, returning its config with a NIC on the network added
+    pub fn attach(
+        &mut self,
+        name: impl Into<String>,
+        config: &QemuSystemConfig,
+    ) -> Result<QemuSystemConfig, Error> {
+        let node = self.nodes.len() + 1;
+        // Keeps TAP interface names within the kernel's 15 characters
+        if node > 0xff {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Too many VMs on network {}", self.name),
+            ));
+        }
+        let mac = format!("52:54:00:{:02x}:00:{node:02x}", self.index);
+        let id = format!("net-{}", self.name);
+        let (netdev, tap) = match &self.segment {
+            Segment::Multicast(group) => (
+                NetDev::Socket {
+                    mcast: Some(group.to_string()),
+                    localaddr: Some(Ipv4Addr::LOCALHOST.to_string()),
+                },
+                None,
+            ),
+            #[cfg(target_os = "linux")]
+            Segment::Bridge(bridge) => {
+                let tap = self.create_tap(bridge, node)?;
+                let netdev = NetDev::Tap {
+                    ifname: tap.clone(),
+                    script: Some(String::from("no")),
+                    downscript: Some(String::from("no")),
+                };
+                (netdev, Some(tap))
+            }
+        };
+        let device = Device::new(&self.nic)
+            .property("netdev", &id)
+            .property("mac", &mac);
+        let config = config.with_overrides(|builder| builder.netdev(&id, netdev).device(device));
+        self.nodes.push(NetworkNode {
+            name: name.into(),
+            mac,
+            netdev: id,
+            tap,
+        });
+        Ok(config)
+    }
+
+    /// Create a TAP interface on the bridge, owned by the harness's user so
+    /// QEMU can open it
+    #[cfg(target_os = "linux")]
+    fn create_tap(&self, bridge: &str, node: usize) -> Result<String, Error> {
+        use std::os::unix::fs::MetadataExt;
+
+        let tap = format!("sht{:x}.{:x}.{node:x}", std::process::id(), self.index);
+        let uid = std::fs::metadata("/proc/self")?.uid().to_string();
+        ip(&["tuntap", "add", "dev", &tap, "mode", "tap", "user", &uid])?;
+        log::trace!("Created TAP interface: {tap}");
+        let attached = ip(&["link", "set", &tap, "master", bridge])
+            .and_then(|_| ip(&["link", "set", &tap, "up"]));
+        if let Err(err) = attached {
+            let _ = ip(&["link", "del", &tap]);
+            return Err(err);
+        }
+        Ok(tap)
+    }
+}
+
+impl Display for VirtualNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} ({})", self.name, self.segment)?;
+        for node in &self.nodes {
+            write!(f, "  {} {}", node.name, node.mac)?;
+            if let Some(tap) = &node.tap {
+                write!(f, " {tap}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VirtualNetwork {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Segment::Bridge(bridge) = &self.segment {
+            let interfaces = self.nodes.iter().filter_map(|node| node.tap.as_deref());
+            for interface in interfaces.chain([bridge.as_str()]) {
+                if let Err(err) = ip(&["link", "del", interface]) {
+                    log::warn!("Failed to delete interface {interface}: {err}");
+                }
+            }
+        }
+    }
+}
+
+/// Run `ip` with the given arguments
+#[cfg(target_os = "linux")]
+fn ip(args: &[&str]) -> Result<(), Error> {
+    let output = std::process::Command::new("ip").args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::HarnessError,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use cmdstruct::Command;
+
+    #[test]
+    fn multicast() {
+        let config = QemuSystemConfig::builder().arch("x86_64").build();
+        let mut network = VirtualNetwork::multicast("cluster").unwrap();
+        let first = network.attach("node-a", &config).unwrap();
+        network.attach("node-b", &config).unwrap();
+
+        let Segment::Multicast(group) = network.segment else {
+            panic!("Expected a multicast network");
+        };
+        let args: Vec<_> = first
+            .command()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mac = format!("52:54:00:{:02x}:00:01", network.index);
+        assert_eq!(
+            vec![
+                String::from("-device"),
+                format!("driver=virtio-net-pci,mac={mac},netdev=net-cluster"),
+                String::from("-netdev"),
+                format!("socket,id=net-cluster,mcast={group},localaddr=127.0.0.1"),
+            ],
+            args
+        );
+        assert_ne!(network.nodes()[0].mac, network.nodes()[1].mac);
+        assert_eq!(
+            format!(
+                "cluster (multicast {group})\n  node-a {mac}\n  node-b {}\n",
+                network.nodes()[1].mac
+            ),
+            network.to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_name() {
+        let err = VirtualNetwork::multicast("my network").err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+}
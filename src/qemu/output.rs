@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// An output stream of the QEMU process
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Destination for an output stream of the QEMU process
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputSink {
+    /// Forward to the harness process's own stream
+    Inherit,
+
+    /// Discard output
+    Null,
+
+    /// Forward each line to the `log` crate
+    Log,
+
+    /// Write to a file
+    File(PathBuf),
+}
+
+/// A trait representing a listener for QEMU process output
+pub trait OutputSubscriber: Send + 'static {
+    /// Action to be performed on each line of output
+    fn on_output(&mut self, stream: OutputStream, line: &str);
+}
+
+impl<F> OutputSubscriber for F
+where
+    F: FnMut(OutputStream, &str) + Send + 'static,
+{
+    fn on_output(&mut self, stream: OutputStream, line: &str) {
+        (self)(stream, line)
+    }
+}
+
+/// Subscribers shared between a system and its output pumps
+pub(crate) type OutputSubscribers = Arc<Mutex<Vec<Box<dyn OutputSubscriber>>>>;
+
+/// Forwards one output stream of the QEMU process to its destinations
+pub(crate) struct OutputPump {
+    stream: OutputStream,
+    writers: Vec<Box<dyn Write + Send>>,
+    log: bool,
+    subscribers: OutputSubscribers,
+}
+
+impl OutputPump {
+    pub fn new(
+        stream: OutputStream,
+        sink: Option<&OutputSink>,
+        subscribers: OutputSubscribers,
+    ) -> std::io::Result<Self> {
+        let mut pump = Self {
+            stream,
+            writers: Vec::new(),
+            log: false,
+            subscribers,
+        };
+        match sink {
+            Some(OutputSink::Inherit) | None => match stream {
+                OutputStream::Stdout => pump.writers.push(Box::new(std::io::stdout())),
+                OutputStream::Stderr => pump.writers.push(Box::new(std::io::stderr())),
+            },
+            Some(OutputSink::Null) => {}
+            Some(OutputSink::Log) => pump.log = true,
+            Some(OutputSink::File(path)) => {
+                pump.writers.push(Box::new(std::fs::File::create(path)?))
+            }
+        }
+        Ok(pump)
+    }
+
+    /// Also write output to the given writer
+    pub fn tee(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.writers.push(Box::new(writer));
+        self
+    }
+
+    /// Forward lines from the reader until it is closed
+    pub fn spawn(mut self, reader: impl Read + Send + 'static) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            while let Ok(count) = reader.read_until(b'\n', &mut line) {
+                if count == 0 {
+                    break;
+                }
+                self.forward(&line);
+                line.clear();
+            }
+        })
+    }
+
+    fn forward(&mut self, line: &[u8]) {
+        for writer in &mut self.writers {
+            let _ = writer.write_all(line);
+        }
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        if self.log {
+            match self.stream {
+                OutputStream::Stdout => log::info!("qemu: {line}"),
+                OutputStream::Stderr => log::warn!("qemu: {line}"),
+            }
+        }
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            for subscriber in subscribers.iter_mut() {
+                subscriber.on_output(self.stream, line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn pump_to_subscriber() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let subscribers: OutputSubscribers = Arc::default();
+        {
+            let lines = lines.clone();
+            subscribers
+                .lock()
+                .unwrap()
+                .push(Box::new(move |stream: OutputStream, line: &str| {
+                    lines.lock().unwrap().push((stream, line.to_string()));
+                }));
+        }
+        let pump =
+            OutputPump::new(OutputStream::Stderr, Some(&OutputSink::Null), subscribers).unwrap();
+        pump.spawn(&b"first\nsecond\n"[..]).join().unwrap();
+        assert_eq!(
+            vec![
+                (OutputStream::Stderr, "first".to_string()),
+                (OutputStream::Stderr, "second".to_string())
+            ],
+            *lines.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use super::{KernelCommandLine, Machine, QemuSystemConfig, QemuSystemConfigBuilder, Smp};
+use crate::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+
+/// A named starting point for common machine types
+///
+/// Presets expand into a builder, so any field can be overridden before
+/// the configuration is built. Disks, kernels and devices are left to the
+/// caller.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QemuPreset {
+    /// x86_64 `microvm` with KVM, booting a kernel directly on `ttyS0`
+    ///
+    /// The kernel must be set with [`QemuSystemConfigBuilder::kernel`].
+    MicrovmDirectKernel,
+
+    /// aarch64 `virt` booting QEMU's bundled UEFI firmware with TCG
+    Aarch64VirtUefi,
+
+    /// x86_64 `q35` with KVM and the host CPU model
+    #[serde(rename = "x86_64-q35-kvm")]
+    X86_64Q35Kvm,
+}
+
+impl QemuPreset {
+    /// All presets
+    pub const ALL: [QemuPreset; 3] = [
+        QemuPreset::MicrovmDirectKernel,
+        QemuPreset::Aarch64VirtUefi,
+        QemuPreset::X86_64Q35Kvm,
+    ];
+
+    /// Name of the preset, as used in serialized configurations
+    pub fn name(&self) -> &'static str {
+        match self {
+            QemuPreset::MicrovmDirectKernel => "microvm-direct-kernel",
+            QemuPreset::Aarch64VirtUefi => "aarch64-virt-uefi",
+            QemuPreset::X86_64Q35Kvm => "x86_64-q35-kvm",
+        }
+    }
+
+    /// Create a builder populated with the preset
+    pub fn builder(self) -> QemuSystemConfigBuilder {
+        match self {
+            QemuPreset::MicrovmDirectKernel => QemuSystemConfig::builder()
+                .arch("x86_64")
+                .machine(Machine::new("microvm").property("x-option-roms", "off"))
+                .accel("kvm")
+                .cpu("host")
+                .smp(Smp::default().cpus(1))
+                .memory(512)
+                .append(KernelCommandLine::default().param("console", "ttyS0")),
+            QemuPreset::Aarch64VirtUefi => QemuSystemConfig::builder()
+                .arch("aarch64")
+                .machine(Machine::new("virt"))
+                .accel("tcg")
+                .cpu("max")
+                .smp(Smp::default().cpus(2))
+                .memory(1024)
+                .bios("edk2-aarch64-code.fd"),
+            QemuPreset::X86_64Q35Kvm => QemuSystemConfig::builder()
+                .arch("x86_64")
+                .machine(Machine::new("q35"))
+                .accel("kvm")
+                .cpu("host")
+                .smp(Smp::default().cpus(2))
+                .memory(1024),
+        }
+    }
+}
+
+impl std::str::FromStr for QemuPreset {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.name() == name)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidConfig, format!("Unknown preset: {name}")))
+    }
+}
+
+impl QemuSystemConfig {
+    /// Create a builder populated with a preset
+    pub fn preset(preset: QemuPreset) -> QemuSystemConfigBuilder {
+        preset.builder()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use cmdstruct::Command;
+
+    fn args(config: &QemuSystemConfig) -> Vec<String> {
+        config
+            .command()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn microvm() {
+        let config = QemuSystemConfig::preset(QemuPreset::MicrovmDirectKernel)
+            .kernel("bzImage")
+            .build();
+        assert_eq!("qemu-system-x86_64", config.command().get_program());
+        assert_eq!(
+            vec![
+                "-cpu",
+                "host",
+                "-machine",
+                "type=microvm,x-option-roms=off",
+                "-smp",
+                "cpus=1",
+                "-accel",
+                "kvm",
+                "-m",
+                "512",
+                "-kernel",
+                "bzImage",
+                "-append",
+                "console=ttyS0"
+            ],
+            args(&config)
+        );
+    }
+
+    #[test]
+    fn override_preset() {
+        let config = QemuSystemConfig::preset(QemuPreset::X86_64Q35Kvm)
+            .memory(4096)
+            .accel("tcg")
+            .build();
+        assert_eq!(
+            vec![
+                "-cpu", "host", "-machine", "type=q35", "-smp", "cpus=2", "-accel", "tcg", "-m",
+                "4096"
+            ],
+            args(&config)
+        );
+    }
+
+    #[test]
+    fn names() {
+        for preset in QemuPreset::ALL {
+            assert_eq!(preset, preset.name().parse().unwrap());
+            assert_eq!(
+                format!("\"{}\"", preset.name()),
+                serde_json::to_string(&preset).unwrap()
+            );
+        }
+        let err = "pdp-11".parse::<QemuPreset>().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+}
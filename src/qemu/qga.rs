@@ -0,0 +1,723 @@
+use super::transport::Channel;
+use crate::{Error, ErrorKind, ExecOutput, FileTransfer};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, SeekFrom, Write};
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Interval between `guest-exec-status` polls
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Maximum time to wait for a single readiness probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum number of bytes transferred per file read or write
+const FILE_CHUNK_SIZE: usize = 48 * 1024;
+
+/// A connection to the QEMU guest agent
+pub struct GuestAgent {
+    stream: BufReader<Channel>,
+}
+
+/// Read a line from the agent, failing if the channel has closed
+fn read_line(stream: &mut BufReader<Channel>) -> Result<String, Error> {
+    let mut line = String::new();
+    if stream.read_line(&mut line)? == 0 {
+        return Err(Error::new(
+            ErrorKind::PipeError,
+            "Guest agent channel closed",
+        ));
+    }
+    Ok(line)
+}
+
+fn read_response<D>(stream: &mut BufReader<Channel>) -> Result<D, Error>
+where
+    D: for<'de> serde::Deserialize<'de>,
+{
+    let line = read_line(stream)?;
+    log::trace!("Received guest response: {}", line.trim_end());
+    let response: GuestResponse<D> =
+        serde_json::from_str(&line).map_err(|err| Error::new(ErrorKind::HarnessError, err))?;
+    match response {
+        GuestResponse::Success { return_data } => Ok(return_data),
+        GuestResponse::Error { error } => Err(Error::new(ErrorKind::HarnessError, error.desc)),
+    }
+}
+
+impl GuestAgent {
+    /// Create a new guest agent connection
+    pub(crate) fn new(stream: Channel) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    pub(crate) fn try_clone(&self) -> Result<Self, Error> {
+        let stream = self.stream.get_ref().try_clone()?;
+        Ok(Self::new(stream))
+    }
+
+    /// Write guest agent command without waiting for a response
+    fn write_command(&mut self, command: GuestCommand) -> Result<(), Error> {
+        let message = serde_json::to_string(&command)
+            .map_err(|err| Error::new(ErrorKind::HarnessError, err))?;
+        log::trace!("Sending guest command: {message}");
+        self.stream
+            .get_mut()
+            .write_all(message.as_bytes())
+            .map_err(|err| Error::new(ErrorKind::PipeError, err))
+    }
+
+    /// Send guest agent command
+    fn send_command<D>(&mut self, command: GuestCommand) -> Result<D, Error>
+    where
+        D: for<'de> serde::Deserialize<'de>,
+    {
+        self.write_command(command)?;
+        read_response(&mut self.stream)
+    }
+
+    /// Synchronize with the agent, discarding any stale responses
+    fn sync(&mut self) -> Result<(), Error> {
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.subsec_nanos() as u64)
+            .unwrap_or_default();
+        self.write_command(GuestCommand::Sync(GuestSync { id }))?;
+        loop {
+            let line = read_line(&mut self.stream)?;
+            match serde_json::from_str::<GuestResponse<u64>>(&line) {
+                Ok(GuestResponse::Success { return_data }) if return_data == id => return Ok(()),
+                _ => log::trace!("Discarding guest response: {}", line.trim_end()),
+            }
+        }
+    }
+
+    /// Check that the agent is responsive
+    pub fn ping(&mut self) -> Result<(), Error> {
+        self.send_command(GuestCommand::Ping)
+            .map(|_: GuestEmpty| ())
+    }
+
+    /// Wait until the agent responds or the timeout expires
+    pub fn wait(&mut self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        let result = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for guest agent",
+                ));
+            }
+            self.stream
+                .get_ref()
+                .set_read_timeout(Some(remaining.min(PROBE_TIMEOUT)))?;
+            match self.sync().and_then(|_| self.ping()) {
+                Ok(()) => break Ok(()),
+                Err(err) if err.kind() == ErrorKind::IO => {
+                    log::trace!("Guest agent not ready: {err}")
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        self.stream.get_ref().set_read_timeout(None)?;
+        result
+    }
+
+    /// Ask the guest OS to shut down
+    ///
+    /// The agent does not respond to a successful shutdown request, so this
+    /// returns as soon as the command is sent.
+    pub fn shutdown(&mut self, mode: GuestShutdownMode) -> Result<(), Error> {
+        self.write_command(GuestCommand::Shutdown(GuestShutdown { mode }))
+    }
+
+    /// Freeze guest filesystems, returning the number frozen
+    pub fn fsfreeze(&mut self) -> Result<usize, Error> {
+        self.send_command(GuestCommand::FsfreezeFreeze)
+    }
+
+    /// Thaw guest filesystems, returning the number thawed
+    pub fn fsthaw(&mut self) -> Result<usize, Error> {
+        self.send_command(GuestCommand::FsfreezeThaw)
+    }
+
+    /// Get the guest's system time
+    pub fn time(&mut self) -> Result<SystemTime, Error> {
+        let nanos: u64 = self.send_command(GuestCommand::GetTime)?;
+        Ok(UNIX_EPOCH + Duration::from_nanos(nanos))
+    }
+
+    /// Set the guest's system time, and its hardware clock from it
+    ///
+    /// Without a time, the system time is set from the hardware clock.
+    pub fn set_time(&mut self, time: Option<SystemTime>) -> Result<(), Error> {
+        let time = time
+            .map(|time| {
+                time.duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_nanos() as u64)
+                    .map_err(|_| Error::new(ErrorKind::HarnessError, "Time before the epoch"))
+            })
+            .transpose()?;
+        self.send_command(GuestCommand::SetTime(GuestSetTime { time }))
+            .map(|_: GuestEmpty| ())
+    }
+
+    /// Get the guest's network interfaces and their addresses
+    pub fn network_interfaces(&mut self) -> Result<Vec<GuestNetworkInterface>, Error> {
+        self.send_command(GuestCommand::NetworkGetInterfaces)
+    }
+
+    /// Execute a command in the guest and wait up to the timeout for it to
+    /// exit
+    ///
+    /// A process that doesn't exit in time is left running in the guest.
+    pub fn exec(
+        &mut self,
+        cmd: &str,
+        args: &[&str],
+        stdin: Option<&[u8]>,
+        timeout: Duration,
+    ) -> Result<ExecOutput, Error> {
+        let exec = GuestExec {
+            path: cmd.to_string(),
+            arg: args.iter().map(|arg| arg.to_string()).collect(),
+            input_data: stdin.map(|data| STANDARD.encode(data)),
+            capture_output: true,
+        };
+        let GuestExecPid { pid } = self.send_command(GuestCommand::Exec(exec))?;
+        log::trace!("Started guest process: {pid}");
+        let deadline = Instant::now() + timeout;
+        loop {
+            let status: GuestExecStatus =
+                self.send_command(GuestCommand::ExecStatus(GuestExecPid { pid }))?;
+            if status.exited {
+                return status.try_into();
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("Guest process {pid} did not exit within {timeout:?}"),
+                ));
+            }
+            std::thread::sleep(remaining.min(EXEC_POLL_INTERVAL));
+        }
+    }
+
+    /// Open a file in the guest, returning its handle
+    ///
+    /// The mode follows `fopen()` conventions (e.g. `r`, `w`, `a+`).
+    pub fn file_open(&mut self, path: &str, mode: &str) -> Result<i64, Error> {
+        self.send_command(GuestCommand::FileOpen(GuestFileOpen {
+            path: path.to_string(),
+            mode: mode.to_string(),
+        }))
+    }
+
+    /// Read up to `count` bytes from an open guest file
+    ///
+    /// An empty result indicates end of file.
+    pub fn file_read(&mut self, handle: i64, count: usize) -> Result<Vec<u8>, Error> {
+        self.read_chunk(handle, count).map(|(data, _)| data)
+    }
+
+    /// Read up to `count` bytes from an open guest file, and whether the
+    /// end of the file was reached
+    fn read_chunk(&mut self, handle: i64, count: usize) -> Result<(Vec<u8>, bool), Error> {
+        let read: GuestFileRead =
+            self.send_command(GuestCommand::FileRead(GuestFileCount { handle, count }))?;
+        let data = STANDARD
+            .decode(read.buf_b64)
+            .map_err(|err| Error::new(ErrorKind::SerializationError, err))?;
+        Ok((data, read.eof))
+    }
+
+    /// Write bytes to an open guest file, returning the number written
+    pub fn file_write(&mut self, handle: i64, data: &[u8]) -> Result<usize, Error> {
+        let write: GuestFileWrite =
+            self.send_command(GuestCommand::FileWrite(GuestFileBuffer {
+                handle,
+                buf_b64: STANDARD.encode(data),
+            }))?;
+        Ok(write.count)
+    }
+
+    /// Seek within an open guest file, returning the new position
+    pub fn file_seek(&mut self, handle: i64, pos: SeekFrom) -> Result<u64, Error> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, GuestSeek::Set),
+            SeekFrom::Current(offset) => (offset, GuestSeek::Cur),
+            SeekFrom::End(offset) => (offset, GuestSeek::End),
+        };
+        let seek: GuestFileSeekPosition =
+            self.send_command(GuestCommand::FileSeek(GuestFileSeek {
+                handle,
+                offset,
+                whence,
+            }))?;
+        Ok(seek.position)
+    }
+
+    /// Close an open guest file
+    pub fn file_close(&mut self, handle: i64) -> Result<(), Error> {
+        self.send_command(GuestCommand::FileClose(GuestFileHandle { handle }))
+            .map(|_: GuestEmpty| ())
+    }
+}
+
+impl FileTransfer for GuestAgent {
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), Error> {
+        let handle = self.file_open(path, "w")?;
+        let result = data.chunks(FILE_CHUNK_SIZE).try_for_each(|mut chunk| {
+            while !chunk.is_empty() {
+                let count = self.file_write(handle, chunk)?;
+                if count == 0 || count > chunk.len() {
+                    return Err(Error::new(
+                        ErrorKind::HarnessError,
+                        format!(
+                            "Guest agent wrote {count} of {} bytes to {path}",
+                            chunk.len()
+                        ),
+                    ));
+                }
+                chunk = &chunk[count..];
+            }
+            Ok(())
+        });
+        // Close even if writing failed, but report the write error first
+        let closed = self.file_close(handle);
+        result?;
+        closed
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, Error> {
+        let handle = self.file_open(path, "r")?;
+        let mut data = Vec::new();
+        let result = loop {
+            match self.read_chunk(handle, FILE_CHUNK_SIZE) {
+                Ok((chunk, eof)) => {
+                    let empty = chunk.is_empty();
+                    data.extend(chunk);
+                    if eof || empty {
+                        break Ok(());
+                    }
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        // Close even if reading failed, but report the read error first
+        let closed = self.file_close(handle);
+        result?;
+        closed.map(|_| data)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "execute", content = "arguments")]
+enum GuestCommand {
+    #[serde(rename = "guest-sync")]
+    Sync(GuestSync),
+    #[serde(rename = "guest-ping")]
+    Ping,
+    #[serde(rename = "guest-shutdown")]
+    Shutdown(GuestShutdown),
+    #[serde(rename = "guest-network-get-interfaces")]
+    NetworkGetInterfaces,
+    #[serde(rename = "guest-get-time")]
+    GetTime,
+    #[serde(rename = "guest-set-time")]
+    SetTime(GuestSetTime),
+    #[serde(rename = "guest-fsfreeze-freeze")]
+    FsfreezeFreeze,
+    #[serde(rename = "guest-fsfreeze-thaw")]
+    FsfreezeThaw,
+    #[serde(rename = "guest-exec")]
+    Exec(GuestExec),
+    #[serde(rename = "guest-exec-status")]
+    ExecStatus(GuestExecPid),
+    #[serde(rename = "guest-file-open")]
+    FileOpen(GuestFileOpen),
+    #[serde(rename = "guest-file-read")]
+    FileRead(GuestFileCount),
+    #[serde(rename = "guest-file-write")]
+    FileWrite(GuestFileBuffer),
+    #[serde(rename = "guest-file-seek")]
+    FileSeek(GuestFileSeek),
+    #[serde(rename = "guest-file-close")]
+    FileClose(GuestFileHandle),
+}
+
+#[derive(Deserialize)]
+struct GuestEmpty {}
+
+/// A network interface in the guest
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GuestNetworkInterface {
+    /// Interface name
+    pub name: String,
+
+    /// Hardware (MAC) address
+    pub hardware_address: Option<String>,
+
+    /// Addresses assigned to the interface
+    #[serde(default)]
+    pub ip_addresses: Vec<GuestIpAddress>,
+}
+
+/// An address assigned to a guest network interface
+#[derive(Clone, Debug, Deserialize)]
+pub struct GuestIpAddress {
+    /// IP address
+    #[serde(rename = "ip-address")]
+    pub address: IpAddr,
+
+    /// Network prefix length
+    pub prefix: u8,
+}
+
+/// Guest agent shutdown mode
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GuestShutdownMode {
+    /// Power down the guest
+    Powerdown,
+
+    /// Reboot the guest
+    Reboot,
+
+    /// Halt the guest
+    Halt,
+}
+
+#[derive(Serialize)]
+struct GuestShutdown {
+    mode: GuestShutdownMode,
+}
+
+#[derive(Serialize)]
+struct GuestSync {
+    id: u64,
+}
+
+#[derive(Serialize)]
+struct GuestSetTime {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct GuestFileOpen {
+    path: String,
+    mode: String,
+}
+
+#[derive(Serialize)]
+struct GuestFileHandle {
+    handle: i64,
+}
+
+#[derive(Serialize)]
+struct GuestFileCount {
+    handle: i64,
+    count: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct GuestFileBuffer {
+    handle: i64,
+    buf_b64: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum GuestSeek {
+    Set,
+    Cur,
+    End,
+}
+
+#[derive(Serialize)]
+struct GuestFileSeek {
+    handle: i64,
+    offset: i64,
+    whence: GuestSeek,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GuestFileRead {
+    buf_b64: String,
+    #[serde(default)]
+    eof: bool,
+}
+
+#[derive(Deserialize)]
+struct GuestFileWrite {
+    count: usize,
+}
+
+#[derive(Deserialize)]
+struct GuestFileSeekPosition {
+    position: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct GuestExec {
+    path: String,
+    arg: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_data: Option<String>,
+    capture_output: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GuestExecPid {
+    pid: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GuestExecStatus {
+    exited: bool,
+    exitcode: Option<i32>,
+    out_data: Option<String>,
+    err_data: Option<String>,
+}
+
+impl TryInto<ExecOutput> for GuestExecStatus {
+    type Error = Error;
+
+    fn try_into(self) -> Result<ExecOutput, Self::Error> {
+        let decode = |data: Option<String>| -> Result<Vec<u8>, Error> {
+            data.map(|data| STANDARD.decode(data))
+                .transpose()
+                .map(Option::unwrap_or_default)
+                .map_err(|err| Error::new(ErrorKind::SerializationError, err))
+        };
+        Ok(ExecOutput {
+            exit_code: self.exitcode,
+            stdout: decode(self.out_data)?,
+            stderr: decode(self.err_data)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct GuestError {
+    desc: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GuestResponse<D> {
+    Success {
+        #[serde(rename = "return")]
+        return_data: D,
+    },
+    Error {
+        error: GuestError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn serialize_exec() {
+        const EXPECTED_COMMAND: &str = r#"{"execute":"guest-exec","arguments":{"path":"/bin/cat","arg":["-"],"input-data":"aGk=","capture-output":true}}"#;
+        let command = GuestCommand::Exec(GuestExec {
+            path: "/bin/cat".to_string(),
+            arg: vec!["-".to_string()],
+            input_data: Some(STANDARD.encode("hi")),
+            capture_output: true,
+        });
+        assert_eq!(EXPECTED_COMMAND, serde_json::to_string(&command).unwrap());
+    }
+
+    #[test]
+    fn serialize_shutdown() {
+        const EXPECTED_COMMAND: &str =
+            r#"{"execute":"guest-shutdown","arguments":{"mode":"reboot"}}"#;
+        let command = GuestCommand::Shutdown(GuestShutdown {
+            mode: GuestShutdownMode::Reboot,
+        });
+        assert_eq!(EXPECTED_COMMAND, serde_json::to_string(&command).unwrap());
+    }
+
+    #[test]
+    fn serialize_set_time() {
+        let command = GuestCommand::SetTime(GuestSetTime {
+            time: Some(1_700_000_000_000_000_000),
+        });
+        assert_eq!(
+            r#"{"execute":"guest-set-time","arguments":{"time":1700000000000000000}}"#,
+            serde_json::to_string(&command).unwrap()
+        );
+        let command = GuestCommand::SetTime(GuestSetTime { time: None });
+        assert_eq!(
+            r#"{"execute":"guest-set-time","arguments":{}}"#,
+            serde_json::to_string(&command).unwrap()
+        );
+    }
+
+    #[test]
+    fn serialize_file_seek() {
+        const EXPECTED_COMMAND: &str =
+            r#"{"execute":"guest-file-seek","arguments":{"handle":1,"offset":-4,"whence":"end"}}"#;
+        let command = GuestCommand::FileSeek(GuestFileSeek {
+            handle: 1,
+            offset: -4,
+            whence: GuestSeek::End,
+        });
+        assert_eq!(EXPECTED_COMMAND, serde_json::to_string(&command).unwrap());
+    }
+
+    /// An agent answering each command it receives with the next response
+    #[cfg(unix)]
+    fn scripted_agent(responses: Vec<String>) -> GuestAgent {
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        std::thread::spawn(move || {
+            let mut writer = server.try_clone().unwrap();
+            let commands =
+                serde_json::Deserializer::from_reader(server).into_iter::<serde_json::Value>();
+            for (_, response) in commands.zip(responses) {
+                writeln!(writer, "{response}").unwrap();
+            }
+        });
+        GuestAgent::new(Channel::Unix(client))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_timeout() {
+        let mut responses = vec![String::from(r#"{"return":{"exited":false}}"#); 100];
+        responses.insert(0, String::from(r#"{"return":{"pid":7}}"#));
+        let mut agent = scripted_agent(responses);
+        let err = agent
+            .exec("/bin/sleep", &["inf"], None, Duration::from_millis(250))
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::Timeout, err.kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_file_stalled() {
+        let mut agent = scripted_agent(vec![
+            String::from(r#"{"return":1}"#),
+            String::from(r#"{"return":{"count":0,"eof":false}}"#),
+            String::from(r#"{"return":{}}"#),
+        ]);
+        let err = agent.write_file("/tmp/data", b"hi").err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_file_error_kept() {
+        let mut agent = scripted_agent(vec![
+            String::from(r#"{"return":1}"#),
+            String::from(r#"{"error":{"class":"GenericError","desc":"No space left on device"}}"#),
+            String::from(r#"{"error":{"class":"GenericError","desc":"Bad file descriptor"}}"#),
+        ]);
+        let err = agent.write_file("/tmp/data", b"hi").err().unwrap();
+        assert!(err.to_string().contains("No space left on device"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_file_eof() {
+        // A further read would get the close response and fail to decode
+        let mut agent = scripted_agent(vec![
+            String::from(r#"{"return":1}"#),
+            String::from(r#"{"return":{"count":2,"buf-b64":"aGk=","eof":true}}"#),
+            String::from(r#"{"return":{}}"#),
+        ]);
+        assert_eq!(b"hi".to_vec(), agent.read_file("/tmp/data").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn closed_mid_sync() {
+        let (client, mut server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let responder = std::thread::spawn(move || {
+            let mut reader = BufReader::new(server.try_clone().unwrap());
+            let mut buf = Vec::new();
+            reader.read_until(b'}', &mut buf).unwrap();
+            reader.read_until(b'}', &mut buf).unwrap();
+            // Answer a stale command, then close before answering the sync
+            writeln!(server, r#"{{"return": {{}}}}"#).unwrap();
+        });
+        let mut agent = GuestAgent::new(Channel::Unix(client));
+        let err = agent.sync().err().unwrap();
+        responder.join().unwrap();
+        assert_eq!(ErrorKind::PipeError, err.kind());
+        let err = agent.ping().err().unwrap();
+        assert_eq!(ErrorKind::PipeError, err.kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wait_for_agent() {
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let responder = std::thread::spawn(move || {
+            let mut reader = BufReader::new(server.try_clone().unwrap());
+            let mut server = server;
+            let mut buf = Vec::new();
+            // Commands are not newline delimited, so read up to each closing brace
+            reader.read_until(b'}', &mut buf).unwrap();
+            reader.read_until(b'}', &mut buf).unwrap();
+            let sync: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+            let id = &sync["arguments"]["id"];
+            writeln!(server, r#"{{"return": {{}}}}"#).unwrap();
+            writeln!(server, r#"{{"return": {id}}}"#).unwrap();
+            buf.clear();
+            reader.read_until(b'}', &mut buf).unwrap();
+            writeln!(server, r#"{{"return": {{}}}}"#).unwrap();
+        });
+        let mut agent = GuestAgent::new(Channel::Unix(client));
+        agent.wait(Duration::from_secs(5)).unwrap();
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn network_interfaces() {
+        const RESPONSE: &str = r#"{"return":[{"name":"lo","ip-addresses":[{"ip-address-type":"ipv4","ip-address":"127.0.0.1","prefix":8}]},{"name":"eth0","hardware-address":"52:54:00:12:34:56","ip-addresses":[{"ip-address-type":"ipv4","ip-address":"10.0.2.15","prefix":24},{"ip-address-type":"ipv6","ip-address":"fe80::5054:ff:fe12:3456","prefix":64}]},{"name":"eth1"}]}"#;
+        let response: GuestResponse<Vec<GuestNetworkInterface>> =
+            serde_json::from_str(RESPONSE).unwrap();
+        let GuestResponse::Success { return_data } = response else {
+            panic!("Expected success");
+        };
+        assert_eq!(3, return_data.len());
+        let eth0 = &return_data[1];
+        assert_eq!(Some("52:54:00:12:34:56"), eth0.hardware_address.as_deref());
+        assert_eq!(
+            "10.0.2.15".parse::<IpAddr>().unwrap(),
+            eth0.ip_addresses[0].address
+        );
+        assert_eq!(24, eth0.ip_addresses[0].prefix);
+        assert!(return_data[2].ip_addresses.is_empty());
+    }
+
+    #[test]
+    fn exec_status_output() {
+        const STATUS: &str = r#"{"return":{"exited":true,"exitcode":3,"out-data":"aGk="}}"#;
+        let response: GuestResponse<GuestExecStatus> = serde_json::from_str(STATUS).unwrap();
+        let GuestResponse::Success { return_data } = response else {
+            panic!("Expected success");
+        };
+        let output: ExecOutput = return_data.try_into().unwrap();
+        assert_eq!(Some(3), output.exit_code);
+        assert_eq!(b"hi".to_vec(), output.stdout);
+        assert!(output.stderr.is_empty());
+    }
+}
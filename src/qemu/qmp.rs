@@ -1,23 +1,35 @@
 #![allow(dead_code)]
+use super::transport::{Channel, QemuEndpoint};
+use crate::metrics::{self, Counter};
+use crate::retry::RetryPolicy;
+use crate::trace;
 use crate::{Error, ErrorKind, Event, EventKind, EventPublisher, EventSubscriber, Key, Status};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io::{BufRead, BufReader, Write};
 use std::iter::FromIterator;
-use std::os::unix::net::UnixStream;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub struct QmpStream {
-    stream: BufReader<UnixStream>,
+    stream: BufReader<Channel>,
+    /// Id of the system, for tracing
+    id: String,
     version: QemuVersion,
     subscribers: Vec<Box<dyn EventSubscriber>>,
+    /// Reason of the `SHUTDOWN` event, once seen
+    shutdown_reason: Option<String>,
+    /// How failed commands are retried, and the monitor reconnected to
+    retry: Option<(RetryPolicy, QemuEndpoint)>,
 }
 
-pub fn read_message<D>(stream: &mut BufReader<UnixStream>) -> Result<D, Error>
+pub fn read_message<D>(stream: &mut BufReader<Channel>) -> Result<D, Error>
 where
     D: for<'de> serde::Deserialize<'de>,
 {
     let mut line = String::new();
-    stream.read_line(&mut line)?;
+    if stream.read_line(&mut line)? == 0 {
+        return Err(Error::new(ErrorKind::PipeError, "QMP connection closed"));
+    }
     line.truncate(line.len() - 1);
     log::trace!("Received response: {line}");
     serde_json::from_str(&line).map_err(|err| Error::new(ErrorKind::HarnessError, err))
@@ -29,6 +41,7 @@ fn create_event(timestamp: QmpTimestamp, event: String) -> Option<Event> {
         "POWERDOWN" => Some(EventKind::Shutdown),
         "STOP" => Some(EventKind::Pause),
         "RESUME" => Some(EventKind::Resume),
+        "SUSPEND" | "SUSPEND_DISK" => Some(EventKind::Suspend),
         _ => None,
     }
     .map(|kind| Event {
@@ -38,14 +51,17 @@ fn create_event(timestamp: QmpTimestamp, event: String) -> Option<Event> {
 }
 
 impl QmpStream {
-    /// Create new connection QMP
-    pub fn new(stream: UnixStream) -> Result<Self, Error> {
+    /// Create new connection QMP to the system with the given id
+    pub fn new(stream: Channel, id: impl Into<String>) -> Result<Self, Error> {
         let mut wrapped_stream = BufReader::new(stream);
         let caps: Capabilities = read_message(&mut wrapped_stream)?;
         let mut qmp_stream = Self {
             stream: wrapped_stream,
+            id: id.into(),
             version: caps.qmp.version.qemu,
             subscribers: Vec::new(),
+            shutdown_reason: None,
+            retry: None,
         };
         qmp_stream.send_command(QmpCommand::QmpCapabilities)?;
         Ok(qmp_stream)
@@ -55,28 +71,76 @@ impl QmpStream {
         let stream = self.stream.get_ref().try_clone()?;
         Ok(Self {
             stream: BufReader::new(stream),
+            id: self.id.clone(),
             version: self.version,
-            subscribers: Vec::new()
+            subscribers: Vec::new(),
+            shutdown_reason: None,
+            retry: None,
         })
     }
 
+    /// Retry commands that fail for transient reasons, reconnecting to the
+    /// monitor at the endpoint before each retry
+    ///
+    /// A new connection keeps a retry from reading the failed command's
+    /// late response. Clones don't retry, as they share the connection.
+    ///
+    /// A command whose response is lost may already have run, so only
+    /// `query-*` commands are resent then. Others are only resent if
+    /// sending them failed.
+    pub fn set_retry(&mut self, retry: RetryPolicy, endpoint: QemuEndpoint) {
+        self.retry = Some((retry, endpoint));
+    }
+
+    /// Replace the connection with a new one to the endpoint
+    fn reconnect(&mut self, endpoint: &QemuEndpoint) -> Result<(), Error> {
+        log::trace!("Reconnecting to QMP monitor: {}", endpoint.chardev());
+        let timeout = self.stream.get_ref().read_timeout()?;
+        let channel = endpoint.connect()?;
+        channel.set_read_timeout(timeout)?;
+        // The old connection is dropped rather than shut down, as terminals
+        // share it. The monitor serves one client at a time, so it only
+        // greets the new one once they're all gone.
+        self.stream = BufReader::new(channel);
+        let _: Capabilities = read_message(&mut self.stream)?;
+        self.send_message(&serde_json::to_string(&QmpCommand::QmpCapabilities)?)
+            .map(|_| ())
+    }
+    /// Set timeout for reading responses
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.stream
+            .get_ref()
+            .set_read_timeout(timeout)
+            .map_err(|err| err.into())
+    }
+
     fn send_event(&mut self, event: &Event) -> Result<(), Error> {
         for subscriber in &mut self.subscribers {
-            subscriber.on_event(&event);
+            subscriber.on_event(event);
         }
         Ok(())
     }
 
+    fn handle_event(&mut self, timestamp: QmpTimestamp, event: String, data: QmpEventData) {
+        metrics::increment(Counter::EventsReceived, "qemu");
+        if event == "SHUTDOWN" {
+            self.shutdown_reason = Some(data.reason.unwrap_or_default());
+        }
+        if let Some(event) = create_event(timestamp, event) {
+            let _ = self.send_event(&event);
+        }
+    }
+
     fn wait_for_return(&mut self) -> Result<QmpReturn, Error> {
         loop {
             let response: QmpResponse = read_message(&mut self.stream)?;
             match response {
                 QmpResponse::Success { return_data } => return Ok(return_data),
-                QmpResponse::Event { timestamp, event } => {
-                    if let Some(event) = create_event(timestamp, event) {
-                        self.send_event(&event)?;
-                    }
-                }
+                QmpResponse::Event {
+                    timestamp,
+                    event,
+                    data,
+                } => self.handle_event(timestamp, event, data),
                 QmpResponse::Error { error } => {
                     return Err(Error::new(ErrorKind::HarnessError, error))
                 }
@@ -84,16 +148,80 @@ impl QmpStream {
         }
     }
 
+    /// Read events until the guest shuts down or the deadline passes,
+    /// returning the reported reason
+    ///
+    /// Returns `None` if the connection closes first, such as when QEMU
+    /// exits without a `SHUTDOWN` event.
+    pub fn wait_for_shutdown(&mut self, deadline: Instant) -> Result<Option<String>, Error> {
+        let result = self.read_until_shutdown(deadline);
+        self.set_read_timeout(None)?;
+        result
+    }
+
+    fn read_until_shutdown(&mut self, deadline: Instant) -> Result<Option<String>, Error> {
+        while self.shutdown_reason.is_none() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for system to exit",
+                ));
+            }
+            self.set_read_timeout(Some(remaining))?;
+            match read_message(&mut self.stream) {
+                Ok(QmpResponse::Event {
+                    timestamp,
+                    event,
+                    data,
+                }) => self.handle_event(timestamp, event, data),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::PipeError => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(self.shutdown_reason.clone())
+    }
+
     /// Send QMP command
     pub fn send_command(&mut self, command: QmpCommand) -> Result<QmpReturn, Error> {
-        let message = serde_json::to_string(&command)
+        let message = serde_json::to_value(&command)
             .map_err(|err| Error::new(ErrorKind::HarnessError, err))?;
+        let execute = message["execute"].as_str().unwrap_or_default().to_string();
+        let _span = trace::qmp_command(&self.id, &execute);
+        let message = message.to_string();
+        let Some((retry, endpoint)) = self.retry.clone() else {
+            return self.send_message(&message);
+        };
+        let idempotent = execute.starts_with("query-");
+        let mut retrying = false;
+        // Errors in the outer result are retried, the inner result is returned
+        retry.run(&execute, || {
+            if std::mem::replace(&mut retrying, true) {
+                self.reconnect(&endpoint)?;
+            }
+            self.write_message(&message)?;
+            if idempotent {
+                self.wait_for_return().map(Ok)
+            } else {
+                Ok(self.wait_for_return())
+            }
+        })?
+    }
+
+    /// Send a serialized command and wait for its return
+    fn send_message(&mut self, message: &str) -> Result<QmpReturn, Error> {
+        self.write_message(message)?;
+        self.wait_for_return()
+    }
+
+    /// Send a serialized command without waiting for its return
+    fn write_message(&mut self, message: &str) -> Result<(), Error> {
         log::trace!("Sending command: {message}");
         self.stream
             .get_mut()
             .write_all(message.as_bytes())
-            .map_err(|err| Error::new(ErrorKind::HarnessError, err))?;
-        self.wait_for_return()
+            .map_err(|err| Error::new(ErrorKind::PipeError, err))
     }
 }
 
@@ -157,9 +285,41 @@ pub enum QmpCommand {
     Quit,
     #[serde(rename = "system_powerdown")]
     SystemPowerdown,
+    HumanMonitorCommand(HumanMonitorCommand),
+    ObjectAdd(ObjectAdd),
+    ObjectDel(ObjectDel),
+    Screendump(Screendump),
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HumanMonitorCommand {
+    pub command_line: String,
+}
+
+/// Create a QOM object, such as a network filter
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ObjectAdd {
+    pub qom_type: String,
+    pub id: String,
+    #[serde(flatten)]
+    pub properties: BTreeMap<String, String>,
+}
+
+/// Delete a QOM object created with `object-add`
+#[derive(Serialize)]
+pub struct ObjectDel {
+    pub id: String,
+}
+
+/// Save the display to an image file
+#[derive(Serialize)]
+pub struct Screendump {
+    pub filename: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct QmpStatusInfo {
     /// If all vCPUs are runnable.
     running: bool,
@@ -180,6 +340,7 @@ impl TryInto<Status> for QmpStatusInfo {
             "shutdown" => Ok(Status::Shutdown),
             "paused" => Ok(Status::Paused),
             "save-vm" => Ok(Status::Paused),
+            "suspended" => Ok(Status::Suspended),
             err => Err(Error::new(
                 ErrorKind::HarnessError,
                 format!("Unsupported status: {err}"),
@@ -196,6 +357,7 @@ pub struct QmpEmptyReturn {}
 pub enum QmpReturn {
     StatusInfo(QmpStatusInfo),
     Empty(QmpEmptyReturn),
+    HumanMonitor(String),
 }
 
 #[derive(Deserialize, Debug)]
@@ -225,9 +387,18 @@ pub enum QmpResponse {
     Event {
         timestamp: QmpTimestamp,
         event: String,
+        #[serde(default)]
+        data: QmpEventData,
     },
 }
 
+/// Event details used by the harness
+#[derive(Deserialize, Default)]
+pub struct QmpEventData {
+    /// Why the guest shut down, for `SHUTDOWN` events
+    reason: Option<String>,
+}
+
 pub enum KeyValueKind {
     Qcode,
     Number,
@@ -255,7 +426,7 @@ mod tests {
 
     #[test]
     fn serialize_send_key() {
-        const EXPECTED_COMMAND: &'static str =
+        const EXPECTED_COMMAND: &str =
             r#"{"execute":"send-key","arguments":{"keys":[{"type":"qcode","data":"ret"}]}}"#;
         let command = QmpCommand::SendKey(KeyCommand {
             keys: vec![Key::Enter.into()],
@@ -266,8 +437,66 @@ mod tests {
 
     #[test]
     fn serialize_quit() {
-        const EXPECTED_COMMAND: &'static str = r#"{"execute":"quit"}"#;
+        const EXPECTED_COMMAND: &str = r#"{"execute":"quit"}"#;
         let actual = serde_json::to_string(&QmpCommand::Quit).unwrap();
         assert_eq!(EXPECTED_COMMAND, actual);
     }
+
+    #[test]
+    fn serialize_human_monitor_command() {
+        const EXPECTED_COMMAND: &str =
+            r#"{"execute":"human-monitor-command","arguments":{"command-line":"savevm snap"}}"#;
+        let command = QmpCommand::HumanMonitorCommand(HumanMonitorCommand {
+            command_line: "savevm snap".to_string(),
+        });
+        let actual = serde_json::to_string(&command).unwrap();
+        assert_eq!(EXPECTED_COMMAND, actual);
+    }
+
+    #[test]
+    fn serialize_object_add() {
+        const EXPECTED_COMMAND: &str = r#"{"execute":"object-add","arguments":{"qom-type":"filter-dump","id":"dump0","file":"net.pcap","netdev":"net0"}}"#;
+        let command = QmpCommand::ObjectAdd(ObjectAdd {
+            qom_type: "filter-dump".to_string(),
+            id: "dump0".to_string(),
+            properties: BTreeMap::from([
+                ("netdev".to_string(), "net0".to_string()),
+                ("file".to_string(), "net.pcap".to_string()),
+            ]),
+        });
+        let actual = serde_json::to_string(&command).unwrap();
+        assert_eq!(EXPECTED_COMMAND, actual);
+    }
+
+    #[test]
+    fn suspend_event() {
+        for name in ["SUSPEND", "SUSPEND_DISK"] {
+            let timestamp = QmpTimestamp {
+                seconds: 0,
+                microseconds: 0,
+            };
+            let event = create_event(timestamp, name.to_string()).unwrap();
+            assert_eq!(EventKind::Suspend, event.kind);
+        }
+    }
+
+    #[test]
+    fn shutdown_event() {
+        const EVENT: &str = r#"{"timestamp":{"seconds":1,"microseconds":0},"event":"SHUTDOWN","data":{"guest":true,"reason":"guest-shutdown"}}"#;
+        match serde_json::from_str(EVENT).unwrap() {
+            QmpResponse::Event { event, data, .. } => {
+                assert_eq!("SHUTDOWN", event);
+                assert_eq!(Some("guest-shutdown".to_string()), data.reason);
+            }
+            _ => panic!("Expected an event"),
+        }
+    }
+
+    #[test]
+    fn suspended_status() {
+        const STATUS: &str = r#"{"running":false,"singlestep":false,"status":"suspended"}"#;
+        let info: QmpStatusInfo = serde_json::from_str(STATUS).unwrap();
+        let status: Status = info.try_into().unwrap();
+        assert_eq!(Status::Suspended, status);
+    }
 }
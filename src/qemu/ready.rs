@@ -0,0 +1,142 @@
+use crate::{Error, ErrorKind};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Interval between readiness checks
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A condition indicating that a system is usable
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum ReadyCondition {
+    /// Serial console output matches a regular expression
+    Serial { pattern: String },
+
+    /// The guest agent responds
+    GuestAgent,
+
+    /// A TCP port accepts connections
+    TcpPort {
+        #[serde(default = "default_host")]
+        host: String,
+        port: u16,
+    },
+}
+
+fn default_host() -> String {
+    String::from("127.0.0.1")
+}
+
+fn timeout_error(condition: &str) -> Error {
+    Error::new(
+        ErrorKind::Timeout,
+        format!("Timed out waiting for {condition}"),
+    )
+}
+
+/// Read from the reader until the pattern matches or the deadline passes
+pub(crate) fn wait_for_match<R: Read>(
+    reader: &mut R,
+    pattern: &str,
+    deadline: Instant,
+    set_timeout: impl Fn(&mut R, Duration) -> std::io::Result<()>,
+) -> Result<(), Error> {
+    let regex = Regex::new(pattern).map_err(|err| Error::new(ErrorKind::HarnessError, err))?;
+    let mut output = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(timeout_error(&format!(
+                "serial output matching '{pattern}'"
+            )));
+        }
+        set_timeout(reader, remaining)?;
+        match reader.read(&mut buf) {
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::PipeError,
+                    "Serial console closed while waiting for output",
+                ))
+            }
+            Ok(count) => {
+                output.extend_from_slice(&buf[..count]);
+                if regex.is_match(&output) {
+                    return Ok(());
+                }
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Wait until a TCP port accepts connections or the deadline passes
+pub(crate) fn wait_for_port(host: &str, port: u16, deadline: Instant) -> Result<(), Error> {
+    let addrs: Vec<_> = (host, port).to_socket_addrs()?.collect();
+    loop {
+        for addr in &addrs {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(timeout_error(&format!("{host}:{port}")));
+            }
+            if TcpStream::connect_timeout(addr, remaining.min(POLL_INTERVAL)).is_ok() {
+                return Ok(());
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::TcpListener;
+    #[cfg(unix)]
+    use std::{io::Write, os::unix::net::UnixStream};
+
+    #[cfg(unix)]
+    #[test]
+    fn serial_match() {
+        let (mut reader, mut writer) = UnixStream::pair().unwrap();
+        writer.write_all(b"Booting...\nlocalhost login: ").unwrap();
+        wait_for_match(
+            &mut reader,
+            "login: $",
+            Instant::now() + Duration::from_secs(5),
+            |reader, timeout| reader.set_read_timeout(Some(timeout)),
+        )
+        .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serial_match_timeout() {
+        let (mut reader, _writer) = UnixStream::pair().unwrap();
+        let err = wait_for_match(
+            &mut reader,
+            "login:",
+            Instant::now() + Duration::from_millis(100),
+            |reader, timeout| reader.set_read_timeout(Some(timeout)),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(ErrorKind::Timeout, err.kind());
+    }
+
+    #[test]
+    fn tcp_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        wait_for_port("127.0.0.1", port, Instant::now() + Duration::from_secs(5)).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use crate::runtime::RuntimeDir;
+use crate::{reserve_port, Error, ErrorKind, PortReservation};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How the harness connects to QEMU's monitor, serial console and guest
+/// agent
+///
+/// Defaults to Unix sockets on Unix hosts and TCP elsewhere.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum QemuTransport {
+    /// Unix sockets in the runtime directory
+    #[cfg_attr(unix, default)]
+    Unix,
+
+    /// TCP ports on the loopback interface
+    #[cfg_attr(not(unix), default)]
+    Tcp,
+
+    /// Windows named pipes
+    ///
+    /// Named pipes have no read timeout, so waits on the monitor block
+    /// until QEMU responds.
+    Pipe,
+}
+
+/// Address of a QEMU monitor, serial console or guest agent channel
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QemuEndpoint {
+    /// Unix socket path
+    #[cfg(unix)]
+    Unix(PathBuf),
+
+    /// TCP address
+    Tcp(SocketAddr),
+
+    /// Windows named pipe name, without the `\\.\pipe\` prefix
+    #[cfg(windows)]
+    Pipe(String),
+}
+
+impl QemuEndpoint {
+    /// Endpoint of a channel of a system started by the harness
+    ///
+    /// TCP ports are reserved until the reservations are dropped, so they
+    /// should be kept until QEMU has bound them.
+    pub(crate) fn new(
+        transport: QemuTransport,
+        runtime_dir: &RuntimeDir,
+        name: &str,
+        reservations: &mut Vec<PortReservation>,
+    ) -> Result<Self, Error> {
+        match transport {
+            #[cfg(unix)]
+            QemuTransport::Unix => Ok(Self::Unix(runtime_dir.file(&format!("{name}.sock")))),
+            QemuTransport::Tcp => {
+                let reservation = reserve_port()?;
+                let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, reservation.port()));
+                reservations.push(reservation);
+                Ok(Self::Tcp(addr))
+            }
+            #[cfg(windows)]
+            QemuTransport::Pipe => {
+                let dir = runtime_dir.path().file_name().unwrap_or_default();
+                Ok(Self::Pipe(format!("{}-{name}", dir.to_string_lossy())))
+            }
+            #[allow(unreachable_patterns)]
+            transport => Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("{transport:?} transport is not supported on this host"),
+            )),
+        }
+    }
+
+    /// Character device argument of `-qmp` and `-serial`
+    pub(crate) fn chardev(&self) -> String {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(path) => format!("unix:{},server=on,wait=off", path.display()),
+            Self::Tcp(addr) => format!("tcp:{addr},server=on,wait=off"),
+            #[cfg(windows)]
+            Self::Pipe(name) => format!("pipe:{name}"),
+        }
+    }
+
+    /// Backend options of `-chardev`
+    pub(crate) fn chardev_backend(&self) -> String {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(path) => format!("socket,path={},server=on,wait=off", path.display()),
+            Self::Tcp(addr) => format!(
+                "socket,host={},port={},server=on,wait=off",
+                addr.ip(),
+                addr.port()
+            ),
+            #[cfg(windows)]
+            Self::Pipe(name) => format!("pipe,path={name}"),
+        }
+    }
+
+    /// Connect to the endpoint
+    pub(crate) fn connect(&self) -> std::io::Result<Channel> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(path) => UnixStream::connect(path).map(Channel::Unix),
+            Self::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                Ok(Channel::Tcp(stream))
+            }
+            #[cfg(windows)]
+            Self::Pipe(name) => std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(format!(r"\\.\pipe\{name}"))
+                .map(Channel::Pipe),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<&Path> for QemuEndpoint {
+    fn from(path: &Path) -> Self {
+        Self::Unix(path.to_path_buf())
+    }
+}
+
+#[cfg(unix)]
+impl From<&PathBuf> for QemuEndpoint {
+    fn from(path: &PathBuf) -> Self {
+        Self::Unix(path.clone())
+    }
+}
+
+#[cfg(unix)]
+impl From<PathBuf> for QemuEndpoint {
+    fn from(path: PathBuf) -> Self {
+        Self::Unix(path)
+    }
+}
+
+#[cfg(unix)]
+impl From<&str> for QemuEndpoint {
+    fn from(path: &str) -> Self {
+        Self::Unix(PathBuf::from(path))
+    }
+}
+
+impl From<SocketAddr> for QemuEndpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+/// A connection to a QEMU channel
+pub(crate) enum Channel {
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Tcp(TcpStream),
+    #[cfg(windows)]
+    Pipe(std::fs::File),
+}
+
+impl Channel {
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            #[cfg(windows)]
+            Self::Pipe(file) => file.try_clone().map(Self::Pipe),
+        }
+    }
+
+    /// Set the read timeout, which named pipes ignore
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(windows)]
+            Self::Pipe(_) => Ok(()),
+        }
+    }
+
+    /// Get the read timeout, which named pipes don't have
+    pub fn read_timeout(&self) -> std::io::Result<Option<Duration>> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read_timeout(),
+            Self::Tcp(stream) => stream.read_timeout(),
+            #[cfg(windows)]
+            Self::Pipe(_) => Ok(None),
+        }
+    }
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(windows)]
+            Self::Pipe(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(windows)]
+            Self::Pipe(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(windows)]
+            Self::Pipe(file) => file.flush(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Channel {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Unix(stream) => stream.as_raw_fd(),
+            Self::Tcp(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn tcp_endpoint() {
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let mut reservations = Vec::new();
+        let endpoint =
+            QemuEndpoint::new(QemuTransport::Tcp, &runtime_dir, "qmp", &mut reservations).unwrap();
+        let QemuEndpoint::Tcp(addr) = &endpoint else {
+            panic!("Expected a TCP endpoint");
+        };
+        assert_eq!(
+            format!("tcp:127.0.0.1:{},server=on,wait=off", addr.port()),
+            endpoint.chardev()
+        );
+        assert_eq!(
+            format!(
+                "socket,host=127.0.0.1,port={},server=on,wait=off",
+                addr.port()
+            ),
+            endpoint.chardev_backend()
+        );
+        let listener = TcpListener::bind(addr).unwrap();
+        let mut channel = endpoint.connect().unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        server.write_all(b"hi").unwrap();
+        let mut buf = [0u8; 2];
+        channel.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hi", &buf);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_endpoint() {
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let endpoint =
+            QemuEndpoint::new(QemuTransport::Unix, &runtime_dir, "serial", &mut Vec::new())
+                .unwrap();
+        assert_eq!(
+            format!(
+                "unix:{},server=on,wait=off",
+                runtime_dir.file("serial.sock").display()
+            ),
+            endpoint.chardev()
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use super::{qemu_system_bin, QemuSystemConfig};
+use crate::{check_config, Diagnostic, Error, ErrorKind, Severity};
+use std::fs::OpenOptions;
+use std::path::Path;
+
+/// Extensions of executables on Windows when `PATHEXT` isn't set
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// Check if an executable can be found directly or in `PATH`
+///
+/// On Windows, a name without an extension is also tried with each
+/// extension in `PATHEXT`, such as `.exe`.
+fn executable_exists(name: &str) -> bool {
+    let pathext = cfg!(windows)
+        .then(|| std::env::var("PATHEXT").unwrap_or_else(|_| String::from(DEFAULT_PATHEXT)));
+    let names = executable_names(name, pathext.as_deref());
+    if name.contains('/') || (cfg!(windows) && name.contains('\\')) {
+        return names.iter().any(|name| Path::new(name).is_file());
+    }
+    std::env::var_os("PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths)
+                .any(|dir| names.iter().any(|name| dir.join(name).is_file()))
+        })
+        .unwrap_or(false)
+}
+
+/// Names an executable may have, given the extensions in `PATHEXT`
+fn executable_names(name: &str, pathext: Option<&str>) -> Vec<String> {
+    let mut names = vec![name.to_string()];
+    if let Some(pathext) = pathext.filter(|_| Path::new(name).extension().is_none()) {
+        names.extend(
+            pathext
+                .split(';')
+                .filter(|extension| !extension.is_empty())
+                .map(|extension| format!("{name}{extension}")),
+        );
+    }
+    names
+}
+
+/// Check if KVM can be opened for reading and writing
+fn kvm_usable() -> bool {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .is_ok()
+}
+
+impl QemuSystemConfig {
+    /// Check a config before deserializing it
+    ///
+    /// Reports every field that fails to deserialize and, if the config
+    /// deserializes, every problem found by [`Self::diagnostics`].
+    pub fn check(config: &serde_json::Value) -> Vec<Diagnostic> {
+        match check_config::<Self>(config) {
+            Ok(config) => config.diagnostics(),
+            Err(diagnostics) => diagnostics,
+        }
+    }
+
+    /// Find problems with the configuration before spawning QEMU
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        let bin = qemu_system_bin(self);
+        if !executable_exists(&bin) {
+            diagnostics.push(Diagnostic::error(
+                "/arch",
+                format!("Emulator '{bin}' not found"),
+            ));
+        }
+
+        // A bare firmware name is looked up in QEMU's data directory
+        let bios = self.bios.as_deref().filter(|bios| bios.contains('/'));
+        let files = [
+            ("bios", bios),
+            ("cdrom", self.cdrom.as_deref()),
+            ("hda", self.hda.as_deref()),
+            ("hdb", self.hdb.as_deref()),
+            ("kernel", self.kernel.as_deref()),
+            ("initrd", self.initrd.as_deref()),
+        ];
+        for (name, file) in files {
+            if let Some(file) = file {
+                if !Path::new(file).exists() {
+                    diagnostics.push(Diagnostic::error(
+                        format!("/{name}"),
+                        format!("{name} '{file}' does not exist"),
+                    ));
+                }
+            }
+        }
+        for (index, fw_cfg) in self.fw_cfg.iter().flatten().enumerate() {
+            if let Some(file) = fw_cfg.path() {
+                if !Path::new(file).exists() {
+                    diagnostics.push(Diagnostic::error(
+                        format!("/fw_cfg/{index}/file"),
+                        format!("fw_cfg file '{file}' does not exist"),
+                    ));
+                }
+            }
+        }
+        for (index, blockdev) in self.blockdev.iter().flatten().enumerate() {
+            blockdev.validate(&format!("/blockdev/{index}"), &mut diagnostics);
+        }
+
+        // Two writers to one image corrupt it
+        for (name, disk) in [("hda", &self.hda), ("hdb", &self.hdb)] {
+            let Some(disk) = disk else {
+                continue;
+            };
+            for blockdev in self.blockdev.iter().flatten() {
+                if blockdev.filename() == Some(disk.as_str()) {
+                    diagnostics.push(Diagnostic::error(
+                        format!("/{name}"),
+                        format!(
+                            "{name} '{disk}' is also opened by blockdev '{}'",
+                            blockdev.node_name()
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let kvm_requested = self
+            .accel
+            .as_deref()
+            .map(|accel| accel.split(',').next() == Some("kvm"))
+            .unwrap_or(false)
+            || self
+                .machine
+                .as_ref()
+                .map(|machine| machine.accelerators().any(|accel| accel == "kvm"))
+                .unwrap_or(false);
+        if kvm_requested && !kvm_usable() {
+            diagnostics.push(Diagnostic::error(
+                "/accel",
+                "KVM requested but /dev/kvm is not usable",
+            ));
+        }
+        if let Some(machine) = &self.machine {
+            machine.validate("/machine", &mut diagnostics);
+        }
+
+        if self.memory == Some(0) {
+            diagnostics.push(Diagnostic::error(
+                "/memory",
+                "Memory must be greater than zero",
+            ));
+        }
+        if let Some(smp) = &self.smp {
+            smp.validate("/smp", &mut diagnostics);
+        }
+
+        diagnostics
+    }
+
+    /// Check the configuration for problems before spawning QEMU
+    ///
+    /// All errors found are reported together in a single error, and
+    /// warnings are logged.
+    pub fn validate(&self) -> Result<(), Error> {
+        let (errors, warnings): (Vec<_>, Vec<_>) = self
+            .diagnostics()
+            .into_iter()
+            .partition(|diagnostic| diagnostic.severity == Severity::Error);
+        for warning in warnings {
+            log::warn!("{warning}");
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            let errors: Vec<_> = errors.iter().map(|error| error.to_string()).collect();
+            Err(Error::new(ErrorKind::InvalidConfig, errors.join("; ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn windows_executables() {
+        assert_eq!(
+            vec![
+                "qemu-system-x86_64",
+                "qemu-system-x86_64.COM",
+                "qemu-system-x86_64.EXE"
+            ],
+            executable_names("qemu-system-x86_64", Some(".COM;.EXE"))
+        );
+        assert_eq!(
+            vec!["qemu-img.exe"],
+            executable_names("qemu-img.exe", Some(".EXE"))
+        );
+        assert_eq!(vec!["qemu-img"], executable_names("qemu-img", None));
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        const JSON_CONFIG: &str = r#"{
+            "arch": "does-not-exist",
+            "memory": 0,
+            "hda": "tests/data/missing.raw",
+            "smp": { "cpus": 4, "maxcpus": 2 }
+        }"#;
+        let config: QemuSystemConfig = serde_json::from_str(JSON_CONFIG).unwrap();
+        let err = config.validate().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+        let message = format!("{err}");
+        assert!(message.contains("qemu-system-does-not-exist"));
+        assert!(message.contains("missing.raw"));
+        assert!(message.contains("Memory"));
+        assert!(message.contains("maxcpus"));
+    }
+
+    #[test]
+    fn check() {
+        let config = serde_json::json!({
+            "arch": "x86_64",
+            "memory": "lots",
+            "machine": { "type": "q35", "acel": "kvm" },
+            "chardev": [
+                { "id": "serial", "backend": "stdio" },
+                { "id": "monitor", "backend": { "socket": {} } }
+            ],
+            "hda": "tests/data/test.raw",
+            "blockdev": [
+                { "driver": "file", "node-name": "f1", "filename": "tests/data/test.raw" }
+            ]
+        });
+        let diagnostics = QemuSystemConfig::check(&config);
+        assert_eq!(
+            vec![
+                "/chardev/1/backend/socket: missing field `path`",
+                "/memory: invalid type: string \"lots\", expected usize",
+            ],
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<_>>()
+        );
+
+        let mut config = config;
+        config["memory"] = 512.into();
+        config["chardev"][1]["backend"]["socket"]["path"] = "monitor.sock".into();
+        let diagnostics = QemuSystemConfig::check(&config);
+        let hda = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.pointer == "/hda")
+            .unwrap();
+        assert_eq!(Severity::Error, hda.severity);
+        assert!(hda.message.contains("blockdev 'f1'"));
+        let machine = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.pointer == "/machine/acel")
+            .unwrap();
+        assert_eq!(Severity::Warning, machine.severity);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{DynSystemHarness, Error, ErrorKind, SystemHarness};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Key of the tag selecting a system's backend in a config
+const TYPE_TAG: &str = "type";
+
+/// A constructor of systems for one backend
+///
+/// Factories are registered with a [`HarnessRegistry`] under the value of
+/// the `type` tag that selects them in a config.
+pub trait HarnessFactory: Send + Sync {
+    /// Value of the `type` tag selecting this backend
+    fn type_tag(&self) -> &str;
+
+    /// Build a system from its config, without the `type` tag
+    fn build(&self, config: Value) -> Result<Box<dyn DynSystemHarness>, Error>;
+}
+
+/// A factory for a backend whose config is deserialized with serde
+pub struct ConfigFactory<C, S> {
+    type_tag: &'static str,
+    build: fn(&C) -> Result<S, Error>,
+}
+
+impl<C, S> ConfigFactory<C, S> {
+    /// Create a factory deserializing configs and building them with the
+    /// given function, e.g. a config type's `build` method
+    pub fn new(type_tag: &'static str, build: fn(&C) -> Result<S, Error>) -> Self {
+        Self { type_tag, build }
+    }
+}
+
+impl<C, S> HarnessFactory for ConfigFactory<C, S>
+where
+    C: DeserializeOwned,
+    S: SystemHarness + 'static,
+    S::Terminal: 'static,
+{
+    fn type_tag(&self) -> &str {
+        self.type_tag
+    }
+
+    fn build(&self, config: Value) -> Result<Box<dyn DynSystemHarness>, Error> {
+        let config: C = serde_json::from_value(config)
+            .map_err(|err| Error::new(ErrorKind::InvalidConfig, err))?;
+        Ok(Box::new((self.build)(&config)?))
+    }
+}
+
+/// Backend factories keyed by their config `type` tag
+///
+/// A config selects its backend with a `type` tag next to the backend's
+/// own fields, e.g. `{"type": "qemu", "arch": "x86_64"}`. New registries
+/// include the backends enabled in this crate, and other crates can
+/// register their own.
+pub struct HarnessRegistry {
+    factories: BTreeMap<String, Box<dyn HarnessFactory>>,
+}
+
+impl Default for HarnessRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HarnessRegistry {
+    /// Create a registry with the backends enabled in this crate
+    pub fn new() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::empty();
+        #[cfg(feature = "qemu")]
+        registry.insert(ConfigFactory::new("qemu", crate::QemuSystemConfig::build));
+        #[cfg(all(unix, feature = "container"))]
+        registry.insert(ConfigFactory::new(
+            "container",
+            crate::ContainerSystemConfig::build,
+        ));
+        #[cfg(all(unix, feature = "crosvm"))]
+        registry.insert(ConfigFactory::new(
+            "crosvm",
+            crate::CrosvmSystemConfig::build,
+        ));
+        #[cfg(all(unix, feature = "lxd"))]
+        registry.insert(ConfigFactory::new("lxd", crate::LxdSystemConfig::build));
+        #[cfg(all(unix, feature = "remote"))]
+        registry.insert(ConfigFactory::new(
+            "remote",
+            crate::RemoteSystemConfig::build,
+        ));
+        #[cfg(all(unix, feature = "vagrant"))]
+        registry.insert(ConfigFactory::new(
+            "vagrant",
+            crate::VagrantSystemConfig::build,
+        ));
+        #[cfg(all(unix, feature = "xen"))]
+        registry.insert(ConfigFactory::new("xen", crate::XenSystemConfig::build));
+        #[cfg(all(unix, feature = "uml"))]
+        registry.insert(ConfigFactory::new("uml", crate::UmlSystemConfig::build));
+        #[cfg(all(unix, feature = "process"))]
+        registry.insert(ConfigFactory::new(
+            "process",
+            crate::ProcessSystemConfig::build,
+        ));
+        #[cfg(all(unix, feature = "chroot"))]
+        registry.insert(ConfigFactory::new(
+            "chroot",
+            crate::ChrootSystemConfig::build,
+        ));
+        registry
+    }
+
+    /// Create a registry without any backends
+    pub fn empty() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, factory: impl HarnessFactory + 'static) {
+        self.factories
+            .insert(factory.type_tag().to_string(), Box::new(factory));
+    }
+
+    /// Register a backend
+    ///
+    /// Fails if a backend is already registered under the same tag.
+    pub fn register(&mut self, factory: impl HarnessFactory + 'static) -> Result<(), Error> {
+        if self.factories.contains_key(factory.type_tag()) {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("System type already registered: {}", factory.type_tag()),
+            ));
+        }
+        self.insert(factory);
+        Ok(())
+    }
+
+    /// Tags of the registered backends
+    pub fn types(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Build a system with the backend selected by the config's `type` tag
+    pub fn build(&self, config: Value) -> Result<Box<dyn DynSystemHarness>, Error> {
+        let Value::Object(mut config) = config else {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "System config must be an object",
+            ));
+        };
+        let type_tag = match config.remove(TYPE_TAG) {
+            Some(Value::String(type_tag)) => type_tag,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidConfig,
+                    "System config has no type",
+                ))
+            }
+        };
+        let factory = self.factories.get(&type_tag).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Unknown system type: {type_tag}"),
+            )
+        })?;
+        log::trace!("Building {type_tag} system...");
+        factory.build(Value::Object(config))
+    }
+
+    /// Build a system from a JSON config
+    pub fn build_json(&self, json: &str) -> Result<Box<dyn DynSystemHarness>, Error> {
+        let config =
+            serde_json::from_str(json).map_err(|err| Error::new(ErrorKind::InvalidConfig, err))?;
+        self.build(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::testing::FakeSystem;
+    use crate::SystemTerminal;
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    struct FakeSystemConfig {
+        code: i32,
+    }
+
+    impl FakeSystemConfig {
+        /// Build a system that has already exited
+        fn build(&self) -> Result<FakeSystem, Error> {
+            let mut system = FakeSystem::new();
+            system.code = self.code;
+            system.shutdown()?;
+            Ok(system)
+        }
+    }
+
+    #[test]
+    fn external_backend() {
+        let mut registry = HarnessRegistry::new();
+        registry
+            .register(ConfigFactory::new("fake", FakeSystemConfig::build))
+            .unwrap();
+        assert!(registry.types().any(|type_tag| type_tag == "fake"));
+        let mut system = registry
+            .build_json(r#"{ "type": "fake", "code": 3 }"#)
+            .unwrap();
+        assert!(!system.dyn_running().unwrap());
+        let status = system.dyn_wait(Duration::from_secs(1)).unwrap();
+        assert_eq!(Some(3), status.code);
+        system.dyn_terminal().unwrap().send_command("true").unwrap();
+
+        let err = registry
+            .register(ConfigFactory::new("fake", FakeSystemConfig::build))
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let registry = HarnessRegistry::empty();
+        for json in [
+            r#"[]"#,
+            r#"{ "code": 3 }"#,
+            r#"{ "type": "fake", "code": 3 }"#,
+        ] {
+            let err = registry.build_json(json).err().unwrap();
+            assert_eq!(ErrorKind::InvalidConfig, err.kind());
+        }
+        let mut registry = HarnessRegistry::empty();
+        registry
+            .register(ConfigFactory::new("fake", FakeSystemConfig::build))
+            .unwrap();
+        let err = registry.build_json(r#"{ "type": "fake" }"#).err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+}
@@ -0,0 +1,392 @@
+use crate::metrics::{self, Counter};
+use crate::power::{PowerControl, PowerController, PowerState};
+use crate::pty::PtySession;
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Default time allowed for the host to become reachable
+const DEFAULT_BOOT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default time allowed for each SSH connection attempt, in seconds
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+/// Default remote command shutting the host down
+const DEFAULT_SHUTDOWN_COMMAND: &str = "poweroff";
+
+/// Exit code of `ssh` when the connection fails or drops
+const SSH_CONNECTION_ERROR: i32 = 255;
+
+/// Interval between reachability checks
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A configuration for driving a remote or physical host over SSH
+///
+/// Authentication is non-interactive, using an identity file or the
+/// user's SSH agent and configuration.
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RemoteSystemConfig {
+    /// Host name or address
+    host: String,
+
+    /// Login user (defaults to the SSH configuration's)
+    user: Option<String>,
+
+    /// SSH port
+    port: Option<u16>,
+
+    /// Private key used to authenticate
+    identity_file: Option<PathBuf>,
+
+    /// Extra SSH options, e.g. `StrictHostKeyChecking=no`
+    ssh_options: Option<Vec<String>>,
+
+    /// Power controller, used to power the host on before connecting and
+    /// off when the system is dropped
+    power: Option<PowerControl>,
+
+    /// Remote command pausing the host
+    pause_command: Option<String>,
+
+    /// Remote command resuming the host
+    resume_command: Option<String>,
+
+    /// Remote command shutting the host down (defaults to `poweroff`)
+    shutdown_command: Option<String>,
+
+    /// Seconds to wait for the host to become reachable (defaults to 300)
+    boot_timeout: Option<u64>,
+
+    /// Seconds allowed for each SSH connection attempt (defaults to 10)
+    connect_timeout: Option<u64>,
+}
+
+impl RemoteSystemConfig {
+    /// SSH command connecting to the host, with a terminal if requested
+    fn ssh_command(&self, tty: bool) -> Command {
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes"]);
+        command.arg("-o").arg(format!(
+            "ConnectTimeout={}",
+            self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+        ));
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        if let Some(identity_file) = &self.identity_file {
+            command.arg("-i").arg(identity_file);
+        }
+        for option in self.ssh_options.iter().flatten() {
+            command.arg("-o").arg(option);
+        }
+        if tty {
+            command.arg("-tt");
+        }
+        match &self.user {
+            Some(user) => command.arg(format!("{user}@{}", self.host)),
+            None => command.arg(&self.host),
+        };
+        command
+    }
+
+    /// Connect to the host, powering it on first if configured
+    pub fn build(&self) -> Result<RemoteSystem, Error> {
+        let system = RemoteSystem {
+            config: self.clone(),
+            paused: false,
+        };
+        if let Some(power) = &self.power {
+            log::trace!("Powering on {}...", self.host);
+            power.power_on()?;
+        }
+        let timeout = self
+            .boot_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_BOOT_TIMEOUT);
+        let deadline = Instant::now() + timeout;
+        while !system.reachable() {
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("Timed out connecting to {}", self.host),
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        log::trace!("Connected to {}.", self.host);
+        Ok(system)
+    }
+}
+
+/// A remote or physical host driven over SSH
+pub struct RemoteSystem {
+    config: RemoteSystemConfig,
+    paused: bool,
+}
+
+impl RemoteSystem {
+    fn power(&self) -> Result<&PowerControl, Error> {
+        self.config
+            .power
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidConfig, "No power controller configured"))
+    }
+
+    /// Turn the host off and on again with its power controller
+    pub fn power_cycle(&mut self) -> Result<(), Error> {
+        self.power()?.power_cycle()?;
+        self.paused = false;
+        metrics::increment(Counter::Restarts, "remote");
+        Ok(())
+    }
+
+    /// Hard reset the host with its power controller
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.power()?.reset()?;
+        self.paused = false;
+        metrics::increment(Counter::Restarts, "remote");
+        Ok(())
+    }
+
+    /// Power state reported by the host's power controller
+    pub fn power_state(&self) -> Result<PowerState, Error> {
+        self.power()?.power_state()
+    }
+
+    /// Whether the host accepts SSH connections
+    fn reachable(&self) -> bool {
+        self.config
+            .ssh_command(false)
+            .arg("true")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// Run a command on the host
+    ///
+    /// A dropped connection is accepted when `may_disconnect` is set, as
+    /// commands like `poweroff` end the session.
+    fn run(&self, command: &str, may_disconnect: bool) -> Result<(), Error> {
+        log::trace!("Running on {}: {command}", self.config.host);
+        let output = self
+            .config
+            .ssh_command(false)
+            .arg(command)
+            .stdin(Stdio::null())
+            .output()?;
+        match output.status.code() {
+            Some(0) => Ok(()),
+            Some(SSH_CONNECTION_ERROR) if may_disconnect => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "{command} failed on {}: {}",
+                    self.config.host,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            )),
+        }
+    }
+
+    /// Run the configured remote command for an operation
+    fn run_configured(&self, operation: &str, command: Option<&String>) -> Result<(), Error> {
+        match command {
+            Some(command) => self.run(command, false),
+            None => Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("No {operation} command configured"),
+            )),
+        }
+    }
+}
+
+/// An SSH session on the host
+pub struct RemoteSystemTerminal {
+    session: PtySession,
+}
+
+impl Read for RemoteSystemTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.session.read(buf)
+    }
+}
+
+impl Write for RemoteSystemTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.session.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.session.flush()
+    }
+}
+
+/// Descriptor the terminal's output is read from
+impl AsRawFd for RemoteSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.session.as_raw_fd()
+    }
+}
+
+impl SystemTerminal for RemoteSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\r")?,
+        }
+        self.flush().map_err(|err| err.into())
+    }
+
+    /// SSH forwards the new size to the remote terminal
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        self.session.resize(cols, rows).map_err(|err| err.into())
+    }
+}
+
+impl SystemHarness for RemoteSystem {
+    type Terminal = RemoteSystemTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        let session = PtySession::spawn(&mut self.config.ssh_command(true))?;
+        Ok(RemoteSystemTerminal { session })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.run_configured("pause", self.config.pause_command.as_ref())?;
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.run_configured("resume", self.config.resume_command.as_ref())?;
+        self.paused = false;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        let command = self
+            .config
+            .shutdown_command
+            .as_deref()
+            .unwrap_or(DEFAULT_SHUTDOWN_COMMAND);
+        self.run(command, true)
+    }
+
+    /// A paused host is tracked by the harness, otherwise a host that is
+    /// powered off or can't be reached is considered shut down
+    fn status(&mut self) -> Result<Status, Error> {
+        let powered_off = match &self.config.power {
+            Some(power) => power
+                .power_state()
+                .is_ok_and(|state| state == PowerState::Off),
+            None => false,
+        };
+        if powered_off {
+            Ok(Status::Shutdown)
+        } else if self.paused {
+            Ok(Status::Paused)
+        } else if self.reachable() {
+            Ok(Status::Running)
+        } else {
+            Ok(Status::Shutdown)
+        }
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.status()? == Status::Running)
+    }
+
+    /// Hosts report no exit code, so only the loss of SSH is observed
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let deadline = Instant::now() + timeout;
+        while self.reachable() {
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!("Timed out waiting for {} to shut down", self.config.host),
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Ok(ExitStatus {
+            code: None,
+            reason: None,
+        })
+    }
+}
+
+impl Drop for RemoteSystem {
+    fn drop(&mut self) {
+        if let Some(power) = &self.config.power {
+            log::trace!("Powering off {}...", self.config.host);
+            if let Err(err) = power.power_off() {
+                log::warn!("Error powering off {}: {err}", self.config.host);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn config() -> RemoteSystemConfig {
+        serde_json::from_str(
+            r#"{
+                "host": "board-1.lab",
+                "user": "root",
+                "port": 2222,
+                "identity_file": "/keys/lab",
+                "ssh_options": ["StrictHostKeyChecking=no"],
+                "connect_timeout": 5
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ssh_command() {
+        let command = config().ssh_command(true);
+        assert_eq!("ssh", command.get_program());
+        assert_eq!(
+            vec![
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "ConnectTimeout=5",
+                "-p",
+                "2222",
+                "-i",
+                "/keys/lab",
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-tt",
+                "root@board-1.lab"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn unconfigured_pause() {
+        let mut system = RemoteSystem {
+            config: config(),
+            paused: false,
+        };
+        let err = system.pause().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+        assert!(!system.paused);
+        let err = system.power_cycle().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Default number of attempts, including the first
+const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// Default delay before the first retry
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest delay between attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Kinds of error retried by default
+const DEFAULT_RETRY_ON: [ErrorKind; 2] = [ErrorKind::PipeError, ErrorKind::IO];
+
+/// Messages of errors retried by default, whatever their kind
+const DEFAULT_TRANSIENT: [&str; 5] = [
+    "text file busy",
+    "connection reset by peer",
+    "resource temporarily unavailable",
+    "i/o timeout",
+    "TLS handshake timeout",
+];
+
+/// How operations that fail for transient reasons are retried
+///
+/// CI hosts under load produce one-off failures, such as `text file busy`
+/// from a container runtime or a dropped monitor connection. An operation
+/// failing with a retryable error is attempted again after a delay that
+/// doubles each time.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RetryPolicy {
+    /// Attempts made in all, including the first (defaults to 3)
+    attempts: Option<u32>,
+
+    /// Milliseconds to wait before the first retry (defaults to 100)
+    backoff: Option<u64>,
+
+    /// Kinds of error retried (defaults to pipe and I/O errors)
+    retry_on: Option<Vec<ErrorKind>>,
+
+    /// Errors retried whatever their kind, matched anywhere in their
+    /// message (defaults to common races such as `text file busy`)
+    transient: Option<Vec<String>>,
+}
+
+impl RetryPolicy {
+    /// Retry with the default attempts, backoff and retryable errors
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Never retry
+    pub fn never() -> Self {
+        Self::new().attempts(1)
+    }
+
+    /// Attempts made in all, including the first
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    /// Delay before the first retry
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = Some(backoff.as_millis() as u64);
+        self
+    }
+
+    /// Kinds of error retried
+    pub fn retry_on(mut self, kinds: impl IntoIterator<Item = ErrorKind>) -> Self {
+        self.retry_on = Some(kinds.into_iter().collect());
+        self
+    }
+
+    /// Errors retried whatever their kind, matched anywhere in their message
+    pub fn transient(mut self, messages: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.transient = Some(messages.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Check if an error should be retried
+    fn retryable(&self, err: &Error) -> bool {
+        let kind_matches = match &self.retry_on {
+            Some(kinds) => kinds.contains(&err.kind()),
+            None => DEFAULT_RETRY_ON.contains(&err.kind()),
+        };
+        let message = err.to_string();
+        let message_matches = match &self.transient {
+            Some(messages) => messages.iter().any(|m| message.contains(m.as_str())),
+            None => DEFAULT_TRANSIENT.iter().any(|m| message.contains(m)),
+        };
+        kind_matches || message_matches
+    }
+
+    /// Run an operation, retrying it while it fails with retryable errors
+    pub(crate) fn run<T>(
+        &self,
+        operation: &str,
+        mut attempt: impl FnMut() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let attempts = self.attempts.unwrap_or(DEFAULT_ATTEMPTS).max(1);
+        let mut backoff = self
+            .backoff
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_BACKOFF);
+        let mut tries = 1;
+        loop {
+            match attempt() {
+                Err(err) if tries < attempts && self.retryable(&err) => {
+                    log::warn!(
+                        "{operation} failed ({tries}/{attempts}), retrying in {backoff:?}: {err}"
+                    );
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    tries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Run an operation failing with the given errors before succeeding,
+    /// returning the number of attempts made
+    fn attempts(policy: &RetryPolicy, errors: &[(ErrorKind, &str)]) -> (bool, usize) {
+        let mut tries = 0;
+        let result = policy.run("test", || {
+            tries += 1;
+            match errors.get(tries - 1) {
+                Some((kind, message)) => Err(Error::new(*kind, *message)),
+                None => Ok(()),
+            }
+        });
+        (result.is_ok(), tries)
+    }
+
+    #[test]
+    fn retries() {
+        let policy = RetryPolicy::new().backoff(Duration::ZERO);
+        let pipe = (ErrorKind::PipeError, "QMP connection closed");
+        let busy = (ErrorKind::HarnessError, "exec: text file busy");
+        let config = (ErrorKind::HarnessError, "no such image");
+        assert_eq!((true, 3), attempts(&policy, &[pipe, busy]));
+        assert_eq!((false, 3), attempts(&policy, &[pipe, pipe, pipe]));
+        assert_eq!((false, 1), attempts(&policy, &[config]));
+        assert_eq!((false, 1), attempts(&RetryPolicy::never(), &[pipe]));
+
+        let policy = policy
+            .retry_on([ErrorKind::Timeout])
+            .transient(["no such image"]);
+        assert_eq!((false, 1), attempts(&policy, &[pipe]));
+        assert_eq!((true, 2), attempts(&policy, &[config]));
+    }
+
+    #[test]
+    fn deserialize() {
+        let policy: RetryPolicy =
+            serde_json::from_str(r#"{"attempts": 5, "backoff": 0, "retry_on": ["timeout", "io"]}"#)
+                .unwrap();
+        let timeout = (ErrorKind::Timeout, "timed out");
+        assert_eq!((true, 5), attempts(&policy, &[timeout; 4]));
+    }
+}
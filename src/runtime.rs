@@ -0,0 +1,120 @@
+use crate::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter used to give each temporary runtime directory a unique name
+static INSTANCE: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory holding a system's runtime sockets
+pub struct RuntimeDir {
+    path: PathBuf,
+
+    /// Whether the directory was created by the harness
+    owned: bool,
+
+    /// Whether the directory should outlive the system
+    keep: bool,
+
+    /// Files the system creates, removed from directories it doesn't own
+    files: &'static [&'static str],
+}
+
+impl RuntimeDir {
+    /// Use the given directory, or create a unique temporary one
+    pub fn new(path: Option<&Path>, files: &'static [&'static str]) -> Result<Self, Error> {
+        match path {
+            Some(path) => {
+                std::fs::create_dir_all(path)?;
+                Ok(Self {
+                    path: path.to_path_buf(),
+                    owned: false,
+                    keep: false,
+                    files,
+                })
+            }
+            None => loop {
+                let name = format!(
+                    "system-harness-{}-{}",
+                    std::process::id(),
+                    INSTANCE.fetch_add(1, Ordering::Relaxed)
+                );
+                let path = std::env::temp_dir().join(name);
+                // A directory left behind by an earlier process with the
+                // same PID may hold stale sockets, so skip to the next name
+                match std::fs::create_dir(&path) {
+                    Ok(()) => {
+                        return Ok(Self {
+                            path,
+                            owned: true,
+                            keep: false,
+                            files,
+                        })
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            },
+        }
+    }
+
+    /// Path of the directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the directory was created by the harness
+    pub fn owned(&self) -> bool {
+        self.owned
+    }
+
+    /// Keep the directory after it is dropped, returning its path
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        self.path.clone()
+    }
+
+    /// Path of a file in the directory
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for RuntimeDir {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        log::trace!("Cleaning up runtime directory: {}", self.path.display());
+        if self.owned {
+            let _ = std::fs::remove_dir_all(&self.path);
+        } else {
+            for file in self.files {
+                let _ = std::fs::remove_file(self.file(file));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn unique_temporary_dirs() {
+        let first = RuntimeDir::new(None, &[]).unwrap();
+        let second = RuntimeDir::new(None, &[]).unwrap();
+        assert_ne!(first.path(), second.path());
+        let path = first.path().to_path_buf();
+        assert!(path.is_dir());
+        drop(first);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keep_dir() {
+        let path = RuntimeDir::new(None, &[]).unwrap().keep();
+        assert!(path.is_dir());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{DynSystemHarness, Error, ErrorKind, SystemConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A system in a scenario
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ScenarioSystem {
+    /// Systems built before this one
+    #[serde(default)]
+    depends_on: Vec<String>,
+
+    /// Scenario networks joined (container systems only)
+    ///
+    /// Other containers on a network reach the system by its name.
+    #[serde(default)]
+    networks: Vec<String>,
+
+    /// The system's config, with its `type` tag
+    #[serde(flatten)]
+    config: SystemConfig,
+}
+
+impl ScenarioSystem {
+    /// A system built from a config
+    pub fn new(config: SystemConfig) -> Self {
+        Self {
+            depends_on: Vec::new(),
+            networks: Vec::new(),
+            config,
+        }
+    }
+
+    /// Build another system before this one
+    pub fn depends_on(mut self, name: impl Into<String>) -> Self {
+        self.depends_on.push(name.into());
+        self
+    }
+
+    /// Join a scenario network
+    pub fn network(mut self, name: impl Into<String>) -> Self {
+        self.networks.push(name.into());
+        self
+    }
+}
+
+/// A configuration for several named systems tested together
+///
+/// Systems can use different backends, e.g. a QEMU server and a container
+/// client. They're built in dependency order, and a system's dependencies
+/// can be waited on with [`build_with_ready`](Self::build_with_ready)
+/// before it's built.
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ScenarioConfig {
+    /// Prefix of created networks
+    ///
+    /// Defaults to `system-harness-<pid>`.
+    name: Option<String>,
+
+    /// Networks shared between systems
+    #[serde(default)]
+    networks: Vec<String>,
+
+    /// Systems by name
+    #[serde(default)]
+    systems: BTreeMap<String, ScenarioSystem>,
+}
+
+impl ScenarioConfig {
+    /// An empty scenario
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prefix of created networks
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Add a network shared between systems
+    pub fn network(mut self, name: impl Into<String>) -> Self {
+        self.networks.push(name.into());
+        self
+    }
+
+    /// Add a system
+    pub fn system(mut self, name: impl Into<String>, system: ScenarioSystem) -> Self {
+        self.systems.insert(name.into(), system);
+        self
+    }
+
+    /// Order systems so that dependencies are built first
+    fn build_order(&self) -> Result<Vec<&str>, Error> {
+        fn visit<'a>(
+            config: &'a ScenarioConfig,
+            name: &'a str,
+            visiting: &mut Vec<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> Result<(), Error> {
+            if order.contains(&name) {
+                return Ok(());
+            }
+            if visiting.contains(&name) {
+                return Err(Error::new(
+                    ErrorKind::InvalidConfig,
+                    format!("Dependency cycle: {} -> {name}", visiting.join(" -> ")),
+                ));
+            }
+            let system = config.systems.get(name).ok_or(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Unknown system '{name}'"),
+            ))?;
+            visiting.push(name);
+            for dependency in &system.depends_on {
+                visit(config, dependency, visiting, order)?;
+            }
+            visiting.pop();
+            order.push(name);
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        for name in self.systems.keys() {
+            visit(self, name, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// Check that a system only joins known networks, and that it can
+    /// join networks at all
+    fn check_networks(&self, name: &str, system: &ScenarioSystem) -> Result<(), Error> {
+        if let Some(network) = system
+            .networks
+            .iter()
+            .find(|network| !self.networks.contains(network))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Unknown network '{network}'"),
+            ));
+        }
+        let can_join = match &system.config {
+            #[cfg(all(unix, feature = "container"))]
+            SystemConfig::Container(_) => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        };
+        if !system.networks.is_empty() && !can_join {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("System '{name}' can't join networks, only containers can"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Build all systems
+    pub fn build(&self) -> Result<Scenario, Error> {
+        self.build_with_ready(|_, _| Ok(()))
+    }
+
+    /// Build all systems, calling `ready` with each system once it's built
+    ///
+    /// A system's dependents are only built once `ready` returns, so it can
+    /// wait for the system to be usable, e.g. for a server to log in. If
+    /// `ready` fails, the systems built so far are torn down.
+    pub fn build_with_ready(
+        &self,
+        mut ready: impl FnMut(&str, &mut Box<dyn DynSystemHarness>) -> Result<(), Error>,
+    ) -> Result<Scenario, Error> {
+        let order = self.build_order()?;
+        for (name, system) in &self.systems {
+            self.check_networks(name, system)?;
+        }
+        let mut scenario = Scenario {
+            systems: Vec::new(),
+            #[cfg(all(unix, feature = "container"))]
+            networks: Vec::new(),
+        };
+        for name in order {
+            log::trace!("Building system: {name}");
+            let mut system = self.build_system(&mut scenario, name)?;
+            ready(name, &mut system)?;
+            scenario.systems.push((name.to_string(), system));
+        }
+        Ok(scenario)
+    }
+
+    /// Build a system, creating the networks it joins
+    fn build_system(
+        &self,
+        #[allow(unused_variables)] scenario: &mut Scenario,
+        name: &str,
+    ) -> Result<Box<dyn DynSystemHarness>, Error> {
+        let system = &self.systems[name];
+        match &system.config {
+            #[cfg(all(unix, feature = "container"))]
+            SystemConfig::Container(config) if !system.networks.is_empty() => {
+                let runtime = config.runtime()?;
+                let prefix = self
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("system-harness-{}", std::process::id()));
+                for network in &system.networks {
+                    let network = format!("{prefix}_{network}");
+                    if !scenario.networks.iter().any(|n| n.name() == network) {
+                        let created =
+                            crate::container::ContainerNetwork::create(&runtime, &network)?;
+                        scenario.networks.push(created);
+                    }
+                }
+                let networks: Vec<_> = system
+                    .networks
+                    .iter()
+                    .filter_map(|network| {
+                        let network = format!("{prefix}_{network}");
+                        scenario.networks.iter().find(|n| n.name() == network)
+                    })
+                    .collect();
+                Ok(Box::new(config.build_on_networks(name, &networks)?))
+            }
+            config => config.build(),
+        }
+    }
+}
+
+/// Several named systems tested together
+///
+/// Built with a [`ScenarioConfig`]. Systems are torn down in the reverse
+/// of the order they were built in when the scenario is dropped, so a
+/// system outlives the systems depending on it. Networks are removed last.
+pub struct Scenario {
+    systems: Vec<(String, Box<dyn DynSystemHarness>)>,
+    #[cfg(all(unix, feature = "container"))]
+    networks: Vec<crate::container::ContainerNetwork>,
+}
+
+impl Scenario {
+    /// Get a system by name
+    pub fn system(&mut self, name: &str) -> Option<&mut Box<dyn DynSystemHarness>> {
+        self.systems
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .map(|(_, system)| system)
+    }
+
+    /// Names of the systems in the order they were built
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.systems.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl Drop for Scenario {
+    fn drop(&mut self) {
+        while let Some((name, system)) = self.systems.pop() {
+            log::trace!("Tearing down system: {name}");
+            drop(system);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[cfg(feature = "qemu")]
+    fn config(systems: &str) -> ScenarioConfig {
+        serde_json::from_str(&format!(
+            r#"{{ "networks": ["lan"], "systems": {systems} }}"#
+        ))
+        .unwrap()
+    }
+
+    #[cfg(feature = "qemu")]
+    #[test]
+    fn build_order() {
+        let chain = config(
+            r#"{
+                "client": { "type": "qemu", "arch": "x86_64", "depends_on": ["server"] },
+                "server": { "type": "qemu", "arch": "x86_64", "depends_on": ["storage"] },
+                "storage": { "type": "qemu", "arch": "x86_64" }
+            }"#,
+        );
+        assert_eq!(
+            vec!["storage", "server", "client"],
+            chain.build_order().unwrap()
+        );
+
+        let cycle = config(
+            r#"{
+                "client": { "type": "qemu", "arch": "x86_64", "depends_on": ["server"] },
+                "server": { "type": "qemu", "arch": "x86_64", "depends_on": ["client"] }
+            }"#,
+        );
+        let err = cycle.build_order().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+
+    #[cfg(feature = "qemu")]
+    #[test]
+    fn check_networks() {
+        let config = config(
+            r#"{
+                "server": { "type": "qemu", "arch": "x86_64", "networks": ["lan"] },
+                "client": { "type": "qemu", "arch": "x86_64", "networks": ["wan"] }
+            }"#,
+        );
+        let err = config.build().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+        assert!(err.to_string().contains("'wan'"));
+        let err = config
+            .check_networks("server", &config.systems["server"])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("can't join networks"));
+    }
+
+    #[cfg(all(unix, feature = "process"))]
+    #[test]
+    fn build() {
+        let process = |script: &str| {
+            let config = serde_json::json!({
+                "type": "process",
+                "program": "sh",
+                "args": ["-c", script],
+            });
+            ScenarioSystem::new(serde_json::from_value(config).unwrap())
+        };
+        let mut built = Vec::new();
+        let mut scenario = ScenarioConfig::new()
+            .system("client", process("exit 1").depends_on("server"))
+            .system("server", process("exit 2"))
+            .build_with_ready(|name, system| {
+                built.push(name.to_string());
+                system
+                    .dyn_wait(std::time::Duration::from_secs(5))
+                    .map(|_| ())
+            })
+            .unwrap();
+        assert_eq!(vec!["server", "client"], built);
+        assert_eq!(
+            vec!["server", "client"],
+            scenario.names().collect::<Vec<_>>()
+        );
+        let status = scenario
+            .system("server")
+            .unwrap()
+            .dyn_wait(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(Some(2), status.code);
+        assert!(scenario.system("database").is_none());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{Error, ErrorKind, ExecOutput};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default time allowed for each connection attempt
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval between connection attempts while waiting for sshd
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Exit code of `ssh` and `scp` when the connection fails or drops
+const SSH_CONNECTION_ERROR: i32 = 255;
+
+/// Options for guests, whose host keys change whenever they're rebuilt
+const GUEST_OPTIONS: [&str; 3] = [
+    "StrictHostKeyChecking=no",
+    "UserKnownHostsFile=/dev/null",
+    "LogLevel=ERROR",
+];
+
+/// Counter used to give each session's control socket a unique name
+static SESSION: AtomicUsize = AtomicUsize::new(0);
+
+/// SSH access to a system, such as a guest's sshd reached through a
+/// forwarded port or a container's published port
+///
+/// Authentication is non-interactive, using an identity file or the
+/// user's SSH agent. Host keys aren't checked, since guests generate new
+/// ones when they're rebuilt.
+#[derive(Clone, Debug)]
+pub struct Ssh {
+    host: String,
+    port: u16,
+    user: Option<String>,
+    identity_file: Option<PathBuf>,
+    options: Vec<String>,
+    connect_timeout: Duration,
+}
+
+impl Ssh {
+    /// Connect to sshd listening on a host port
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            user: None,
+            identity_file: None,
+            options: Vec::new(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+
+    /// Log in as a user
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Authenticate with a private key
+    pub fn identity_file(mut self, identity_file: impl Into<PathBuf>) -> Self {
+        self.identity_file = Some(identity_file.into());
+        self
+    }
+
+    /// Add an SSH option, e.g. `Ciphers=aes128-ctr`
+    pub fn option(mut self, option: impl Into<String>) -> Self {
+        self.options.push(option.into());
+        self
+    }
+
+    /// Time allowed for each connection attempt (defaults to 5 seconds)
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// `ssh` or `scp` with the connection options
+    ///
+    /// The port flag differs between them, and SSH uses the first value
+    /// given for an option, so the caller's options come first.
+    fn command(&self, program: &str, control_path: &Path) -> Command {
+        let mut command = Command::new(program);
+        command.arg(if program == "scp" { "-P" } else { "-p" });
+        command.arg(self.port.to_string());
+        if let Some(identity_file) = &self.identity_file {
+            command.arg("-i").arg(identity_file);
+        }
+        for option in &self.options {
+            command.arg("-o").arg(option);
+        }
+        for option in GUEST_OPTIONS {
+            command.arg("-o").arg(option);
+        }
+        command.args(["-o", "BatchMode=yes"]);
+        command.arg("-o").arg(format!(
+            "ConnectTimeout={}",
+            self.connect_timeout.as_secs().max(1)
+        ));
+        command
+            .arg("-o")
+            .arg(format!("ControlPath={}", control_path.display()));
+        command
+    }
+
+    /// `[user@]host`
+    fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// Wait for sshd to accept a connection and open a session
+    ///
+    /// The session's connection is shared by every command and transfer
+    /// made with it, so they don't each have to log in.
+    pub fn connect(&self, timeout: Duration) -> Result<SshSession, Error> {
+        let control_path = std::env::temp_dir().join(format!(
+            "system-harness-ssh-{}-{}",
+            std::process::id(),
+            SESSION.fetch_add(1, Ordering::Relaxed)
+        ));
+        let deadline = Instant::now() + timeout;
+        log::trace!("Waiting for sshd on {}:{}...", self.host, self.port);
+        loop {
+            // With -f, ssh goes to the background once logged in, leaving
+            // the connection open for the session's commands
+            let output = self
+                .command("ssh", &control_path)
+                .args(["-o", "ControlMaster=yes", "-o", "ControlPersist=yes"])
+                .args(["-f", "-N"])
+                .arg(self.destination())
+                .stdin(Stdio::null())
+                .output()?;
+            if output.status.success() {
+                log::trace!("Connected to sshd on {}:{}", self.host, self.port);
+                return Ok(SshSession {
+                    ssh: self.clone(),
+                    control_path,
+                });
+            }
+            if Instant::now() + POLL_INTERVAL >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    format!(
+                        "Timed out waiting for sshd on {}:{}: {}",
+                        self.host,
+                        self.port,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ),
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// An open SSH connection to a system
+///
+/// The connection is closed when the session is dropped.
+pub struct SshSession {
+    ssh: Ssh,
+    control_path: PathBuf,
+}
+
+impl SshSession {
+    /// Run a shell command and capture its output
+    ///
+    /// A command that fails is not an error, so check its exit code.
+    pub fn run(&self, command: &str) -> Result<ExecOutput, Error> {
+        log::trace!("Running over SSH on {}: {command}", self.ssh.host);
+        let output = self
+            .ssh
+            .command("ssh", &self.control_path)
+            .arg(self.ssh.destination())
+            .arg(command)
+            .stdin(Stdio::null())
+            .output()?;
+        if output.status.code() == Some(SSH_CONNECTION_ERROR) {
+            return Err(Error::new(
+                ErrorKind::PipeError,
+                format!(
+                    "SSH connection to {} failed: {}",
+                    self.ssh.host,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        Ok(ExecOutput {
+            exit_code: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    /// Copy a file to the system
+    pub fn upload(&self, local: impl AsRef<Path>, remote: &str) -> Result<(), Error> {
+        let remote = format!("{}:{remote}", self.ssh.destination());
+        self.copy(local.as_ref().as_os_str(), remote.as_ref())
+    }
+
+    /// Copy a file from the system
+    pub fn download(&self, remote: &str, local: impl AsRef<Path>) -> Result<(), Error> {
+        let remote = format!("{}:{remote}", self.ssh.destination());
+        self.copy(remote.as_ref(), local.as_ref().as_os_str())
+    }
+
+    fn copy(&self, from: &std::ffi::OsStr, to: &std::ffi::OsStr) -> Result<(), Error> {
+        log::trace!("Copying over SSH: {from:?} to {to:?}");
+        let output = self
+            .ssh
+            .command("scp", &self.control_path)
+            .arg("-q")
+            .arg(from)
+            .arg(to)
+            .stdin(Stdio::null())
+            .output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "Failed to copy {from:?} to {to:?}: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))
+        }
+    }
+}
+
+impl Drop for SshSession {
+    fn drop(&mut self) {
+        let result = self
+            .ssh
+            .command("ssh", &self.control_path)
+            .args(["-O", "exit"])
+            .arg(self.ssh.destination())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if let Err(err) = result {
+            log::warn!("Error closing SSH connection to {}: {err}", self.ssh.host);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn command() {
+        let ssh = Ssh::new("127.0.0.1", 2222)
+            .user("root")
+            .identity_file("/keys/guest")
+            .option("StrictHostKeyChecking=yes");
+        for (program, port_flag) in [("ssh", "-p"), ("scp", "-P")] {
+            let command = ssh.command(program, Path::new("/tmp/control"));
+            assert_eq!(program, command.get_program());
+            assert_eq!(
+                vec![
+                    port_flag,
+                    "2222",
+                    "-i",
+                    "/keys/guest",
+                    "-o",
+                    "StrictHostKeyChecking=yes",
+                    "-o",
+                    "StrictHostKeyChecking=no",
+                    "-o",
+                    "UserKnownHostsFile=/dev/null",
+                    "-o",
+                    "LogLevel=ERROR",
+                    "-o",
+                    "BatchMode=yes",
+                    "-o",
+                    "ConnectTimeout=5",
+                    "-o",
+                    "ControlPath=/tmp/control",
+                ],
+                command.get_args().collect::<Vec<_>>()
+            );
+        }
+        assert_eq!("root@127.0.0.1", ssh.destination());
+    }
+
+    #[test]
+    fn connect_timeout() {
+        // A port that refuses connections, as a guest's does before sshd starts
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = Ssh::new("127.0.0.1", port)
+            .connect(Duration::from_millis(100))
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::Timeout, err.kind());
+    }
+}
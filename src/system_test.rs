@@ -0,0 +1,168 @@
+use crate::{Error, SystemHarness};
+use std::any::Any;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the directory artifacts are collected in
+const ARTIFACTS_VAR: &str = "SYSTEM_HARNESS_ARTIFACTS";
+
+/// Collects backend-specific artifacts into a directory
+type Collector<S> = Box<dyn FnOnce(&mut S, &Path) -> Result<(), Error>>;
+
+/// A test run against a system
+///
+/// Usually generated by the [`system_test`](crate::system_test)
+/// attribute. The system is shut down once the test ends, whether or not
+/// it panics. If it panics and an artifacts directory is set, the panic
+/// message and the system's status are written to a directory named
+/// after the test, along with any artifacts from the collector.
+pub struct SystemTest<S> {
+    name: String,
+    artifacts: Option<PathBuf>,
+    collect: Option<Collector<S>>,
+}
+
+impl<S: SystemHarness> SystemTest<S> {
+    /// Create a test, collecting artifacts in the directory named by
+    /// `SYSTEM_HARNESS_ARTIFACTS` if it is set
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            artifacts: std::env::var_os(ARTIFACTS_VAR).map(PathBuf::from),
+            collect: None,
+        }
+    }
+
+    /// Directory to collect artifacts in if the test panics
+    pub fn artifacts(mut self, artifacts: impl Into<PathBuf>) -> Self {
+        self.artifacts = Some(artifacts.into());
+        self
+    }
+
+    /// Collect backend-specific artifacts, such as logs or screenshots, if
+    /// the test panics
+    pub fn collect(
+        mut self,
+        collect: impl FnOnce(&mut S, &Path) -> Result<(), Error> + 'static,
+    ) -> Self {
+        self.collect = Some(Box::new(collect));
+        self
+    }
+
+    /// Build the system and run the test against it
+    ///
+    /// Panics if the system can't be built, or with the test's panic.
+    pub fn run(mut self, build: impl FnOnce() -> Result<S, Error>, test: impl FnOnce(&mut S)) {
+        let mut system = match build() {
+            Ok(system) => system,
+            Err(err) => panic!("Failed to build system for {}: {err}", self.name),
+        };
+        let result = catch_unwind(AssertUnwindSafe(|| test(&mut system)));
+        if let Err(panic) = &result {
+            self.collect_artifacts(&mut system, panic.as_ref());
+        }
+        if let Ok(true) = system.running() {
+            if let Err(err) = system.shutdown() {
+                log::warn!("Error shutting down system for {}: {err}", self.name);
+            }
+        }
+        drop(system);
+        if let Err(panic) = result {
+            resume_unwind(panic);
+        }
+    }
+
+    fn collect_artifacts(&mut self, system: &mut S, panic: &(dyn Any + Send)) {
+        let Some(artifacts) = &self.artifacts else {
+            return;
+        };
+        let dir = artifacts.join(self.name.replace("::", "-"));
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        let status = match system.status() {
+            Ok(status) => format!("{status:?}"),
+            Err(err) => format!("Error getting status: {err}"),
+        };
+        let result = std::fs::create_dir_all(&dir)
+            .and_then(|_| std::fs::write(dir.join("panic.txt"), message))
+            .and_then(|_| std::fs::write(dir.join("status.txt"), status))
+            .map_err(Error::from)
+            .and_then(|_| match self.collect.take() {
+                Some(collect) => collect(system, &dir),
+                None => Ok(()),
+            });
+        match result {
+            Ok(()) => log::info!("Collected artifacts in {}", dir.display()),
+            Err(err) => log::warn!("Error collecting artifacts in {}: {err}", dir.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::testing::FakeSystem;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// A system reporting its shutdown through `shutdown`
+    fn watched(shutdown: Arc<AtomicBool>) -> FakeSystem {
+        let mut system = FakeSystem::new();
+        system.shutdown = shutdown;
+        system
+    }
+
+    #[test]
+    fn teardown_on_panic() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let artifacts = std::env::temp_dir().join(format!("system-test-{}", std::process::id()));
+        let collected = Arc::new(AtomicBool::new(false));
+        let result = {
+            let shutdown = shutdown.clone();
+            let collected = collected.clone();
+            catch_unwind(move || {
+                SystemTest::new("tests::boots")
+                    .artifacts(&artifacts)
+                    .collect(move |_system, dir| {
+                        collected.store(dir.ends_with("tests-boots"), Ordering::SeqCst);
+                        Ok(())
+                    })
+                    .run(
+                        || Ok(watched(shutdown)),
+                        |_system| panic!("no login prompt"),
+                    )
+            })
+        };
+        assert!(result.is_err());
+        assert!(shutdown.load(Ordering::SeqCst));
+        assert!(collected.load(Ordering::SeqCst));
+
+        let dir = std::env::temp_dir()
+            .join(format!("system-test-{}", std::process::id()))
+            .join("tests-boots");
+        assert_eq!(
+            "no login prompt",
+            std::fs::read_to_string(dir.join("panic.txt")).unwrap()
+        );
+        assert_eq!(
+            "Running",
+            std::fs::read_to_string(dir.join("status.txt")).unwrap()
+        );
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn teardown_on_success() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let system_shutdown = shutdown.clone();
+        SystemTest::new("tests::passes").run(
+            || Ok(watched(system_shutdown)),
+            |system| assert!(system.running().unwrap()),
+        );
+        assert!(shutdown.load(Ordering::SeqCst));
+    }
+}
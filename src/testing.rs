@@ -0,0 +1,177 @@
+//! Fake systems shared by unit tests
+
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A terminal that replies to each line typed into it
+pub struct FakeTerminal {
+    output: VecDeque<u8>,
+    replies: VecDeque<&'static [u8]>,
+    /// Most bytes returned by a single read
+    chunk: usize,
+    /// Everything written to the terminal, with keys as newlines
+    pub typed: Vec<u8>,
+}
+
+impl FakeTerminal {
+    /// Create a terminal showing a banner, then a reply for each line
+    pub fn new(banner: &[u8], replies: &[&'static [u8]]) -> Self {
+        Self {
+            output: banner.iter().copied().collect(),
+            replies: replies.iter().copied().collect(),
+            chunk: usize::MAX,
+            typed: Vec::new(),
+        }
+    }
+
+    /// Split output across reads, as real terminals do
+    pub fn chunked(mut self, chunk: usize) -> Self {
+        self.chunk = chunk;
+        self
+    }
+}
+
+impl Default for FakeTerminal {
+    fn default() -> Self {
+        Self::new(b"", &[])
+    }
+}
+
+impl Read for FakeTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.output.len()).min(self.chunk);
+        for (byte, output) in buf.iter_mut().zip(self.output.drain(..len)) {
+            *byte = output;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for FakeTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.typed.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SystemTerminal for FakeTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.typed.push(b'\n'),
+        }
+        if let Some(reply) = self.replies.pop_front() {
+            self.output.extend(reply);
+        }
+        Ok(())
+    }
+}
+
+/// A socket standing in for a guest's serial console
+#[cfg(unix)]
+impl SystemTerminal for std::os::unix::net::UnixStream {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\r")?,
+        }
+        Ok(())
+    }
+}
+
+/// Opens a terminal to a fake system
+type TerminalFactory<T> = Box<dyn Fn() -> Result<T, Error> + Send>;
+
+/// A system whose state only changes when asked to
+pub struct FakeSystem<T = FakeTerminal> {
+    terminal: TerminalFactory<T>,
+    /// Status queries block until this is signalled
+    hang: Option<Receiver<()>>,
+    /// Exit code once shut down
+    pub code: i32,
+    pub paused: bool,
+    /// Set once shut down, and shared with whoever asked for it
+    pub shutdown: Arc<AtomicBool>,
+}
+
+impl FakeSystem {
+    /// Create a running system with a blank terminal
+    pub fn new() -> Self {
+        Self::with_terminal(|| Ok(FakeTerminal::default()))
+    }
+}
+
+impl<T> FakeSystem<T> {
+    /// Create a running system, opening terminals with `terminal`
+    pub fn with_terminal(terminal: impl Fn() -> Result<T, Error> + Send + 'static) -> Self {
+        Self {
+            terminal: Box::new(terminal),
+            hang: None,
+            code: 0,
+            paused: false,
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Make each status query hang until `release` is signalled
+    pub fn hang(mut self, release: Receiver<()>) -> Self {
+        self.hang = Some(release);
+        self
+    }
+}
+
+impl<T: SystemTerminal> SystemHarness for FakeSystem<T> {
+    type Terminal = T;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        (self.terminal)()
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.paused = false;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        if let Some(hang) = &self.hang {
+            let _ = hang.recv();
+        }
+        Ok(match (self.shutdown.load(Ordering::SeqCst), self.paused) {
+            (true, _) => Status::Shutdown,
+            (false, true) => Status::Paused,
+            (false, false) => Status::Running,
+        })
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(!self.shutdown.load(Ordering::SeqCst))
+    }
+
+    fn wait(&mut self, _timeout: Duration) -> Result<ExitStatus, Error> {
+        if self.shutdown.load(Ordering::SeqCst) {
+            Ok(ExitStatus {
+                code: Some(self.code),
+                reason: None,
+            })
+        } else {
+            Err(Error::new(ErrorKind::Timeout, "Still running"))
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{Error, Key, SystemTerminal};
+use std::io::{Read, Write};
+
+/// Where an [`AnsiStripper`] is within an escape sequence
+#[derive(Clone, Copy, Default, PartialEq)]
+enum AnsiState {
+    /// Plain text
+    #[default]
+    Text,
+
+    /// After ESC
+    Escape,
+
+    /// In a control sequence (`ESC [`), ended by a byte in `@` to `~`
+    Control,
+
+    /// In an operating system command (`ESC ]`), ended by BEL or `ESC \`
+    Command,
+
+    /// After ESC in an operating system command
+    CommandEscape,
+}
+
+/// Removes ANSI escape sequences from a stream of bytes
+///
+/// Sequences split across calls are handled, so it can be fed output as
+/// it's read.
+#[derive(Default)]
+pub(crate) struct AnsiStripper {
+    state: AnsiState,
+}
+
+impl AnsiStripper {
+    /// Append the plain text in `data` to `text`
+    pub(crate) fn strip(&mut self, data: &[u8], text: &mut Vec<u8>) {
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (AnsiState::Text, 0x1b) => AnsiState::Escape,
+                (AnsiState::Text, byte) => {
+                    text.push(byte);
+                    AnsiState::Text
+                }
+                (AnsiState::Escape, b'[') => AnsiState::Control,
+                (AnsiState::Escape, b']') => AnsiState::Command,
+                (AnsiState::Escape, _) => AnsiState::Text,
+                (AnsiState::Control, 0x40..=0x7e) => AnsiState::Text,
+                (AnsiState::Control, _) => AnsiState::Control,
+                (AnsiState::Command, 0x07) => AnsiState::Text,
+                (AnsiState::Command, 0x1b) => AnsiState::CommandEscape,
+                (AnsiState::Command, _) => AnsiState::Command,
+                (AnsiState::CommandEscape, b'\\') => AnsiState::Text,
+                (AnsiState::CommandEscape, _) => AnsiState::Command,
+            };
+        }
+    }
+}
+
+/// A reader that strips ANSI escape sequences, such as colors and cursor
+/// movement, from a terminal's output
+///
+/// Input is passed through unchanged, so it can still be used as a
+/// terminal.
+pub struct PlainTextReader<R> {
+    inner: R,
+    stripper: AnsiStripper,
+    /// Plain text not yet read
+    text: Vec<u8>,
+}
+
+impl<R: Read> PlainTextReader<R> {
+    /// Strip escape sequences from a reader
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            stripper: AnsiStripper::default(),
+            text: Vec::new(),
+        }
+    }
+
+    /// Get the wrapped reader back
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for PlainTextReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut raw = [0u8; 1024];
+        // Output made up only of escape sequences leaves nothing to return,
+        // so keep reading rather than signalling end of file
+        while self.text.is_empty() {
+            let len = self.inner.read(&mut raw)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            self.stripper.strip(&raw[..len], &mut self.text);
+        }
+        let len = buf.len().min(self.text.len());
+        buf[..len].copy_from_slice(&self.text[..len]);
+        self.text.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<R: Write> Write for PlainTextReader<R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: SystemTerminal> SystemTerminal for PlainTextReader<R> {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        self.inner.send_key(key)
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        self.inner.resize(cols, rows)
+    }
+}
+
+/// A reader that yields a terminal's output a complete line at a time
+///
+/// Lines end with `\n` or `\r\n`, which aren't included in the line, and
+/// bytes that aren't UTF-8 are replaced. Wrap a [`PlainTextReader`] to
+/// strip escape sequences too. Input is passed through unchanged, so it can
+/// still be used as a terminal.
+pub struct LineReader<R> {
+    inner: R,
+    /// Output after the last complete line
+    partial: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> LineReader<R> {
+    /// Read lines from a reader
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            partial: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Read the next complete line
+    ///
+    /// Blocks until a line is complete. At the end of the output, any
+    /// unterminated text is returned as a last line, then `None`.
+    pub fn read_line(&mut self) -> Result<Option<String>, Error> {
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Some(end) = self.partial.iter().position(|byte| *byte == b'\n') {
+                let mut line: Vec<u8> = self.partial.drain(..=end).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+            }
+            if self.eof {
+                if self.partial.is_empty() {
+                    return Ok(None);
+                }
+                let line = std::mem::take(&mut self.partial);
+                return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+            }
+            let len = self.inner.read(&mut buf)?;
+            if len == 0 {
+                self.eof = true;
+            }
+            self.partial.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    /// Output after the last complete line, such as a shell prompt
+    pub fn partial(&self) -> String {
+        String::from_utf8_lossy(&self.partial).into_owned()
+    }
+
+    /// Get the wrapped reader back, dropping any unread output
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for LineReader<R> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_line().transpose()
+    }
+}
+
+impl<R: Write> Write for LineReader<R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for LineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.partial.is_empty() {
+            return self.inner.read(buf);
+        }
+        let len = buf.len().min(self.partial.len());
+        buf[..len].copy_from_slice(&self.partial[..len]);
+        self.partial.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<R: SystemTerminal> SystemTerminal for LineReader<R> {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        self.inner.send_key(key)
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        self.inner.resize(cols, rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// A reader returning its output in chunks
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        let mut reader = PlainTextReader::new(Chunks(vec![
+            b"\x1b[1;3",
+            b"2mgreen\x1b[0m \x1b]0;title\x07",
+            b"\x1b[2J",
+            b"\x1b]0;title\x1b\\done\x1bc",
+        ]));
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!("green done", text);
+    }
+
+    #[test]
+    fn lines() {
+        let mut reader = LineReader::new(PlainTextReader::new(Chunks(vec![
+            b"Welcome\r",
+            b"\n\x1b[32mok\x1b[0m\nlog",
+            b"in: ",
+        ])));
+        assert_eq!("Welcome", reader.read_line().unwrap().unwrap());
+        assert_eq!("ok", reader.read_line().unwrap().unwrap());
+        assert_eq!("log", reader.partial());
+        assert_eq!(
+            vec!["login: ".to_string()],
+            reader.collect::<Result<Vec<_>, _>>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+/// An operation run on a worker's value
+type Job<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+/// A thread owning a value, which operations on it are run on
+///
+/// An operation that hangs only blocks the thread, so the caller can give
+/// up on it. Operations run in order, so ones queued behind a hung
+/// operation wait for it.
+struct Worker<T> {
+    jobs: Option<Sender<Job<T>>>,
+    /// Signalled once the value has been dropped
+    done: Receiver<()>,
+}
+
+impl<T: Send + 'static> Worker<T> {
+    fn spawn(mut value: T) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job<T>>();
+        let (finished, done) = mpsc::channel();
+        std::thread::spawn(move || {
+            for job in receiver {
+                job(&mut value);
+            }
+            drop(value);
+            let _ = finished.send(());
+        });
+        Self {
+            jobs: Some(jobs),
+            done,
+        }
+    }
+
+    /// Queue an operation, returning a receiver for its result
+    fn submit<R: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut T) -> R + Send + 'static,
+    ) -> Result<Receiver<R>, Error> {
+        let (sender, receiver) = mpsc::channel();
+        self.jobs
+            .as_ref()
+            .ok_or(Error::new(ErrorKind::HarnessError, "Worker stopped"))?
+            .send(Box::new(move |value| {
+                let _ = sender.send(operation(value));
+            }))
+            .map_err(|_| Error::new(ErrorKind::HarnessError, "Worker stopped"))?;
+        Ok(receiver)
+    }
+
+    /// Wait up to the deadline for an operation's result
+    fn wait<R>(&self, name: &str, deadline: Duration, result: &Receiver<R>) -> Result<R, Error> {
+        result.recv_timeout(deadline).map_err(|err| match err {
+            RecvTimeoutError::Timeout => Error::new(
+                ErrorKind::Timeout,
+                format!("{name} did not complete within {deadline:?}"),
+            ),
+            RecvTimeoutError::Disconnected => {
+                Error::new(ErrorKind::HarnessError, format!("{name} panicked"))
+            }
+        })
+    }
+
+    /// Run an operation, waiting up to the deadline for its result
+    fn call<R: Send + 'static>(
+        &self,
+        name: &str,
+        deadline: Duration,
+        operation: impl FnOnce(&mut T) -> R + Send + 'static,
+    ) -> Result<R, Error> {
+        let result = self.submit(operation)?;
+        self.wait(name, deadline, &result)
+    }
+
+    /// Stop the thread, waiting up to the deadline for the value to be
+    /// dropped
+    fn stop(&mut self, deadline: Duration) {
+        self.jobs = None;
+        if self.done.recv_timeout(deadline).is_err() {
+            log::warn!("Worker still busy after {deadline:?}, leaving it behind");
+        }
+    }
+}
+
+/// Convert an error from a terminal operation to an I/O error
+fn io_error(err: Error) -> std::io::Error {
+    let kind = match err.kind() {
+        ErrorKind::Timeout => std::io::ErrorKind::TimedOut,
+        _ => std::io::ErrorKind::Other,
+    };
+    std::io::Error::new(kind, err.to_string())
+}
+
+/// A harness whose every operation must complete within a deadline
+///
+/// An operation that doesn't, such as a status query to a wedged guest,
+/// fails with [`ErrorKind::Timeout`] instead of hanging the test. The
+/// harness runs on a thread of its own, so a hung operation keeps that
+/// thread busy and later operations queue behind it, each failing once its
+/// own deadline passes. [`wait`](SystemHarness::wait) is allowed its
+/// timeout on top of the deadline.
+///
+/// The harness is dropped on its thread. Dropping waits up to the deadline
+/// for that, then leaves it behind.
+pub struct Timeboxed<H: Send + 'static> {
+    worker: Worker<H>,
+    deadline: Duration,
+}
+
+impl<H: SystemHarness + Send + 'static> Timeboxed<H> {
+    /// Apply a deadline to each of a harness's operations
+    pub fn new(harness: H, deadline: Duration) -> Self {
+        Self {
+            worker: Worker::spawn(harness),
+            deadline,
+        }
+    }
+}
+
+impl<H> SystemHarness for Timeboxed<H>
+where
+    H: SystemHarness + Send + 'static,
+    H::Terminal: Send + 'static,
+{
+    type Terminal = TimeboxedTerminal<H::Terminal>;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        let terminal = self
+            .worker
+            .call("terminal", self.deadline, |harness| harness.terminal())??;
+        Ok(TimeboxedTerminal::new(terminal, self.deadline))
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.worker
+            .call("pause", self.deadline, |harness| harness.pause())?
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.worker
+            .call("resume", self.deadline, |harness| harness.resume())?
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.worker
+            .call("shutdown", self.deadline, |harness| harness.shutdown())?
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        self.worker
+            .call("status", self.deadline, |harness| harness.status())?
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        self.worker
+            .call("running", self.deadline, |harness| harness.running())?
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        self.worker
+            .call("wait", timeout + self.deadline, move |harness| {
+                harness.wait(timeout)
+            })?
+    }
+}
+
+impl<H: Send + 'static> Drop for Timeboxed<H> {
+    fn drop(&mut self) {
+        self.worker.stop(self.deadline);
+    }
+}
+
+/// A terminal whose every operation must complete within a deadline
+///
+/// Reads and writes that don't fail with [`std::io::ErrorKind::TimedOut`],
+/// and other operations with [`ErrorKind::Timeout`]. A read that times out
+/// is left waiting for output, which the next read returns. Until then,
+/// other operations queue behind it.
+pub struct TimeboxedTerminal<T: Send + 'static> {
+    worker: Worker<T>,
+    deadline: Duration,
+    /// Read that timed out before output arrived
+    pending: Option<Receiver<std::io::Result<Vec<u8>>>>,
+    /// Output read but not yet returned
+    unread: Vec<u8>,
+}
+
+impl<T: SystemTerminal + Send + 'static> TimeboxedTerminal<T> {
+    /// Apply a deadline to each of a terminal's operations
+    pub fn new(terminal: T, deadline: Duration) -> Self {
+        Self {
+            worker: Worker::spawn(terminal),
+            deadline,
+            pending: None,
+            unread: Vec::new(),
+        }
+    }
+}
+
+impl<T: SystemTerminal + Send + 'static> Read for TimeboxedTerminal<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.unread.is_empty() {
+            let result = match self.pending.take() {
+                Some(result) => result,
+                None => {
+                    let len = buf.len();
+                    self.worker
+                        .submit(move |terminal| {
+                            let mut data = vec![0u8; len];
+                            terminal.read(&mut data).map(|len| {
+                                data.truncate(len);
+                                data
+                            })
+                        })
+                        .map_err(io_error)?
+                }
+            };
+            match self.worker.wait("read", self.deadline, &result) {
+                Ok(data) => self.unread = data?,
+                Err(err) => {
+                    if err.kind() == ErrorKind::Timeout {
+                        self.pending = Some(result);
+                    }
+                    return Err(io_error(err));
+                }
+            }
+        }
+        let len = buf.len().min(self.unread.len());
+        buf[..len].copy_from_slice(&self.unread[..len]);
+        self.unread.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<T: SystemTerminal + Send + 'static> Write for TimeboxedTerminal<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let data = buf.to_vec();
+        self.worker
+            .call("write", self.deadline, move |terminal| {
+                terminal.write(&data)
+            })
+            .map_err(io_error)?
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.worker
+            .call("flush", self.deadline, |terminal| terminal.flush())
+            .map_err(io_error)?
+    }
+}
+
+impl<T: SystemTerminal + Send + 'static> SystemTerminal for TimeboxedTerminal<T> {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        self.worker
+            .call("send_key", self.deadline, move |terminal| {
+                terminal.send_key(key)
+            })?
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        self.worker.call("resize", self.deadline, move |terminal| {
+            terminal.resize(cols, rows)
+        })?
+    }
+}
+
+impl<T: Send + 'static> Drop for TimeboxedTerminal<T> {
+    fn drop(&mut self) {
+        self.worker.stop(self.deadline);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::testing::FakeSystem;
+    use std::sync::mpsc::SyncSender;
+
+    /// A system whose status hangs until released
+    fn wedged() -> (Timeboxed<FakeSystem>, SyncSender<()>) {
+        let (release, wedge) = mpsc::sync_channel(1);
+        let system = Timeboxed::new(FakeSystem::new().hang(wedge), Duration::from_millis(50));
+        (system, release)
+    }
+
+    #[test]
+    fn harness_timeout() {
+        let (mut system, release) = wedged();
+        assert!(system.running().unwrap());
+        let err = system.status().err().unwrap();
+        assert_eq!(ErrorKind::Timeout, err.kind());
+        // Queued behind the hung status query
+        assert_eq!(ErrorKind::Timeout, system.pause().err().unwrap().kind());
+
+        release.send(()).unwrap();
+        system.resume().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn terminal_timeout() {
+        use std::os::unix::net::UnixStream;
+
+        // Reads from the socket block until the guest writes to it
+        let (terminal, mut guest) = UnixStream::pair().unwrap();
+        let mut terminal = TimeboxedTerminal::new(terminal, Duration::from_millis(50));
+        let mut buf = [0u8; 16];
+        let err = terminal.read(&mut buf).err().unwrap();
+        assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+
+        // Output arriving late goes to the next read
+        guest.write_all(b"login: ").unwrap();
+        let len = terminal.read(&mut buf[..3]).unwrap();
+        assert_eq!(b"log", &buf[..len]);
+        let len = terminal.read(&mut buf).unwrap();
+        assert_eq!(b"in: ", &buf[..len]);
+        terminal.send_command("root").unwrap();
+    }
+}
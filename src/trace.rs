@@ -0,0 +1,150 @@
+use crate::metrics::CommandTimer;
+
+/// A span entered until dropped
+///
+/// Without the `tracing` feature, spans are empty. Spans for commands also
+/// count the command and time it for metrics.
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    _timer: Option<CommandTimer>,
+}
+
+impl Span {
+    #[cfg(feature = "tracing")]
+    fn new(span: tracing::Span, timer: Option<CommandTimer>) -> Self {
+        Self {
+            span: span.entered(),
+            _timer: timer,
+        }
+    }
+
+    /// Record the id of the system the span is for, once it is known
+    pub(crate) fn record_system(&self, system: &str) {
+        #[cfg(feature = "tracing")]
+        self.span.record("system", system);
+        #[cfg(not(feature = "tracing"))]
+        let _ = system;
+    }
+}
+
+/// Span for building a system with a backend
+pub(crate) fn build(backend: &'static str) -> Span {
+    #[cfg(feature = "tracing")]
+    return Span::new(
+        tracing::info_span!("build", backend, system = tracing::field::Empty),
+        None,
+    );
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = backend;
+        Span { _timer: None }
+    }
+}
+
+/// Span for a QMP command sent to a QEMU system
+#[cfg(feature = "qemu")]
+pub(crate) fn qmp_command(system: &str, command: &str) -> Span {
+    let timer = Some(CommandTimer::start("qemu"));
+    #[cfg(feature = "tracing")]
+    return Span::new(tracing::debug_span!("qmp", system, command), timer);
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (system, command);
+        Span { _timer: timer }
+    }
+}
+
+/// Span for a container runtime CLI invocation, or the equivalent API
+/// request
+#[cfg(all(unix, feature = "container"))]
+pub(crate) fn container_command(system: &str, command: &str) -> Span {
+    let timer = Some(CommandTimer::start("container"));
+    #[cfg(feature = "tracing")]
+    return Span::new(tracing::debug_span!("container", system, command), timer);
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (system, command);
+        Span { _timer: timer }
+    }
+}
+
+/// Span for a container runtime CLI invocation on an image, before a
+/// container exists
+#[cfg(all(unix, feature = "container"))]
+pub(crate) fn image_command(image: &str, command: &str) -> Span {
+    let timer = Some(CommandTimer::start("container"));
+    #[cfg(feature = "tracing")]
+    return Span::new(tracing::debug_span!("container", image, command), timer);
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (image, command);
+        Span { _timer: timer }
+    }
+}
+
+#[cfg(all(test, feature = "tracing", feature = "qemu"))]
+mod tests {
+
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records span names and fields as `name field=value`
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let mut spans = self.0.lock().unwrap();
+            let span = spans.last_mut().unwrap();
+            span.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.0
+                .lock()
+                .unwrap()
+                .push(span.metadata().name().to_string());
+            span.record(&mut self.clone());
+            Id::from_u64(self.0.lock().unwrap().len() as u64)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn spans() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let build = build("qemu");
+            build.record_system("/tmp/system");
+            let _qmp = qmp_command("/tmp/system", "stop");
+        });
+        assert_eq!(
+            vec![
+                "build backend=\"qemu\" system=\"/tmp/system\"",
+                "qmp system=\"/tmp/system\" command=\"stop\"",
+            ],
+            *recorder.0.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,730 @@
+use crate::text::AnsiStripper;
+use crate::{Error, ErrorKind, Key, SystemTerminal};
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Environment variable that, when set, makes golden files be rewritten
+/// rather than compared against
+const UPDATE_VAR: &str = "SYSTEM_HARNESS_UPDATE_GOLDEN";
+
+/// Kind of terminal interaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    /// Input written to the terminal
+    Sent,
+
+    /// A key sent to the terminal
+    Key,
+
+    /// Output read from the terminal
+    Received,
+
+    /// Output that was waited for and found
+    Expected,
+}
+
+impl EntryKind {
+    fn marker(&self) -> char {
+        match self {
+            Self::Sent => '>',
+            Self::Key => 'k',
+            Self::Received => '<',
+            Self::Expected => '?',
+        }
+    }
+
+    fn from_marker(marker: &str) -> Option<Self> {
+        match marker {
+            ">" => Some(Self::Sent),
+            "k" => Some(Self::Key),
+            "<" => Some(Self::Received),
+            "?" => Some(Self::Expected),
+            _ => None,
+        }
+    }
+}
+
+/// A single terminal interaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranscriptEntry {
+    /// Time since recording started
+    pub elapsed: Duration,
+
+    /// Kind of interaction
+    pub kind: EntryKind,
+
+    /// Bytes sent, received or expected, or the name of the key sent
+    pub data: Vec<u8>,
+}
+
+/// Rules applied to transcripts before they are compared
+///
+/// Output that changes from run to run, such as kernel timestamps or the
+/// escape codes a shell colors its prompt with, would otherwise make every
+/// comparison fail. Output between inputs is always merged first, since how
+/// it is split across reads, and so where expected output is found in it,
+/// varies too.
+#[derive(Clone, Debug)]
+pub struct Normalize {
+    ansi: bool,
+    timestamps: bool,
+    line_endings: bool,
+    replacements: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Default for Normalize {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Normalize {
+    /// Create rules that strip ANSI escape codes, mask kernel timestamps
+    /// and convert `\r\n` to `\n`
+    pub fn new() -> Self {
+        Self {
+            ansi: true,
+            timestamps: true,
+            line_endings: true,
+            replacements: Vec::new(),
+        }
+    }
+
+    /// Create rules that only merge output between inputs
+    pub fn none() -> Self {
+        Self {
+            ansi: false,
+            timestamps: false,
+            line_endings: false,
+            replacements: Vec::new(),
+        }
+    }
+
+    /// Whether to strip ANSI escape codes
+    pub fn ansi(mut self, ansi: bool) -> Self {
+        self.ansi = ansi;
+        self
+    }
+
+    /// Whether to replace kernel timestamps, like `[    1.234567]`, with
+    /// `[TIMESTAMP]`
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Whether to convert `\r\n` to `\n`
+    pub fn line_endings(mut self, line_endings: bool) -> Self {
+        self.line_endings = line_endings;
+        self
+    }
+
+    /// Replace text that varies between runs, such as a hostname, with
+    /// a fixed placeholder
+    pub fn replace(mut self, from: impl Into<Vec<u8>>, to: impl Into<Vec<u8>>) -> Self {
+        self.replacements.push((from.into(), to.into()));
+        self
+    }
+
+    fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        if self.ansi {
+            data = strip_ansi(&data);
+        }
+        if self.line_endings {
+            data = replace(&data, b"\r\n", b"\n");
+        }
+        if self.timestamps {
+            data = mask_timestamps(&data);
+        }
+        for (from, to) in &self.replacements {
+            data = replace(&data, from, to);
+        }
+        data
+    }
+}
+
+/// Remove ANSI escape sequences
+fn strip_ansi(data: &[u8]) -> Vec<u8> {
+    let mut text = Vec::with_capacity(data.len());
+    AnsiStripper::default().strip(data, &mut text);
+    text
+}
+
+/// Replace kernel timestamps, like `[    1.234567]`, with `[TIMESTAMP]`
+fn mask_timestamps(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len() {
+        if data[index] == b'[' {
+            if let Some(len) = timestamp_len(&data[index + 1..]) {
+                result.extend_from_slice(b"[TIMESTAMP]");
+                index += len + 1;
+                continue;
+            }
+        }
+        result.push(data[index]);
+        index += 1;
+    }
+    result
+}
+
+/// Length of a timestamp following a `[`, including the closing `]`
+fn timestamp_len(data: &[u8]) -> Option<usize> {
+    let spaces = data.iter().take_while(|byte| **byte == b' ').count();
+    let seconds = data[spaces..]
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+    let rest = &data[spaces + seconds..];
+    if seconds == 0 || rest.first() != Some(&b'.') {
+        return None;
+    }
+    let fraction = rest[1..]
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+    if fraction == 0 || rest.get(fraction + 1) != Some(&b']') {
+        return None;
+    }
+    Some(spaces + seconds + fraction + 2)
+}
+
+fn replace(data: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    if from.is_empty() {
+        return data.to_vec();
+    }
+    let mut result = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len() {
+        if data[index..].starts_with(from) {
+            result.extend_from_slice(to);
+            index += from.len();
+        } else {
+            result.push(data[index]);
+            index += 1;
+        }
+    }
+    result
+}
+
+/// Escape data so it fits on one line
+fn escape(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len());
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => result.push_str("\\\\"),
+                '\n' => result.push_str("\\n"),
+                '\r' => result.push_str("\\r"),
+                '\t' => result.push_str("\\t"),
+                c if c.is_control() => {
+                    let mut buf = [0u8; 4];
+                    for byte in c.encode_utf8(&mut buf).bytes() {
+                        let _ = write!(result, "\\x{byte:02x}");
+                    }
+                }
+                c => result.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(result, "\\x{byte:02x}");
+        }
+    }
+    result
+}
+
+fn unescape(text: &str) -> Result<Vec<u8>, Error> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::SerializationError,
+            format!("Invalid escape in transcript: {text}"),
+        )
+    };
+    let mut result = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            result.push(byte);
+            continue;
+        }
+        match bytes.next().ok_or_else(invalid)? {
+            b'\\' => result.push(b'\\'),
+            b'n' => result.push(b'\n'),
+            b'r' => result.push(b'\r'),
+            b't' => result.push(b'\t'),
+            b'x' => {
+                let hex = [
+                    bytes.next().ok_or_else(invalid)?,
+                    bytes.next().ok_or_else(invalid)?,
+                ];
+                let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+                result.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(result)
+}
+
+/// A recording of interactions with a terminal
+///
+/// Transcripts are saved one entry per line, as the seconds since recording
+/// started, a marker (`>` sent, `k` key, `<` received, `?` expected) and
+/// the escaped data, e.g. `1.250 ? login: `. Times are kept to help
+/// diagnose slow boots, but are never compared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    /// Interactions in the order they happened
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl std::fmt::Display for Transcript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:.3} {} {}",
+                entry.elapsed.as_secs_f64(),
+                entry.kind.marker(),
+                escape(&entry.data)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Transcript {
+    /// Parse a saved transcript
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut entries = Vec::new();
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let invalid = || {
+                Error::new(
+                    ErrorKind::SerializationError,
+                    format!("Invalid transcript entry: {line}"),
+                )
+            };
+            let (elapsed, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            let (marker, data) = rest.split_once(' ').unwrap_or((rest, ""));
+            entries.push(TranscriptEntry {
+                elapsed: elapsed
+                    .parse()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(invalid)?,
+                kind: EntryKind::from_marker(marker).ok_or_else(invalid)?,
+                data: unescape(data)?,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    /// Load a saved transcript
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Save the transcript
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(std::fs::write(path, self.to_string())?)
+    }
+
+    /// Apply normalization rules, merging output between inputs first
+    ///
+    /// All output received between two inputs becomes one entry, followed
+    /// by the output expected in that time.
+    pub fn normalize(&self, rules: &Normalize) -> Self {
+        let mut entries: Vec<TranscriptEntry> = Vec::with_capacity(self.entries.len());
+        let mut received: Option<TranscriptEntry> = None;
+        let mut expected = Vec::new();
+        for entry in &self.entries {
+            match entry.kind {
+                EntryKind::Received => match &mut received {
+                    Some(received) => received.data.extend_from_slice(&entry.data),
+                    None => received = Some(entry.clone()),
+                },
+                EntryKind::Expected => expected.push(entry.clone()),
+                EntryKind::Sent | EntryKind::Key => {
+                    entries.extend(received.take());
+                    entries.append(&mut expected);
+                    entries.push(entry.clone());
+                }
+            }
+        }
+        entries.extend(received);
+        entries.append(&mut expected);
+        for entry in &mut entries {
+            if entry.kind != EntryKind::Key {
+                entry.data = rules.apply(&entry.data);
+            }
+        }
+        entries.retain(|entry| entry.kind != EntryKind::Received || !entry.data.is_empty());
+        Self { entries }
+    }
+
+    /// Describe how this transcript differs from what was expected once
+    /// both are normalized, or `None` if they match
+    ///
+    /// Each line of the difference is an entry, without its time, prefixed
+    /// with `-` if only expected, `+` if only in this transcript, or a
+    /// space if in both.
+    pub fn diff(&self, expected: &Transcript, rules: &Normalize) -> Option<String> {
+        let lines = |transcript: &Transcript| -> Vec<String> {
+            transcript
+                .normalize(rules)
+                .entries
+                .iter()
+                .map(|entry| format!("{} {}", entry.kind.marker(), escape(&entry.data)))
+                .collect()
+        };
+        let actual = lines(self);
+        let expected = lines(expected);
+        if actual == expected {
+            return None;
+        }
+
+        // Longest common subsequence, from the end, so the difference can be
+        // walked from the start
+        let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+        for i in (0..expected.len()).rev() {
+            for j in (0..actual.len()).rev() {
+                common[i][j] = if expected[i] == actual[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+        let mut diff = String::new();
+        let (mut i, mut j) = (0, 0);
+        while i < expected.len() || j < actual.len() {
+            if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+                let _ = writeln!(diff, "  {}", expected[i]);
+                i += 1;
+                j += 1;
+            } else if i < expected.len()
+                && (j == actual.len() || common[i + 1][j] >= common[i][j + 1])
+            {
+                let _ = writeln!(diff, "- {}", expected[i]);
+                i += 1;
+            } else {
+                let _ = writeln!(diff, "+ {}", actual[j]);
+                j += 1;
+            }
+        }
+        Some(diff)
+    }
+
+    /// Check the transcript against a golden file
+    ///
+    /// When `SYSTEM_HARNESS_UPDATE_GOLDEN` is set, the normalized transcript
+    /// is saved as the golden file instead.
+    pub fn check_golden(&self, path: impl AsRef<Path>, rules: &Normalize) -> Result<(), Error> {
+        let path = path.as_ref();
+        if std::env::var_os(UPDATE_VAR).is_some() {
+            log::info!("Updating golden transcript {}", path.display());
+            return self.normalize(rules).save(path);
+        }
+        let expected = Self::load(path)?;
+        match self.diff(&expected, rules) {
+            None => Ok(()),
+            Some(diff) => Err(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "Transcript differs from {} (set {UPDATE_VAR} to update it):\n{diff}",
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    /// Replay the transcript's input against a terminal, recording what
+    /// happens
+    ///
+    /// Input and keys are sent as they were recorded, and each expected
+    /// output is waited for before carrying on. The returned transcript can
+    /// then be diffed against this one.
+    pub fn replay<T: SystemTerminal>(&self, terminal: T) -> Result<Transcript, Error> {
+        let mut terminal = RecordingTerminal::new(terminal);
+        for entry in &self.entries {
+            match entry.kind {
+                EntryKind::Sent => {
+                    terminal.write_all(&entry.data)?;
+                    terminal.flush()?;
+                }
+                EntryKind::Key => terminal.send_key(parse_key(&entry.data)?)?,
+                EntryKind::Expected => terminal.expect(&entry.data)?,
+                EntryKind::Received => {}
+            }
+        }
+        Ok(terminal.into_transcript())
+    }
+}
+
+fn key_name(key: &Key) -> &'static [u8] {
+    match key {
+        Key::Enter => b"Enter",
+    }
+}
+
+fn parse_key(name: &[u8]) -> Result<Key, Error> {
+    match name {
+        b"Enter" => Ok(Key::Enter),
+        _ => Err(Error::new(
+            ErrorKind::SerializationError,
+            format!("Unknown key in transcript: {}", escape(name)),
+        )),
+    }
+}
+
+/// A terminal that records a transcript of everything sent and received
+pub struct RecordingTerminal<T> {
+    terminal: T,
+    start: Instant,
+    transcript: Transcript,
+    /// Output received since the last expected output
+    pending: Vec<u8>,
+}
+
+impl<T: SystemTerminal> RecordingTerminal<T> {
+    /// Start recording interactions with a terminal
+    pub fn new(terminal: T) -> Self {
+        Self {
+            terminal,
+            start: Instant::now(),
+            transcript: Transcript::default(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, kind: EntryKind, data: &[u8]) {
+        self.transcript.entries.push(TranscriptEntry {
+            elapsed: self.start.elapsed(),
+            kind,
+            data: data.to_vec(),
+        });
+    }
+
+    /// Read until the output contains `text`
+    ///
+    /// Only output received since the last expected output is searched.
+    /// Fails if the terminal ends first, and blocks for as long as reads
+    /// from the terminal do.
+    pub fn expect(&mut self, text: impl AsRef<[u8]>) -> Result<(), Error> {
+        let text = text.as_ref();
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Some(position) = self
+                .pending
+                .windows(text.len().max(1))
+                .position(|window| window == text)
+            {
+                self.pending.drain(..position + text.len());
+                self.record(EntryKind::Expected, text);
+                return Ok(());
+            }
+            if self.read(&mut buf)? == 0 {
+                return Err(Error::new(
+                    ErrorKind::IO,
+                    format!("Terminal ended before expected output: {}", escape(text)),
+                ));
+            }
+        }
+    }
+
+    /// Transcript recorded so far
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Stop recording, returning the transcript
+    pub fn into_transcript(self) -> Transcript {
+        self.transcript
+    }
+}
+
+impl<T: SystemTerminal> Read for RecordingTerminal<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.terminal.read(buf)?;
+        if len > 0 {
+            self.pending.extend_from_slice(&buf[..len]);
+            self.record(EntryKind::Received, &buf[..len]);
+        }
+        Ok(len)
+    }
+}
+
+impl<T: SystemTerminal> Write for RecordingTerminal<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.terminal.write(buf)?;
+        self.record(EntryKind::Sent, &buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.terminal.flush()
+    }
+}
+
+impl<T: SystemTerminal> SystemTerminal for RecordingTerminal<T> {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        self.record(EntryKind::Key, key_name(&key));
+        self.terminal.send_key(key)
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        self.terminal.resize(cols, rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::testing::FakeTerminal;
+
+    /// A terminal that answers each line sent to it from a script
+    fn scripted(banner: &[u8], replies: &[&'static [u8]]) -> FakeTerminal {
+        // Split output across reads, as real terminals do
+        FakeTerminal::new(banner, replies).chunked(4)
+    }
+
+    fn login(terminal: FakeTerminal) -> Transcript {
+        let mut terminal = RecordingTerminal::new(terminal);
+        terminal.expect("login: ").unwrap();
+        terminal.send_command("root").unwrap();
+        terminal.expect("# ").unwrap();
+        terminal.into_transcript()
+    }
+
+    #[test]
+    fn record() {
+        let transcript = login(scripted(
+            b"[    0.000000] Linux\r\nlogin: ",
+            &[b"\x1b[1;32mroot\x1b[0m# "],
+        ));
+        let normalized = transcript.normalize(&Normalize::new());
+        assert_eq!(
+            vec![
+                (EntryKind::Received, &b"[TIMESTAMP] Linux\nlogin: "[..]),
+                (EntryKind::Expected, b"login: "),
+                (EntryKind::Sent, b"root"),
+                (EntryKind::Key, b"Enter"),
+                (EntryKind::Received, b"root# "),
+                (EntryKind::Expected, b"# "),
+            ],
+            normalized
+                .entries
+                .iter()
+                .map(|entry| (entry.kind, entry.data.as_slice()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn save_and_parse() {
+        let transcript = login(scripted(
+            b"\xffuptime\t\\ login: ",
+            &[b"\x1b]0;title\x07# "],
+        ));
+        let text = transcript.to_string();
+        assert!(text.contains("< \\xffupt"));
+        let parsed = Transcript::parse(&text).unwrap();
+        assert_eq!(None, parsed.diff(&transcript, &Normalize::none()));
+        assert_eq!(
+            transcript
+                .entries
+                .iter()
+                .map(|entry| &entry.data)
+                .collect::<Vec<_>>(),
+            parsed
+                .entries
+                .iter()
+                .map(|entry| &entry.data)
+                .collect::<Vec<_>>()
+        );
+        assert!(Transcript::parse("0.000 x data").is_err());
+        assert!(Transcript::parse("0.000 > \\q").is_err());
+    }
+
+    #[test]
+    fn replay_and_diff() {
+        let golden = login(scripted(b"[    0.000000] Linux\r\nlogin: ", &[b"# "]));
+        let replayed = golden
+            .replay(scripted(b"[    1.234567] Linux\r\nlogin: ", &[b"# "]))
+            .unwrap();
+        assert_eq!(None, replayed.diff(&golden, &Normalize::new()));
+
+        let changed = golden
+            .replay(scripted(b"Welcome\r\nlogin: ", &[b"$ # "]))
+            .unwrap();
+        assert_eq!(
+            Some(
+                [
+                    "- < [TIMESTAMP] Linux\\nlogin: ",
+                    "+ < Welcome\\nlogin: ",
+                    "  ? login: ",
+                    "  > root",
+                    "  k Enter",
+                    "- < # ",
+                    "+ < $ # ",
+                    "  ? # ",
+                    "",
+                ]
+                .join("\n")
+            ),
+            changed.diff(&golden, &Normalize::new())
+        );
+        assert_eq!(
+            None,
+            changed.diff(
+                &golden,
+                &Normalize::new()
+                    .replace("Welcome", "[TIMESTAMP] Linux")
+                    .replace("$ ", "")
+            )
+        );
+
+        let err = golden.replay(scripted(b"Linux\r\n", &[])).err().unwrap();
+        assert_eq!(ErrorKind::IO, err.kind());
+    }
+
+    #[test]
+    fn unchunked() {
+        let boot = |terminal: FakeTerminal| {
+            let mut terminal = RecordingTerminal::new(terminal);
+            terminal.expect("Linux").unwrap();
+            terminal.expect("login: ").unwrap();
+            terminal.send_command("root").unwrap();
+            terminal.expect("# ").unwrap();
+            terminal.into_transcript()
+        };
+        let banner = b"Linux\r\nlogin: ";
+        let golden = boot(scripted(banner, &[b"# "]));
+        let unchunked = boot(FakeTerminal::new(banner, &[b"# "]));
+        assert_eq!(None, unchunked.diff(&golden, &Normalize::new()));
+
+        let replayed = golden
+            .normalize(&Normalize::new())
+            .replay(FakeTerminal::new(banner, &[b"# "]))
+            .unwrap();
+        assert_eq!(None, replayed.diff(&golden, &Normalize::new()));
+    }
+
+    #[test]
+    fn golden() {
+        let path = std::env::temp_dir().join(format!("transcript-{}.txt", std::process::id()));
+        let golden = login(scripted(b"login: ", &[b"# "]));
+        golden.save(&path).unwrap();
+        golden.check_golden(&path, &Normalize::new()).unwrap();
+
+        let other = login(scripted(b"login: ", &[b"$ # "]));
+        let err = other.check_golden(&path, &Normalize::new()).err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+        assert!(err.to_string().contains("+ < $ # "));
+        std::fs::remove_file(path).unwrap();
+    }
+}
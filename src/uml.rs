@@ -0,0 +1,522 @@
+use crate::pty::{set_window_size, Pty};
+use crate::runtime::RuntimeDir;
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+/// Default time allowed for the kernel to start its management console
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time allowed for the kernel to exit before it is killed
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Interval between checks while waiting for the kernel
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time allowed for the management console to reply to a request
+const MCONSOLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Magic number starting every management console request
+const MCONSOLE_MAGIC: u32 = 0xcafebabe;
+
+/// Management console protocol version
+const MCONSOLE_VERSION: u32 = 2;
+
+/// Maximum length of a management console request or reply
+const MCONSOLE_MAX_DATA: usize = 512;
+
+/// Unique ID of the kernel, naming its directory under `uml_dir`
+const UMID: &str = "harness";
+
+/// Files created by a system in its runtime directory
+const RUNTIME_FILES: [&str; 1] = ["mconsole.client"];
+
+fn default_executable() -> String {
+    String::from("linux")
+}
+
+/// A configuration for running a User-Mode Linux kernel
+///
+/// The first console is attached to the harness terminal. Other consoles
+/// and serial lines are disabled unless configured.
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UmlSystemConfig {
+    /// UML kernel executable (defaults to `linux`)
+    #[serde(default = "default_executable")]
+    executable: String,
+
+    /// Memory in MiB
+    memory: Option<usize>,
+
+    /// Block devices, attached in order as `ubd0`, `ubd1`, ...
+    disks: Option<Vec<String>>,
+
+    /// Network devices, attached in order as `eth0`, `eth1`, ...,
+    /// e.g. `tuntap,tap0`
+    networks: Option<Vec<String>>,
+
+    /// Channel of the remaining consoles (defaults to `null`)
+    con: Option<String>,
+
+    /// Channel of the serial lines (defaults to `null`)
+    ssl: Option<String>,
+
+    /// Extra kernel command line parameters
+    params: Option<Vec<String>>,
+
+    /// Directory for the management console socket
+    ///
+    /// Defaults to a temporary directory unique to each system.
+    runtime_dir: Option<PathBuf>,
+
+    /// Seconds to wait for the kernel to start (defaults to 30)
+    startup_timeout: Option<u64>,
+
+    /// Seconds to wait for the kernel to exit when dropped before killing
+    /// it (defaults to 10)
+    grace_period: Option<u64>,
+}
+
+impl UmlSystemConfig {
+    /// Command running the kernel with its state under the given directory
+    fn command(&self, uml_dir: &Path) -> Command {
+        let mut command = Command::new(&self.executable);
+        command.arg(format!("umid={UMID}"));
+        command.arg(format!("uml_dir={}", uml_dir.display()));
+        if let Some(memory) = self.memory {
+            command.arg(format!("mem={memory}M"));
+        }
+        for (index, disk) in self.disks.iter().flatten().enumerate() {
+            command.arg(format!("ubd{index}={disk}"));
+        }
+        for (index, network) in self.networks.iter().flatten().enumerate() {
+            command.arg(format!("eth{index}={network}"));
+        }
+        command.arg("con0=fd:0,fd:1");
+        command.arg(format!("con={}", self.con.as_deref().unwrap_or("null")));
+        command.arg(format!("ssl={}", self.ssl.as_deref().unwrap_or("null")));
+        command.args(self.params.iter().flatten());
+        command
+    }
+
+    /// Boot the kernel
+    pub fn build(&self) -> Result<UmlSystem, Error> {
+        let runtime_dir = RuntimeDir::new(self.runtime_dir.as_deref(), &RUNTIME_FILES)?;
+        let client_path = runtime_dir.file("mconsole.client");
+        let _ = std::fs::remove_file(&client_path);
+        let mconsole =
+            MConsole::bind(&client_path, runtime_dir.path().join(UMID).join("mconsole"))?;
+
+        log::trace!("Starting UML kernel...");
+        let (mut process, tty) = Pty::open()?.spawn(&mut self.command(runtime_dir.path()))?;
+
+        let timeout = self
+            .startup_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+        let deadline = Instant::now() + timeout;
+        if let Err(err) = wait_started(&mut process, &mconsole, deadline) {
+            let _ = process.kill();
+            let _ = process.wait();
+            return Err(err);
+        }
+        log::trace!("UML kernel started.");
+
+        Ok(UmlSystem {
+            process,
+            tty,
+            mconsole,
+            paused: false,
+            grace_period: self
+                .grace_period
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_GRACE_PERIOD),
+            runtime_dir,
+        })
+    }
+}
+
+/// Wait for a started kernel to answer on its management console
+fn wait_started(process: &mut Child, mconsole: &MConsole, deadline: Instant) -> Result<(), Error> {
+    loop {
+        if let Some(status) = process.try_wait()? {
+            return Err(Error::new(
+                ErrorKind::HarnessError,
+                format!("UML kernel exited during startup ({status})"),
+            ));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(
+                ErrorKind::Timeout,
+                "Timed out waiting for the management console",
+            ));
+        }
+        // Don't let a probe outlast the deadline
+        mconsole.set_timeout(remaining.min(MCONSOLE_TIMEOUT))?;
+        let probe = mconsole.command("version");
+        mconsole.set_timeout(MCONSOLE_TIMEOUT)?;
+        if probe.is_ok() {
+            return Ok(());
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// A client of a kernel's management console
+struct MConsole {
+    socket: UnixDatagram,
+    server: PathBuf,
+}
+
+/// Encode a management console request
+fn encode_request(command: &str) -> Result<Vec<u8>, Error> {
+    if command.len() >= MCONSOLE_MAX_DATA {
+        return Err(Error::new(
+            ErrorKind::HarnessError,
+            "Management console command too long",
+        ));
+    }
+    let mut request = Vec::with_capacity(12 + MCONSOLE_MAX_DATA);
+    request.extend(MCONSOLE_MAGIC.to_ne_bytes());
+    request.extend(MCONSOLE_VERSION.to_ne_bytes());
+    request.extend((command.len() as u32).to_ne_bytes());
+    request.extend(command.as_bytes());
+    request.resize(12 + MCONSOLE_MAX_DATA, 0);
+    Ok(request)
+}
+
+/// A decoded management console reply
+#[derive(Debug, PartialEq)]
+struct Reply {
+    err: bool,
+    more: bool,
+    data: String,
+}
+
+/// Decode a management console reply
+fn decode_reply(reply: &[u8]) -> Option<Reply> {
+    let field = |index: usize| {
+        let bytes = reply.get(index * 4..index * 4 + 4)?;
+        Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+    };
+    let len = field(2)? as usize;
+    let data = reply.get(12..12 + len)?;
+    let data = data.split(|byte| *byte == 0).next().unwrap_or_default();
+    Some(Reply {
+        err: field(0)? != 0,
+        more: field(1)? != 0,
+        data: String::from_utf8_lossy(data).to_string(),
+    })
+}
+
+impl MConsole {
+    /// Bind a client socket for the server at the given path
+    fn bind(client: &Path, server: PathBuf) -> Result<Self, Error> {
+        let socket = UnixDatagram::bind(client)?;
+        socket.set_read_timeout(Some(MCONSOLE_TIMEOUT))?;
+        Ok(Self { socket, server })
+    }
+
+    /// Set how long to wait for each reply
+    fn set_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        Ok(())
+    }
+
+    /// Send a command, returning its output
+    fn command(&self, command: &str) -> Result<String, Error> {
+        log::trace!("Sending {command} to management console");
+        self.socket
+            .send_to(&encode_request(command)?, &self.server)?;
+        let mut output = String::new();
+        let mut buf = [0u8; 12 + MCONSOLE_MAX_DATA];
+        loop {
+            let len = self.socket.recv(&mut buf)?;
+            let reply = decode_reply(&buf[..len]).ok_or_else(|| {
+                Error::new(ErrorKind::HarnessError, "Invalid management console reply")
+            })?;
+            output.push_str(&reply.data);
+            if reply.err {
+                return Err(Error::new(
+                    ErrorKind::HarnessError,
+                    format!("{command} failed: {}", output.trim()),
+                ));
+            }
+            if !reply.more {
+                return Ok(output);
+            }
+        }
+    }
+}
+
+/// A running User-Mode Linux kernel
+pub struct UmlSystem {
+    process: Child,
+    tty: File,
+    mconsole: MConsole,
+    paused: bool,
+    grace_period: Duration,
+    runtime_dir: RuntimeDir,
+}
+
+impl UmlSystem {
+    /// Directory holding the kernel's management console socket
+    pub fn runtime_dir(&self) -> &Path {
+        self.runtime_dir.path()
+    }
+
+    /// Send a command to the management console, returning its output
+    pub fn mconsole(&self, command: &str) -> Result<String, Error> {
+        self.mconsole.command(command)
+    }
+
+    /// Halt the kernel immediately, without shutting down the guest
+    pub fn halt(&mut self) -> Result<(), Error> {
+        self.mconsole("halt").map(|_| ())
+    }
+
+    /// Wait for the kernel to exit, returning its exit code
+    fn wait_exit(&mut self, deadline: Instant) -> Result<Option<i32>, Error> {
+        loop {
+            if let Some(status) = self.process.try_wait()? {
+                return Ok(status.code());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for the UML kernel to exit",
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// A terminal on the kernel's first console
+pub struct UmlSystemTerminal {
+    tty: File,
+}
+
+impl Read for UmlSystemTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.tty.read(buf) {
+            // Linux reports EIO once the kernel closes its console
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+impl Write for UmlSystemTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tty.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.tty.flush()
+    }
+}
+
+/// Descriptor the terminal's output is read from
+impl AsRawFd for UmlSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.tty.as_raw_fd()
+    }
+}
+
+impl SystemTerminal for UmlSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\r")?,
+        }
+        self.flush().map_err(|err| err.into())
+    }
+
+    /// The kernel forwards its terminal's size to the console
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        set_window_size(&self.tty, cols, rows).map_err(|err| err.into())
+    }
+}
+
+impl SystemHarness for UmlSystem {
+    type Terminal = UmlSystemTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        Ok(UmlSystemTerminal {
+            tty: self.tty.try_clone()?,
+        })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.mconsole("stop")?;
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.mconsole("go")?;
+        self.paused = false;
+        Ok(())
+    }
+
+    /// Sends Ctrl-Alt-Del, leaving the shutdown to the guest's init
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.mconsole("cad").map(|_| ())
+    }
+
+    /// The management console has no status query, so pausing is tracked
+    /// by the harness
+    fn status(&mut self) -> Result<Status, Error> {
+        if self.process.try_wait()?.is_some() {
+            Ok(Status::Shutdown)
+        } else if self.paused {
+            Ok(Status::Paused)
+        } else {
+            Ok(Status::Running)
+        }
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.process.try_wait()?.is_none())
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let code = self.wait_exit(Instant::now() + timeout)?;
+        Ok(ExitStatus { code, reason: None })
+    }
+}
+
+impl Drop for UmlSystem {
+    fn drop(&mut self) {
+        if let Ok(true) = self.running() {
+            log::trace!("Halting UML kernel...");
+            if let Err(err) = self.halt() {
+                log::warn!("Error halting UML kernel: {err}");
+            }
+            if self.wait_exit(Instant::now() + self.grace_period).is_err() {
+                log::warn!(
+                    "UML kernel did not exit within {:?}, killing...",
+                    self.grace_period
+                );
+                let _ = self.process.kill();
+                let _ = self.process.wait();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn command() {
+        let config: UmlSystemConfig = serde_json::from_str(
+            r#"{
+                "memory": 256,
+                "disks": ["rootfs.img"],
+                "networks": ["tuntap,tap0"],
+                "ssl": "pts",
+                "params": ["root=/dev/ubda", "init=/sbin/init"]
+            }"#,
+        )
+        .unwrap();
+        let command = config.command(Path::new("/run/uml"));
+        assert_eq!("linux", command.get_program());
+        assert_eq!(
+            vec![
+                "umid=harness",
+                "uml_dir=/run/uml",
+                "mem=256M",
+                "ubd0=rootfs.img",
+                "eth0=tuntap,tap0",
+                "con0=fd:0,fd:1",
+                "con=null",
+                "ssl=pts",
+                "root=/dev/ubda",
+                "init=/sbin/init"
+            ],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn mconsole() {
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let server_path = runtime_dir.file("mconsole");
+        let server = UnixDatagram::bind(&server_path).unwrap();
+        let client = MConsole::bind(&runtime_dir.file("client"), server_path).unwrap();
+        let responder = std::thread::spawn(move || {
+            let mut buf = [0u8; 12 + MCONSOLE_MAX_DATA];
+            let (len, addr) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(12 + MCONSOLE_MAX_DATA, len);
+            assert_eq!(MCONSOLE_MAGIC.to_ne_bytes(), buf[..4]);
+            assert_eq!(b"version", &buf[12..19]);
+            let client = addr.as_pathname().unwrap();
+            for (more, data) in [(1u32, "Linux "), (0, "6.6.0")] {
+                let mut reply = Vec::new();
+                reply.extend(0u32.to_ne_bytes());
+                reply.extend(more.to_ne_bytes());
+                reply.extend((data.len() as u32).to_ne_bytes());
+                reply.extend(data.as_bytes());
+                server.send_to(&reply, client).unwrap();
+            }
+        });
+        assert_eq!("Linux 6.6.0", client.command("version").unwrap());
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn error_reply() {
+        let mut reply = Vec::new();
+        reply.extend(1u32.to_ne_bytes());
+        reply.extend(0u32.to_ne_bytes());
+        reply.extend(7u32.to_ne_bytes());
+        reply.extend(b"Unknown\0\0");
+        assert_eq!(
+            Some(Reply {
+                err: true,
+                more: false,
+                data: String::from("Unknown"),
+            }),
+            decode_reply(&reply)
+        );
+        assert_eq!(None, decode_reply(&reply[..8]));
+    }
+
+    #[test]
+    fn probe_capped_by_deadline() {
+        let runtime_dir = RuntimeDir::new(None, &[]).unwrap();
+        let server_path = runtime_dir.file("mconsole");
+        // A console that never replies
+        let _server = UnixDatagram::bind(&server_path).unwrap();
+        let client = MConsole::bind(&runtime_dir.file("client"), server_path).unwrap();
+        let mut process = Command::new("sleep").arg("30").spawn().unwrap();
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(300);
+        let err = wait_started(&mut process, &client, deadline).err().unwrap();
+        assert_eq!(ErrorKind::Timeout, err.kind());
+        assert!(start.elapsed() < MCONSOLE_TIMEOUT);
+        process.kill().unwrap();
+        process.wait().unwrap();
+    }
+
+    #[test]
+    fn early_exit() {
+        let config: UmlSystemConfig =
+            serde_json::from_str(r#"{ "executable": "false", "startup_timeout": 5 }"#).unwrap();
+        let err = config.build().err().unwrap();
+        assert_eq!(ErrorKind::HarnessError, err.kind());
+    }
+}
@@ -0,0 +1,334 @@
+use crate::pty::PtySession;
+use crate::runtime::RuntimeDir;
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Interval between state checks while waiting for a machine
+const STATE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A configuration for running a Vagrant machine
+///
+/// The machine comes from an existing Vagrantfile or from a box, for which
+/// a minimal Vagrantfile is generated.
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VagrantSystemConfig {
+    /// Directory containing the Vagrantfile
+    vagrantfile: Option<PathBuf>,
+
+    /// Box to run without a Vagrantfile
+    #[serde(rename = "box")]
+    box_name: Option<String>,
+
+    /// Machine of a multi-machine Vagrantfile
+    machine: Option<String>,
+
+    /// Provider, e.g. `virtualbox` or `libvirt`
+    provider: Option<String>,
+
+    /// Run or skip provisioners (defaults to Vagrant's behavior)
+    provision: Option<bool>,
+
+    /// Leave the machine in place when the system is dropped instead of
+    /// destroying it
+    #[serde(default)]
+    keep: bool,
+}
+
+/// Contents of a Vagrantfile running a single box
+fn box_vagrantfile(box_name: &str) -> String {
+    let box_name = box_name.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("Vagrant.configure(\"2\") do |config|\n  config.vm.box = \"{box_name}\"\nend\n")
+}
+
+impl VagrantSystemConfig {
+    /// Arguments of `vagrant up`
+    fn up_args(&self) -> Vec<String> {
+        let mut args = vec![String::from("up")];
+        if let Some(provider) = &self.provider {
+            args.push(format!("--provider={provider}"));
+        }
+        match self.provision {
+            Some(true) => args.push(String::from("--provision")),
+            Some(false) => args.push(String::from("--no-provision")),
+            None => {}
+        }
+        args.extend(self.machine.clone());
+        args
+    }
+
+    /// Bring the machine up
+    pub fn build(&self) -> Result<VagrantSystem, Error> {
+        let (directory, runtime_dir) = match (&self.vagrantfile, &self.box_name) {
+            (Some(vagrantfile), None) => (vagrantfile.clone(), None),
+            (None, Some(box_name)) => {
+                let runtime_dir = RuntimeDir::new(None, &[])?;
+                std::fs::write(runtime_dir.file("Vagrantfile"), box_vagrantfile(box_name))?;
+                (runtime_dir.path().to_path_buf(), Some(runtime_dir))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidConfig,
+                    "Exactly one of vagrantfile and box must be set",
+                ))
+            }
+        };
+        let system = VagrantSystem {
+            directory,
+            machine: self.machine.clone(),
+            keep: self.keep,
+            _runtime_dir: runtime_dir,
+        };
+        log::trace!("Bringing up Vagrant machine...");
+        system.run(&self.up_args())?;
+        Ok(system)
+    }
+}
+
+/// Map a machine state to a system status
+///
+/// `vagrant suspend` leaves a machine `paused`, `saved` or `suspended`,
+/// depending on the provider, so all of them are reported as paused.
+fn machine_status(state: &str) -> Result<Status, Error> {
+    match state {
+        "running" => Ok(Status::Running),
+        "paused" | "saved" | "suspended" => Ok(Status::Paused),
+        "poweroff" | "shutoff" | "stopped" | "aborted" | "not_created" => Ok(Status::Shutdown),
+        state => Err(Error::new(
+            ErrorKind::HarnessError,
+            format!("Unhandled Vagrant machine state: {state}"),
+        )),
+    }
+}
+
+/// Find a machine's state in `vagrant status --machine-readable` output
+fn parse_state<'a>(output: &'a str, machine: Option<&str>) -> Option<&'a str> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(',').collect();
+        match fields.as_slice() {
+            [_, target, "state", state, ..] if machine.is_none_or(|machine| machine == *target) => {
+                Some(*state)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// A running Vagrant machine
+pub struct VagrantSystem {
+    directory: PathBuf,
+    machine: Option<String>,
+    keep: bool,
+    _runtime_dir: Option<RuntimeDir>,
+}
+
+impl VagrantSystem {
+    /// Directory the machine's Vagrantfile is in
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Vagrant command run against the machine's Vagrantfile
+    fn command(&self, args: &[String]) -> Command {
+        let mut command = Command::new("vagrant");
+        command.args(args).current_dir(&self.directory);
+        command
+    }
+
+    /// Run a Vagrant command, returning its standard output
+    fn run(&self, args: &[String]) -> Result<String, Error> {
+        let output = self.command(args).stdin(Stdio::null()).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        for line in stdout.lines() {
+            log::info!("{line}");
+        }
+        if output.status.success() {
+            Ok(stdout)
+        } else {
+            Err(Error::new(
+                ErrorKind::HarnessError,
+                format!(
+                    "vagrant {} failed: {}",
+                    args.first().map(String::as_str).unwrap_or_default(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))
+        }
+    }
+
+    /// Run a subcommand against the machine
+    fn machine_command(&self, subcommand: &str) -> Result<String, Error> {
+        let mut args = vec![String::from(subcommand)];
+        args.extend(self.machine.clone());
+        self.run(&args)
+    }
+}
+
+/// An SSH session on the machine
+pub struct VagrantSystemTerminal {
+    session: PtySession,
+}
+
+impl Read for VagrantSystemTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.session.read(buf)
+    }
+}
+
+impl Write for VagrantSystemTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.session.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.session.flush()
+    }
+}
+
+/// Descriptor the terminal's output is read from
+impl AsRawFd for VagrantSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.session.as_raw_fd()
+    }
+}
+
+impl SystemTerminal for VagrantSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\r")?,
+        }
+        self.flush().map_err(|err| err.into())
+    }
+
+    /// SSH forwards the new size to the machine's terminal
+    fn resize(&mut self, cols: u16, rows: u16) -> Result<(), Error> {
+        self.session.resize(cols, rows).map_err(|err| err.into())
+    }
+}
+
+impl SystemHarness for VagrantSystem {
+    type Terminal = VagrantSystemTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        let mut args = vec![String::from("ssh")];
+        args.extend(self.machine.clone());
+        let session = PtySession::spawn(&mut self.command(&args))?;
+        Ok(VagrantSystemTerminal { session })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.machine_command("suspend").map(|_| ())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.machine_command("resume").map(|_| ())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.machine_command("halt").map(|_| ())
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        let mut args = vec![String::from("status"), String::from("--machine-readable")];
+        args.extend(self.machine.clone());
+        let output = self.run(&args)?;
+        let state = parse_state(&output, self.machine.as_deref()).ok_or_else(|| {
+            Error::new(ErrorKind::HarnessError, "Vagrant reported no machine state")
+        })?;
+        machine_status(state)
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.status()? == Status::Running)
+    }
+
+    /// Machines report no exit code, so only the shutdown is observed
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let deadline = Instant::now() + timeout;
+        while self.status()? != Status::Shutdown {
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::Timeout,
+                    "Timed out waiting for the Vagrant machine to shut down",
+                ));
+            }
+            std::thread::sleep(STATE_POLL_INTERVAL);
+        }
+        Ok(ExitStatus {
+            code: None,
+            reason: None,
+        })
+    }
+}
+
+impl Drop for VagrantSystem {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        log::trace!("Destroying Vagrant machine...");
+        let mut args = vec![String::from("destroy"), String::from("--force")];
+        args.extend(self.machine.clone());
+        if let Err(err) = self.run(&args) {
+            log::warn!("Error destroying Vagrant machine: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn up_args() {
+        let config: VagrantSystemConfig = serde_json::from_str(
+            r#"{
+                "vagrantfile": "env",
+                "machine": "web",
+                "provider": "libvirt",
+                "provision": false
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["up", "--provider=libvirt", "--no-provision", "web"],
+            config.up_args()
+        );
+    }
+
+    #[test]
+    fn invalid_source() {
+        let config: VagrantSystemConfig = serde_json::from_str(r#"{}"#).unwrap();
+        let err = config.build().err().unwrap();
+        assert_eq!(ErrorKind::InvalidConfig, err.kind());
+    }
+
+    #[test]
+    fn vagrantfile() {
+        assert_eq!(
+            "Vagrant.configure(\"2\") do |config|\n  config.vm.box = \"debian/bookworm64\"\nend\n",
+            box_vagrantfile("debian/bookworm64")
+        );
+    }
+
+    #[test]
+    fn state() {
+        let output = "1700000000,web,metadata,provider,libvirt\n\
+                      1700000000,web,state,running\n\
+                      1700000000,db,state,saved\n";
+        assert_eq!(Some("running"), parse_state(output, None));
+        assert_eq!(Some("saved"), parse_state(output, Some("db")));
+        assert_eq!(Status::Paused, machine_status("saved").unwrap());
+        assert_eq!(Status::Paused, machine_status("suspended").unwrap());
+        assert_eq!(Status::Shutdown, machine_status("not_created").unwrap());
+        assert!(machine_status("unknown").is_err());
+    }
+}
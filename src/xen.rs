@@ -0,0 +1,363 @@
+use crate::pty::PtySession;
+use crate::runtime::RuntimeDir;
+use crate::{Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Files created by a system in its runtime directory
+const RUNTIME_FILES: [&str; 1] = ["domain.cfg"];
+
+/// Interval between state checks while waiting for a domain
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Counter used to give each generated domain a unique name
+static INSTANCE: AtomicUsize = AtomicUsize::new(0);
+
+/// Quote a string for an xl domain config
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quote a list of strings for an xl domain config
+fn quote_list(values: &[String]) -> String {
+    let values: Vec<_> = values.iter().map(|value| quote(value)).collect();
+    format!("[ {} ]", values.join(", "))
+}
+
+/// A configuration for running a Xen domain with `xl`
+///
+/// This config can be serialized and deserialized using serde.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct XenSystemConfig {
+    /// Domain name (defaults to a generated, unique name)
+    name: Option<String>,
+
+    /// Guest type: `pv`, `pvh` or `hvm`
+    #[serde(rename = "type")]
+    guest_type: Option<String>,
+
+    /// Kernel image booted directly
+    kernel: Option<String>,
+
+    /// Initial ramdisk
+    ramdisk: Option<String>,
+
+    /// Kernel command line
+    cmdline: Option<String>,
+
+    /// Memory in MiB
+    memory: Option<usize>,
+
+    /// Number of virtual CPUs
+    vcpus: Option<usize>,
+
+    /// Disk specifications, e.g. `format=raw,vdev=xvda,target=disk.img`
+    disks: Option<Vec<String>>,
+
+    /// Network interface specifications, e.g. `bridge=xenbr0`
+    vifs: Option<Vec<String>>,
+
+    /// Extra lines appended to the domain config
+    extra_config: Option<Vec<String>>,
+
+    /// Directory for the generated domain config
+    ///
+    /// Defaults to a temporary directory unique to each system.
+    runtime_dir: Option<PathBuf>,
+}
+
+impl XenSystemConfig {
+    /// Contents of the xl domain config
+    fn domain_config(&self, name: &str) -> String {
+        let mut lines = vec![format!("name = {}", quote(name))];
+        if let Some(guest_type) = &self.guest_type {
+            lines.push(format!("type = {}", quote(guest_type)));
+        }
+        if let Some(kernel) = &self.kernel {
+            lines.push(format!("kernel = {}", quote(kernel)));
+        }
+        if let Some(ramdisk) = &self.ramdisk {
+            lines.push(format!("ramdisk = {}", quote(ramdisk)));
+        }
+        if let Some(cmdline) = &self.cmdline {
+            lines.push(format!("cmdline = {}", quote(cmdline)));
+        }
+        if let Some(memory) = self.memory {
+            lines.push(format!("memory = {memory}"));
+        }
+        if let Some(vcpus) = self.vcpus {
+            lines.push(format!("vcpus = {vcpus}"));
+        }
+        if let Some(disks) = &self.disks {
+            lines.push(format!("disk = {}", quote_list(disks)));
+        }
+        if let Some(vifs) = &self.vifs {
+            lines.push(format!("vif = {}", quote_list(vifs)));
+        }
+        lines.extend(self.extra_config.iter().flatten().cloned());
+        lines.join("\n") + "\n"
+    }
+
+    /// Create the domain
+    pub fn build(&self) -> Result<XenSystem, Error> {
+        let runtime_dir = RuntimeDir::new(self.runtime_dir.as_deref(), &RUNTIME_FILES)?;
+        let name = self.name.clone().unwrap_or_else(|| {
+            format!(
+                "system-harness-{}-{}",
+                std::process::id(),
+                INSTANCE.fetch_add(1, Ordering::Relaxed)
+            )
+        });
+        let config_path = runtime_dir.file("domain.cfg");
+        std::fs::write(&config_path, self.domain_config(&name))?;
+
+        log::trace!("Creating domain {name}...");
+        xl(Command::new("xl").arg("create").arg(&config_path))?;
+        Ok(XenSystem {
+            name,
+            _runtime_dir: runtime_dir,
+        })
+    }
+}
+
+/// Run an xl command, returning its standard output
+fn xl(command: &mut Command) -> Result<String, Error> {
+    let output = command.stdin(Stdio::null()).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(Error::new(
+            ErrorKind::HarnessError,
+            format!(
+                "xl failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+/// State flags of a domain as reported by `xl list`
+#[derive(Debug, PartialEq)]
+enum DomainState {
+    /// Running or blocked waiting for events
+    Active,
+    Paused,
+
+    /// Shut down or dying but not yet destroyed
+    Shutdown,
+    Crashed,
+}
+
+/// Parse the state column of `xl list` output, e.g. `-b----`
+fn parse_state(output: &str) -> Option<DomainState> {
+    let flags = output.lines().nth(1)?.split_whitespace().nth(4)?;
+    let flag = |index: usize, value: char| flags.chars().nth(index) == Some(value);
+    if flag(4, 'c') {
+        Some(DomainState::Crashed)
+    } else if flag(3, 's') || flag(5, 'd') {
+        Some(DomainState::Shutdown)
+    } else if flag(2, 'p') {
+        Some(DomainState::Paused)
+    } else {
+        Some(DomainState::Active)
+    }
+}
+
+/// A running Xen domain
+pub struct XenSystem {
+    name: String,
+    _runtime_dir: RuntimeDir,
+}
+
+impl XenSystem {
+    /// Name of the domain
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Run an xl subcommand against the domain
+    fn xl(&self, subcommand: &str) -> Result<(), Error> {
+        xl(Command::new("xl").arg(subcommand).arg(&self.name)).map(|_| ())
+    }
+
+    /// State of the domain, or `None` once it has been destroyed
+    fn state(&self) -> Result<Option<DomainState>, Error> {
+        let output = Command::new("xl")
+            .arg("list")
+            .arg(&self.name)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        parse_state(&String::from_utf8_lossy(&output.stdout))
+            .map(Some)
+            .ok_or_else(|| Error::new(ErrorKind::HarnessError, "Unexpected xl list output"))
+    }
+}
+
+/// The console of a domain
+pub struct XenSystemTerminal {
+    session: PtySession,
+}
+
+impl Read for XenSystemTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.session.read(buf)
+    }
+}
+
+impl Write for XenSystemTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.session.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.session.flush()
+    }
+}
+
+/// Descriptor the terminal's output is read from
+impl AsRawFd for XenSystemTerminal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.session.as_raw_fd()
+    }
+}
+
+impl SystemTerminal for XenSystemTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.write_all(b"\r")?,
+        }
+        self.flush().map_err(|err| err.into())
+    }
+}
+
+impl SystemHarness for XenSystem {
+    type Terminal = XenSystemTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        let session = PtySession::spawn(Command::new("xl").arg("console").arg(&self.name))?;
+        Ok(XenSystemTerminal { session })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.xl("pause")
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.xl("unpause")
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.xl("shutdown")
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        match self.state()? {
+            Some(DomainState::Active) => Ok(Status::Running),
+            Some(DomainState::Paused) => Ok(Status::Paused),
+            Some(DomainState::Shutdown) | Some(DomainState::Crashed) | None => Ok(Status::Shutdown),
+        }
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.status()? == Status::Running)
+    }
+
+    /// Domains report no exit code, but a crash is reported as the reason
+    fn wait(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.state()? {
+                Some(DomainState::Crashed) => {
+                    return Ok(ExitStatus {
+                        code: None,
+                        reason: Some(String::from("crashed")),
+                    })
+                }
+                Some(DomainState::Shutdown) | None => {
+                    return Ok(ExitStatus {
+                        code: None,
+                        reason: None,
+                    })
+                }
+                _ if Instant::now() >= deadline => {
+                    return Err(Error::new(
+                        ErrorKind::Timeout,
+                        format!("Timed out waiting for domain {} to shut down", self.name),
+                    ))
+                }
+                _ => std::thread::sleep(STATE_POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+impl Drop for XenSystem {
+    fn drop(&mut self) {
+        if let Ok(Some(_)) = self.state() {
+            log::trace!("Destroying domain {}...", self.name);
+            if let Err(err) = self.xl("destroy") {
+                log::warn!("Error destroying domain {}: {err}", self.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn domain_config() {
+        let config: XenSystemConfig = serde_json::from_str(
+            r#"{
+                "type": "pvh",
+                "kernel": "/boot/vmlinuz",
+                "cmdline": "console=hvc0 root=/dev/xvda",
+                "memory": 512,
+                "vcpus": 2,
+                "disks": ["format=raw,vdev=xvda,target=rootfs.img"],
+                "vifs": ["bridge=xenbr0"],
+                "extra_config": ["on_crash = \"preserve\""]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            "name = \"test\"\n\
+             type = \"pvh\"\n\
+             kernel = \"/boot/vmlinuz\"\n\
+             cmdline = \"console=hvc0 root=/dev/xvda\"\n\
+             memory = 512\n\
+             vcpus = 2\n\
+             disk = [ \"format=raw,vdev=xvda,target=rootfs.img\" ]\n\
+             vif = [ \"bridge=xenbr0\" ]\n\
+             on_crash = \"preserve\"\n",
+            config.domain_config("test")
+        );
+    }
+
+    #[test]
+    fn state() {
+        let list = |flags: &str| {
+            format!(
+                "Name    ID   Mem VCPUs      State   Time(s)\n\
+                 test     3   512     2     {flags}       1.2\n"
+            )
+        };
+        assert_eq!(Some(DomainState::Active), parse_state(&list("-b----")));
+        assert_eq!(Some(DomainState::Paused), parse_state(&list("--p---")));
+        assert_eq!(Some(DomainState::Shutdown), parse_state(&list("---s--")));
+        assert_eq!(Some(DomainState::Crashed), parse_state(&list("----c-")));
+        assert_eq!(None, parse_state("Name ID Mem VCPUs State Time(s)\n"));
+    }
+}
@@ -2,7 +2,7 @@ extern crate system_harness;
 
 use system_harness::{ContainerSystemConfig, SystemHarness};
 
-const JSON_CONFIG: &'static str = include_str!("../tests/data/container-config.json");
+const JSON_CONFIG: &str = include_str!("../tests/data/container-config.json");
 
 #[test_log::test]
 fn build() {
@@ -3,7 +3,7 @@ extern crate system_harness;
 use std::sync::{Arc, Mutex};
 use system_harness::{Event, EventKind, EventPublisher, QemuSystemConfig, SystemHarness};
 
-const JSON_CONFIG: &'static str = include_str!("../tests/data/qemu-config.json");
+const JSON_CONFIG: &str = include_str!("../tests/data/qemu-config.json");
 
 #[derive(Default, Debug)]
 struct Events {
@@ -26,7 +26,7 @@ fn build() {
                     EventKind::Shutdown => guard.shutdown += 1,
                     EventKind::Resume => guard.resume += 1,
                     EventKind::Pause => guard.pause += 1,
-                    EventKind::Suspend => {}
+                    _ => {}
                 }
             })
             .unwrap();
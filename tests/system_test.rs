@@ -0,0 +1,112 @@
+extern crate system_harness;
+
+use std::io::{Read, Write};
+use std::time::Duration;
+use system_harness::{
+    system_test, Error, ErrorKind, ExitStatus, Key, Status, SystemHarness, SystemTerminal,
+};
+
+struct EchoTerminal(Vec<u8>);
+
+impl Read for EchoTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.0.len());
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for EchoTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SystemTerminal for EchoTerminal {
+    fn send_key(&mut self, key: Key) -> Result<(), Error> {
+        match key {
+            Key::Enter => self.0.push(b'\n'),
+        }
+        Ok(())
+    }
+}
+
+struct EchoSystem {
+    running: bool,
+}
+
+impl SystemHarness for EchoSystem {
+    type Terminal = EchoTerminal;
+
+    fn terminal(&self) -> Result<Self::Terminal, Error> {
+        Ok(EchoTerminal(Vec::new()))
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        self.running = false;
+        Ok(())
+    }
+
+    fn status(&mut self) -> Result<Status, Error> {
+        Ok(Status::Running)
+    }
+
+    fn running(&mut self) -> Result<bool, Error> {
+        Ok(self.running)
+    }
+
+    fn wait(&mut self, _timeout: Duration) -> Result<ExitStatus, Error> {
+        if self.running {
+            Err(Error::new(ErrorKind::Timeout, "Still running"))
+        } else {
+            Ok(ExitStatus {
+                code: Some(0),
+                reason: None,
+            })
+        }
+    }
+}
+
+fn echo_system() -> Result<EchoSystem, Error> {
+    Ok(EchoSystem { running: true })
+}
+
+#[system_test(builder = echo_system)]
+fn builder(system: &mut impl SystemHarness) {
+    assert!(system.running().unwrap());
+    let mut terminal = system.terminal().unwrap();
+    terminal.send_command("echo").unwrap();
+    let mut output = String::new();
+    terminal.read_to_string(&mut output).unwrap();
+    assert_eq!("echo\n", output);
+}
+
+#[system_test(builder = echo_system)]
+#[should_panic(expected = "no prompt")]
+fn panics(_system: &mut impl SystemHarness) {
+    panic!("no prompt");
+}
+
+#[cfg(feature = "process")]
+#[system_test(config = "tests/data/process-config.json")]
+fn config(system: &mut impl SystemHarness) {
+    let mut terminal = system.terminal().unwrap();
+    terminal.send_command("hello").unwrap();
+    let mut line = [0u8; 6];
+    terminal.read_exact(&mut line).unwrap();
+    assert_eq!(b"hello\n", &line);
+}